@@ -0,0 +1,83 @@
+use std::collections::BTreeSet;
+use std::time::{Duration, Instant};
+use rumqttc::v5::mqttbytes::v5::SubscribeReasonCode;
+
+// How long a probe has to come back before the broker is taken to drop it.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+// Checks, after connecting, that the broker lets this device use its
+// topics. A broker whose ACL leaves one out may still acknowledge what is
+// published there and never forward it, so each topic gets a probe that
+// has to come back, and each subscription at startup has to be granted.
+pub struct Check {
+    // Topics in the order they were subscribed to at startup, which is the
+    // order their acks come in.
+    subscribed: Vec<String>,
+    acked: usize,
+    waiting: BTreeSet<String>,
+    deadline: Option<Instant>,
+    done: bool,
+}
+
+impl Check {
+    pub fn new(subscribed: Vec<String>, enabled: bool) -> Check {
+        let acked = if enabled { 0 } else { subscribed.len() };
+        Check { subscribed, acked, waiting: BTreeSet::new(), deadline: None, done: !enabled }
+    }
+
+    // Takes the next subscription ack, with an error naming a topic the
+    // broker turned down.
+    pub fn suback(&mut self, codes: &[SubscribeReasonCode]) -> Result<(), String> {
+        let Some(topic) = self.subscribed.get(self.acked) else {
+            return Ok(());
+        };
+        self.acked += 1;
+        match codes.first() {
+            Some(SubscribeReasonCode::Success(_)) | None => Ok(()),
+            Some(code) => Err(format!("the broker refused the subscription to {topic} ({code:?})")),
+        }
+    }
+
+    // The topics to probe once connected, unless that is done.
+    pub fn start(&mut self, topics: &[String]) -> bool {
+        if self.done {
+            return false;
+        }
+        self.waiting = topics.iter().cloned().collect();
+        self.deadline = Some(Instant::now() + TIMEOUT);
+        true
+    }
+
+    // A lost connection leaves the probes for the next one.
+    pub fn interrupted(&mut self) {
+        self.waiting.clear();
+        self.deadline = None;
+    }
+
+    pub fn is_waiting(&self, topic: &str) -> bool {
+        self.waiting.contains(topic)
+    }
+
+    pub fn returned(&mut self, topic: &str) {
+        self.waiting.remove(topic);
+        if self.waiting.is_empty() && self.deadline.is_some() {
+            self.deadline = None;
+            self.done = true;
+        }
+    }
+
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    // Once the deadline has passed, the topics whose probe never came back.
+    pub fn missing(&self) -> Option<String> {
+        self.missing_at(Instant::now())
+    }
+
+    // The same at `now`, for a test that cannot wait out the deadline.
+    pub fn missing_at(&self, now: Instant) -> Option<String> {
+        let deadline = self.deadline?;
+        (now >= deadline && !self.waiting.is_empty()).then(|| self.waiting.iter().cloned().collect::<Vec<_>>().join(", "))
+    }
+}
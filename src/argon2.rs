@@ -0,0 +1,334 @@
+// Argon2id (RFC 9106) with the BLAKE2b (RFC 7693) it is built on, neither
+// of which ring has. Lanes are filled one after another on the calling
+// thread, which gives the same output as filling them in parallel.
+
+const BLOCK_WORDS: usize = 128;
+const SYNC_POINTS: usize = 4;
+const VERSION: u32 = 0x13;
+const ARGON2ID: u32 = 2;
+
+type Block = [u64; BLOCK_WORDS];
+
+pub struct Params {
+    pub memory_kib: u32,
+    pub passes: u32,
+    pub lanes: u32,
+}
+
+pub fn argon2id(password: &[u8], salt: &[u8], params: &Params, out: &mut [u8]) {
+    argon2id_keyed(password, salt, &[], &[], params, out);
+}
+
+// With the secret and associated data that cloudboard leaves empty, which
+// RFC 9106's test vector sets.
+pub fn argon2id_keyed(password: &[u8], salt: &[u8], secret: &[u8], data: &[u8], params: &Params, out: &mut [u8]) {
+    let lanes = params.lanes.max(1) as usize;
+    let memory = (params.memory_kib as usize).max(2 * SYNC_POINTS * lanes) / (SYNC_POINTS * lanes) * (SYNC_POINTS * lanes);
+    let columns = memory / lanes;
+    let segment = columns / SYNC_POINTS;
+
+    let mut h0 = Blake2b::new(64);
+    for value in [lanes as u32, out.len() as u32, params.memory_kib, params.passes, VERSION, ARGON2ID] {
+        h0.update(&value.to_le_bytes());
+    }
+    for input in [password, salt, secret, data] {
+        h0.update(&(input.len() as u32).to_le_bytes());
+        h0.update(input);
+    }
+    let h0 = h0.finalize();
+
+    let passes = params.passes.max(1);
+    let mut blocks = vec![[0u64; BLOCK_WORDS]; memory];
+    for lane in 0..lanes {
+        for column in 0..2 {
+            let mut seed = [0u8; 72];
+            seed[..64].copy_from_slice(&h0);
+            seed[64..68].copy_from_slice(&(column as u32).to_le_bytes());
+            seed[68..].copy_from_slice(&(lane as u32).to_le_bytes());
+            let mut bytes = [0u8; 1024];
+            hash_long(&seed, &mut bytes);
+            blocks[lane * columns + column] = from_bytes(&bytes);
+        }
+    }
+
+    let mut memory = Memory { blocks, lanes, columns, segment, passes };
+    for pass in 0..passes as u64 {
+        for slice in 0..SYNC_POINTS {
+            for lane in 0..lanes {
+                memory.fill_segment(pass, slice, lane);
+            }
+        }
+    }
+
+    let mut last = memory.blocks[columns - 1];
+    for lane in 1..lanes {
+        for (word, other) in last.iter_mut().zip(&memory.blocks[lane * columns + columns - 1]) {
+            *word ^= other;
+        }
+    }
+    hash_long(&to_bytes(&last), out);
+}
+
+// The blocks as `lanes` rows of `columns`, each row cut into SYNC_POINTS
+// segments of `segment` blocks.
+struct Memory {
+    blocks: Vec<Block>,
+    lanes: usize,
+    columns: usize,
+    segment: usize,
+    passes: u32,
+}
+
+impl Memory {
+    fn fill_segment(&mut self, pass: u64, slice: usize, lane: usize) {
+        let (lanes, columns, segment) = (self.lanes, self.columns, self.segment);
+        let blocks = &mut self.blocks;
+        // The first half of the first pass picks references independently of
+        // the password, against side channels; the rest depends on the data,
+        // against trading memory for time.
+        let independent = pass == 0 && slice < SYNC_POINTS / 2;
+        let mut input = [0u64; BLOCK_WORDS];
+        let mut addresses = [0u64; BLOCK_WORDS];
+        if independent {
+            input[..7].copy_from_slice(&[pass, lane as u64, slice as u64, blocks.len() as u64, self.passes as u64, ARGON2ID as u64, 0]);
+        }
+        let first = if pass == 0 && slice == 0 { 2 } else { 0 };
+        if independent && first > 0 {
+            next_addresses(&mut input, &mut addresses);
+        }
+
+        for index in first..segment {
+            let column = slice * segment + index;
+            let current = lane * columns + column;
+            let previous = if column == 0 { current + columns - 1 } else { current - 1 };
+            let random = if independent {
+                if index % BLOCK_WORDS == 0 {
+                    next_addresses(&mut input, &mut addresses);
+                }
+                addresses[index % BLOCK_WORDS]
+            } else {
+                blocks[previous][0]
+            };
+
+            let ref_lane = if pass == 0 && slice == 0 { lane } else { (random >> 32) as usize % lanes };
+            let same_lane = ref_lane == lane;
+            let area = match (pass, same_lane) {
+                (0, true) => slice * segment + index - 1,
+                (0, false) => slice * segment - usize::from(index == 0),
+                (_, true) => columns - segment + index - 1,
+                (_, false) => columns - segment - usize::from(index == 0),
+            };
+            let j1 = random & 0xffff_ffff;
+            let x = (j1 * j1) >> 32;
+            let y = (area as u64 * x) >> 32;
+            let offset = area - 1 - y as usize;
+            let start = if pass == 0 || slice == SYNC_POINTS - 1 { 0 } else { (slice + 1) * segment };
+            let reference = ref_lane * columns + (start + offset) % columns;
+
+            let block = compress(&blocks[previous], &blocks[reference]);
+            if pass == 0 {
+                blocks[current] = block;
+            } else {
+                for (word, new) in blocks[current].iter_mut().zip(&block) {
+                    *word ^= new;
+                }
+            }
+        }
+    }
+}
+
+fn next_addresses(input: &mut Block, addresses: &mut Block) {
+    input[6] += 1;
+    let zero = [0u64; BLOCK_WORDS];
+    *addresses = compress(&zero, &compress(&zero, input));
+}
+
+// G from the RFC: the permutation over the rows of X xor Y, then over the
+// columns, xored with X xor Y again.
+fn compress(x: &Block, y: &Block) -> Block {
+    let mut r = [0u64; BLOCK_WORDS];
+    for (word, (a, b)) in r.iter_mut().zip(x.iter().zip(y)) {
+        *word = a ^ b;
+    }
+    let mut q = r;
+    for row in 0..8 {
+        let mut v: [usize; 16] = [0; 16];
+        for (i, index) in v.iter_mut().enumerate() {
+            *index = row * 16 + i;
+        }
+        permute(&mut q, &v);
+    }
+    for column in 0..8 {
+        let mut v: [usize; 16] = [0; 16];
+        for (i, index) in v.iter_mut().enumerate() {
+            *index = (i / 2) * 16 + column * 2 + i % 2;
+        }
+        permute(&mut q, &v);
+    }
+    for (word, saved) in q.iter_mut().zip(&r) {
+        *word ^= saved;
+    }
+    q
+}
+
+fn permute(q: &mut Block, v: &[usize; 16]) {
+    for [a, b, c, d] in [[0, 4, 8, 12], [1, 5, 9, 13], [2, 6, 10, 14], [3, 7, 11, 15], [0, 5, 10, 15], [1, 6, 11, 12], [2, 7, 8, 13], [3, 4, 9, 14]] {
+        let (a, b, c, d) = (v[a], v[b], v[c], v[d]);
+        q[a] = fma(q[a], q[b]);
+        q[d] = (q[d] ^ q[a]).rotate_right(32);
+        q[c] = fma(q[c], q[d]);
+        q[b] = (q[b] ^ q[c]).rotate_right(24);
+        q[a] = fma(q[a], q[b]);
+        q[d] = (q[d] ^ q[a]).rotate_right(16);
+        q[c] = fma(q[c], q[d]);
+        q[b] = (q[b] ^ q[c]).rotate_right(63);
+    }
+}
+
+fn fma(a: u64, b: u64) -> u64 {
+    a.wrapping_add(b).wrapping_add(2u64.wrapping_mul(a & 0xffff_ffff).wrapping_mul(b & 0xffff_ffff))
+}
+
+// H' from the RFC, for outputs longer than BLAKE2b's 64 bytes.
+fn hash_long(input: &[u8], out: &mut [u8]) {
+    let length = (out.len() as u32).to_le_bytes();
+    if out.len() <= 64 {
+        let mut hash = Blake2b::new(out.len());
+        hash.update(&length);
+        hash.update(input);
+        out.copy_from_slice(&hash.finalize()[..out.len()]);
+        return;
+    }
+    let mut hash = Blake2b::new(64);
+    hash.update(&length);
+    hash.update(input);
+    let mut v = hash.finalize();
+    let mut written = 0;
+    while out.len() - written > 64 {
+        out[written..written + 32].copy_from_slice(&v[..32]);
+        written += 32;
+        let mut hash = Blake2b::new(if out.len() - written > 64 { 64 } else { out.len() - written });
+        hash.update(&v);
+        v = hash.finalize();
+    }
+    let rest = out.len() - written;
+    out[written..].copy_from_slice(&v[..rest]);
+}
+
+fn from_bytes(bytes: &[u8; 1024]) -> Block {
+    let mut block = [0u64; BLOCK_WORDS];
+    for (word, chunk) in block.iter_mut().zip(bytes.chunks_exact(8)) {
+        *word = u64::from_le_bytes(chunk.try_into().unwrap());
+    }
+    block
+}
+
+fn to_bytes(block: &Block) -> [u8; 1024] {
+    let mut bytes = [0u8; 1024];
+    for (chunk, word) in bytes.chunks_exact_mut(8).zip(block) {
+        chunk.copy_from_slice(&word.to_le_bytes());
+    }
+    bytes
+}
+
+const IV: [u64; 8] = [
+    0x6a09e667f3bcc908, 0xbb67ae8584caa73b, 0x3c6ef372fe94f82b, 0xa54ff53a5f1d36f1,
+    0x510e527fade682d1, 0x9b05688c2b3e6c1f, 0x1f83d9abfb41bd6b, 0x5be0cd19137e2179,
+];
+
+const SIGMA: [[usize; 16]; 12] = [
+    [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15],
+    [14, 10, 4, 8, 9, 15, 13, 6, 1, 12, 0, 2, 11, 7, 5, 3],
+    [11, 8, 12, 0, 5, 2, 15, 13, 10, 14, 3, 6, 7, 1, 9, 4],
+    [7, 9, 3, 1, 13, 12, 11, 14, 2, 6, 5, 10, 4, 0, 15, 8],
+    [9, 0, 5, 7, 2, 4, 10, 15, 14, 1, 11, 12, 6, 8, 3, 13],
+    [2, 12, 6, 10, 0, 11, 8, 3, 4, 13, 7, 5, 15, 14, 1, 9],
+    [12, 5, 1, 15, 14, 13, 4, 10, 0, 7, 6, 3, 9, 2, 8, 11],
+    [13, 11, 7, 14, 12, 1, 3, 9, 5, 0, 15, 4, 8, 6, 2, 10],
+    [6, 15, 14, 9, 11, 3, 0, 8, 12, 2, 13, 7, 1, 4, 10, 5],
+    [10, 2, 8, 4, 7, 6, 1, 5, 15, 11, 9, 14, 3, 12, 13, 0],
+    [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15],
+    [14, 10, 4, 8, 9, 15, 13, 6, 1, 12, 0, 2, 11, 7, 5, 3],
+];
+
+pub fn blake2b_512(input: &[u8]) -> [u8; 64] {
+    let mut hash = Blake2b::new(64);
+    hash.update(input);
+    hash.finalize()
+}
+
+// Unkeyed BLAKE2b with an output of up to 64 bytes.
+struct Blake2b {
+    h: [u64; 8],
+    buffer: [u8; 128],
+    filled: usize,
+    counter: u128,
+    out_len: usize,
+}
+
+impl Blake2b {
+    fn new(out_len: usize) -> Blake2b {
+        let mut h = IV;
+        h[0] ^= 0x0101_0000 ^ out_len as u64;
+        Blake2b { h, buffer: [0; 128], filled: 0, counter: 0, out_len }
+    }
+
+    fn update(&mut self, mut input: &[u8]) {
+        while !input.is_empty() {
+            // The last block is compressed in finalize, flagged as such.
+            if self.filled == 128 {
+                self.counter += 128;
+                let block = self.buffer;
+                self.compress(&block, false);
+                self.filled = 0;
+            }
+            let take = input.len().min(128 - self.filled);
+            self.buffer[self.filled..self.filled + take].copy_from_slice(&input[..take]);
+            self.filled += take;
+            input = &input[take..];
+        }
+    }
+
+    fn finalize(mut self) -> [u8; 64] {
+        self.counter += self.filled as u128;
+        let mut block = self.buffer;
+        block[self.filled..].fill(0);
+        self.compress(&block, true);
+        let mut out = [0u8; 64];
+        for (chunk, word) in out.chunks_exact_mut(8).zip(&self.h) {
+            chunk.copy_from_slice(&word.to_le_bytes());
+        }
+        out[self.out_len..].fill(0);
+        out
+    }
+
+    fn compress(&mut self, block: &[u8; 128], last: bool) {
+        let mut m = [0u64; 16];
+        for (word, chunk) in m.iter_mut().zip(block.chunks_exact(8)) {
+            *word = u64::from_le_bytes(chunk.try_into().unwrap());
+        }
+        let mut v = [0u64; 16];
+        v[..8].copy_from_slice(&self.h);
+        v[8..].copy_from_slice(&IV);
+        v[12] ^= self.counter as u64;
+        v[13] ^= (self.counter >> 64) as u64;
+        if last {
+            v[14] = !v[14];
+        }
+        for s in &SIGMA {
+            for (i, [a, b, c, d]) in [[0, 4, 8, 12], [1, 5, 9, 13], [2, 6, 10, 14], [3, 7, 11, 15], [0, 5, 10, 15], [1, 6, 11, 12], [2, 7, 8, 13], [3, 4, 9, 14]].into_iter().enumerate() {
+                v[a] = v[a].wrapping_add(v[b]).wrapping_add(m[s[2 * i]]);
+                v[d] = (v[d] ^ v[a]).rotate_right(32);
+                v[c] = v[c].wrapping_add(v[d]);
+                v[b] = (v[b] ^ v[c]).rotate_right(24);
+                v[a] = v[a].wrapping_add(v[b]).wrapping_add(m[s[2 * i + 1]]);
+                v[d] = (v[d] ^ v[a]).rotate_right(16);
+                v[c] = v[c].wrapping_add(v[d]);
+                v[b] = (v[b] ^ v[c]).rotate_right(63);
+            }
+        }
+        for i in 0..8 {
+            self.h[i] ^= v[i] ^ v[i + 8];
+        }
+    }
+}
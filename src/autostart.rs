@@ -0,0 +1,163 @@
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use clap::Subcommand;
+use crate::service::{self, xml_escape};
+
+const LABEL: &str = "io.github.featherl.cloudboard.autostart";
+const RUN_KEY: &str = r"HKCU\Software\Microsoft\Windows\CurrentVersion\Run";
+
+#[derive(Subcommand, Debug)]
+pub enum AutostartCommand {
+    /// Start the daemon with the current config file when you log in
+    Enable,
+    /// Stop starting the daemon when you log in
+    Disable,
+}
+
+// A login item starts the daemon once per session. Unlike the service from
+// `init` it is not restarted when it exits and does not start until the
+// next login.
+pub fn command(config_path: &Path, command: AutostartCommand) {
+    let result = match command {
+        AutostartCommand::Enable => enable(config_path),
+        AutostartCommand::Disable => disable(),
+    };
+    match result {
+        Ok(message) => println!("{message}"),
+        Err(e) => {
+            eprintln!("Failed to change autostart: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn enable(config_path: &Path) -> io::Result<String> {
+    let exe = std::env::current_exe()?;
+    // Login items do not start in the directory this runs in.
+    let config_path = std::path::absolute(config_path)?;
+    if !config_path.exists() {
+        eprintln!("warning: {} does not exist, run `cloudboard init` before you log in again", config_path.display());
+    }
+    // Both would connect under the same client ID and take turns taking
+    // over each other's session.
+    if service::is_installed() {
+        eprintln!("warning: cloudboard is also installed as a service, which already starts it");
+    }
+    if cfg!(windows) {
+        enable_run_key(&exe, &config_path)
+    } else if cfg!(target_os = "macos") {
+        enable_launch_agent(&exe, &config_path)
+    } else {
+        enable_desktop_entry(&exe, &config_path)
+    }
+}
+
+fn disable() -> io::Result<String> {
+    if cfg!(windows) {
+        let query = Command::new("reg").args(["query", RUN_KEY, "/v", "cloudboard"]).stdout(Stdio::null()).stderr(Stdio::null()).status()?;
+        if !query.success() {
+            return Ok("autostart is not enabled".to_string());
+        }
+        let status = Command::new("reg").args(["delete", RUN_KEY, "/v", "cloudboard", "/f"]).stdout(Stdio::null()).status()?;
+        if !status.success() {
+            return Err(io::Error::other("reg delete failed"));
+        }
+        return Ok(format!("removed cloudboard from {}", RUN_KEY));
+    }
+    let path = if cfg!(target_os = "macos") { launch_agent_path() } else { desktop_entry_path() };
+    match std::fs::remove_file(&path) {
+        Ok(()) => Ok(format!("removed {}", path.display())),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok("autostart is not enabled".to_string()),
+        Err(e) => Err(e),
+    }
+}
+
+fn desktop_entry_path() -> PathBuf {
+    service::config_home().join("autostart/cloudboard.desktop")
+}
+
+// XDG autostart, which desktop sessions on Linux and the BSDs follow.
+fn enable_desktop_entry(exe: &Path, config_path: &Path) -> io::Result<String> {
+    let path = desktop_entry_path();
+    let entry = format!(
+        "[Desktop Entry]\n\
+         Type=Application\n\
+         Name=cloudboard\n\
+         Comment=Clipboard sync\n\
+         Exec={} --config {}\n\
+         Terminal=false\n\
+         NoDisplay=true\n\
+         X-GNOME-Autostart-enabled=true\n",
+        exec_quote(&exe.display().to_string()),
+        exec_quote(&config_path.display().to_string()),
+    );
+    std::fs::create_dir_all(path.parent().unwrap())?;
+    std::fs::write(&path, entry)?;
+    Ok(format!("cloudboard starts when you log in, from {}", path.display()))
+}
+
+// An Exec argument is quoted with `"`, inside which `"`, `` ` ``, `$` and
+// `\` take a backslash, and is then escaped once more like any value in
+// the file, which doubles the backslashes. `%` starts a field code.
+fn exec_quote(arg: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in arg.chars() {
+        match c {
+            '"' | '`' | '$' => {
+                quoted.push_str(r"\\");
+                quoted.push(c);
+            }
+            '\\' => quoted.push_str(r"\\\\"),
+            '%' => quoted.push_str("%%"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+fn launch_agent_path() -> PathBuf {
+    service::home().join(format!("Library/LaunchAgents/{LABEL}.plist"))
+}
+
+// Loaded by launchd at the next login, without KeepAlive.
+fn enable_launch_agent(exe: &Path, config_path: &Path) -> io::Result<String> {
+    let path = launch_agent_path();
+    let plist = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <!DOCTYPE plist PUBLIC \"-//Apple//DTD PLIST 1.0//EN\" \"http://www.apple.com/DTDs/PropertyList-1.0.dtd\">\n\
+         <plist version=\"1.0\">\n\
+         <dict>\n\
+         \t<key>Label</key>\n\
+         \t<string>{LABEL}</string>\n\
+         \t<key>ProgramArguments</key>\n\
+         \t<array>\n\
+         \t\t<string>{}</string>\n\
+         \t\t<string>--config</string>\n\
+         \t\t<string>{}</string>\n\
+         \t</array>\n\
+         \t<key>RunAtLoad</key>\n\
+         \t<true/>\n\
+         </dict>\n\
+         </plist>\n",
+        xml_escape(&exe.display().to_string()),
+        xml_escape(&config_path.display().to_string()),
+    );
+    std::fs::create_dir_all(path.parent().unwrap())?;
+    std::fs::write(&path, plist)?;
+    Ok(format!("cloudboard starts when you log in, from {}", path.display()))
+}
+
+// Windows paths cannot contain `"`, so quoting them is enough.
+fn enable_run_key(exe: &Path, config_path: &Path) -> io::Result<String> {
+    let command = format!("\"{}\" --config \"{}\"", exe.display(), config_path.display());
+    let status = Command::new("reg")
+        .args(["add", RUN_KEY, "/v", "cloudboard", "/t", "REG_SZ", "/d", &command, "/f"])
+        .stdout(Stdio::null())
+        .status()?;
+    if !status.success() {
+        return Err(io::Error::other("reg add failed"));
+    }
+    Ok(format!("cloudboard starts when you log in, from {}", RUN_KEY))
+}
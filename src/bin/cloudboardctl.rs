@@ -0,0 +1,3 @@
+fn main() {
+    cloudboard::control::main()
+}
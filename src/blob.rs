@@ -0,0 +1,64 @@
+use std::sync::Arc;
+use std::time::Duration;
+use log::error;
+use crate::stats;
+use crate::store::Store;
+
+const PREFIX: &str = "blob/";
+// Blobs are only worth keeping for content that comes around again soon.
+const MAX_BLOBS: usize = 32;
+
+// Content this device published as a blob or received as one, under
+// `blob/<stored at>-<sha256>`, so an item that comes around again only
+// needs a reference. The newest MAX_BLOBS are kept.
+pub struct Blobs {
+    store: Arc<dyn Store>,
+    // How long the broker keeps a blob, past which it is published again.
+    expiry: Duration,
+}
+
+impl Blobs {
+    pub fn new(store: Arc<dyn Store>, expiry: Duration) -> Blobs {
+        Blobs { store, expiry }
+    }
+
+    pub fn get(&self, sha256: &str) -> Option<String> {
+        let key = self.find(sha256)?.0;
+        let content = self.store.get(&key).ok().flatten()?;
+        String::from_utf8(content).ok()
+    }
+
+    pub fn put(&self, sha256: &str, content: &str) {
+        let key = format!("{PREFIX}{:020}-{sha256}", stats::now());
+        if let Err(e) = self.store.put(&key, content.as_bytes()) {
+            error!("Failed to save blob {}: {}", sha256, e);
+            return;
+        }
+        let keys = self.store.list(PREFIX).unwrap_or_default();
+        if keys.len() > MAX_BLOBS {
+            let _ = self.store.prune(PREFIX, &keys[keys.len() - MAX_BLOBS]);
+        }
+    }
+
+    // Whether the broker still holds the blob, going by when it was
+    // published or received here and with half of the expiry to spare.
+    pub fn is_fresh(&self, sha256: &str) -> bool {
+        self.find(sha256).is_some_and(|(_, stored)| stats::now().saturating_sub(stored) < self.expiry.as_secs() / 2)
+    }
+
+    // The newest key holding `sha256`, and when it was stored.
+    fn find(&self, sha256: &str) -> Option<(String, u64)> {
+        let keys = self.store.list(PREFIX).ok()?;
+        keys.into_iter().rev().find_map(|key| {
+            let (stored, hash) = key.strip_prefix(PREFIX)?.split_once('-')?;
+            let stored = stored.parse().ok()?;
+            (hash == sha256).then_some((key, stored))
+        })
+    }
+}
+
+// Only a hash can name a blob, which keeps other topics out of reach of a
+// forged reference.
+pub fn is_hash(sha256: &str) -> bool {
+    sha256.len() == 64 && sha256.bytes().all(|b| b.is_ascii_hexdigit())
+}
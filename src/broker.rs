@@ -0,0 +1,103 @@
+use std::fmt::Write as _;
+use clap::ValueEnum;
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum Broker {
+    Mosquitto,
+    Emqx,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct GenArgs {
+    #[arg(long, value_enum, default_value = "mosquitto")]
+    broker: Broker,
+
+    #[arg(short, long)]
+    user: String,
+
+    /// Names of the user's devices, each with a <user>-<device> certificate
+    #[arg(required = true)]
+    devices: Vec<String>,
+
+    /// Where the broker keeps ca.crt, server.crt and server.key
+    #[arg(long)]
+    broker_cert_dir: Option<String>,
+
+    #[arg(short, long, default_value = "8883")]
+    port: u16,
+
+    /// Team clipboards these devices may use, shared with other users
+    #[arg(long, value_delimiter = ',')]
+    group: Vec<String>,
+}
+
+// Brokers take the client certificate's CN as the username, and cloudboard
+// certificates are issued to `<user>-<device>`, so every device gets read and
+// write access to exactly its own user's topics and the groups it is given.
+fn topics(args: &GenArgs) -> Vec<String> {
+    let user = &args.user;
+    let mut topics = vec![format!("clipboard/{user}"), crate::control_topic(user), crate::ack_topic(user), crate::meta_topic(user), crate::fetch_topic(user) + "/#", crate::blob_topic(user) + "/#", crate::slot_topic(user) + "/#", crate::revoke_topic(user) + "/#", crate::policy_topic(user)];
+    topics.extend(args.group.iter().map(|name| crate::group_topic(name)));
+    topics
+}
+
+fn mosquitto(args: &GenArgs) -> String {
+    let cert_dir = args.broker_cert_dir.as_deref().unwrap_or("/etc/mosquitto/certs");
+    let mut out = String::new();
+    let _ = writeln!(out, "# mosquitto.conf");
+    let _ = writeln!(out, "listener {}", args.port);
+    let _ = writeln!(out, "cafile {}/ca.crt", cert_dir);
+    let _ = writeln!(out, "certfile {}/server.crt", cert_dir);
+    let _ = writeln!(out, "keyfile {}/server.key", cert_dir);
+    let _ = writeln!(out, "require_certificate true");
+    let _ = writeln!(out, "use_identity_as_username true");
+    let _ = writeln!(out, "allow_anonymous false");
+    let _ = writeln!(out, "acl_file /etc/mosquitto/cloudboard.acl");
+    let _ = writeln!(out);
+    let _ = writeln!(out, "# /etc/mosquitto/cloudboard.acl");
+    for device in &args.devices {
+        let _ = writeln!(out, "user {}-{}", args.user, device);
+        for topic in topics(args) {
+            let _ = writeln!(out, "topic readwrite {topic}");
+        }
+        let _ = writeln!(out);
+    }
+    out
+}
+
+fn emqx(args: &GenArgs) -> String {
+    let cert_dir = args.broker_cert_dir.as_deref().unwrap_or("etc/certs");
+    let mut out = String::new();
+    let _ = writeln!(out, "# emqx.conf");
+    let _ = writeln!(out, "mqtt.peer_cert_as_username = cn");
+    let _ = writeln!(out, "listeners.ssl.default {{");
+    let _ = writeln!(out, "  bind = \"0.0.0.0:{}\"", args.port);
+    let _ = writeln!(out, "  ssl_options {{");
+    let _ = writeln!(out, "    cacertfile = \"{}/ca.crt\"", cert_dir);
+    let _ = writeln!(out, "    certfile = \"{}/server.crt\"", cert_dir);
+    let _ = writeln!(out, "    keyfile = \"{}/server.key\"", cert_dir);
+    let _ = writeln!(out, "    verify = verify_peer");
+    let _ = writeln!(out, "    fail_if_no_peer_cert = true");
+    let _ = writeln!(out, "  }}");
+    let _ = writeln!(out, "}}");
+    let _ = writeln!(out, "authorization {{");
+    let _ = writeln!(out, "  no_match = deny");
+    let _ = writeln!(out, "  sources = [{{ type = file, path = \"etc/cloudboard_acl.conf\" }}]");
+    let _ = writeln!(out, "}}");
+    let _ = writeln!(out);
+    let _ = writeln!(out, "%% etc/cloudboard_acl.conf");
+    let topics = topics(args).iter().map(|topic| format!("\"{topic}\"")).collect::<Vec<_>>().join(", ");
+    for device in &args.devices {
+        let _ = writeln!(out, "{{allow, {{user, \"{}-{}\"}}, all, [{}]}}.", args.user, device, topics);
+    }
+    let _ = writeln!(out, "{{deny, all}}.");
+    out
+}
+
+pub fn gen_config(args: GenArgs) {
+    let config = match args.broker {
+        Broker::Mosquitto => mosquitto(&args),
+        Broker::Emqx => emqx(&args),
+    };
+    print!("{config}");
+}
@@ -0,0 +1,723 @@
+use std::ffi::OsString;
+#[cfg(any(feature = "http-api", feature = "e2e"))]
+use std::io;
+use std::path::{Path, PathBuf};
+#[cfg(feature = "e2e")]
+use std::time::{Duration, Instant};
+use clipboard_rs::{Clipboard, ClipboardContext};
+use clap::{CommandFactory, FromArgMatches, Parser, Subcommand};
+use log::error;
+use rumqttc::v5::mqttbytes::QoS;
+use rumqttc::v5::{Client, Event, Incoming};
+use crate::config::{self, Config};
+#[cfg(feature = "e2e")]
+use crate::crypto::{Derivation, Key, Keyring};
+use crate::envelope::{self, Envelope, Offer};
+use crate::logging::{self, CONNECT};
+use crate::sync::{ClipboardSync, SyncEvent};
+use crate::{autostart, broker, clipboard, completions, devices, diag, doctor, init, paths, pin, policy, profile, revoke, rules, sim, stats, status, trigger, trust, when, Args};
+#[cfg(feature = "http-api")]
+use crate::{http, native_host, slot, snapshot, tail, text};
+#[cfg(feature = "history")]
+use crate::retention;
+
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None, subcommand_negates_reqs = true, after_help = config::PRECEDENCE)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[arg(long, global = true)]
+    config: Option<PathBuf>,
+
+    #[arg(long, global = true)]
+    data_dir: Option<PathBuf>,
+
+    /// Log filters in RUST_LOG syntax, e.g. info,cloudboard::receive=debug
+    #[arg(long, global = true)]
+    log: Option<String>,
+
+    /// Show times in UTC instead of the local timezone
+    #[arg(long, global = true)]
+    utc: bool,
+
+    #[command(flatten)]
+    sync: Option<Args>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Interactively create the config file
+    Init,
+    /// Start the daemon when you log in, without installing it as a service
+    Autostart {
+        #[command(subcommand)]
+        command: autostart::AutostartCommand,
+    },
+    /// Show the state of the running daemon
+    Status,
+    /// Show when other devices were last seen and whether they applied the latest item
+    Devices,
+    /// Check the certificates, the broker and the clipboard backend
+    Doctor {
+        #[command(flatten)]
+        sync: Box<Args>,
+    },
+    /// Publish content through the running daemon, by default its current clipboard
+    #[cfg(feature = "http-api")]
+    Push {
+        /// Content to publish instead of the current clipboard
+        content: Option<String>,
+        /// Publish even if the same content was published or received recently
+        #[arg(long)]
+        force: bool,
+        /// Publish to this team clipboard instead of the personal one
+        #[arg(long, conflicts_with = "force")]
+        group: Option<String>,
+        /// Have each device fill in placeholders like {date}, {hostname} or {clipboard} as it pastes it
+        #[arg(long, conflicts_with = "group")]
+        template: bool,
+        /// Label it, for --accept-tag, triggers and `cloudboard stats --tag`; may be repeated
+        #[arg(long, conflicts_with_all = ["group", "template"])]
+        tag: Vec<String>,
+        /// Send a text file, which devices with --inbox save instead of pasting
+        #[cfg(feature = "files")]
+        #[arg(long, conflicts_with_all = ["content", "force", "group", "template", "tag"])]
+        file: Option<PathBuf>,
+    },
+    /// Print sync events as JSON lines, from the running daemon or a receive-only engine
+    Watch {
+        /// Connect on our own instead of attaching to the daemon
+        #[arg(long)]
+        standalone: bool,
+        #[command(flatten)]
+        sync: Box<Args>,
+    },
+    /// Follow the running daemon's events as readable lines, leaving out contents
+    #[cfg(feature = "http-api")]
+    Tail(tail::TailArgs),
+    /// Ask the device behind the latest offer for its content
+    Fetch {
+        #[command(flatten)]
+        sync: Box<Args>,
+    },
+    /// Clear the OS clipboard
+    Clear {
+        /// Also remove the content the broker retains for devices that start later
+        #[arg(long)]
+        remote: bool,
+        #[command(flatten)]
+        sync: Box<Args>,
+    },
+    /// Measure the round trip to the broker and to other devices through the running daemon
+    #[cfg(feature = "http-api")]
+    Ping {
+        /// Only ping this device
+        device: Option<String>,
+    },
+    /// Show the text the running daemon held back with --hold-suspicious, with hidden characters spelled out
+    #[cfg(feature = "http-api")]
+    Held {
+        /// Put it on the clipboard after all
+        #[arg(long)]
+        apply: bool,
+        /// Drop it
+        #[arg(long, conflicts_with = "apply")]
+        discard: bool,
+    },
+    /// Put what the running daemon kept in --standby on the clipboard, and apply what is received from now on
+    #[cfg(feature = "http-api")]
+    Promote,
+    /// Carry several items between machines at once in the running daemon's --slots
+    #[cfg(feature = "http-api")]
+    Slot {
+        #[command(subcommand)]
+        command: slot::SlotCommand,
+    },
+    /// Save the clipboard to put back later, like before something that overwrites it
+    #[cfg(feature = "http-api")]
+    Snapshot {
+        #[command(subcommand)]
+        command: snapshot::SnapshotCommand,
+    },
+    /// Bundle the config, certificates and trust list for another machine
+    ExportProfile {
+        path: PathBuf,
+        /// Protect the bundle with a passphrase
+        #[arg(long)]
+        encrypt: bool,
+    },
+    /// Set up this machine from a bundle made by `export-profile`
+    ImportProfile {
+        path: PathBuf,
+        /// Replace an existing config file
+        #[arg(long)]
+        force: bool,
+    },
+    /// Bridge a browser extension to the synced clipboard over native messaging
+    #[cfg(feature = "http-api")]
+    NativeHost {
+        // Whatever the browser passes to identify the extension.
+        #[arg(hide = true)]
+        caller: Vec<String>,
+    },
+    /// Print broker settings and ACLs that confine each device to its user's topics
+    GenBrokerConfig(broker::GenArgs),
+    /// Run devices against a simulated broker with latency and loss, to check that each item is applied at most once and all end on the same clipboard
+    Sim(sim::Settings),
+    /// Show sync statistics per device
+    #[cfg(feature = "history")]
+    Stats(stats::StatsArgs),
+    /// Show what the retention limits drop from the log behind `stats`, which the daemon prunes hourly
+    #[cfg(feature = "history")]
+    Prune {
+        /// Prune now instead of only showing what would go
+        #[arg(long)]
+        now: bool,
+        #[command(flatten)]
+        retention: retention::Retention,
+    },
+    /// Manage end-to-end encryption keys
+    #[cfg(feature = "e2e")]
+    Key {
+        #[command(subcommand)]
+        command: KeyCommand,
+    },
+    /// Manage the settings this device publishes for the others as their --primary
+    Policy {
+        #[command(subcommand)]
+        command: policy::PolicyCommand,
+    },
+    /// Have every device drop a device from its trust list and refuse its messages
+    Revoke {
+        /// The device's name, or its ID
+        #[arg(value_name = "DEVICE")]
+        revoked: String,
+        #[command(flatten)]
+        sync: Box<Args>,
+    },
+    /// Manage which devices may decrypt this device's clipboard
+    Trust {
+        #[command(subcommand)]
+        command: trust::TrustCommand,
+    },
+    /// Look into problems after the fact
+    Diag {
+        #[command(subcommand)]
+        command: diag::DiagCommand,
+    },
+    /// Show or update the key --pin holds the broker to
+    Pin {
+        #[command(subcommand)]
+        command: pin::PinCommand,
+    },
+    /// Print a completion script for a shell
+    Completions {
+        shell: completions::Shell,
+    },
+    /// Print the man page
+    Man,
+}
+
+#[cfg(feature = "e2e")]
+#[derive(Subcommand, Debug)]
+enum KeyCommand {
+    /// Create a new keyring file with a fresh key
+    Generate {
+        path: PathBuf,
+    },
+    /// Add a key code printed by `key rotate` on another device
+    Import {
+        path: PathBuf,
+        code: String,
+        #[arg(long, default_value = "24h", value_parser = humantime::parse_duration)]
+        grace: Duration,
+    },
+    /// Replace the current key and send it to the connected devices that trust this one; it does not lock out a device that had the old key
+    Rotate {
+        #[command(flatten)]
+        sync: Box<Args>,
+    },
+    /// Derive the key from a passphrase; run it with the same passphrase on every device
+    SetPassphrase {
+        #[command(flatten)]
+        sync: Box<Args>,
+    },
+}
+
+pub fn main() {
+    let argv: Vec<OsString> = std::env::args_os().collect();
+    // The browser runs the binary named in the host manifest without a
+    // subcommand, so recognise its arguments instead.
+    #[cfg(feature = "http-api")]
+    let argv = {
+        let mut argv = argv;
+        let args: Vec<String> = argv.iter().map(|arg| arg.to_string_lossy().into_owned()).collect();
+        if native_host::is_browser_launch(&args) {
+            argv.insert(1, "native-host".into());
+        }
+        argv
+    };
+    let config_path = config::path_from_args(&argv);
+    let mut config = Config::load(&config_path).unwrap_or_else(|e| {
+        eprintln!("Failed to load config: {}", e);
+        std::process::exit(1);
+    });
+    config.apply_env();
+    config.apply_paranoid(&argv);
+    let cli = parse_cli(&config, argv);
+    logging::init(cli.log.as_deref());
+
+    let data_dir = cli.data_dir.unwrap_or_else(paths::data_dir);
+    let render = when::Render::new(cli.utc);
+    match cli.command {
+        Some(Command::Init) => init::run(&config_path, &data_dir),
+        Some(Command::Autostart { command }) => autostart::command(&config_path, command),
+        Some(Command::Status) => status::print(&data_dir),
+        Some(Command::Devices) => devices::print(&data_dir, render),
+        Some(Command::Doctor { sync }) => doctor::run(&sync, &data_dir),
+        #[cfg(all(feature = "http-api", feature = "files"))]
+        Some(Command::Push { file: Some(file), .. }) => push_file(&data_dir, &file),
+        #[cfg(feature = "http-api")]
+        Some(Command::Push { content, force, group, template, tag, .. }) => push(&data_dir, content, force, group, template, tag),
+        Some(Command::Watch { standalone, sync }) => watch(&sync, &data_dir, standalone),
+        #[cfg(feature = "http-api")]
+        Some(Command::Tail(args)) => tail::run(&data_dir, &args, render),
+        Some(Command::Fetch { sync }) => fetch(&sync, &data_dir),
+        Some(Command::Clear { remote, sync }) => clear(&sync, remote),
+        #[cfg(feature = "http-api")]
+        Some(Command::Ping { device }) => ping(&data_dir, device),
+        #[cfg(feature = "http-api")]
+        Some(Command::Held { apply, discard }) => held(&data_dir, apply, discard),
+        #[cfg(feature = "http-api")]
+        Some(Command::Promote) => promote(&data_dir),
+        #[cfg(feature = "http-api")]
+        Some(Command::Slot { command }) => slot::command(&data_dir, command),
+        #[cfg(feature = "http-api")]
+        Some(Command::Snapshot { command }) => snapshot::command(&data_dir, command),
+        Some(Command::ExportProfile { path, encrypt }) => profile::export(&config_path, &data_dir, &path, encrypt),
+        Some(Command::ImportProfile { path, force }) => profile::import(&config_path, &data_dir, &path, force),
+        #[cfg(feature = "http-api")]
+        Some(Command::NativeHost { .. }) => native_host::run(&data_dir),
+        Some(Command::GenBrokerConfig(args)) => broker::gen_config(args),
+        Some(Command::Sim(settings)) => sim::command(settings),
+        #[cfg(feature = "history")]
+        Some(Command::Stats(args)) => stats::print(&data_dir, args, render),
+        #[cfg(feature = "history")]
+        Some(Command::Prune { now, retention }) => retention::command(&data_dir, &retention, now),
+        #[cfg(feature = "e2e")]
+        Some(Command::Key { command }) => key_command(&data_dir, command),
+        Some(Command::Policy { command }) => policy::command(&data_dir, command),
+        Some(Command::Revoke { revoked, sync }) => revoke::run(&sync, &data_dir, &revoked),
+        Some(Command::Trust { command }) => trust::command(&data_dir, command),
+        Some(Command::Diag { command }) => diag::command(&data_dir, command, render),
+        Some(Command::Pin { command }) => pin::command(&config_path, command),
+        Some(Command::Completions { shell }) => completions::print(shell, Cli::command()),
+        Some(Command::Man) => completions::man(Cli::command()),
+        None => {
+            let sync = cli.sync.expect("sync arguments are required without a subcommand");
+            let triggers = trigger::load(&config, &sync.device).unwrap_or_else(|e| {
+                eprintln!("Failed to load triggers: {}", e);
+                std::process::exit(1);
+            });
+            let rules = rules::load(&config, &sync.device).unwrap_or_else(|e| {
+                eprintln!("Failed to load rules: {}", e);
+                std::process::exit(1);
+            });
+            crate::run(sync, &data_dir, &triggers, rules)
+        }
+    }
+}
+
+fn parse_cli<I, T>(config: &Config, argv: I) -> Cli
+where
+    I: IntoIterator<Item = T>,
+    T: Into<OsString> + Clone,
+{
+    let matches = config.apply(Cli::command()).get_matches_from(argv);
+    let mut cli = Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    // Arguments that only come from the config file are defaults, which do
+    // not make clap consider the flattened sync arguments present.
+    if cli.command.is_none() && cli.sync.is_none() {
+        cli.sync = Some(Args::from_arg_matches(&matches).unwrap_or_else(|e| e.exit()));
+    }
+    cli
+}
+
+pub(crate) fn parse_args<I, T>(config: &Config, argv: I) -> Args
+where
+    I: IntoIterator<Item = T>,
+    T: Into<OsString> + Clone,
+{
+    parse_cli(config, argv).sync.expect("sync arguments are required without a subcommand")
+}
+
+// For a user's mistake or a problem with the machine, which is worth a
+// message rather than a panic.
+pub fn or_exit<T, E: std::fmt::Display>(result: Result<T, E>, failed: &str) -> T {
+    result.unwrap_or_else(|e| {
+        eprintln!("Failed to {}: {}", failed, e);
+        std::process::exit(1);
+    })
+}
+
+#[cfg(feature = "e2e")]
+fn key_command(data_dir: &Path, command: KeyCommand) {
+    match command {
+        KeyCommand::Generate { path } => {
+            let keyring = or_exit(Keyring::generate(&path), &format!("create {}", path.display()));
+            println!("created {} with key {}", path.display(), keyring.current().id);
+            println!("copy it to your other devices, or import there with: cloudboard key import <path> {}", keyring.current().code());
+        }
+        KeyCommand::Import { path, code, grace } => {
+            let key = or_exit(Key::from_code(&code).ok_or("it is not a key code from `key rotate` or `key generate`"), "import the key");
+            let mut keyring = or_exit(Keyring::load(&path), &format!("read {}", path.display()));
+            if keyring.contains(&key.id) {
+                println!("key {} is already known", key.id);
+                return;
+            }
+            println!("switched to key {}", key.id);
+            keyring.rotate(key, grace);
+            or_exit(keyring.save(), &format!("save {}", path.display()));
+        }
+        KeyCommand::Rotate { sync } => rotate_key(*sync, data_dir),
+        KeyCommand::SetPassphrase { sync } => set_passphrase(*sync),
+    }
+}
+
+// Goes through the daemon's HTTP API because only the daemon may number
+// this device's messages.
+#[cfg(feature = "http-api")]
+fn push(data_dir: &Path, content: Option<String>, force: bool, group: Option<String>, template: bool, tags: Vec<String>) {
+    if let Some(tag) = tags.iter().find(|tag| !envelope::is_tag(tag)) {
+        eprintln!("Failed to push clipboard: {tag:?} is not a tag, which is letters, digits and - _ . / only");
+        std::process::exit(1);
+    }
+    let result = match content {
+        Some(content) => Ok(content),
+        None => http::request(data_dir, "GET", "/clipboard", "").and_then(|(status, body)| match status {
+            200 => Ok(body),
+            _ => Err(io::Error::other("the clipboard is empty")),
+        }),
+    };
+    let mut path = match group {
+        Some(group) => format!("/clipboard?group={group}"),
+        None if template => "/clipboard?template=1".to_string(),
+        None if force => "/clipboard?force=1".to_string(),
+        None => "/clipboard".to_string(),
+    };
+    for tag in &tags {
+        path.push(if path.contains('?') { '&' } else { '?' });
+        path.push_str(&format!("tag={}", http::percent_encode(tag)));
+    }
+    if let Err(e) = result.and_then(|content| http::request(data_dir, "PUT", &path, &content)) {
+        eprintln!("Failed to push clipboard: {}", e);
+        std::process::exit(1);
+    }
+}
+
+#[cfg(all(feature = "http-api", feature = "files"))]
+fn push_file(data_dir: &Path, file: &Path) {
+    let name = file.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+    let result = std::fs::read(file)
+        .and_then(|content| String::from_utf8(content).map_err(|_| io::Error::other("only UTF-8 text files can be sent")))
+        .and_then(|content: String| http::request(data_dir, "PUT", &format!("/clipboard?name={}", http::percent_encode(&name)), &content));
+    if let Err(e) = result {
+        eprintln!("Failed to push {}: {}", file.display(), e);
+        std::process::exit(1);
+    }
+}
+
+// The daemon's connection is used when there is one, as a second one under
+// the same client ID would take over its session.
+fn watch(args: &Args, data_dir: &Path, standalone: bool) {
+    #[cfg(feature = "http-api")]
+    if !standalone && status::get(data_dir, "pid").is_some() {
+        if let Err(e) = http::stream(data_dir, "/events", |line| println!("{line}")) {
+            eprintln!("Failed to attach to the daemon: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+    #[cfg(not(feature = "http-api"))]
+    if !standalone && status::get(data_dir, "pid").is_some() {
+        eprintln!("attaching to the daemon needs a build with the http-api feature, use --standalone");
+        std::process::exit(1);
+    }
+    let sync = or_exit(ClipboardSync::start(args, data_dir), "start");
+    for event in sync.events() {
+        println!("{}", event.to_json());
+        // Printed is as applied as it gets here.
+        if let SyncEvent::Received(envelope) = &event {
+            sync.publisher().applied(envelope);
+        }
+    }
+}
+
+// The request goes out under this device's name, so the answer arrives on
+// the running daemon's connection, which checks it against the offer and
+// applies it.
+fn fetch(args: &Args, data_dir: &Path) {
+    let Some(offer) = status::get(data_dir, "offer").and_then(|offer| offer.parse::<Offer>().ok()) else {
+        eprintln!("nothing to fetch, no offer has been received");
+        std::process::exit(1);
+    };
+    let mut request = Envelope::text(&args.device, offer.sha256.clone());
+    request.content_type = envelope::FETCH.to_string();
+    let payload = or_exit(crate::wrap(args, data_dir, &request), "sign the fetch request");
+
+    let options = or_exit(crate::mqtt_options(args, &format!("{}-{}-fetch", args.user, args.device)), "connect");
+    let (client, mut connection) = Client::new(options, 10);
+    or_exit(client.publish(crate::fetch_topic(&args.user), QoS::AtLeastOnce, false, payload), "publish fetch request");
+    for notification in connection.iter() {
+        match notification {
+            Ok(Event::Incoming(Incoming::PubAck(_))) => break,
+            Err(err) => {
+                error!(target: CONNECT, "Failed to publish fetch request: {:?}", err);
+                std::process::exit(1);
+            }
+            _ => {}
+        }
+    }
+    println!("asked {} for {}", offer.device, stats::format_bytes(offer.size));
+}
+
+#[cfg(feature = "http-api")]
+fn ping(data_dir: &Path, device: Option<String>) {
+    let path = match &device {
+        Some(device) => format!("/ping?device={}", http::percent_encode(device)),
+        None => "/ping".to_string(),
+    };
+    let body = match http::request(data_dir, "GET", &path, "") {
+        Ok((_, body)) => body,
+        Err(e) => {
+            eprintln!("Failed to ping: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let mut answered = false;
+    for (from, millis) in body.lines().filter_map(|line| line.split_once(' ')) {
+        match from {
+            "-" => println!("{:<20} {:>6} ms", "broker", millis),
+            from => {
+                println!("{:<20} {:>6} ms", from, millis);
+                answered = true;
+            }
+        }
+    }
+    if !answered {
+        match device {
+            Some(device) => eprintln!("no answer from {device}"),
+            None => eprintln!("no other device answered"),
+        }
+        std::process::exit(1);
+    }
+}
+
+// How much of a held item `held` shows.
+#[cfg(feature = "http-api")]
+const HELD_SHOWN: usize = 4096;
+#[cfg(feature = "http-api")]
+const HELD_LINES: usize = 40;
+
+#[cfg(feature = "http-api")]
+fn held(data_dir: &Path, apply: bool, discard: bool) {
+    let result = match (apply, discard) {
+        (true, _) => http::request(data_dir, "POST", "/held", "").map(|_| println!("put the held text on the clipboard")),
+        (_, true) => http::request(data_dir, "DELETE", "/held", "").map(|_| println!("dropped the held text")),
+        _ => http::request(data_dir, "GET", "/held", "").map(|(status, body)| match (status, body.split_once('\n')) {
+            (200, Some((head, content))) => {
+                let (device, reason) = head.split_once('\t').unwrap_or((head, ""));
+                println!("{device} sent this, which {reason}:");
+                // Escaped so what does not show, and what would control
+                // the terminal, is seen rather than acted on. A large item
+                // is only shown as far as its start.
+                let shown = text::truncate(content, HELD_SHOWN);
+                for line in shown.split_inclusive('\n').take(HELD_LINES) {
+                    println!("  {}", line.escape_debug());
+                }
+                let lines = shown.split_inclusive('\n').count();
+                if shown.len() < content.len() || lines > HELD_LINES {
+                    println!("  ... and more, {} in all", stats::format_bytes(content.len()));
+                }
+                println!("run `cloudboard held --apply` to paste it or `--discard` to drop it");
+            }
+            _ => println!("nothing is held"),
+        }),
+    };
+    if let Err(e) = result {
+        eprintln!("Failed to reach the held text: {}", e);
+        std::process::exit(1);
+    }
+}
+
+#[cfg(feature = "http-api")]
+fn promote(data_dir: &Path) {
+    match http::request(data_dir, "POST", "/promote", "") {
+        Ok((200, device)) => println!("put what {} sent last on the clipboard; applying what is received now", device.trim_end()),
+        Ok(_) => println!("nothing was kept; applying what is received now"),
+        Err(e) => {
+            eprintln!("Failed to promote: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+// An empty retained message is how MQTT removes a retained one. Devices
+// that are connected get it too and ignore it.
+fn clear(args: &Args, remote: bool) {
+    // The virtual and CopyQ backends live in the daemon, not here.
+    if let clipboard::Backend::Watch | clipboard::Backend::Poll = args.clipboard_backend {
+        if let Err(e) = ClipboardContext::new().and_then(|ctx| ctx.clear()) {
+            error!("Failed to clear the clipboard: {}", e);
+        }
+    }
+    if !remote {
+        return;
+    }
+
+    let options = or_exit(crate::mqtt_options(args, &format!("{}-{}-clear", args.user, args.device)), "connect");
+    let (client, mut connection) = Client::new(options, 10);
+    or_exit(client.publish(crate::clipboard_topic(&args.user), QoS::AtLeastOnce, true, Vec::new()), "clear retained content");
+    for notification in connection.iter() {
+        match notification {
+            Ok(Event::Incoming(Incoming::PubAck(_))) => break,
+            Err(err) => {
+                error!(target: CONNECT, "Failed to clear retained content: {:?}", err);
+                std::process::exit(1);
+            }
+            _ => {}
+        }
+    }
+    println!("cleared the content retained by the broker");
+}
+
+// The new key is sealed with the current one and signed by this device,
+// which the others take it by only if it is in their trust list. Whoever
+// had the current key can read it, so rotating never locks out a device
+// holding an old key: that takes `key import` of a fresh key on each device
+// that stays, or device keys. It is not retained, as a removed device could
+// read it off the broker for as long as it stayed there, and what an older
+// release retained is cleared; devices offline now need `key import`.
+#[cfg(feature = "e2e")]
+fn rotate_key(args: Args, data_dir: &Path) {
+    let path = or_exit(args.e2e_key.as_deref().ok_or("--e2e-key is required"), "rotate keys");
+    let mut keyring = or_exit(Keyring::load(path), &format!("read {}", path.display()));
+    let key = Key::random();
+    let trust = or_exit(trust::Trust::load(data_dir), "read this device's keys");
+    let message = keyring.seal(&trust.sign(format!("rotate {}", key.code()).as_bytes()));
+
+    let (client, mut connection) = Client::new(or_exit(crate::mqtt_options(&args, &format!("{}-key-rotate", args.device)), "connect"), 10);
+    or_exit(client.publish(crate::control_topic(&args.user), QoS::AtLeastOnce, true, Vec::new()), "publish new key");
+    or_exit(client.publish(crate::control_topic(&args.user), QoS::AtLeastOnce, false, message), "publish new key");
+    let mut acked = 0;
+    for notification in connection.iter() {
+        match notification {
+            Ok(Event::Incoming(Incoming::PubAck(_))) if acked == 0 => acked += 1,
+            Ok(Event::Incoming(Incoming::PubAck(_))) => break,
+            Err(err) => {
+                error!(target: CONNECT, "Failed to publish new key: {:?}", err);
+                std::process::exit(1);
+            }
+            _ => {}
+        }
+    }
+
+    println!("rotated to key {}", key.id);
+    println!("devices that missed the update can run: cloudboard key import <path> {}", key.code());
+    keyring.rotate(key, args.key_grace);
+    or_exit(keyring.save(), &format!("save {}", path.display()));
+}
+
+// The first device to set a passphrase publishes a fresh salt, which the
+// others derive the same key with. The key is kept in the --e2e-key file
+// like any other, so the daemon never asks for the passphrase and does not
+// spend the derivation on every start.
+#[cfg(feature = "e2e")]
+fn set_passphrase(args: Args) {
+    let path = or_exit(args.e2e_key.as_deref().ok_or("--e2e-key is required"), "set a passphrase");
+    let topic = crate::meta_topic(&args.user);
+    let (client, mut connection) = Client::new(or_exit(crate::mqtt_options(&args, &format!("{}-key-passphrase", args.device)), "connect"), 10);
+    or_exit(client.subscribe(topic.clone(), QoS::AtLeastOnce), "read the key derivation");
+    let mut published = None;
+    let mut deadline = None;
+    loop {
+        let timeout = deadline.map_or(Duration::from_secs(10), |deadline: Instant| deadline.saturating_duration_since(Instant::now()));
+        match connection.recv_timeout(timeout) {
+            Ok(Ok(Event::Incoming(Incoming::SubAck(_)))) => deadline = Some(Instant::now() + Duration::from_secs(2)),
+            Ok(Ok(Event::Incoming(Incoming::Publish(publish)))) if publish.topic == topic => {
+                published = std::str::from_utf8(&publish.payload).ok().and_then(Derivation::decode);
+                if published.is_none() {
+                    eprintln!("Failed to read the key derivation on {}: it is invalid, or its cost is too low or too high", topic);
+                    std::process::exit(1);
+                }
+                break;
+            }
+            Ok(Ok(_)) => {}
+            Ok(Err(err)) => {
+                error!(target: CONNECT, "Failed to read the key derivation: {:?}", err);
+                std::process::exit(1);
+            }
+            Err(_) if deadline.is_some() => break,
+            Err(_) => {
+                eprintln!("Failed to read the key derivation: timed out waiting for the broker");
+                std::process::exit(1);
+            }
+        }
+    }
+
+    let key = match &published {
+        Some(derivation) => {
+            let key = derivation.derive(&init::prompt_secret("Passphrase"));
+            if derivation.key_id() != key.id {
+                eprintln!("That is not the passphrase the other devices use");
+                std::process::exit(1);
+            }
+            key
+        }
+        None => {
+            let passphrase = init::prompt_secret("New passphrase");
+            if init::prompt_secret("Repeat passphrase") != passphrase {
+                eprintln!("The passphrases do not match");
+                std::process::exit(1);
+            }
+            let derivation = Derivation::random();
+            let key = derivation.derive(&passphrase);
+            or_exit(client.publish(topic, QoS::AtLeastOnce, true, derivation.encode(&key)), "publish the key derivation");
+            for notification in connection.iter() {
+                match notification {
+                    Ok(Event::Incoming(Incoming::PubAck(_))) => break,
+                    Err(err) => {
+                        error!(target: CONNECT, "Failed to publish the key derivation: {:?}", err);
+                        std::process::exit(1);
+                    }
+                    _ => {}
+                }
+            }
+            key
+        }
+    };
+
+    // A keyring that has not used this key before switches to it, keeping
+    // the previous keys for --key-grace.
+    let result = match Keyring::load(path) {
+        Ok(keyring) if keyring.current().id == key.id => {
+            println!("already using key {}", key.id);
+            return;
+        }
+        Ok(mut keyring) => {
+            keyring.rotate(key.clone(), args.key_grace);
+            keyring.save()
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Keyring::create(path, key.clone()).map(|_| ()),
+        Err(e) => Err(e),
+    };
+    if let Err(e) = result {
+        eprintln!("Failed to save {}: {}", path.display(), e);
+        std::process::exit(1);
+    }
+    println!("switched to key {}", key.id);
+    if published.is_none() {
+        println!("run `cloudboard key set-passphrase` with the same passphrase on your other devices");
+    }
+}
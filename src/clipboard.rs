@@ -0,0 +1,491 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::{Duration, Instant};
+use clap::ValueEnum;
+use clipboard_rs::{Clipboard, ClipboardContent, ClipboardContext, ContentFormat, ClipboardHandler, ClipboardWatcher, ClipboardWatcherContext, WatcherShutdown};
+use log::{error, info};
+use crate::codec::{self, Decoded};
+use crate::copyq;
+use crate::envelope::Envelope;
+use crate::idle::Idle;
+use crate::lock::lock;
+use crate::logging::{PUBLISH, RECEIVE};
+use crate::secrets;
+use crate::shortcut::Shortcut;
+use crate::source;
+use crate::status::Status;
+use crate::sync::Publisher;
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum Backend {
+    Watch,
+    Poll,
+    /// Keep the clipboard in memory only, for containers and servers with no
+    /// OS clipboard; pair it with --http
+    Virtual,
+    /// Sync CopyQ's clipboard history through its command line instead of
+    /// the OS clipboard
+    Copyq,
+}
+
+#[derive(ValueEnum, Clone, Copy, PartialEq, Debug)]
+pub enum Startup {
+    /// Publish the local clipboard
+    Publish,
+    /// Take the last content published by any device
+    Adopt,
+    /// Do nothing until the next change on either side
+    Idle,
+}
+
+// Which of the formats an item comes with wins when pasted here, as apps
+// take the richest one they know.
+#[derive(ValueEnum, Clone, Copy, PartialEq, Debug)]
+pub enum Prefer {
+    /// Put HTML on the clipboard next to the text, for apps that paste formatting
+    Html,
+    /// Put only the text, as for terminals and editors that would paste markup
+    Text,
+}
+
+// Windows reads these formats as a DWORD, and zero keeps the content out of
+// clipboard history (Win+V) and Microsoft's cloud clipboard.
+const WINDOWS_NO_HISTORY: &[&str] = &["CanIncludeInClipboardHistory", "CanUploadToCloudClipboard"];
+// Types macOS clipboard managers skip, by the convention at nspasteboard.org.
+const MACOS_CONCEALED: &[&str] = &["org.nspasteboard.ConcealedType", "org.nspasteboard.TransientType"];
+
+// Written on X11 next to received text, holding the ID of the process that
+// applied it, so the watcher can tell it from a local copy.
+const ORIGIN: &str = "application/x-cloudboard-origin";
+
+// Formats written to the OS clipboard next to received text, as name and data.
+pub type Marks = Arc<Vec<(String, Vec<u8>)>>;
+
+pub fn marks(no_windows_history: bool) -> Marks {
+    let mut marks = if no_windows_history && cfg!(windows) { concealed() } else { Vec::new() };
+    if cfg!(target_os = "linux") {
+        marks.push((ORIGIN.to_string(), std::process::id().to_string().into_bytes()));
+    }
+    Arc::new(marks)
+}
+
+fn is_applied(ctx: &ClipboardContext) -> bool {
+    cfg!(target_os = "linux")
+        && ctx.has(ContentFormat::Other(ORIGIN.to_string()))
+        && ctx.get_buffer(ORIGIN).is_ok_and(|origin| origin == std::process::id().to_string().as_bytes())
+}
+
+// What keeps sensitive content out of clipboard history on this platform.
+fn concealed() -> Vec<(String, Vec<u8>)> {
+    if cfg!(windows) {
+        WINDOWS_NO_HISTORY.iter().map(|format| (format.to_string(), 0u32.to_le_bytes().to_vec())).collect()
+    } else if cfg!(target_os = "macos") {
+        MACOS_CONCEALED.iter().map(|format| (format.to_string(), Vec::new())).collect()
+    } else {
+        Vec::new()
+    }
+}
+
+// Writes a marker to the OS clipboard and reads it back, then puts back the
+// text that was there, so a clipboard that opens but does not work, as
+// without a display or where Wayland keeps it from background programs,
+// is caught before anything syncs. Content other than text could not be
+// put back, so with any on the clipboard only reading is tried.
+pub fn self_test() -> Result<(), String> {
+    let ctx = ClipboardContext::new().map_err(|e| format!("{}{}", e, display_hint()))?;
+    let saved = ctx.get_text().ok();
+    let rich = [ContentFormat::Image, ContentFormat::Files, ContentFormat::Rtf, ContentFormat::Html].into_iter().any(|format| ctx.has(format));
+    if rich {
+        return ctx.available_formats().map(drop).map_err(|e| format!("{}{}", e, display_hint()));
+    }
+    let marker = format!("cloudboard self-test {}", std::process::id());
+    let mut contents = vec![ClipboardContent::Text(marker.clone())];
+    // Kept out of clipboard history like sensitive content.
+    contents.extend(concealed().into_iter().map(|(format, data)| ClipboardContent::Other(format, data)));
+    ctx.set(contents).map_err(|e| format!("writing failed: {}{}", e, display_hint()))?;
+    let read = ctx.get_text();
+    // Unless something else was copied meanwhile.
+    if read.as_deref().is_ok_and(|read| read == marker) {
+        let restored = match saved {
+            Some(saved) => ctx.set_text(saved),
+            None => ctx.clear(),
+        };
+        restored.map_err(|e| format!("putting back what was on the clipboard failed: {}", e))?;
+    }
+    match read {
+        Ok(read) if read == marker => Ok(()),
+        Ok(_) => Err(format!("reading back what was written gave something else{}", display_hint())),
+        Err(e) => Err(format!("reading back what was written failed: {}{}", e, display_hint())),
+    }
+}
+
+fn display_hint() -> &'static str {
+    if !cfg!(all(unix, not(target_os = "macos"))) {
+        ""
+    } else if std::env::var_os("DISPLAY").is_none() && std::env::var_os("WAYLAND_DISPLAY").is_none() {
+        "; there is no display, as neither DISPLAY nor WAYLAND_DISPLAY is set: run it in the desktop session, or use --clipboard-backend virtual"
+    } else if std::env::var_os("WAYLAND_DISPLAY").is_some() {
+        "; Wayland compositors may only let the focused window at the clipboard, so try XWayland with DISPLAY set"
+    } else {
+        ""
+    }
+}
+
+// Where synced content ends up: the OS clipboard, CopyQ, or in memory when
+// there is none. Writes to the OS clipboard and CopyQ are published by their
+// watchers, the virtual one publishes them directly.
+#[derive(Clone)]
+pub enum Target {
+    System(Arc<Mutex<ClipboardContext>>, Publisher, Marks),
+    Copyq(Publisher),
+    Virtual(Arc<Mutex<Option<String>>>, Publisher),
+}
+
+impl Target {
+    pub fn get(&self) -> Option<String> {
+        match self {
+            Target::System(ctx, _, _) => lock(ctx).get_text().ok(),
+            Target::Copyq(_) => copyq::read().ok().flatten(),
+            Target::Virtual(content, _) => lock(content).clone(),
+        }
+    }
+
+    // Every format content is synced in, for a snapshot to put back as it
+    // was.
+    pub fn formats(&self) -> Option<Decoded> {
+        match self {
+            Target::System(ctx, _, _) => {
+                let ctx = lock(ctx);
+                let text = ctx.get_text().ok()?;
+                Some(Decoded { text, html: ctx.get_html().ok().filter(|html| !html.is_empty()) })
+            }
+            _ => self.get().map(|text| Decoded { text, html: None }),
+        }
+    }
+
+    // Puts a snapshot back. Its watcher leaves it alone unless `synced`, in
+    // which case it goes to the other devices as copied in `source`.
+    pub fn restore(&self, snapshot: Decoded, source: String, synced: bool) -> Result<(), String> {
+        let publisher = self.publisher();
+        if synced {
+            publisher.restored(snapshot.text.clone(), source).map_err(|e| e.to_string())?;
+        } else {
+            publisher.remember(&snapshot.text);
+        }
+        let sensitive = secrets::find(&snapshot.text).is_some();
+        self.set(snapshot, sensitive)
+    }
+
+    // Applies content received from another device, along with the other
+    // formats its codec gives it. Sensitive content is kept out of clipboard
+    // history where the platform has a way to.
+    pub fn set(&self, decoded: Decoded, sensitive: bool) -> Result<(), String> {
+        match self {
+            Target::System(ctx, _, marks) => {
+                let mut contents = Vec::new();
+                if let Some(html) = decoded.html {
+                    contents.push(ClipboardContent::Html(html));
+                }
+                contents.push(ClipboardContent::Text(decoded.text));
+                let mut formats = marks.to_vec();
+                if sensitive {
+                    formats.retain(|(format, _)| !WINDOWS_NO_HISTORY.contains(&format.as_str()));
+                    formats.extend(concealed());
+                }
+                contents.extend(formats.into_iter().map(|(format, data)| ClipboardContent::Other(format, data)));
+                lock(ctx).set(contents).map_err(|e| e.to_string())
+            }
+            Target::Copyq(_) => copyq::add(&decoded.text),
+            Target::Virtual(current, _) => {
+                *lock(current) = Some(decoded.text);
+                Ok(())
+            }
+        }
+    }
+
+    // Writes content as if it had been copied on this device. With `force`
+    // it is published even if it was published or received just before.
+    pub fn copy(&self, content: String, force: bool, tags: Vec<String>) -> Result<(), String> {
+        // Only what is published here carries tags, so tagged content goes
+        // out before the watcher sees it, which then takes it for a repeat.
+        if !tags.is_empty() {
+            self.publisher().tagged(content.clone(), force, tags).map_err(|e| e.to_string())?;
+            return match self {
+                Target::System(ctx, _, _) => lock(ctx).set_text(content).map_err(|e| e.to_string()),
+                Target::Copyq(_) => copyq::add(&content),
+                Target::Virtual(current, _) => {
+                    *lock(current) = Some(content);
+                    Ok(())
+                }
+            };
+        }
+        let publisher = match self {
+            Target::System(ctx, publisher, _) => {
+                lock(ctx).set_text(content.clone()).map_err(|e| e.to_string())?;
+                if !force {
+                    return Ok(());
+                }
+                publisher
+            }
+            Target::Copyq(publisher) => {
+                copyq::add(&content)?;
+                if !force {
+                    return Ok(());
+                }
+                publisher
+            }
+            Target::Virtual(current, publisher) => {
+                *lock(current) = Some(content.clone());
+                publisher
+            }
+        };
+        let result = if force { publisher.force(content) } else { publisher.publish(content) };
+        result.map_err(|e| e.to_string())
+    }
+
+    // Publishes content to a group without touching this clipboard.
+    pub fn share(&self, group: &str, content: String) -> Result<(), String> {
+        self.publisher().share(group, content).map_err(|e| e.to_string())
+    }
+
+    // Publishes a file, also without touching this clipboard.
+    #[cfg(feature = "files")]
+    pub fn file(&self, name: &str, content: String) -> Result<(), String> {
+        self.publisher().file(name, content).map_err(|e| e.to_string())
+    }
+
+    pub fn publisher(&self) -> &Publisher {
+        let (Target::System(_, publisher, _) | Target::Copyq(publisher) | Target::Virtual(_, publisher)) = self;
+        publisher
+    }
+}
+
+// A burst can hold back applying for no longer than this many settle times.
+const MAX_SETTLES: u32 = 5;
+// Content is applied after this long even if local changes never stop.
+const MAX_DEFER: Duration = Duration::from_secs(10);
+
+// Local clipboard changes, minus the ones made by applying received
+// content. Apps like IDEs and spreadsheets write several formats in a row
+// and take the clipboard back right after, so two changes in quick
+// succession mean one of them is at work.
+#[derive(Default)]
+pub struct Activity {
+    // The latest two changes, newest first.
+    changes: Mutex<[Option<Instant>; 2]>,
+    applied: Mutex<Option<u64>>,
+}
+
+impl Activity {
+    pub fn changed(&self, text: &str) {
+        if *lock(&self.applied) == Some(hash_text(text)) {
+            return;
+        }
+        let mut changes = lock(&self.changes);
+        *changes = [Some(Instant::now()), changes[0]];
+    }
+
+    fn applying(&self, text: &str) {
+        *lock(&self.applied) = Some(hash_text(text));
+    }
+
+    // Whether the latest two changes came within `window` of each other,
+    // the latest of them less than `window` ago.
+    fn is_busy(&self, window: Duration) -> bool {
+        match *lock(&self.changes) {
+            [Some(latest), Some(previous)] => latest.elapsed() < window && latest - previous < window,
+            _ => false,
+        }
+    }
+}
+
+// Applies received content on a thread of its own. In a burst, like the
+// queue a reconnect flushes, each item waits `settle` for a newer one and
+// only the newest is applied, so the clipboard and its watcher see a single
+// change instead of every item in turn. While a local app is busy with the
+// clipboard, see Activity, applying waits until it has been left alone for
+// `defer`.
+pub fn applier(target: Target, settle: Duration, activity: Arc<Activity>, defer: Duration, prefer: Prefer) -> mpsc::Sender<Envelope> {
+    let (sender, receiver) = mpsc::channel::<Envelope>();
+    std::thread::spawn(move || {
+        while let Ok(mut received) = receiver.recv() {
+            let deadline = Instant::now() + settle * MAX_SETTLES;
+            let mut superseded = 0;
+            while let Ok(newer) = receiver.recv_timeout(settle.min(deadline.saturating_duration_since(Instant::now()))) {
+                target.publisher().applied(&received);
+                received = newer;
+                superseded += 1;
+            }
+            if superseded > 0 {
+                info!(target: RECEIVE, "applying the newest of {} items received in a burst", superseded + 1);
+            }
+            let deadline = Instant::now() + MAX_DEFER;
+            if activity.is_busy(defer) {
+                info!(target: RECEIVE, "the clipboard is changing locally, waiting to apply received content");
+                while activity.is_busy(defer) && Instant::now() < deadline {
+                    if let Ok(newer) = receiver.recv_timeout(defer.min(deadline.saturating_duration_since(Instant::now()))) {
+                        target.publisher().applied(&received);
+                        received = newer;
+                    }
+                }
+            }
+            let codec = codec::find(&received.content_type).unwrap_or(codec::PLAIN);
+            let mut decoded = codec.decode(std::mem::take(&mut received.content), &|| target.get());
+            if prefer == Prefer::Text {
+                decoded.html = None;
+            }
+            if codec.content_type() != codec::PLAIN.content_type() {
+                target.publisher().remember(&decoded.text);
+            }
+            activity.applying(&decoded.text);
+            // Committed only once it is on the clipboard, so an item lost to
+            // a crash before is applied when the sender publishes it again.
+            match target.set(decoded, received.sensitive) {
+                Ok(()) => target.publisher().applied(&received),
+                Err(e) => error!(target: RECEIVE, "Failed to set clipboard content: {}", e),
+            }
+        }
+    });
+    sender
+}
+
+// Hands every change to the engine, which decides whether it repeats
+// something recent; see --dedup-window.
+#[derive(Clone)]
+pub struct Manager {
+    publisher: Publisher,
+    ctx: Arc<Mutex<ClipboardContext>>,
+    paused: Arc<AtomicBool>,
+    activity: Arc<Activity>,
+    // Whether to look up the application that copied; see --no-source.
+    sources: bool,
+    // With --copy-shortcut, only changes just after a copy shortcut count.
+    shortcut: Option<Arc<Shortcut>>,
+}
+
+impl Manager {
+    pub fn new(ctx: Arc<Mutex<ClipboardContext>>, paused: Arc<AtomicBool>, publisher: Publisher, activity: Arc<Activity>, sources: bool, shortcut: Option<Arc<Shortcut>>) -> Manager {
+        Manager { ctx, paused, publisher, activity, sources, shortcut }
+    }
+}
+
+impl ClipboardHandler for Manager {
+    fn on_clipboard_change(&mut self) {
+        let ctx = lock(&self.ctx);
+        if is_applied(&ctx) {
+            return;
+        }
+
+        if let Ok(text) = ctx.get_text() {
+            self.activity.changed(&text);
+            if self.paused.load(Ordering::Relaxed) {
+                return;
+            }
+            if self.shortcut.as_ref().is_some_and(|shortcut| !shortcut.is_recent()) {
+                info!(target: PUBLISH, "not publishing a clipboard change made without a copy shortcut");
+                return;
+            }
+            let source = if self.sources { source::frontmost() } else { None };
+            if let Err(e) = self.publisher.copied(text, source) {
+                error!("Error sending message: {}", e);
+            }
+        }
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct Shutdown {
+    stopped: Arc<AtomicBool>,
+    watcher: Arc<Mutex<Option<WatcherShutdown>>>,
+}
+
+impl Shutdown {
+    pub fn stop(self) {
+        self.stopped.store(true, Ordering::Relaxed);
+        if let Some(channel) = lock(&self.watcher).take() {
+            channel.stop();
+        }
+    }
+
+    pub(crate) fn is_stopped(&self) -> bool {
+        self.stopped.load(Ordering::Relaxed)
+    }
+}
+
+pub fn spawn(backend: Backend, poll_interval: Duration, idle: Arc<Idle>, manager: Manager, status: Arc<Status>) -> Shutdown {
+    let shutdown = Shutdown::default();
+    if let Backend::Virtual | Backend::Copyq = backend {
+        return shutdown;
+    }
+    let supervised = shutdown.clone();
+    std::thread::spawn(move || supervise(backend, poll_interval, idle, manager, supervised, status));
+    shutdown
+}
+
+// If the backend returns or panics, clipboard changes would silently stop
+// being noticed, so restart it with a capped exponential backoff.
+fn supervise(backend: Backend, poll_interval: Duration, idle: Arc<Idle>, manager: Manager, shutdown: Shutdown, status: Arc<Status>) {
+    let mut failures = 0;
+    while !shutdown.is_stopped() {
+        status.set("watcher", "up");
+        let started = Instant::now();
+        let result = match backend {
+            Backend::Watch => run_watcher(manager.clone(), &shutdown),
+            Backend::Poll => run_poller(poll_interval, idle.clone(), manager.clone(), &shutdown),
+            Backend::Virtual | Backend::Copyq => return,
+        };
+        if shutdown.is_stopped() {
+            break;
+        }
+
+        status.set("watcher", "down");
+        if started.elapsed() > Duration::from_secs(60) {
+            failures = 0;
+        }
+        let delay = Duration::from_secs(1 << failures.min(5));
+        failures += 1;
+        match result {
+            Ok(()) => error!("clipboard watcher stopped unexpectedly, restarting in {:?}", delay),
+            Err(e) => error!("clipboard watcher failed: {}, restarting in {:?}", e, delay),
+        }
+        std::thread::sleep(delay);
+    }
+}
+
+fn run_watcher(manager: Manager, shutdown: &Shutdown) -> Result<(), String> {
+    let mut watcher = ClipboardWatcherContext::new().map_err(|e| e.to_string())?;
+    *lock(&shutdown.watcher) = Some(watcher.add_handler(manager).get_shutdown_channel());
+
+    std::thread::spawn(move || {
+        watcher.start_watch();
+    }).join().map_err(|_| "watcher thread panicked".to_string())
+}
+
+// Some environments (VMs, RDP sessions) never deliver change events, so fall
+// back to reading the clipboard periodically and comparing content hashes,
+// less often while idle.
+fn run_poller(interval: Duration, idle: Arc<Idle>, mut manager: Manager, shutdown: &Shutdown) -> Result<(), String> {
+    info!("polling clipboard every {:?}", interval);
+    let shutdown = shutdown.clone();
+
+    std::thread::spawn(move || {
+        // What is already there is left to --startup.
+        let mut last_hash = lock(&manager.ctx).get_text().ok().map(|text| hash_text(&text));
+        while !shutdown.is_stopped() {
+            let hash = lock(&manager.ctx).get_text().ok().map(|text| hash_text(&text));
+            if hash.is_some() && hash != last_hash {
+                last_hash = hash;
+                manager.on_clipboard_change();
+            }
+            std::thread::sleep(idle.poll_interval(interval));
+        }
+    }).join().map_err(|_| "poller thread panicked".to_string())
+}
+
+pub(crate) fn hash_text(text: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    text.hash(&mut hasher);
+    hasher.finish()
+}
@@ -0,0 +1,106 @@
+use std::collections::{BTreeSet, HashMap};
+use crate::envelope;
+use crate::template;
+
+// What a content type turns into on the clipboard: the text every target
+// takes, and the formats the OS clipboard gets next to it.
+pub struct Decoded {
+    pub text: String,
+    pub html: Option<String>,
+}
+
+// How one content type is applied. A new format is a codec here, and
+// devices tell each other which ones they have in a capabilities signal,
+// so one is only sent to devices that know it.
+pub trait Codec: Sync {
+    fn content_type(&self) -> &'static str;
+
+    // Text is sanitized on arrival and passes --text-only.
+    fn is_text(&self) -> bool {
+        true
+    }
+
+    // `clipboard` reads what is on the clipboard now, for codecs that need it.
+    fn decode(&self, content: String, clipboard: &dyn Fn() -> Option<String>) -> Decoded;
+
+    // What a device without this codec gets instead, as plain text.
+    fn fallback(&self, content: String) -> String {
+        content
+    }
+}
+
+struct Plain;
+
+impl Codec for Plain {
+    fn content_type(&self) -> &'static str {
+        "text/plain"
+    }
+
+    fn decode(&self, content: String, _: &dyn Fn() -> Option<String>) -> Decoded {
+        Decoded { text: content, html: None }
+    }
+}
+
+// The markup goes on the clipboard as HTML and, for apps that only paste
+// text, as text too.
+struct Html;
+
+impl Codec for Html {
+    fn content_type(&self) -> &'static str {
+        "text/html"
+    }
+
+    fn decode(&self, content: String, _: &dyn Fn() -> Option<String>) -> Decoded {
+        Decoded { text: content.clone(), html: Some(content) }
+    }
+}
+
+struct Template;
+
+impl Codec for Template {
+    fn content_type(&self) -> &'static str {
+        envelope::TEMPLATE
+    }
+
+    fn decode(&self, content: String, clipboard: &dyn Fn() -> Option<String>) -> Decoded {
+        Decoded { text: template::expand(&content, clipboard), html: None }
+    }
+
+    // Filled in here then, which leaves `{clipboard}` empty.
+    fn fallback(&self, content: String) -> String {
+        template::expand(&content, || None)
+    }
+}
+
+pub const PLAIN: &dyn Codec = &Plain;
+
+const CODECS: &[&dyn Codec] = &[PLAIN, &Html, &Template];
+
+pub fn find(content_type: &str) -> Option<&'static dyn Codec> {
+    CODECS.iter().find(|codec| codec.content_type() == content_type).copied()
+}
+
+// What items can be sent as other than the content itself.
+const WRAPPERS: &[&str] = &[envelope::DELTA];
+
+// Sent on every connect, as the content types separated by spaces.
+pub fn capabilities() -> String {
+    CODECS.iter().map(|codec| codec.content_type()).chain(WRAPPERS.iter().copied()).collect::<Vec<_>>().join(" ")
+}
+
+// The content types other devices have said they take, by device ID. A
+// device that has said nothing, like one on a release from before
+// capabilities, is not held against any.
+#[derive(Default)]
+pub struct Peers(HashMap<String, BTreeSet<String>>);
+
+impl Peers {
+    pub fn announced(&mut self, device: &str, capabilities: &str) {
+        self.0.insert(device.to_string(), capabilities.split_whitespace().map(str::to_string).collect());
+    }
+
+    // Whether every device that has said what it takes takes this.
+    pub fn all_take(&self, content_type: &str) -> bool {
+        self.0.values().all(|taken| taken.contains(content_type))
+    }
+}
@@ -0,0 +1,202 @@
+use std::io::{self, Write};
+use clap::{Arg, Command, ValueEnum};
+
+// Both are made from the command line definition itself, so they cannot
+// fall behind it: packagers run them at build time, users for the shell
+// they have.
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+pub fn print(shell: Shell, command: Command) {
+    let script = match shell {
+        Shell::Bash => bash(&command),
+        // zsh runs bash completions through bashcompinit.
+        Shell::Zsh => format!("#compdef {0}\n\nautoload -U +X bashcompinit && bashcompinit\n{1}", command.get_name(), bash(&command)),
+        Shell::Fish => fish(&command),
+    };
+    let _ = io::stdout().lock().write_all(script.as_bytes());
+}
+
+// Every subcommand, with the names leading to it from the top.
+fn walk<'a>(command: &'a Command, path: &mut Vec<&'a str>, out: &mut Vec<(Vec<&'a str>, &'a Command)>) {
+    path.push(command.get_name());
+    out.push((path.clone(), command));
+    for subcommand in command.get_subcommands().filter(|subcommand| !subcommand.is_hide_set()) {
+        walk(subcommand, path, out);
+    }
+    path.pop();
+}
+
+fn commands(command: &Command) -> Vec<(Vec<&str>, &Command)> {
+    let mut out = Vec::new();
+    walk(command, &mut Vec::new(), &mut out);
+    out
+}
+
+fn options(command: &Command) -> impl Iterator<Item = &Arg> {
+    command.get_arguments().filter(|arg| !arg.is_hide_set() && arg.get_long().is_some())
+}
+
+fn values(arg: &Arg) -> Vec<String> {
+    if !arg.get_action().takes_values() {
+        return Vec::new();
+    }
+    arg.get_possible_values().iter().filter(|value| !value.is_hide_set()).map(|value| value.get_name().to_string()).collect()
+}
+
+fn bash(command: &Command) -> String {
+    let name = command.get_name();
+    let function = format!("_{}", name.replace('-', "_"));
+    let commands = commands(command);
+    let paths: String = commands.iter().map(|(path, _)| format!("|{}", path.join(" "))).collect();
+    let mut script = format!("{function}() {{\n");
+    script += "    local cur prev path word opts\n";
+    script += "    cur=\"${COMP_WORDS[COMP_CWORD]}\"\n";
+    script += "    prev=\"${COMP_WORDS[COMP_CWORD-1]}\"\n";
+    script += &format!("    path=\"{name}\"\n");
+    script += "    for word in \"${COMP_WORDS[@]:1:COMP_CWORD-1}\"; do\n";
+    script += &format!("        [[ \"{paths}|\" == *\"|$path $word|\"* ]] && path=\"$path $word\"\n");
+    script += "    done\n";
+    script += "    case \"$path $prev\" in\n";
+    for (path, command) in &commands {
+        for arg in options(command) {
+            let values = values(arg);
+            if !values.is_empty() {
+                script += &format!("        \"{} --{}\") opts=\"{}\" ;;\n", path.join(" "), arg.get_long().unwrap_or_default(), values.join(" "));
+            }
+        }
+    }
+    script += "        *)\n";
+    script += "            case \"$path\" in\n";
+    for (path, command) in &commands {
+        let words: Vec<String> = command.get_subcommands()
+            .filter(|subcommand| !subcommand.is_hide_set())
+            .map(|subcommand| subcommand.get_name().to_string())
+            .chain(options(command).map(|arg| format!("--{}", arg.get_long().unwrap_or_default())))
+            .collect();
+        script += &format!("                \"{}\") opts=\"{}\" ;;\n", path.join(" "), words.join(" "));
+    }
+    script += "            esac ;;\n";
+    script += "    esac\n";
+    script += "    COMPREPLY=($(compgen -W \"$opts\" -- \"$cur\"))\n";
+    script += "}\n";
+    script += &format!("complete -F {function} {name}\n");
+    script
+}
+
+fn fish(command: &Command) -> String {
+    let name = command.get_name();
+    let mut script = String::new();
+    for (path, command) in commands(command) {
+        let inner = &path[1..];
+        // Right after its parent, before any of its siblings.
+        let mut condition = match inner.last() {
+            Some(_) => inner.iter().map(|name| format!("__fish_seen_subcommand_from {name}")).collect::<Vec<_>>().join("; and "),
+            None => String::new(),
+        };
+        let subcommands: Vec<&Command> = command.get_subcommands().filter(|subcommand| !subcommand.is_hide_set()).collect();
+        if !subcommands.is_empty() {
+            let names: Vec<&str> = subcommands.iter().map(|subcommand| subcommand.get_name()).collect();
+            let listing = match condition.as_str() {
+                "" => "__fish_use_subcommand".to_string(),
+                condition => format!("{condition}; and not __fish_seen_subcommand_from {}", names.join(" ")),
+            };
+            for subcommand in subcommands {
+                script += &format!("complete -c {name} -f -n '{listing}' -a {} -d {}\n", subcommand.get_name(), fish_quote(&help(subcommand.get_about())));
+            }
+        }
+        if condition.is_empty() {
+            condition = "__fish_use_subcommand".to_string();
+        }
+        for arg in options(command) {
+            let mut line = format!("complete -c {name}");
+            if !(arg.is_global_set() && inner.is_empty()) {
+                line += &format!(" -n '{condition}'");
+            }
+            line += &format!(" -l {}", arg.get_long().unwrap_or_default());
+            if let Some(short) = arg.get_short() {
+                line += &format!(" -s {short}");
+            }
+            let values = values(arg);
+            if !values.is_empty() {
+                line += &format!(" -x -a '{}'", values.join(" "));
+            } else if arg.get_action().takes_values() {
+                line += " -r";
+            }
+            line += &format!(" -d {}\n", fish_quote(&help(arg.get_help())));
+            script += &line;
+        }
+    }
+    script
+}
+
+fn fish_quote(text: &str) -> String {
+    format!("'{}'", text.replace('\\', "\\\\").replace('\'', "\\'"))
+}
+
+fn help(text: Option<&clap::builder::StyledStr>) -> String {
+    text.map(|text| text.to_string()).unwrap_or_default()
+}
+
+// A page in man(7) format, with every subcommand and its options.
+pub fn man(command: Command) {
+    let name = command.get_name().to_string();
+    let mut page = format!(".TH {} 1 \"\" \"{} {}\"\n", name.to_uppercase(), name, command.get_version().unwrap_or_default());
+    page += &format!(".SH NAME\n{} \\- {}\n", name, roff(&help(command.get_about())));
+    page += &format!(".SH SYNOPSIS\n\\fB{}\\fR [\\fIOPTIONS\\fR] [\\fICOMMAND\\fR]\n", name);
+    if let Some(after) = command.get_after_help() {
+        page += &format!(".SH DESCRIPTION\n{}\n", roff(&after.to_string()));
+    }
+    page += ".SH OPTIONS\n";
+    page += &man_options(&command);
+    page += ".SH COMMANDS\n";
+    for (path, subcommand) in commands(&command).into_iter().skip(1) {
+        page += &format!(".TP\n\\fB{}\\fR\n{}\n", roff(&path.join(" ")), roff(&help(subcommand.get_about())));
+        let options = man_options(subcommand);
+        if !options.is_empty() {
+            page += &format!(".RS\n{options}.RE\n");
+        }
+    }
+    let _ = io::stdout().lock().write_all(page.as_bytes());
+}
+
+fn man_options(command: &Command) -> String {
+    let mut out = String::new();
+    for arg in options(command) {
+        let mut flag = format!("\\fB\\-\\-{}\\fR", roff(arg.get_long().unwrap_or_default()));
+        if let Some(short) = arg.get_short() {
+            flag = format!("\\fB\\-{short}\\fR, {flag}");
+        }
+        if arg.get_action().takes_values() {
+            let value = arg.get_value_names().and_then(|names| names.first()).map(|name| name.to_string()).unwrap_or_else(|| arg.get_id().to_string().to_uppercase());
+            flag += &format!(" \\fI{}\\fR", roff(&value));
+        }
+        let mut text = help(arg.get_help());
+        let values = values(arg);
+        if !values.is_empty() {
+            text += &format!(" [possible values: {}]", values.join(", "));
+        }
+        let defaults: Vec<String> = arg.get_default_values().iter().map(|value| value.to_string_lossy().into_owned()).collect();
+        if !defaults.is_empty() && !defaults.iter().all(|value| value == "false") {
+            text += &format!(" [default: {}]", defaults.join(", "));
+        }
+        out += &format!(".TP\n{}\n{}\n", flag, roff(text.trim()));
+    }
+    out
+}
+
+// Backslashes and dashes are escaped, and a line starting with a dot or
+// quote would be taken for a request.
+fn roff(text: &str) -> String {
+    text.lines()
+        .map(|line| {
+            let line = line.replace('\\', "\\e").replace('-', "\\-");
+            if line.starts_with(['.', '\'']) { format!("\\&{line}") } else { line }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
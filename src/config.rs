@@ -0,0 +1,269 @@
+use std::ffi::OsString;
+use std::fmt::Write as _;
+use std::io;
+use std::path::{Path, PathBuf};
+use clap::Command;
+
+// A small subset of TOML: `key = value` lines with string, integer, boolean
+// and single-line string array values, `#` comments and `[section]` headers.
+// Top-level keys are named after the command line flags, with underscores.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    String(String),
+    Integer(i64),
+    Bool(bool),
+    Array(Vec<String>),
+}
+
+impl Value {
+    fn to_args(&self) -> Vec<String> {
+        match self {
+            Value::String(s) => vec![s.clone()],
+            Value::Integer(i) => vec![i.to_string()],
+            Value::Bool(b) => vec![b.to_string()],
+            Value::Array(items) => items.clone(),
+        }
+    }
+
+    fn to_toml(&self) -> String {
+        match self {
+            Value::String(s) => quote(s),
+            Value::Integer(i) => i.to_string(),
+            Value::Bool(b) => b.to_string(),
+            Value::Array(items) => format!("[{}]", items.iter().map(|s| quote(s)).collect::<Vec<_>>().join(", ")),
+        }
+    }
+}
+
+pub const PRECEDENCE: &str = "Settings are taken from, in order of precedence: command line flags, \
+CLOUDBOARD_<SETTING> environment variables (e.g. CLOUDBOARD_SERVER, CLOUDBOARD_CLIENT_KEY), \
+the config file, and built-in defaults. Certificates may be given as inline PEM via --ca-cert, \
+--client-cert and --client-key.";
+const ENV_PREFIX: &str = "CLOUDBOARD_";
+
+#[derive(Default, Debug)]
+pub struct Config {
+    entries: Vec<(String, Value)>,
+}
+
+impl Config {
+    pub fn load(path: &Path) -> io::Result<Config> {
+        match std::fs::read_to_string(path) {
+            Ok(content) => Config::parse(&content)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path.display(), e))),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Config::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn parse(content: &str) -> Result<Config, String> {
+        let mut config = Config::default();
+        let mut section = String::new();
+        for (number, line) in content.lines().enumerate() {
+            let line = strip_comment(line).trim();
+            if line.is_empty() {
+                continue;
+            }
+            if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                section = format!("{}.", name.trim());
+                continue;
+            }
+            let Some((key, value)) = line.split_once('=') else {
+                return Err(format!("line {}: expected key = value", number + 1));
+            };
+            let value = parse_value(value.trim()).ok_or_else(|| format!("line {}: invalid value", number + 1))?;
+            config.set(&format!("{}{}", section, key.trim()), value);
+        }
+        Ok(config)
+    }
+
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        self.entries.iter().find(|(k, _)| k == key).map(|(_, v)| v)
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &str> {
+        self.entries.iter().map(|(key, _)| key.as_str())
+    }
+
+    // Names of the `[<prefix>.<name>]` tables, in file order.
+    pub fn tables(&self, prefix: &str) -> Vec<String> {
+        let mut names: Vec<String> = Vec::new();
+        for (key, _) in &self.entries {
+            let Some((name, _)) = key.strip_prefix(prefix).and_then(|rest| rest.strip_prefix('.')?.rsplit_once('.')) else {
+                continue;
+            };
+            if !names.iter().any(|known| known == name) {
+                names.push(name.to_string());
+            }
+        }
+        names
+    }
+
+    pub fn set(&mut self, key: &str, value: Value) {
+        match self.entries.iter_mut().find(|(k, _)| k == key) {
+            Some(entry) => entry.1 = value,
+            None => self.entries.push((key.to_string(), value)),
+        }
+    }
+
+    pub fn remove(&mut self, key: &str) {
+        self.entries.retain(|(k, _)| k != key);
+    }
+
+    // Environment variables are layered over the file before it is applied,
+    // so they override it while flags on the command line still win.
+    pub fn apply_env(&mut self) {
+        for (name, value) in std::env::vars_os() {
+            let (Some(name), Some(value)) = (name.to_str(), value.to_str()) else {
+                continue;
+            };
+            if let Some(key) = name.strip_prefix(ENV_PREFIX).filter(|key| !key.is_empty()) {
+                self.set(&key.to_ascii_lowercase(), Value::String(value.to_string()));
+            }
+        }
+    }
+
+    // `--paranoid`, on the command line or as `paranoid = true`, is a preset
+    // layered over the file and the environment. Flags given on the command
+    // line still win over it.
+    pub fn apply_paranoid(&mut self, argv: &[OsString]) {
+        let enabled = argv.iter().any(|arg| arg == "--paranoid")
+            || matches!(self.get("paranoid"), Some(Value::Bool(true)))
+            || matches!(self.get("paranoid"), Some(Value::String(s)) if s == "true");
+        if !enabled {
+            return;
+        }
+        for key in ["text_only", "filter_secrets", "require_encryption", "no_retain", "no_history", "no_source"] {
+            self.set(key, Value::Bool(true));
+        }
+        self.set("max_size", Value::Integer(10 * 1024));
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut content = String::new();
+        let mut section = "";
+        let (top, nested): (Vec<_>, Vec<_>) = self.entries.iter().partition(|(key, _)| !key.contains('.'));
+        for (key, value) in top.into_iter().chain(nested) {
+            let (table, name) = key.rsplit_once('.').unwrap_or(("", key));
+            if table != section {
+                let _ = write!(content, "\n[{}]\n", table);
+                section = table;
+            }
+            let _ = writeln!(content, "{} = {}", name, value.to_toml());
+        }
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(path, content)
+    }
+
+    // Values from the file become defaults of the matching arguments, so
+    // anything given on the command line still wins.
+    pub fn apply(&self, mut command: Command) -> Command {
+        for (key, value) in self.entries.iter().filter(|(key, _)| !key.contains('.')) {
+            if command.get_arguments().any(|arg| arg.get_id() == key.as_str()) {
+                let values = value.to_args();
+                command = command.mut_arg(key, |arg| arg.default_values(values).required(false));
+            }
+        }
+
+        let names: Vec<String> = command.get_subcommands().map(|sub| sub.get_name().to_string()).collect();
+        for name in names {
+            command = command.mut_subcommand(name, |sub| self.apply(sub));
+        }
+        command
+    }
+}
+
+pub fn default_path() -> PathBuf {
+    if let Some(dir) = std::env::var_os("XDG_CONFIG_HOME") {
+        return PathBuf::from(dir).join("cloudboard/config.toml");
+    }
+    if cfg!(windows) {
+        if let Some(dir) = std::env::var_os("APPDATA") {
+            return PathBuf::from(dir).join("cloudboard\\config.toml");
+        }
+    }
+
+    let home = std::env::var_os("HOME").map(PathBuf::from).unwrap_or_default();
+    if cfg!(target_os = "macos") {
+        home.join("Library/Application Support/cloudboard/config.toml")
+    } else {
+        home.join(".config/cloudboard/config.toml")
+    }
+}
+
+// The config file has to be known before the command line is parsed, since
+// its values become argument defaults.
+pub fn path_from_args(args: &[OsString]) -> PathBuf {
+    let mut args = args.iter().map(|arg| arg.to_string_lossy());
+    while let Some(arg) = args.next() {
+        if arg == "--config" {
+            if let Some(path) = args.next() {
+                return PathBuf::from(path.as_ref());
+            }
+        } else if let Some(path) = arg.strip_prefix("--config=") {
+            return PathBuf::from(path);
+        }
+    }
+    std::env::var_os("CLOUDBOARD_CONFIG").map(PathBuf::from).unwrap_or_else(default_path)
+}
+
+fn strip_comment(line: &str) -> &str {
+    let mut quoted = false;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            '#' if !quoted => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+fn parse_value(value: &str) -> Option<Value> {
+    if let Some(inner) = value.strip_prefix('[').and_then(|v| v.strip_suffix(']')) {
+        let mut items = Vec::new();
+        let mut rest = inner.trim();
+        while !rest.is_empty() {
+            let (item, after) = parse_string(rest)?;
+            items.push(item);
+            rest = after.trim_start();
+            rest = rest.strip_prefix(',').unwrap_or(rest).trim_start();
+        }
+        return Some(Value::Array(items));
+    }
+    if value.starts_with('"') {
+        let (s, rest) = parse_string(value)?;
+        return rest.trim().is_empty().then_some(Value::String(s));
+    }
+    match value {
+        "true" => Some(Value::Bool(true)),
+        "false" => Some(Value::Bool(false)),
+        _ => value.replace('_', "").parse().ok().map(Value::Integer),
+    }
+}
+
+fn parse_string(input: &str) -> Option<(String, &str)> {
+    let mut chars = input.strip_prefix('"')?.char_indices();
+    let mut out = String::new();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => return Some((out, &input[i + 2..])),
+            '\\' => match chars.next()?.1 {
+                'n' => out.push('\n'),
+                't' => out.push('\t'),
+                c => out.push(c),
+            },
+            c => out.push(c),
+        }
+    }
+    None
+}
+
+fn quote(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n").replace('\t', "\\t"))
+}
@@ -0,0 +1,314 @@
+use std::ffi::OsString;
+use std::io::{self, Read};
+use std::net::TcpStream;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use clap::{CommandFactory, FromArgMatches, Parser, Subcommand};
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::client::WebPkiServerVerifier;
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::server::danger::{ClientCertVerified, ClientCertVerifier};
+use rustls::{CertificateError, ClientConfig, ClientConnection, DigitallySignedStruct, DistinguishedName, RootCertStore, ServerConfig, SignatureScheme, StreamOwned};
+use crate::config::{self, Config};
+use crate::crl::Crls;
+use crate::doctor::{self, Files};
+use crate::{http, Args};
+
+// The daemon's side of --control: it shows its own device certificate and
+// takes clients with a certificate from the same CA that is issued to a
+// device of the same user, or to one of --control-from. The CA may sign
+// for other users and their groups too, who get nothing here.
+pub fn server_config(args: &Args) -> io::Result<Arc<ServerConfig>> {
+    let files = load(crate::cert_sources(args)?)?;
+    let roots = roots(&files)?;
+    let verifier = Crls::load(args).and_then(|crls| crls.client_verifier(roots)).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    let allowed = args.control_from.iter().map(|device| format!("{}-{}", args.user, device)).collect();
+    let config = ServerConfig::builder()
+        .with_client_cert_verifier(Arc::new(SameUser { verifier, user: format!("{}-", args.user), allowed }))
+        .with_single_cert(files.chain, files.key)
+        .map_err(io::Error::other)?;
+    Ok(Arc::new(config))
+}
+
+fn load(sources: [String; 3]) -> io::Result<Files> {
+    doctor::load_files(&sources).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+fn roots(files: &Files) -> io::Result<RootCertStore> {
+    files.roots().map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+#[derive(Debug)]
+struct SameUser {
+    verifier: Arc<dyn ClientCertVerifier>,
+    // `<user>-`, which any device of the user's certificate starts with.
+    user: String,
+    allowed: Vec<String>,
+}
+
+impl SameUser {
+    fn allows(&self, name: &str) -> bool {
+        if self.allowed.is_empty() {
+            name.len() > self.user.len() && name.starts_with(&self.user)
+        } else {
+            self.allowed.iter().any(|allowed| allowed == name)
+        }
+    }
+}
+
+impl ClientCertVerifier for SameUser {
+    fn root_hint_subjects(&self) -> &[DistinguishedName] {
+        self.verifier.root_hint_subjects()
+    }
+
+    fn verify_client_cert(&self, end_entity: &CertificateDer<'_>, intermediates: &[CertificateDer<'_>], now: UnixTime) -> Result<ClientCertVerified, rustls::Error> {
+        let verified = self.verifier.verify_client_cert(end_entity, intermediates, now)?;
+        if !subject_names(end_entity).iter().any(|name| self.allows(name)) {
+            return Err(rustls::Error::InvalidCertificate(CertificateError::ApplicationVerificationFailure));
+        }
+        Ok(verified)
+    }
+
+    fn verify_tls12_signature(&self, message: &[u8], cert: &CertificateDer<'_>, dss: &DigitallySignedStruct) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.verifier.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(&self, message: &[u8], cert: &CertificateDer<'_>, dss: &DigitallySignedStruct) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.verifier.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.verifier.supported_verify_schemes()
+    }
+}
+
+// Device certificates are made for the broker to check, so they name the
+// device rather than the host it runs on. So rather than the host name, the
+// certificate has to name the `<user>-<device>` the ctl was asked to reach.
+#[derive(Debug)]
+struct SameCa {
+    verifier: Arc<WebPkiServerVerifier>,
+    expected: String,
+}
+
+impl ServerCertVerifier for SameCa {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        // The host name is checked last, once the chain is known to be good.
+        match self.verifier.verify_server_cert(end_entity, intermediates, server_name, ocsp_response, now) {
+            Ok(_) | Err(rustls::Error::InvalidCertificate(CertificateError::NotValidForName)) => {}
+            Err(e) => return Err(e),
+        }
+        if !subject_names(end_entity).contains(&self.expected) {
+            return Err(rustls::Error::General(format!("the certificate is not issued to {}, give the device the daemon runs as with --remote", self.expected)));
+        }
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(&self, message: &[u8], cert: &CertificateDer<'_>, dss: &DigitallySignedStruct) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.verifier.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(&self, message: &[u8], cert: &CertificateDer<'_>, dss: &DigitallySignedStruct) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.verifier.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.verifier.supported_verify_schemes()
+    }
+}
+
+// The CNs and DNS names a certificate is issued to, which for a device's
+// certificate are its `<user>-<device>`.
+pub fn subject_names(cert: &[u8]) -> Vec<String> {
+    let mut names = Vec::new();
+    let _ = collect_names(cert, &mut names);
+    names
+}
+
+const COMMON_NAME: &[u8] = &[0x55, 0x04, 0x03];
+const SUBJECT_ALT_NAME: &[u8] = &[0x55, 0x1d, 0x11];
+
+fn collect_names(cert: &[u8], names: &mut Vec<String>) -> Option<()> {
+    let (_, cert, _) = doctor::der(cert)?;
+    let (_, mut tbs, _) = doctor::der(cert)?;
+    if tbs.first() == Some(&0xa0) {
+        tbs = doctor::der(tbs)?.2;
+    }
+    // serial, signature algorithm, issuer, validity
+    for _ in 0..4 {
+        tbs = doctor::der(tbs)?.2;
+    }
+    let (_, mut subject, rest) = doctor::der(tbs)?;
+    while !subject.is_empty() {
+        let (_, set, next) = doctor::der(subject)?;
+        subject = next;
+        let (_, oid, value) = doctor::der(doctor::der(set)?.1)?;
+        if oid == COMMON_NAME {
+            names.push(String::from_utf8_lossy(doctor::der(value)?.1).into_owned());
+        }
+    }
+    // After the public key come the optional unique IDs and extensions.
+    let mut rest = doctor::der(rest)?.2;
+    while !rest.is_empty() {
+        let (tag, field, next) = doctor::der(rest)?;
+        rest = next;
+        if tag != 0xa3 {
+            continue;
+        }
+        let mut extensions = doctor::der(field)?.1;
+        while !extensions.is_empty() {
+            let (_, extension, next) = doctor::der(extensions)?;
+            extensions = next;
+            let (_, oid, mut value) = doctor::der(extension)?;
+            if oid != SUBJECT_ALT_NAME {
+                continue;
+            }
+            // the critical flag
+            if value.first() == Some(&0x01) {
+                value = doctor::der(value)?.2;
+            }
+            let mut general = doctor::der(doctor::der(value)?.1)?.1;
+            while !general.is_empty() {
+                let (tag, name, next) = doctor::der(general)?;
+                general = next;
+                if tag == 0x82 {
+                    names.push(String::from_utf8_lossy(name).into_owned());
+                }
+            }
+        }
+    }
+    Some(())
+}
+
+/// Administer a cloudboard daemon on another machine through its --control port
+#[derive(Parser, Debug)]
+#[command(name = "cloudboardctl", version, after_help = config::PRECEDENCE)]
+struct Ctl {
+    #[command(subcommand)]
+    command: CtlCommand,
+
+    /// The daemon's --control address, e.g. mediapc:8740
+    #[arg(long, short = 'H')]
+    host: String,
+
+    /// The device the daemon runs as, which its certificate has to be issued to, if not the host name in --host
+    #[arg(long)]
+    remote: Option<String>,
+
+    #[arg(long, global = true)]
+    config: Option<PathBuf>,
+
+    /// This device, which picks its certificate in --cert-dir
+    #[arg(short, long)]
+    device: String,
+
+    #[arg(short, long)]
+    user: String,
+
+    /// Directory with ca.crt, <user>-<device>.crt and <user>-<device>.key
+    #[arg(short, long)]
+    cert_dir: Option<String>,
+
+    /// CA certificate as a path or inline PEM, instead of the one in --cert-dir
+    #[arg(long)]
+    ca_cert: Option<String>,
+
+    /// Client certificate as a path or inline PEM
+    #[arg(long)]
+    client_cert: Option<String>,
+
+    /// Client private key as a path or inline PEM
+    #[arg(long)]
+    client_key: Option<String>,
+}
+
+#[derive(Subcommand, Debug)]
+enum CtlCommand {
+    /// Show the state of the daemon
+    Status,
+    /// Stop syncing there, both ways, until `resume`
+    Pause,
+    /// Sync there again
+    Resume,
+    /// Print its clipboard
+    Get,
+    /// Publish content through it, read from stdin if not given
+    Push {
+        content: Option<String>,
+        /// Publish even if the same content was published or received recently
+        #[arg(long)]
+        force: bool,
+        /// Publish to this team clipboard instead of the personal one
+        #[arg(long, conflicts_with = "force")]
+        group: Option<String>,
+    },
+}
+
+// Set up from the same config file as `cloudboard`, so on a device that
+// syncs already only --host is needed.
+pub fn main() {
+    let argv: Vec<OsString> = std::env::args_os().collect();
+    let config_path = config::path_from_args(&argv);
+    let mut config = Config::load(&config_path).unwrap_or_else(|e| {
+        eprintln!("Failed to load config: {}", e);
+        std::process::exit(1);
+    });
+    config.apply_env();
+    let matches = config.apply(Ctl::command()).get_matches_from(argv);
+    let ctl = Ctl::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+
+    let result = match &ctl.command {
+        CtlCommand::Status => request(&ctl, "GET", "/status", "").map(|(_, body)| print!("{body}")),
+        CtlCommand::Pause => request(&ctl, "POST", "/pause", "").map(|_| println!("paused {}", ctl.host)),
+        CtlCommand::Resume => request(&ctl, "POST", "/resume", "").map(|_| println!("resumed {}", ctl.host)),
+        CtlCommand::Get => request(&ctl, "GET", "/clipboard", "").map(|(_, body)| print!("{body}")),
+        CtlCommand::Push { content, force, group } => {
+            let path = match (group, force) {
+                (Some(group), _) => format!("/clipboard?group={}", http::percent_encode(group)),
+                (None, true) => "/clipboard?force=1".to_string(),
+                (None, false) => "/clipboard".to_string(),
+            };
+            let content = match content {
+                Some(content) => Ok(content.clone()),
+                None => {
+                    let mut content = String::new();
+                    io::stdin().read_to_string(&mut content).map(|_| content)
+                }
+            };
+            content.and_then(|content| request(&ctl, "PUT", &path, &content)).map(drop)
+        }
+    };
+    if let Err(e) = result {
+        eprintln!("Failed to reach {}: {}", ctl.host, e);
+        std::process::exit(1);
+    }
+}
+
+fn request(ctl: &Ctl, method: &str, path: &str, body: &str) -> io::Result<(u16, String)> {
+    let files = load(crate::find_certs(&ctl.user, &ctl.device, ctl.cert_dir.as_deref(), [&ctl.ca_cert, &ctl.client_cert, &ctl.client_key])?)?;
+    let name = match ctl.host.strip_prefix('[') {
+        Some(rest) => rest.split(']').next().unwrap_or_default(),
+        None => ctl.host.rsplit_once(':').map_or(ctl.host.as_str(), |(name, _)| name),
+    };
+    let verifier = WebPkiServerVerifier::builder(Arc::new(roots(&files)?)).build().map_err(io::Error::other)?;
+    let expected = format!("{}-{}", ctl.user, ctl.remote.as_deref().unwrap_or(name));
+    let config = ClientConfig::builder()
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(SameCa { verifier, expected }))
+        .with_client_auth_cert(files.chain, files.key)
+        .map_err(io::Error::other)?;
+
+    let server_name = ServerName::try_from(name.to_string()).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let tls = ClientConnection::new(Arc::new(config), server_name).map_err(io::Error::other)?;
+    let socket = TcpStream::connect(ctl.host.as_str())?;
+    socket.set_read_timeout(Some(Duration::from_secs(10)))?;
+    http::exchange(&mut StreamOwned::new(tls, socket), &ctl.host, method, path, body)
+}
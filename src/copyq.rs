@@ -0,0 +1,93 @@
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use log::{error, info};
+use crate::clipboard::{hash_text, Activity, Shutdown};
+use crate::idle::Idle;
+use crate::status::Status;
+use crate::sync::Publisher;
+
+// CopyQ is driven through its command line, which talks to the running
+// CopyQ server. Received items are added to its history and selected, so
+// they land on the clipboard through CopyQ like anything copied locally.
+pub fn read() -> Result<Option<String>, String> {
+    let output = Command::new("copyq").args(["read", "text/plain", "0"])
+        .stdin(Stdio::null())
+        .stderr(Stdio::piped())
+        .output()
+        .map_err(|e| format!("failed to run copyq: {}", e))?;
+    if !output.status.success() {
+        return Err(format!("copyq read failed: {}", String::from_utf8_lossy(&output.stderr).trim()));
+    }
+    let text = String::from_utf8_lossy(&output.stdout).into_owned();
+    Ok((!text.is_empty()).then_some(text))
+}
+
+pub fn add(content: &str) -> Result<(), String> {
+    let mut child = Command::new("copyq").args(["add", "-"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .spawn()
+        .map_err(|e| format!("failed to run copyq: {}", e))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(content.as_bytes()).map_err(|e| format!("failed to write to copyq: {}", e))?;
+    }
+    let status = child.wait().map_err(|e| e.to_string())?;
+    if !status.success() {
+        return Err(format!("copyq add failed: {}", status));
+    }
+    let status = Command::new("copyq").args(["select", "0"])
+        .stdout(Stdio::null())
+        .status()
+        .map_err(|e| e.to_string())?;
+    if !status.success() {
+        return Err(format!("copyq select failed: {}", status));
+    }
+    Ok(())
+}
+
+// The newest history item stands for the clipboard, so items picked again
+// from CopyQ's history are published as well as fresh copies.
+pub fn spawn(interval: Duration, publisher: Publisher, paused: Arc<AtomicBool>, activity: Arc<Activity>, idle: Arc<Idle>, status: Arc<Status>) -> Shutdown {
+    let shutdown = Shutdown::default();
+    let polling = shutdown.clone();
+    info!("polling CopyQ every {:?}", interval);
+    std::thread::spawn(move || {
+        // What is already there is left to --startup.
+        let mut last_hash = read().ok().flatten().map(|text| hash_text(&text));
+        let mut failing = false;
+        status.set("watcher", "up");
+        while !polling.is_stopped() {
+            match read() {
+                Ok(text) => {
+                    if failing {
+                        info!("CopyQ is reachable again");
+                        status.set("watcher", "up");
+                        failing = false;
+                    }
+                    let hash = text.as_deref().map(hash_text);
+                    if let Some(text) = text.filter(|_| hash != last_hash) {
+                        last_hash = hash;
+                        activity.changed(&text);
+                        if !paused.load(Ordering::Relaxed) {
+                            if let Err(e) = publisher.publish(text) {
+                                error!("Error sending message: {}", e);
+                            }
+                        }
+                    }
+                }
+                // Usually CopyQ is not running yet, so keep trying.
+                Err(e) if !failing => {
+                    error!("Failed to read from CopyQ: {}", e);
+                    status.set("watcher", "down");
+                    failing = true;
+                }
+                Err(_) => {}
+            }
+            std::thread::sleep(idle.poll_interval(interval));
+        }
+    });
+    shutdown
+}
@@ -0,0 +1,84 @@
+use std::sync::Arc;
+use clap::ValueEnum;
+use rustls::client::WebPkiServerVerifier;
+use rustls::pki_types::CertificateRevocationListDer;
+#[cfg(feature = "http-api")]
+use rustls::server::danger::ClientCertVerifier;
+#[cfg(feature = "http-api")]
+use rustls::server::WebPkiClientVerifier;
+use rustls::RootCertStore;
+use crate::Args;
+
+// Which certificates a --crl has to vouch for. Anything a list does not
+// cover is refused unless the policy allows unknowns, so a missing list
+// fails closed.
+#[derive(ValueEnum, Clone, Copy, PartialEq, Debug)]
+pub enum Policy {
+    Chain,
+    EndEntity,
+    AllowUnknown,
+}
+
+// The lists are read wherever a connection is set up, so a daemon picks up
+// a refreshed file when it next reconnects from scratch, on restart.
+#[derive(Clone)]
+pub struct Crls {
+    lists: Vec<CertificateRevocationListDer<'static>>,
+    policy: Policy,
+}
+
+impl Crls {
+    pub fn load(args: &Args) -> Result<Crls, String> {
+        let mut lists = Vec::new();
+        for source in &args.crl {
+            let pem = crate::read_pem(source).map_err(|e| e.to_string())?;
+            let found = rustls_pemfile::crls(&mut pem.as_slice()).collect::<Result<Vec<_>, _>>().map_err(|e| format!("{}: {}", label(source), e))?;
+            if found.is_empty() {
+                return Err(format!("{}: no PEM certificate revocation lists found", label(source)));
+            }
+            lists.extend(found);
+        }
+        Ok(Crls { lists, policy: args.crl_policy })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lists.is_empty()
+    }
+
+    // For the broker's certificate.
+    pub fn server_verifier(&self, roots: RootCertStore) -> Result<Arc<WebPkiServerVerifier>, String> {
+        let mut builder = WebPkiServerVerifier::builder(Arc::new(roots));
+        if !self.is_empty() {
+            builder = builder.with_crls(self.lists.clone());
+            match self.policy {
+                Policy::Chain => {}
+                Policy::EndEntity => builder = builder.only_check_end_entity_revocation(),
+                Policy::AllowUnknown => builder = builder.allow_unknown_revocation_status(),
+            }
+        }
+        builder.build().map_err(|e| e.to_string())
+    }
+
+    // For other devices connecting to --control.
+    #[cfg(feature = "http-api")]
+    pub fn client_verifier(&self, roots: RootCertStore) -> Result<Arc<dyn ClientCertVerifier>, String> {
+        let mut builder = WebPkiClientVerifier::builder(Arc::new(roots));
+        if !self.is_empty() {
+            builder = builder.with_crls(self.lists.clone());
+            match self.policy {
+                Policy::Chain => {}
+                Policy::EndEntity => builder = builder.only_check_end_entity_revocation(),
+                Policy::AllowUnknown => builder = builder.allow_unknown_revocation_status(),
+            }
+        }
+        builder.build().map_err(|e| e.to_string())
+    }
+}
+
+fn label(source: &str) -> &str {
+    if crate::is_inline_pem(source) {
+        "inline PEM"
+    } else {
+        source
+    }
+}
@@ -0,0 +1,354 @@
+use std::fmt::Write as _;
+use std::io;
+use std::num::NonZeroU32;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use ring::aead::{LessSafeKey, Nonce, UnboundKey, CHACHA20_POLY1305, NONCE_LEN};
+#[cfg(feature = "e2e")]
+use ring::digest::{digest, SHA256};
+use ring::pbkdf2::{self, PBKDF2_HMAC_SHA256};
+use ring::rand::{SecureRandom, SystemRandom};
+use zeroize::Zeroizing;
+#[cfg(feature = "e2e")]
+use crate::argon2::{self, Params};
+use crate::locked::SecretKey;
+use crate::stats::now;
+use crate::trust::{self, Trust};
+use crate::lock::lock;
+
+const MAGIC: &[u8] = b"cloudboard-sealed\n";
+const PASSPHRASE_MAGIC: &[u8] = b"cloudboard-passphrase\n";
+const PBKDF2_ITERATIONS: u32 = 600_000;
+// RFC 9106's second recommended setting, for machines without a spare
+// gigabyte. Published parameters over the caps are refused, as whoever can
+// write the meta topic could otherwise make devices run out of memory, and
+// so are those under this, which would make the key cheap to guess.
+#[cfg(feature = "e2e")]
+const ARGON2_PARAMS: Params = Params { memory_kib: 64 * 1024, passes: 3, lanes: 4 };
+#[cfg(feature = "e2e")]
+const ARGON2_MAX_MEMORY_KIB: u32 = 1024 * 1024;
+#[cfg(feature = "e2e")]
+const ARGON2_MAX_PASSES: u32 = 16;
+
+#[derive(Clone)]
+pub struct Key {
+    pub id: String,
+    secret: SecretKey,
+    expires: Option<u64>,
+}
+
+impl Key {
+    pub fn random() -> Key {
+        let rng = SystemRandom::new();
+        let mut id = [0u8; 4];
+        rng.fill(&mut id).unwrap();
+        let secret = SecretKey::with(|secret| rng.fill(secret).unwrap());
+        Key { id: to_hex(&id), secret, expires: None }
+    }
+
+    // Keys are exchanged out of band as `<id>:<hex secret>`.
+    pub fn code(&self) -> String {
+        format!("{}:{}", self.id, to_hex(&*self.secret))
+    }
+
+    pub fn from_code(code: &str) -> Option<Key> {
+        let (id, secret) = code.trim().split_once(':')?;
+        Some(Key {
+            id: id.to_string(),
+            secret: SecretKey::from_slice(&Zeroizing::new(from_hex(secret)?))?,
+            expires: None,
+        })
+    }
+
+    // Named after its hash, so every device that derives the same key
+    // gives it the same ID.
+    #[cfg(feature = "e2e")]
+    fn named(secret: SecretKey) -> Key {
+        Key { id: to_hex(&digest(&SHA256, &*secret).as_ref()[..4]), secret, expires: None }
+    }
+
+    fn aead(&self) -> LessSafeKey {
+        LessSafeKey::new(UnboundKey::new(&CHACHA20_POLY1305, &*self.secret).unwrap())
+    }
+}
+
+// The first key in the file is the one used for sealing; the others are
+// previous keys still accepted for opening until they expire.
+pub struct Keyring {
+    path: PathBuf,
+    keys: Vec<Key>,
+}
+
+impl Keyring {
+    pub fn generate(path: &Path) -> io::Result<Keyring> {
+        if path.exists() {
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("{} already exists", path.display())));
+        }
+        Keyring::create(path, Key::random())
+    }
+
+    pub fn create(path: &Path, key: Key) -> io::Result<Keyring> {
+        let keyring = Keyring { path: path.to_path_buf(), keys: vec![key] };
+        keyring.save()?;
+        Ok(keyring)
+    }
+
+    pub fn load(path: &Path) -> io::Result<Keyring> {
+        let content = Zeroizing::new(std::fs::read_to_string(path)?);
+        let mut keys = Vec::new();
+        for line in content.lines().map(str::trim).filter(|line| !line.is_empty() && !line.starts_with('#')) {
+            let mut fields = line.split_whitespace();
+            let key = fields.next().and_then(Key::from_code);
+            let Some(mut key) = key else {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, format!("invalid key line in {}", path.display())));
+            };
+            key.expires = fields.next().and_then(|expires| expires.parse().ok());
+            keys.push(key);
+        }
+        if keys.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("no keys in {}", path.display())));
+        }
+
+        let mut keyring = Keyring { path: path.to_path_buf(), keys };
+        keyring.prune();
+        Ok(keyring)
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        let mut content = Zeroizing::new(String::from("# cloudboard keyring: <id>:<key> [expires]\n"));
+        for key in &self.keys {
+            content.push_str(&Zeroizing::new(key.code()));
+            if let Some(expires) = key.expires {
+                let _ = write!(content, " {expires}");
+            }
+            content.push('\n');
+        }
+        write_private(&self.path, content.as_bytes())
+    }
+
+    pub fn current(&self) -> &Key {
+        &self.keys[0]
+    }
+
+    pub fn contains(&self, id: &str) -> bool {
+        self.keys.iter().any(|key| key.id == id)
+    }
+
+    pub fn rotate(&mut self, mut key: Key, grace: Duration) {
+        let expires = now() + grace.as_secs();
+        for old in &mut self.keys {
+            old.expires = Some(old.expires.map_or(expires, |e| e.min(expires)));
+        }
+        key.expires = None;
+        self.keys.insert(0, key);
+        self.prune();
+    }
+
+    fn prune(&mut self) {
+        let now = now();
+        let current = self.keys.remove(0);
+        self.keys.retain(|key| key.expires.is_none_or(|expires| expires > now));
+        self.keys.insert(0, current);
+    }
+
+    pub fn seal(&self, plaintext: &[u8]) -> Vec<u8> {
+        let key = self.current();
+        let mut nonce = [0u8; NONCE_LEN];
+        SystemRandom::new().fill(&mut nonce).unwrap();
+
+        let mut out = MAGIC.to_vec();
+        out.extend_from_slice(format!("key: {}\nnonce: {}\n\n", key.id, to_hex(&nonce)).as_bytes());
+        trust::seal_onto(&key.aead(), nonce, &mut out, plaintext);
+        out
+    }
+
+    pub fn open(&self, payload: &[u8]) -> Option<Plaintext> {
+        let split = payload.windows(2).position(|w| w == b"\n\n")? + 2;
+        let (header, body) = payload.split_at(split);
+        let fields = std::str::from_utf8(header.strip_prefix(MAGIC)?).ok()?;
+
+        let (mut id, mut nonce) = (None, None);
+        for line in fields.lines() {
+            match line.split_once(": ") {
+                Some(("key", value)) => id = Some(value),
+                Some(("nonce", value)) => nonce = from_hex(value),
+                _ => {}
+            }
+        }
+        let key = self.keys.iter().find(|key| Some(key.id.as_str()) == id)?;
+        let nonce = Nonce::try_assume_unique_for_key(&nonce?).ok()?;
+
+        trust::open_body(&key.aead(), nonce, header, body)
+    }
+}
+
+// Opened payloads are wiped once dropped, so what was decrypted does not
+// outlive the envelope decoded from it.
+pub type Plaintext = Zeroizing<Vec<u8>>;
+
+pub enum E2e {
+    Shared(Mutex<Keyring>),
+    Devices(Arc<Trust>),
+}
+
+impl E2e {
+    pub fn seal(&self, plaintext: &[u8]) -> Vec<u8> {
+        match self {
+            E2e::Shared(keyring) => lock(keyring).seal(plaintext),
+            E2e::Devices(trust) => trust.seal(plaintext),
+        }
+    }
+
+    pub fn open(&self, payload: &[u8]) -> Option<Plaintext> {
+        match self {
+            E2e::Shared(keyring) => lock(keyring).open(payload),
+            E2e::Devices(trust) => trust.open(payload),
+        }
+    }
+
+    pub fn keyring(&self) -> Option<&Mutex<Keyring>> {
+        match self {
+            E2e::Shared(keyring) => Some(keyring),
+            E2e::Devices(_) => None,
+        }
+    }
+}
+
+// How a passphrase becomes the shared key, published retained to the meta
+// topic as `argon2id m=<KiB> t=<passes> p=<lanes> salt=<hex> key=<id>`.
+// The parameters go with the salt so the cost can be raised later, and the
+// key ID lets a device tell a mistyped passphrase, or a derivation someone
+// else published, from the right one. Someone who can write the topic can
+// keep devices from agreeing on a key, but not learn it.
+#[cfg(feature = "e2e")]
+pub struct Derivation {
+    params: Params,
+    salt: Vec<u8>,
+    key_id: String,
+}
+
+#[cfg(feature = "e2e")]
+impl Derivation {
+    pub fn random() -> Derivation {
+        let mut salt = vec![0u8; 16];
+        SystemRandom::new().fill(&mut salt).unwrap();
+        Derivation { params: ARGON2_PARAMS, salt, key_id: String::new() }
+    }
+
+    pub fn decode(content: &str) -> Option<Derivation> {
+        let mut fields = content.split_whitespace();
+        if fields.next()? != "argon2id" {
+            return None;
+        }
+        let (mut memory_kib, mut passes, mut lanes, mut salt, mut key_id) = (None, None, None, None, None);
+        for field in fields {
+            match field.split_once('=') {
+                Some(("m", value)) => memory_kib = value.parse().ok(),
+                Some(("t", value)) => passes = value.parse().ok(),
+                Some(("p", value)) => lanes = value.parse().ok(),
+                Some(("salt", value)) => salt = from_hex(value),
+                Some(("key", value)) if !value.is_empty() => key_id = Some(value.to_string()),
+                _ => {}
+            }
+        }
+        let params = Params { memory_kib: memory_kib?, passes: passes?, lanes: lanes? };
+        let sane = (ARGON2_PARAMS.memory_kib..=ARGON2_MAX_MEMORY_KIB).contains(&params.memory_kib)
+            && (ARGON2_PARAMS.passes..=ARGON2_MAX_PASSES).contains(&params.passes)
+            && (1..=params.memory_kib / 8).contains(&params.lanes);
+        // RFC 9106 asks for at least 8 bytes of salt.
+        let salt = salt.filter(|salt| salt.len() >= 8)?;
+        sane.then_some(Derivation { params, salt, key_id: key_id? })
+    }
+
+    pub fn encode(&self, key: &Key) -> String {
+        format!(
+            "argon2id m={} t={} p={} salt={} key={}",
+            self.params.memory_kib, self.params.passes, self.params.lanes, to_hex(&self.salt), key.id,
+        )
+    }
+
+    // The ID of the key the passphrase was first set with, which a key
+    // derived from a published derivation has to match to be used.
+    pub fn key_id(&self) -> &str {
+        &self.key_id
+    }
+
+    pub fn derive(&self, passphrase: &str) -> Key {
+        Key::named(SecretKey::with(|secret| argon2::argon2id(passphrase.as_bytes(), &self.salt, &self.params, secret)))
+    }
+}
+
+pub fn is_sealed(payload: &[u8]) -> bool {
+    payload.starts_with(MAGIC) || trust::is_sealed(payload)
+}
+
+pub fn derive_key(passphrase: &str, salt: &[u8], iterations: NonZeroU32) -> SecretKey {
+    SecretKey::with(|key| pbkdf2::derive(PBKDF2_HMAC_SHA256, iterations, salt, passphrase.as_bytes(), key))
+}
+
+// The salt and iteration count are kept in the header so the cost can be
+// raised later without breaking files sealed today.
+pub fn seal_with_passphrase(passphrase: &str, plaintext: &[u8]) -> Vec<u8> {
+    let rng = SystemRandom::new();
+    let mut salt = [0u8; 16];
+    let mut nonce = [0u8; NONCE_LEN];
+    rng.fill(&mut salt).unwrap();
+    rng.fill(&mut nonce).unwrap();
+    let iterations = NonZeroU32::new(PBKDF2_ITERATIONS).unwrap();
+    let key = derive_key(passphrase, &salt, iterations);
+
+    let mut out = PASSPHRASE_MAGIC.to_vec();
+    out.extend_from_slice(format!("salt: {}\niterations: {}\nnonce: {}\n\n", to_hex(&salt), iterations, to_hex(&nonce)).as_bytes());
+    trust::seal_onto(&trust::aead(&*key), nonce, &mut out, plaintext);
+    out
+}
+
+pub fn open_with_passphrase(passphrase: &str, payload: &[u8]) -> Option<Plaintext> {
+    let split = payload.windows(2).position(|w| w == b"\n\n")? + 2;
+    let (header, body) = payload.split_at(split);
+    let fields = std::str::from_utf8(header.strip_prefix(PASSPHRASE_MAGIC)?).ok()?;
+
+    let (mut salt, mut iterations, mut nonce) = (None, None, None);
+    for line in fields.lines() {
+        match line.split_once(": ") {
+            Some(("salt", value)) => salt = from_hex(value),
+            Some(("iterations", value)) => iterations = value.parse().ok(),
+            Some(("nonce", value)) => nonce = from_hex(value),
+            _ => {}
+        }
+    }
+    let key = derive_key(passphrase, &salt?, iterations?);
+    let nonce = Nonce::try_assume_unique_for_key(&nonce?).ok()?;
+
+    trust::open_body(&trust::aead(&*key), nonce, header, body)
+}
+
+pub fn is_passphrase_sealed(payload: &[u8]) -> bool {
+    payload.starts_with(PASSPHRASE_MAGIC)
+}
+
+pub fn write_private(path: &Path, content: &[u8]) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    io::Write::write_all(&mut options.open(path)?, content)
+}
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::with_capacity(bytes.len() * 2), |mut out, b| {
+        let _ = write!(out, "{b:02x}");
+        out
+    })
+}
+
+pub fn from_hex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len()).step_by(2).map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok()).collect()
+}
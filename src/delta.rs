@@ -0,0 +1,71 @@
+use crate::envelope;
+
+// An edit of the previous item, sent in its place when that is much
+// smaller; see --delta-threshold. Most edits of a snippet touch one stretch
+// of it, so a delta keeps the start and end the two have in common and
+// carries what goes between. Both hashes are checked, and a device that
+// does not have the base, or gets something else out of it, fetches the
+// whole item instead.
+#[derive(Debug, PartialEq)]
+pub struct Delta {
+    pub base: String,
+    pub sha256: String,
+    pub content_type: String,
+    prefix: usize,
+    suffix: usize,
+    insert: String,
+}
+
+// A delta has to come to less than this share of the content to be sent.
+const MAX_SHARE: usize = 2;
+
+impl Delta {
+    pub fn between(base: &str, content: &str, content_type: &str) -> Option<Delta> {
+        let prefix = common(base.char_indices(), content.char_indices(), |(i, a), (_, b)| (a == b).then_some(i + a.len_utf8()));
+        let rest = (&base[prefix..], &content[prefix..]);
+        let suffix = common(rest.0.char_indices().rev(), rest.1.char_indices().rev(), |(i, a), (_, b)| (a == b).then_some(rest.0.len() - i));
+        let insert = content[prefix..content.len() - suffix].to_string();
+        let delta = Delta { base: envelope::sha256(base), sha256: envelope::sha256(content), content_type: content_type.to_string(), prefix, suffix, insert };
+        (delta.encode().len() * MAX_SHARE < content.len()).then_some(delta)
+    }
+
+    pub fn apply(&self, base: &str) -> Option<String> {
+        if envelope::sha256(base) != self.base || self.prefix + self.suffix > base.len() {
+            return None;
+        }
+        let start = base.get(..self.prefix)?;
+        let end = base.get(base.len() - self.suffix..)?;
+        let content = format!("{start}{}{end}", self.insert);
+        (envelope::sha256(&content) == self.sha256).then_some(content)
+    }
+
+    pub fn encode(&self) -> String {
+        format!("base: {}\nsha256: {}\ntype: {}\nkeep: {} {}\n\n{}", self.base, self.sha256, self.content_type, self.prefix, self.suffix, self.insert)
+    }
+
+    pub fn decode(text: &str) -> Option<Delta> {
+        let (header, insert) = text.split_once("\n\n")?;
+        let (mut base, mut sha256, mut content_type, mut keep) = (None, None, None, None);
+        for line in header.lines() {
+            match line.split_once(": ") {
+                Some(("base", value)) => base = Some(value.to_string()),
+                Some(("sha256", value)) => sha256 = Some(value.to_string()),
+                Some(("type", value)) => content_type = Some(value.to_string()),
+                Some(("keep", value)) => keep = value.split_once(' ').and_then(|(prefix, suffix)| Some((prefix.parse().ok()?, suffix.parse().ok()?))),
+                _ => {}
+            }
+        }
+        let (prefix, suffix) = keep?;
+        Some(Delta { base: base?, sha256: sha256?, content_type: content_type?, prefix, suffix, insert: insert.to_string() })
+    }
+}
+
+// The length in bytes of the run both iterators agree on, by what `same`
+// makes of each pair, which is the length up to and including that pair.
+fn common<I, F>(a: I, b: I, same: F) -> usize
+where
+    I: Iterator<Item = (usize, char)>,
+    F: Fn((usize, char), (usize, char)) -> Option<usize>,
+{
+    a.zip(b).map_while(|(a, b)| same(a, b)).last().unwrap_or(0)
+}
@@ -0,0 +1,226 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+use log::error;
+use ring::rand::{SecureRandom, SystemRandom};
+use crate::crypto::to_hex;
+use crate::envelope::Envelope;
+use crate::stats;
+use crate::store::{FileStore, Store};
+use crate::when::Render;
+
+const KEY: &str = "devices";
+const LATEST_KEY: &str = "latest.seq";
+const ID_KEY: &str = "device.id";
+const NAME_KEY: &str = "device.name";
+const PRESENCE_KEY: &str = "presence";
+
+#[derive(Clone, Default)]
+struct Peer {
+    last_seen: u64,
+    // The newest of this device's items the peer has acknowledged applying.
+    acked_seq: Option<u64>,
+    // The name it last used; peers from before device IDs are kept under
+    // their name and have none.
+    name: Option<String>,
+}
+
+// The ID this device goes by whatever its --device name, created on first
+// run. The name is kept next to it so `stats` can show this device's
+// history under its current name.
+pub fn identify(store: &dyn Store, name: &str) -> io::Result<String> {
+    let id = match store.get(ID_KEY)? {
+        Some(id) => parse_id(&id),
+        None => {
+            let id = new_id();
+            store.put(ID_KEY, id.as_bytes())?;
+            id
+        }
+    };
+    store.put(NAME_KEY, name.as_bytes())?;
+    Ok(id)
+}
+
+pub fn id(store: &dyn Store) -> Option<String> {
+    store.get(ID_KEY).ok().flatten().map(|id| parse_id(&id))
+}
+
+fn parse_id(id: &[u8]) -> String {
+    String::from_utf8_lossy(id).trim().to_string()
+}
+
+// A random (version 4) UUID.
+fn new_id() -> String {
+    let mut bytes = [0u8; 16];
+    SystemRandom::new().fill(&mut bytes).unwrap();
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    let hex = to_hex(&bytes);
+    format!("{}-{}-{}-{}-{}", &hex[..8], &hex[8..12], &hex[12..16], &hex[16..20], &hex[20..])
+}
+
+// The latest name of every device by ID, this one included.
+pub fn names(store: &dyn Store) -> HashMap<String, String> {
+    let mut names: HashMap<String, String> = parse(store).into_iter()
+        .filter_map(|(id, peer)| Some((id, peer.name?)))
+        .collect();
+    if let (Some(id), Ok(Some(name))) = (id(store), store.get(NAME_KEY)) {
+        names.insert(id, String::from_utf8_lossy(&name).into_owned());
+    }
+    names
+}
+
+// When each peer device was last heard from, kept one line per device as
+// `<id> <last seen> <acked seq> <name>`, with `-` for no ack yet. Whether
+// it is online is kept apart, as `<id> online|offline <since>`, for the
+// devices that say.
+pub struct Devices {
+    store: Arc<dyn Store>,
+    peers: BTreeMap<String, Peer>,
+    presence: BTreeMap<String, (bool, u64)>,
+}
+
+impl Devices {
+    pub fn load(store: Arc<dyn Store>) -> Devices {
+        let peers = parse(&*store);
+        let presence = parse_presence(&*store);
+        Devices { store, peers, presence }
+    }
+
+    // An offline notice comes from the broker, so it is not the device
+    // being heard from.
+    pub fn presence(&mut self, envelope: &Envelope, online: bool) {
+        if online {
+            self.seen(envelope);
+        }
+        self.presence.insert(envelope.sender().to_string(), (online, stats::now()));
+        self.save_presence();
+    }
+
+    // Takes the envelope a peer was heard from, which identifies it.
+    pub fn seen(&mut self, envelope: &Envelope) {
+        self.peer(envelope);
+        self.save();
+    }
+
+    pub fn acked(&mut self, envelope: &Envelope, seq: u64) {
+        let peer = self.peer(envelope);
+        peer.acked_seq = peer.acked_seq.max(Some(seq));
+        self.save();
+    }
+
+    // The newest item every device that acks has acked, which is as far as
+    // the journal can be let go of.
+    pub fn min_acked(&self) -> Option<u64> {
+        self.peers.values().filter_map(|peer| peer.acked_seq).min()
+    }
+
+    // Anything heard from a device that dropped off means it is back.
+    fn peer(&mut self, envelope: &Envelope) -> &mut Peer {
+        if let Some((online, since)) = self.presence.get_mut(envelope.sender()).filter(|(online, _)| !*online) {
+            (*online, *since) = (true, stats::now());
+            self.save_presence();
+        }
+        let peer = self.peers.entry(envelope.sender().to_string()).or_default();
+        peer.last_seen = stats::now();
+        if envelope.device_id.is_some() {
+            peer.name.clone_from(&envelope.device);
+        }
+        peer
+    }
+
+    fn save(&self) {
+        let content: String = self.peers.iter()
+            .map(|(id, peer)| {
+                let acked = peer.acked_seq.map_or("-".to_string(), |seq| seq.to_string());
+                match &peer.name {
+                    Some(name) => format!("{} {} {} {}\n", id, peer.last_seen, acked, name),
+                    None => format!("{} {} {}\n", id, peer.last_seen, acked),
+                }
+            })
+            .collect();
+        if let Err(e) = self.store.put(KEY, content.as_bytes()) {
+            error!("Failed to save devices: {}", e);
+        }
+    }
+
+    fn save_presence(&self) {
+        let content: String = self.presence.iter()
+            .map(|(id, (online, since))| format!("{} {} {}\n", id, if *online { "online" } else { "offline" }, since))
+            .collect();
+        if let Err(e) = self.store.put(PRESENCE_KEY, content.as_bytes()) {
+            error!("Failed to save presence: {}", e);
+        }
+    }
+}
+
+// Remembers the sequence number of this device's latest item, which the
+// acks are compared against.
+pub fn sent(store: &dyn Store, seq: u64) {
+    if let Err(e) = store.put(LATEST_KEY, seq.to_string().as_bytes()) {
+        error!("Failed to save latest sequence number: {}", e);
+    }
+}
+
+fn parse(store: &dyn Store) -> BTreeMap<String, Peer> {
+    let content = match store.get(KEY) {
+        Ok(content) => content.unwrap_or_default(),
+        Err(e) => {
+            error!("Failed to load devices: {}", e);
+            Vec::new()
+        }
+    };
+    String::from_utf8_lossy(&content)
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.splitn(4, ' ');
+            let id = fields.next()?.to_string();
+            let last_seen = fields.next()?.parse().ok()?;
+            let acked_seq = fields.next()?.parse().ok();
+            let name = fields.next().map(str::to_string);
+            Some((id, Peer { last_seen, acked_seq, name }))
+        })
+        .collect()
+}
+
+fn parse_presence(store: &dyn Store) -> BTreeMap<String, (bool, u64)> {
+    let content = store.get(PRESENCE_KEY).ok().flatten().unwrap_or_default();
+    String::from_utf8_lossy(&content)
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split(' ');
+            let id = fields.next()?.to_string();
+            let online = fields.next()? == "online";
+            Some((id, (online, fields.next()?.parse().ok()?)))
+        })
+        .collect()
+}
+
+pub fn print(data_dir: &Path, render: Render) {
+    let store = FileStore::new(data_dir);
+    let peers = parse(&store);
+    if peers.is_empty() {
+        println!("no other devices seen yet");
+        return;
+    }
+    let latest = store.get(LATEST_KEY).ok().flatten()
+        .and_then(|latest| String::from_utf8_lossy(&latest).trim().parse::<u64>().ok());
+
+    let presence = parse_presence(&store);
+    println!("{:<20} {:>16} {:>12} {:>20}", "DEVICE", "LAST SEEN", "LATEST ITEM", "PRESENCE");
+    for (id, peer) in &peers {
+        let last_seen = render.relative(peer.last_seen);
+        let presence = match presence.get(id) {
+            Some((true, _)) => "online".to_string(),
+            Some((false, since)) => format!("offline {}", render.relative(*since)),
+            None => "-".to_string(),
+        };
+        let latest_item = match (latest, peer.acked_seq) {
+            (None, _) => "-",
+            (Some(latest), Some(acked)) if acked >= latest => "applied",
+            _ => "not yet",
+        };
+        println!("{:<20} {:>16} {:>12} {:>20}", peer.name.as_deref().unwrap_or(id), last_seen, latest_item, presence);
+    }
+}
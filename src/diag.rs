@@ -0,0 +1,86 @@
+use std::collections::VecDeque;
+use std::fmt::Display;
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use clap::Subcommand;
+use log::debug;
+use crate::lock::lock;
+use crate::logging::CONNECT;
+use crate::stats;
+use crate::store::{FileStore, Store};
+use crate::when::Render;
+
+const KEY: &str = "connections";
+const KEPT: usize = 500;
+
+// The latest connection events, kept in the store so intermittent broker
+// trouble can be looked into after the fact, even once the daemon is gone.
+// One line each, `<time> <event> <detail>` separated by tabs.
+pub struct Connections {
+    store: Arc<dyn Store>,
+    events: Mutex<VecDeque<String>>,
+}
+
+impl Connections {
+    pub fn load(store: Arc<dyn Store>) -> Connections {
+        let events = load(&*store);
+        Connections { store, events: Mutex::new(events) }
+    }
+
+    // The store is written on every event, which come seconds apart at
+    // most, however badly the connection is doing.
+    pub fn record(&self, event: &str, detail: impl Display) {
+        let detail = detail.to_string().replace(['\t', '\n', '\r'], " ");
+        let mut events = lock(&self.events);
+        if events.len() >= KEPT {
+            events.pop_front();
+        }
+        events.push_back(format!("{}\t{}\t{}", stats::now(), event, detail));
+        let text: String = events.iter().map(|line| format!("{line}\n")).collect();
+        if let Err(e) = self.store.put(KEY, text.as_bytes()) {
+            debug!(target: CONNECT, "Failed to keep the connection event: {}", e);
+        }
+    }
+}
+
+fn load(store: &dyn Store) -> VecDeque<String> {
+    let text = store.get(KEY).ok().flatten().map(|bytes| String::from_utf8_lossy(&bytes).into_owned()).unwrap_or_default();
+    let mut events: VecDeque<String> = text.lines().map(str::to_string).collect();
+    while events.len() > KEPT {
+        events.pop_front();
+    }
+    events
+}
+
+#[derive(Subcommand, Debug)]
+pub enum DiagCommand {
+    /// Show the latest connection events, like connects, disconnects and what the broker refused, oldest first
+    Connections,
+}
+
+pub fn command(data_dir: &Path, command: DiagCommand, render: Render) {
+    match command {
+        DiagCommand::Connections => connections(data_dir, render),
+    }
+}
+
+fn connections(data_dir: &Path, render: Render) {
+    let events = load(&FileStore::new(data_dir));
+    if events.is_empty() {
+        println!("no connection events recorded");
+        return;
+    }
+    let mut stdout = io::stdout().lock();
+    let _ = writeln!(stdout, "{:<25} {:>10}  {:<18} DETAIL", "TIME", "", "EVENT");
+    for line in events {
+        let mut fields = line.splitn(3, '\t');
+        let (Some(time), Some(event), detail) = (fields.next().and_then(|time| time.parse().ok()), fields.next(), fields.next()) else {
+            continue;
+        };
+        let line = format!("{:<25} {:>10}  {:<18} {}", render.absolute(time), render.relative(time), event, detail.unwrap_or_default());
+        if writeln!(stdout, "{}", line.trim_end()).is_err() {
+            return;
+        }
+    }
+}
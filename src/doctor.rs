@@ -0,0 +1,305 @@
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use clipboard_rs::ClipboardWatcherContext;
+use rumqttc::v5::mqttbytes::v5::SubscribeReasonCode;
+use rumqttc::v5::mqttbytes::QoS;
+use rumqttc::v5::{Client, Connection, Event, Incoming};
+use ring::signature::{self, VerificationAlgorithm};
+use rustls::crypto::ring::sign::any_supported_type;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+use rustls::{ClientConfig, ClientConnection, RootCertStore, SignatureScheme};
+use crate::clipboard::{self, Backend, Manager};
+use crate::crl::Crls;
+use crate::envelope::{self, Envelope};
+use crate::{pin, tunnel};
+use crate::Args;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+const SIGNATURE_SCHEMES: &[SignatureScheme] = &[
+    SignatureScheme::ED25519,
+    SignatureScheme::ECDSA_NISTP256_SHA256,
+    SignatureScheme::ECDSA_NISTP384_SHA384,
+    SignatureScheme::RSA_PSS_SHA256,
+    SignatureScheme::RSA_PKCS1_SHA256,
+];
+
+pub struct Files {
+    pub ca: Vec<CertificateDer<'static>>,
+    pub chain: Vec<CertificateDer<'static>>,
+    pub key: PrivateKeyDer<'static>,
+}
+
+impl Files {
+    pub fn roots(&self) -> Result<RootCertStore, String> {
+        let mut roots = RootCertStore::empty();
+        for cert in &self.ca {
+            roots.add(cert.clone()).map_err(|e| format!("invalid CA certificate: {}", e))?;
+        }
+        Ok(roots)
+    }
+}
+
+#[derive(Default)]
+struct Report {
+    failed: bool,
+}
+
+impl Report {
+    fn check<T>(&mut self, name: &str, result: Result<T, String>) -> Option<T> {
+        match result {
+            Ok(value) => {
+                println!("[PASS] {name}");
+                Some(value)
+            }
+            Err(e) => {
+                println!("[FAIL] {name}: {e}");
+                self.failed = true;
+                None
+            }
+        }
+    }
+
+    fn skip(&self, name: &str) {
+        println!("[SKIP] {name}");
+    }
+}
+
+pub fn run(args: &Args, data_dir: &Path) {
+    let mut report = Report::default();
+    let sources = crate::cert_sources(args).map_err(|e| e.to_string());
+    let files = report.check("certificate files", sources.and_then(|sources| load_files(&sources)));
+
+    let crls = if args.crl.is_empty() { Some(()) } else { report.check("certificate revocation lists", Crls::load(args).map(drop)) };
+
+    let broker = format!("{}:{}", args.server, args.port);
+    let mut handshake = "TLS handshake with the CA".to_string();
+    for (set, flag) in [(!args.pin.is_empty(), "--pin"), (!args.crl.is_empty(), "--crl")] {
+        if set {
+            handshake += &format!(" and {flag}");
+        }
+    }
+    match (&files, crls) {
+        (Some(files), Some(())) => {
+            report.check("private key matches certificate", key_matches(files));
+            if report.check(&format!("TCP connection to {broker}"), connect(args).map(drop)).is_some()
+                && report.check(&handshake, tls_handshake(args, files)).is_some()
+            {
+                check_mqtt(&mut report, args, data_dir);
+            }
+        }
+        _ => report.skip(&format!("connection to {broker}")),
+    }
+
+    report.check(&format!("clipboard backend ({:?})", args.clipboard_backend), check_clipboard(args.clipboard_backend));
+    if report.failed {
+        std::process::exit(1);
+    }
+}
+
+pub fn load_files([ca, cert, key]: &[String; 3]) -> Result<Files, String> {
+    let read = |source: &str| crate::read_pem(source).map_err(|e| e.to_string());
+    let certs = |source: &str| -> Result<Vec<CertificateDer<'static>>, String> {
+        let certs = rustls_pemfile::certs(&mut read(source)?.as_slice())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| format!("{}: {}", label(source), e))?;
+        if certs.is_empty() {
+            return Err(format!("{}: no PEM certificates found", label(source)));
+        }
+        Ok(certs)
+    };
+
+    let private_key = rustls_pemfile::private_key(&mut read(key)?.as_slice())
+        .map_err(|e| format!("{}: {}", label(key), e))?
+        .ok_or_else(|| format!("{}: no PEM private key found", label(key)))?;
+    Ok(Files { ca: certs(ca)?, chain: certs(cert)?, key: private_key })
+}
+
+fn label(source: &str) -> &str {
+    if crate::is_inline_pem(source) {
+        "inline PEM"
+    } else {
+        source
+    }
+}
+
+// Signing a message with the key and verifying it against the certificate's
+// public key works the same for RSA, ECDSA and Ed25519 keys.
+fn key_matches(files: &Files) -> Result<(), String> {
+    const MESSAGE: &[u8] = b"cloudboard doctor";
+    let key = any_supported_type(&files.key).map_err(|e| e.to_string())?;
+    let signer = key.choose_scheme(SIGNATURE_SCHEMES).ok_or("unsupported private key type")?;
+    let signature = signer.sign(MESSAGE).map_err(|e| e.to_string())?;
+
+    let algorithm: &dyn VerificationAlgorithm = match signer.scheme() {
+        SignatureScheme::ED25519 => &signature::ED25519,
+        SignatureScheme::ECDSA_NISTP256_SHA256 => &signature::ECDSA_P256_SHA256_ASN1,
+        SignatureScheme::ECDSA_NISTP384_SHA384 => &signature::ECDSA_P384_SHA384_ASN1,
+        SignatureScheme::RSA_PSS_SHA256 => &signature::RSA_PSS_2048_8192_SHA256,
+        _ => &signature::RSA_PKCS1_2048_8192_SHA256,
+    };
+    let public_key = subject_public_key(&files.chain[0]).ok_or("the certificate could not be parsed")?;
+    signature::UnparsedPublicKey::new(algorithm, public_key)
+        .verify(MESSAGE, &signature)
+        .map_err(|_| "the private key does not belong to the certificate".to_string())
+}
+
+// Walks just far enough into the DER certificate to find the public key,
+// which also handles the version 1 certificates `openssl x509 -req` creates.
+fn subject_public_key<'a>(cert: &'a CertificateDer<'_>) -> Option<&'a [u8]> {
+    let (_, spki, _) = der(subject_public_key_info(cert.as_ref())?)?;
+    let (tag, key, _) = der(der(spki)?.2)?;
+    if tag != 0x03 {
+        return None;
+    }
+    key.strip_prefix(&[0])
+}
+
+// The whole subjectPublicKeyInfo, tag and length included, which is what
+// an SPKI pin hashes.
+pub fn subject_public_key_info(cert: &[u8]) -> Option<&[u8]> {
+    let (_, cert, _) = der(cert)?;
+    let (_, mut tbs, _) = der(cert)?;
+    if tbs.first() == Some(&0xa0) {
+        tbs = der(tbs)?.2;
+    }
+    // serial, signature algorithm, issuer, validity, subject
+    for _ in 0..5 {
+        tbs = der(tbs)?.2;
+    }
+    let rest = der(tbs)?.2;
+    Some(&tbs[..tbs.len() - rest.len()])
+}
+
+// The tag, contents and what follows of the DER value `input` starts with.
+pub fn der(input: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let (&tag, rest) = input.split_first()?;
+    let (&first, rest) = rest.split_first()?;
+    let (len, rest) = if first < 0x80 {
+        (first as usize, rest)
+    } else {
+        let count = (first & 0x7f) as usize;
+        if count > 4 || rest.len() < count {
+            return None;
+        }
+        let (bytes, rest) = rest.split_at(count);
+        (bytes.iter().fold(0, |len, &b| len << 8 | b as usize), rest)
+    };
+    (rest.len() >= len).then(|| (tag, &rest[..len], &rest[len..]))
+}
+
+pub fn connect(args: &Args) -> Result<TcpStream, String> {
+    let addrs = match tunnel::hop(args) {
+        Some(hop) => vec![tunnel::open(&hop, &args.server, args.port).map_err(|e| e.to_string())?],
+        None => (args.server.as_str(), args.port).to_socket_addrs().map_err(|e| e.to_string())?.collect(),
+    };
+    let mut last_error = format!("{} did not resolve to any address", args.server);
+    for addr in addrs {
+        match TcpStream::connect_timeout(&addr, TIMEOUT) {
+            Ok(socket) => {
+                socket.set_read_timeout(Some(TIMEOUT)).map_err(|e| e.to_string())?;
+                socket.set_write_timeout(Some(TIMEOUT)).map_err(|e| e.to_string())?;
+                return Ok(socket);
+            }
+            Err(e) => last_error = format!("{addr}: {e}"),
+        }
+    }
+    Err(last_error)
+}
+
+fn tls_handshake(args: &Args, files: &Files) -> Result<(), String> {
+    let ca = Crls::load(args)?.server_verifier(files.roots()?)?;
+    let config = ClientConfig::builder()
+        .dangerous()
+        .with_custom_certificate_verifier(pin::verifier(ca, &args.pin))
+        .with_client_auth_cert(files.chain.clone(), files.key.clone_key())
+        .map_err(|e| e.to_string())?;
+    let server_name = ServerName::try_from(args.server.clone()).map_err(|e| e.to_string())?;
+
+    let mut tls = ClientConnection::new(Arc::new(config), server_name).map_err(|e| e.to_string())?;
+    let mut socket = connect(args)?;
+    while tls.is_handshaking() {
+        tls.complete_io(&mut socket).map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+// Brokers may acknowledge publishes they are not allowed to
+// forward, so publishing is only confirmed once the probe comes back.
+fn check_mqtt(report: &mut Report, args: &Args, data_dir: &Path) {
+    let topic = crate::clipboard_topic(&args.user);
+    let control_topic = crate::control_topic(&args.user);
+
+    let Some(options) = report.check("MQTT connection settings", crate::mqtt_options(args, &format!("{}-doctor", args.device)).map_err(|e| e.to_string())) else {
+        return;
+    };
+    let (client, mut connection) = Client::new(options, 10);
+    let connected = wait(&mut connection, |incoming| matches!(incoming, Incoming::ConnAck(_)).then_some(()));
+    if report.check("MQTT connection", connected).is_none() {
+        return;
+    }
+
+    for topic in [&topic, &control_topic] {
+        let result = client.subscribe(topic.clone(), QoS::AtLeastOnce).map_err(|e| e.to_string())
+            .and_then(|_| wait(&mut connection, |incoming| match incoming {
+                Incoming::SubAck(ack) => Some(ack.return_codes),
+                _ => None,
+            }))
+            .and_then(|codes| match codes.first() {
+                Some(SubscribeReasonCode::Success(_)) => Ok(()),
+                _ => Err("denied by the broker".to_string()),
+            });
+        report.check(&format!("subscribe to {topic}"), result);
+    }
+
+    let Some(probe) = report.check("encrypt and sign a probe", probe(args, data_dir)) else {
+        let _ = client.disconnect();
+        return;
+    };
+    let result = client.publish(topic.clone(), QoS::AtLeastOnce, false, probe.clone()).map_err(|e| e.to_string())
+        .and_then(|_| wait(&mut connection, |incoming| match incoming {
+            Incoming::Publish(publish) if publish.payload == probe => Some(()),
+            _ => None,
+        }))
+        .map_err(|e| format!("{e}, the broker may not allow this client to publish"));
+    report.check(&format!("publish to {topic}"), result);
+    let _ = client.disconnect();
+}
+
+// The probe is wrapped like real messages so daemons on other devices can
+// recognize and discard it.
+fn probe(args: &Args, data_dir: &Path) -> Result<Vec<u8>, String> {
+    let mut probe = Envelope::text(&args.device, format!("doctor {}", crate::stats::now()));
+    probe.content_type = envelope::PROBE.to_string();
+    crate::wrap(args, data_dir, &probe).map_err(|e| e.to_string())
+}
+
+fn wait<T>(connection: &mut Connection, mut matches: impl FnMut(Incoming) -> Option<T>) -> Result<T, String> {
+    let deadline = Instant::now() + TIMEOUT;
+    loop {
+        match connection.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+            Ok(Ok(Event::Incoming(incoming))) => {
+                if let Some(value) = matches(incoming) {
+                    return Ok(value);
+                }
+            }
+            Ok(Ok(_)) => {}
+            Ok(Err(e)) => return Err(e.to_string()),
+            Err(_) => return Err("timed out waiting for the broker".to_string()),
+        }
+    }
+}
+
+fn check_clipboard(backend: Backend) -> Result<(), String> {
+    match backend {
+        Backend::Virtual => return Ok(()),
+        Backend::Copyq => return crate::copyq::read().map(|_| ()),
+        _ => {}
+    }
+    clipboard::self_test()?;
+    if let Backend::Watch = backend {
+        ClipboardWatcherContext::<Manager>::new().map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
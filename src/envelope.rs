@@ -0,0 +1,475 @@
+use std::fmt;
+use std::str::FromStr;
+use clap::ValueEnum;
+use ring::digest;
+use zeroize::Zeroize;
+use crate::crypto;
+use crate::hlc::Timestamp;
+use crate::logging::Redacted;
+use crate::msgpack::{self, Value};
+use crate::text;
+
+const MAGIC: &str = "cloudboard\n";
+
+// Format versions so far:
+//   0  bare text, from clients that predate the envelope
+//   1  the magic line, `key: value` headers and a blank line before the
+//      content, without a version header
+//   2  adds the version header
+//
+// The framing never changes, and readers ignore headers they do not know,
+// so a new optional header needs no new version. The version goes up only
+// when older readers would misapply a message, and they drop anything newer
+// than they support instead. Readers keep accepting every older version, so
+// devices can be upgraded one at a time, and --envelope-version keeps the
+// upgraded ones writing the old version until the rest have caught up.
+pub const VERSION: u32 = 2;
+
+// Binary envelopes are MessagePack maps with one-letter keys, which save
+// the header text on every message. They carry the same version field, but
+// cloudboard releases from before them cannot read them at all, so they are
+// for when every device has been upgraded.
+#[derive(ValueEnum, Clone, Copy, PartialEq, Debug)]
+pub enum Encoding {
+    Text,
+    Binary,
+}
+
+// Published by `cloudboard doctor` to test the broker ACLs, never applied.
+pub const PROBE: &str = "application/x-cloudboard-probe";
+// Text with placeholders the receiving device fills in when it applies it;
+// see template.rs.
+pub const TEMPLATE: &str = "text/x-cloudboard-template";
+// Stands in for content over --lazy-threshold, which receivers fetch from
+// the sender on request.
+pub const OFFER: &str = "application/x-cloudboard-offer";
+// Stands in for content over --blob-threshold, which receivers that do not
+// have it read from the blob topic, by the hash in the reference. Encoded
+// like an offer.
+pub const BLOB: &str = "application/x-cloudboard-blob";
+// Stands in for text that is an edit of the previous item; see delta.rs.
+pub const DELTA: &str = "application/x-cloudboard-delta";
+// Asks for the content of an offer, by hash.
+pub const FETCH: &str = "application/x-cloudboard-fetch";
+// Tells the other devices that an item was applied, as `<device> <seq>`.
+pub const ACK: &str = "application/x-cloudboard-ack";
+// Asks for a pong, as `<sent at> <device>` with `*` for every device, the
+// time in milliseconds since the epoch on the sender's clock.
+pub const PING: &str = "application/x-cloudboard-ping";
+// Answers a ping, as `<device> <sent at>` copied from it.
+pub const PONG: &str = "application/x-cloudboard-pong";
+// `online`, sent on every connect, or `offline`, left with the broker as
+// the last will it sends when the device drops off without disconnecting.
+pub const PRESENCE: &str = "application/x-cloudboard-presence";
+// The content types the device can apply, separated by spaces, sent on
+// every connect after its presence; see codec.rs.
+// Names a device to refuse from now on, by name and ID separated by
+// spaces, signed by the device that revoked it; see revoke.rs.
+pub const REVOKE: &str = "application/x-cloudboard-revoke";
+// Settings for the devices that have the sender as --primary, in the config
+// file's format; see policy.rs.
+pub const POLICY: &str = "application/x-cloudboard-policy";
+pub const CAPABILITIES: &str = "application/x-cloudboard-capabilities";
+
+#[derive(Clone)]
+pub struct Envelope {
+    pub version: u32,
+    pub device: Option<String>,
+    // Stays the same when the device is renamed, so it is what peers track
+    // the device by.
+    pub device_id: Option<String>,
+    pub seq: Option<u64>,
+    pub hlc: Option<Timestamp>,
+    // Set on messages to a team clipboard, so one cannot be replayed into
+    // another group or the personal clipboard.
+    pub group: Option<String>,
+    // Set on items put in a numbered slot with `cloudboard slot copy`, for
+    // the same reason.
+    pub slot: Option<u32>,
+    // Set on files, which receivers with --inbox save under this name
+    // instead of putting them on the clipboard.
+    pub name: Option<String>,
+    // The application the content was copied in, where the sender's
+    // platform tells.
+    pub source: Option<String>,
+    // Labels given at send time, like `cloudboard push --tag work`, which
+    // receivers, triggers and the history can be filtered by.
+    pub tags: Vec<String>,
+    // Set when the content looks like a password or key, so receivers can
+    // keep it out of clipboard history.
+    pub sensitive: bool,
+    // Never on the wire: the trust list entry whose key checked the
+    // signature, set on the receive path for triggers to go by.
+    pub signer: Option<String>,
+    pub content_type: String,
+    pub content: String,
+}
+
+// Sensitive content is wiped wherever the last copy goes, which covers
+// the engine's own copies though not those the clipboard libraries make.
+impl Drop for Envelope {
+    fn drop(&mut self) {
+        if self.sensitive {
+            self.content.zeroize();
+        }
+    }
+}
+
+impl fmt::Debug for Envelope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Envelope")
+            .field("version", &self.version)
+            .field("device", &self.device)
+            .field("device_id", &self.device_id)
+            .field("seq", &self.seq)
+            .field("hlc", &self.hlc)
+            .field("group", &self.group)
+            .field("slot", &self.slot)
+            .field("name", &self.name)
+            .field("source", &self.source)
+            .field("tags", &self.tags)
+            .field("sensitive", &self.sensitive)
+            .field("signer", &self.signer)
+            .field("content_type", &self.content_type)
+            .field("content", &Redacted(self.content.as_bytes()))
+            .finish()
+    }
+}
+
+impl Envelope {
+    pub fn text(device: &str, content: String) -> Envelope {
+        Envelope {
+            version: VERSION,
+            device: Some(device.to_string()),
+            device_id: None,
+            seq: None,
+            hlc: None,
+            group: None,
+            slot: None,
+            name: None,
+            source: None,
+            tags: Vec::new(),
+            sensitive: false,
+            signer: None,
+            content_type: "text/plain".to_string(),
+            content,
+        }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = String::with_capacity(self.content.len() + 256);
+        out.push_str(MAGIC);
+        if self.version >= 2 {
+            out.push_str(&format!("version: {}\n", self.version));
+        }
+        if let Some(device) = &self.device {
+            out.push_str(&format!("device: {device}\n"));
+        }
+        if let Some(device_id) = &self.device_id {
+            out.push_str(&format!("device-id: {device_id}\n"));
+        }
+        if let Some(seq) = self.seq {
+            out.push_str(&format!("seq: {seq}\n"));
+        }
+        if let Some(hlc) = self.hlc {
+            out.push_str(&format!("hlc: {hlc}\n"));
+        }
+        if let Some(group) = &self.group {
+            out.push_str(&format!("group: {group}\n"));
+        }
+        if let Some(slot) = self.slot {
+            out.push_str(&format!("slot: {slot}\n"));
+        }
+        if let Some(name) = &self.name {
+            out.push_str(&format!("name: {name}\n"));
+        }
+        if let Some(source) = &self.source {
+            out.push_str(&format!("source: {source}\n"));
+        }
+        if !self.tags.is_empty() {
+            out.push_str(&format!("tags: {}\n", self.tags.join(",")));
+        }
+        if self.sensitive {
+            out.push_str("sensitive: true\n");
+        }
+        out.push_str(&format!("type: {}\n\n", self.content_type));
+        out.push_str(&self.content);
+        out.into_bytes()
+    }
+
+    pub fn encode_as(&self, encoding: Encoding) -> Vec<u8> {
+        if encoding == Encoding::Text {
+            return self.encode();
+        }
+        let mut fields = vec![("v", Value::Uint(self.version as u64))];
+        if let Some(device) = &self.device {
+            fields.push(("d", Value::Str(device.clone())));
+        }
+        if let Some(device_id) = &self.device_id {
+            fields.push(("i", Value::Str(device_id.clone())));
+        }
+        if let Some(seq) = self.seq {
+            fields.push(("s", Value::Uint(seq)));
+        }
+        if let Some(hlc) = self.hlc {
+            fields.push(("h", Value::Array(vec![Value::Uint(hlc.millis), Value::Uint(hlc.counter as u64)])));
+        }
+        if let Some(group) = &self.group {
+            fields.push(("g", Value::Str(group.clone())));
+        }
+        if let Some(slot) = self.slot {
+            fields.push(("l", Value::Uint(u64::from(slot))));
+        }
+        if let Some(name) = &self.name {
+            fields.push(("n", Value::Str(name.clone())));
+        }
+        if let Some(source) = &self.source {
+            fields.push(("a", Value::Str(source.clone())));
+        }
+        if !self.tags.is_empty() {
+            fields.push(("k", Value::Array(self.tags.iter().cloned().map(Value::Str).collect())));
+        }
+        if self.sensitive {
+            fields.push(("p", Value::Uint(1)));
+        }
+        if self.content_type != "text/plain" {
+            fields.push(("t", Value::Str(self.content_type.clone())));
+        }
+        let mut out = Vec::with_capacity(self.content.len() + 64);
+        msgpack::write_map_len(&mut out, fields.len() + 1);
+        for (key, value) in &fields {
+            msgpack::write_str(&mut out, key);
+            msgpack::write(&mut out, value);
+        }
+        // The content goes last and is not copied into a Value first.
+        msgpack::write_str(&mut out, "c");
+        msgpack::write_str(&mut out, &self.content);
+        out
+    }
+
+    // Payloads without the magic line come from clients that publish bare
+    // text, so they are accepted as plain text from an unknown device.
+    // Binary envelopes start with a byte that cannot start UTF-8 text.
+    pub fn decode(payload: &[u8]) -> Option<Envelope> {
+        if matches!(payload.first(), Some(0x80..=0x8f | 0xde)) {
+            return decode_binary(payload);
+        }
+        let payload = std::str::from_utf8(payload).ok()?;
+        let Some(rest) = payload.strip_prefix(MAGIC) else {
+            return Some(Envelope {
+                version: 0,
+                device: None,
+                device_id: None,
+                seq: None,
+                hlc: None,
+                group: None,
+                slot: None,
+                name: None,
+                source: None,
+                tags: Vec::new(),
+                sensitive: false,
+                signer: None,
+                content_type: "text/plain".to_string(),
+                content: payload.to_string(),
+            });
+        };
+
+        let (headers, content) = rest.split_once("\n\n")?;
+        let mut envelope = Envelope {
+            version: 1,
+            device: None,
+            device_id: None,
+            seq: None,
+            hlc: None,
+            group: None,
+            slot: None,
+            name: None,
+            source: None,
+            tags: Vec::new(),
+            sensitive: false,
+            signer: None,
+            content_type: "text/plain".to_string(),
+            content: content.to_string(),
+        };
+        for line in headers.lines() {
+            match line.split_once(": ") {
+                Some(("version", value)) => envelope.version = value.parse().ok()?,
+                Some(("device", value)) => envelope.device = Some(value.to_string()),
+                Some(("device-id", value)) => envelope.device_id = Some(value.to_string()),
+                Some(("seq", value)) => envelope.seq = value.parse().ok(),
+                Some(("hlc", value)) => envelope.hlc = value.parse().ok(),
+                Some(("group", value)) => envelope.group = Some(value.to_string()),
+                Some(("slot", value)) => envelope.slot = value.parse().ok(),
+                Some(("name", value)) => envelope.name = Some(value.to_string()),
+                Some(("source", value)) => envelope.source = source(value.to_string()),
+                Some(("tags", value)) => envelope.tags = value.split(',').filter(|tag| is_tag(tag)).map(str::to_string).collect(),
+                Some(("sensitive", value)) => envelope.sensitive = value == "true",
+                Some(("type", value)) => envelope.content_type = value.to_string(),
+                _ => {}
+            }
+        }
+        Some(envelope)
+    }
+
+    pub fn is_supported(&self) -> bool {
+        self.version <= VERSION
+    }
+
+    // What the sender is tracked by: its device ID, or its name when it
+    // runs a release from before device IDs.
+    pub fn sender(&self) -> &str {
+        self.device_id.as_deref().or(self.device.as_deref()).unwrap_or("unknown")
+    }
+}
+
+fn decode_binary(payload: &[u8]) -> Option<Envelope> {
+    let fields = msgpack::read_map(payload)?;
+    let mut envelope = Envelope {
+        version: 0,
+        device: None,
+        device_id: None,
+        seq: None,
+        hlc: None,
+        group: None,
+        slot: None,
+        name: None,
+        source: None,
+        tags: Vec::new(),
+        sensitive: false,
+        signer: None,
+        content_type: "text/plain".to_string(),
+        content: String::new(),
+    };
+    let (mut has_version, mut has_content) = (false, false);
+    // Unknown keys are skipped, like unknown headers.
+    for (key, value) in fields {
+        match (key.as_str(), value) {
+            ("v", Value::Uint(version)) => {
+                envelope.version = u32::try_from(version).ok()?;
+                has_version = true;
+            }
+            ("d", Value::Str(device)) => envelope.device = Some(device),
+            ("i", Value::Str(device_id)) => envelope.device_id = Some(device_id),
+            ("s", Value::Uint(seq)) => envelope.seq = Some(seq),
+            ("h", Value::Array(hlc)) => envelope.hlc = match hlc.as_slice() {
+                [Value::Uint(millis), Value::Uint(counter)] => Some(Timestamp { millis: *millis, counter: u32::try_from(*counter).ok()? }),
+                _ => None,
+            },
+            ("g", Value::Str(group)) => envelope.group = Some(group),
+            ("l", Value::Uint(slot)) => envelope.slot = u32::try_from(slot).ok(),
+            ("n", Value::Str(name)) => envelope.name = Some(name),
+            ("a", Value::Str(name)) => envelope.source = source(name),
+            ("k", Value::Array(tags)) => envelope.tags = tags.into_iter().filter_map(|tag| match tag {
+                Value::Str(tag) if is_tag(&tag) => Some(tag),
+                _ => None,
+            }).collect(),
+            ("p", Value::Uint(sensitive)) => envelope.sensitive = sensitive != 0,
+            ("t", Value::Str(content_type)) => envelope.content_type = content_type,
+            ("c", Value::Str(content)) => {
+                envelope.content = content;
+                has_content = true;
+            }
+            _ => {}
+        }
+    }
+    (has_version && has_content).then_some(envelope)
+}
+
+// Only ever shown, so names that could carry terminal escapes are dropped.
+fn source(name: String) -> Option<String> {
+    (!name.chars().any(char::is_control)).then_some(name)
+}
+
+// Tags are short words, so they fit a header and a column of the history,
+// and a filter compares them exactly.
+pub fn is_tag(tag: &str) -> bool {
+    (1..=MAX_TAG).contains(&tag.len()) && tag.chars().all(|c| c.is_alphanumeric() || matches!(c, '-' | '_' | '.' | '/'))
+}
+
+const MAX_TAG: usize = 64;
+
+// What an offer says about the content it stands in for. It is kept in the
+// daemon's status file as one line so `cloudboard fetch` can ask for it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Offer {
+    pub device: String,
+    pub sha256: String,
+    pub size: usize,
+    pub content_type: String,
+    pub preview: String,
+}
+
+impl Offer {
+    pub fn encode(&self) -> String {
+        format!("sha256: {}\nsize: {}\ntype: {}\npreview: {}\n", self.sha256, self.size, self.content_type, self.preview)
+    }
+
+    pub fn decode(device: &str, content: &str) -> Option<Offer> {
+        let mut offer = Offer {
+            device: device.to_string(),
+            sha256: String::new(),
+            size: 0,
+            content_type: "text/plain".to_string(),
+            preview: String::new(),
+        };
+        for line in content.lines() {
+            match line.split_once(": ") {
+                Some(("sha256", value)) => offer.sha256 = value.to_string(),
+                Some(("size", value)) => offer.size = value.parse().ok()?,
+                Some(("type", value)) => offer.content_type = value.to_string(),
+                Some(("preview", value)) => offer.preview = preview(value),
+                _ => {}
+            }
+        }
+        (!offer.sha256.is_empty()).then_some(offer)
+    }
+}
+
+impl fmt::Display for Offer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {} {} {} {}", self.device, self.sha256, self.size, self.content_type, self.preview)
+    }
+}
+
+impl FromStr for Offer {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Offer, ()> {
+        let mut fields = s.splitn(5, ' ');
+        let mut field = || fields.next().ok_or(());
+        Ok(Offer {
+            device: field()?.to_string(),
+            sha256: field()?.to_string(),
+            size: field()?.parse().map_err(|_| ())?,
+            content_type: field()?.to_string(),
+            preview: fields.next().unwrap_or_default().to_string(),
+        })
+    }
+}
+
+const PREVIEW_GRAPHEMES: usize = 200;
+// Only this many characters are read, so large content costs no more than
+// its start; that leaves room for clusters of many characters.
+const PREVIEW_SCAN: usize = PREVIEW_GRAPHEMES * 16;
+
+// The start of the content on one line and without control characters, so
+// it fits in a header and is safe to print to a terminal.
+pub fn preview(content: &str) -> String {
+    let collapsed: String = content.split_whitespace()
+        .flat_map(|word| std::iter::once(' ').chain(word.chars()))
+        .skip(1)
+        .filter(|c| !c.is_control())
+        .take(PREVIEW_SCAN + 1)
+        .collect();
+    let mut graphemes: Vec<&str> = text::graphemes(&collapsed).collect();
+    // The scan may have stopped in the middle of the last one.
+    if collapsed.chars().count() > PREVIEW_SCAN {
+        graphemes.pop();
+    }
+    graphemes.truncate(PREVIEW_GRAPHEMES);
+    graphemes.concat()
+}
+
+pub fn sha256(content: &str) -> String {
+    crypto::to_hex(digest::digest(&digest::SHA256, content.as_bytes()).as_ref())
+}
@@ -0,0 +1,109 @@
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use log::error;
+use crate::stats::format_bytes;
+use crate::store::Store;
+
+// A copy over this many bytes is huge if it is also FACTOR times the usual
+// size, and an application that makes REPEATS of them within WINDOW seconds
+// is most likely misbehaving, like one that copies a whole document or
+// image on every change.
+const HUGE: usize = 1024 * 1024;
+const FACTOR: usize = 10;
+const REPEATS: usize = 3;
+const WINDOW: u64 = 10 * 60;
+// An application is warned about at most once in this many seconds.
+const QUIET: u64 = 60 * 60;
+// How many recent sizes the usual one is the median of.
+const SIZES_KEPT: usize = 100;
+// Warnings are kept for `cloudboard stats`, this many of them.
+const KEY: &str = "growth";
+const WARNINGS_KEPT: usize = 20;
+
+// The sizes of what this device copied, by the application it was copied in.
+#[derive(Default)]
+pub struct Growth {
+    sizes: VecDeque<usize>,
+    huge: HashMap<String, VecDeque<u64>>,
+    warned: HashMap<String, u64>,
+}
+
+impl Growth {
+    // Takes a copy made at `time`, in seconds since the epoch, and tells
+    // when its application has been copying huge items again and again.
+    pub fn copied(&mut self, source: Option<&str>, bytes: usize, time: u64) -> Option<Unusual> {
+        let usual = self.usual();
+        self.sizes.push_back(bytes);
+        if self.sizes.len() > SIZES_KEPT {
+            self.sizes.pop_front();
+        }
+        let source = source?;
+        if bytes <= HUGE || bytes < usual.saturating_mul(FACTOR) {
+            return None;
+        }
+        let times = self.huge.entry(source.to_string()).or_default();
+        times.push_back(time);
+        times.retain(|copied| time.saturating_sub(*copied) < WINDOW);
+        let count = times.len();
+        if count < REPEATS || self.warned.get(source).is_some_and(|warned| time.saturating_sub(*warned) < QUIET) {
+            return None;
+        }
+        self.warned.insert(source.to_string(), time);
+        Some(Unusual { source: source.to_string(), count, usual })
+    }
+
+    fn usual(&self) -> usize {
+        let mut sizes: Vec<usize> = self.sizes.iter().copied().collect();
+        sizes.sort_unstable();
+        sizes.get(sizes.len() / 2).copied().unwrap_or(0)
+    }
+}
+
+pub struct Unusual {
+    pub source: String,
+    count: usize,
+    usual: usize,
+}
+
+impl Unusual {
+    // A rule for the config file that keeps such copies from syncing.
+    pub fn rule(&self) -> String {
+        let name: String = self.source.chars().map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_lowercase() } else { '-' }).collect();
+        format!("[rule.large-{}]\nsource = \"{}\"\nlarger_than = {}\ndrop = true", name.trim_matches('-'), self.source.replace(['"', '\\'], ""), HUGE)
+    }
+}
+
+// One line per warning, newest last: when, the application, how many copies
+// and the usual size.
+pub fn keep(store: &dyn Store, time: u64, unusual: &Unusual) {
+    let mut lines: Vec<String> = text(store).lines().map(str::to_string).collect();
+    lines.push(format!("{}\t{}\t{}\t{}", time, unusual.source.replace(['\t', '\n'], " "), unusual.count, unusual.usual));
+    let start = lines.len().saturating_sub(WARNINGS_KEPT);
+    let text: String = lines[start..].iter().map(|line| format!("{line}\n")).collect();
+    if let Err(e) = store.put(KEY, text.as_bytes()) {
+        error!("Failed to keep the warning about large copies: {}", e);
+    }
+}
+
+#[cfg(feature = "history")]
+pub fn load(store: &dyn Store) -> Vec<(u64, Unusual)> {
+    text(store)
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split('\t');
+            let time = fields.next()?.parse().ok()?;
+            let source = fields.next()?.to_string();
+            Some((time, Unusual { source, count: fields.next()?.parse().ok()?, usual: fields.next()?.parse().ok()? }))
+        })
+        .collect()
+}
+
+fn text(store: &dyn Store) -> String {
+    store.get(KEY).ok().flatten().map(|bytes| String::from_utf8_lossy(&bytes).into_owned()).unwrap_or_default()
+}
+
+impl fmt::Display for Unusual {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} copied {} items over {} within {} minutes, where {} is usual", self.source, self.count, format_bytes(HUGE), WINDOW / 60, format_bytes(self.usual))
+    }
+}
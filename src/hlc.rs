@@ -0,0 +1,97 @@
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use log::warn;
+use crate::lock::lock;
+use crate::logging::RECEIVE;
+
+// A remote clock further ahead than this is not followed, so one device with
+// a wrong clock cannot drag every other device's timestamps into the future.
+const MAX_DRIFT: Duration = Duration::from_secs(60);
+
+// Hybrid logical clock timestamps: wall-clock milliseconds, with a counter
+// that keeps events ordered when clocks stall, step back or disagree.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug, Default)]
+pub struct Timestamp {
+    pub millis: u64,
+    pub counter: u32,
+}
+
+impl fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.millis, self.counter)
+    }
+}
+
+impl FromStr for Timestamp {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Timestamp, ()> {
+        let (millis, counter) = s.split_once('-').ok_or(())?;
+        Ok(Timestamp {
+            millis: millis.parse().map_err(|_| ())?,
+            counter: counter.parse().map_err(|_| ())?,
+        })
+    }
+}
+
+// The clock's latest timestamp belongs to the newest clipboard event this
+// device knows of, whether it published it or accepted it from a peer.
+#[derive(Default)]
+pub struct Clock {
+    last: Mutex<Timestamp>,
+}
+
+impl Clock {
+    pub fn new() -> Clock {
+        Clock::default()
+    }
+
+    // Stamps a local event.
+    pub fn now(&self) -> Timestamp {
+        self.now_at(physical())
+    }
+
+    // The same at `physical` milliseconds, for a simulation that keeps its
+    // own time.
+    pub fn now_at(&self, physical: u64) -> Timestamp {
+        let mut last = lock(&self.last);
+        *last = advance(*last, physical);
+        *last
+    }
+
+    // Merges a timestamp from another device and returns whether it is newer
+    // than everything seen so far. Stale timestamps leave the clock alone.
+    pub fn observe(&self, remote: Timestamp) -> bool {
+        self.observe_at(remote, physical())
+    }
+
+    pub fn observe_at(&self, remote: Timestamp, physical: u64) -> bool {
+        let mut last = lock(&self.last);
+        if remote <= *last {
+            return false;
+        }
+        if remote.millis > physical + MAX_DRIFT.as_millis() as u64 {
+            warn!(target: RECEIVE, "clock of a peer is {}ms ahead, not following it", remote.millis - physical);
+            *last = advance(*last, physical);
+            return true;
+        }
+        // Not advanced to the local time: another item its sender stamped
+        // before this one arrived must still count as newer.
+        *last = remote;
+        true
+    }
+}
+
+fn advance(last: Timestamp, physical: u64) -> Timestamp {
+    if physical > last.millis {
+        Timestamp { millis: physical, counter: 0 }
+    } else {
+        Timestamp { millis: last.millis, counter: last.counter + 1 }
+    }
+}
+
+fn physical() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64
+}
@@ -0,0 +1,457 @@
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{IpAddr, SocketAddr, TcpListener, TcpStream};
+use std::path::Path;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use log::{error, info, warn};
+use rustls::{ServerConfig, ServerConnection, StreamOwned};
+use crate::clipboard::Target;
+use crate::envelope;
+use crate::pastejack::Hold;
+use crate::snapshot;
+use crate::standby::Standby;
+use crate::store::Store;
+use crate::sync::{ClipboardSync, SyncEvent};
+use crate::status::{self, Status};
+
+const MAX_BODY: usize = 16 * 1024 * 1024;
+// For the request line and the headers together.
+const MAX_HEADER: usize = 16 * 1024;
+const PING_TIMEOUT: Duration = Duration::from_secs(3);
+
+struct Request {
+    method: String,
+    path: String,
+    host: Option<String>,
+    // Whether a browser sent it on behalf of a web page.
+    from_page: bool,
+    body: Vec<u8>,
+}
+
+struct Response {
+    status: u16,
+    body: String,
+}
+
+impl Response {
+    fn status(status: u16) -> Response {
+        Response { status, body: String::new() }
+    }
+}
+
+// What the handlers reach, shared by every connection.
+#[derive(Clone)]
+pub struct Api {
+    pub target: Target,
+    pub sync: Arc<ClipboardSync>,
+    pub hold: Arc<Hold>,
+    pub status: Arc<Status>,
+    pub store: Arc<dyn Store>,
+    pub standby: Arc<Standby>,
+}
+
+pub fn serve(addr: SocketAddr, api: Api) -> io::Result<()> {
+    let listener = TcpListener::bind(addr)?;
+    if !addr.ip().is_loopback() {
+        warn!("HTTP API on {} is reachable from other machines and has no authentication", addr);
+    }
+    info!("HTTP API listening on {}", listener.local_addr()?);
+
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(mut stream) => {
+                    let _ = stream.set_read_timeout(Some(Duration::from_secs(10)));
+                    let api = api.clone();
+                    std::thread::spawn(move || handle(&mut stream, &api, true));
+                }
+                Err(e) => error!("Failed to accept HTTP connection: {}", e),
+            }
+        }
+    });
+    Ok(())
+}
+
+// The same API for `cloudboardctl` on other machines, to clients with a
+// certificate from the CA. DNS rebinding cannot get a browser past that,
+// so any host name is fine.
+pub fn serve_tls(addr: SocketAddr, api: Api, config: Arc<ServerConfig>) -> io::Result<()> {
+    let listener = TcpListener::bind(addr)?;
+    info!("control API listening on {}", listener.local_addr()?);
+
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream.and_then(|stream| Ok((ServerConnection::new(config.clone()).map_err(io::Error::other)?, stream))) {
+                Ok((tls, stream)) => {
+                    let api = api.clone();
+                    std::thread::spawn(move || {
+                        let _ = stream.set_read_timeout(Some(Duration::from_secs(10)));
+                        let peer = stream.peer_addr();
+                        let mut stream = StreamOwned::new(tls, stream);
+                        if let Err(e) = stream.conn.complete_io(&mut stream.sock) {
+                            warn!("Refused control connection from {}: {}", peer.map_or("unknown".to_string(), |peer| peer.to_string()), e);
+                            return;
+                        }
+                        handle(&mut stream, &api, false);
+                        stream.conn.send_close_notify();
+                        let _ = stream.flush();
+                    });
+                }
+                Err(e) => error!("Failed to accept control connection: {}", e),
+            }
+        }
+    });
+    Ok(())
+}
+
+fn handle(stream: &mut (impl Read + Write), api: &Api, local: bool) {
+    let response = match read_request(&mut BufReader::new(&mut *stream)) {
+        Ok(request) if local && !is_allowed(&request) => Response::status(403),
+        Ok(request) if request.method == "GET" && request.path == "/events" => {
+            return stream_events(stream, &api.sync);
+        }
+        Ok(request) => respond(request, api),
+        Err(status) => Response::status(status),
+    };
+
+    let reason = match response.status {
+        200 => "OK",
+        204 => "No Content",
+        400 => "Bad Request",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        409 => "Conflict",
+        413 => "Payload Too Large",
+        431 => "Request Header Fields Too Large",
+        501 => "Not Implemented",
+        _ => "Internal Server Error",
+    };
+    let head = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        response.status, reason, response.body.len()
+    );
+    let _ = stream.write_all(head.as_bytes()).and_then(|_| stream.write_all(response.body.as_bytes())).and_then(|_| stream.flush());
+}
+
+// Reads one line into `line`, out of what is `left` of MAX_HEADER.
+fn read_line(reader: &mut impl BufRead, line: &mut String, left: &mut usize) -> Result<(), u16> {
+    line.clear();
+    let read = reader.by_ref().take(*left as u64).read_line(line).map_err(|_| 400u16)?;
+    if !line.ends_with('\n') {
+        return Err(if read == *left { 431 } else { 400 });
+    }
+    *left -= read;
+    Ok(())
+}
+
+fn read_request(reader: &mut impl BufRead) -> Result<Request, u16> {
+    let mut left = MAX_HEADER;
+    let mut line = String::new();
+    read_line(reader, &mut line, &mut left)?;
+    let mut parts = line.split_whitespace();
+    let (Some(method), Some(path)) = (parts.next(), parts.next()) else {
+        return Err(400);
+    };
+    let mut request = Request { method: method.to_string(), path: path.to_string(), host: None, from_page: false, body: Vec::new() };
+
+    let mut length = 0;
+    loop {
+        read_line(reader, &mut line, &mut left)?;
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        let Some((name, value)) = header.split_once(':') else {
+            return Err(400);
+        };
+        match name.trim().to_ascii_lowercase().as_str() {
+            "host" => request.host = Some(value.trim().to_string()),
+            "origin" => request.from_page = true,
+            "sec-fetch-site" if value.trim() != "none" => request.from_page = true,
+            "content-length" => length = value.trim().parse().map_err(|_| 400u16)?,
+            _ => {}
+        }
+    }
+
+    if length > MAX_BODY {
+        return Err(413);
+    }
+    request.body.resize(length, 0);
+    reader.read_exact(&mut request.body).map_err(|_| 400u16)?;
+    Ok(request)
+}
+
+// Websites can reach localhost through DNS rebinding, but only under a host
+// name of their own, so anything other than an address or localhost is
+// turned away.
+fn is_local_host(host: &str) -> bool {
+    let name = match host.strip_prefix('[') {
+        Some(rest) => rest.split(']').next().unwrap_or_default(),
+        None => host.rsplit_once(':').map_or(host, |(name, _)| name),
+    };
+    name.eq_ignore_ascii_case("localhost") || name.parse::<IpAddr>().is_ok()
+}
+
+// Any web page can also have the browser send a form or a simple request
+// to localhost under its real name, and although it cannot read the answer,
+// a POST to /held, /pause or /promote has done its work by then. Browsers
+// name the page's origin on those, and none of the clients of the API are
+// browsers, so nothing that says where in the web it came from is let in.
+fn is_allowed(request: &Request) -> bool {
+    request.host.as_deref().is_some_and(is_local_host) && !request.from_page
+}
+
+// One JSON line per event until the client goes away or the engine stops.
+fn stream_events(stream: &mut impl Write, sync: &ClipboardSync) {
+    let head = "HTTP/1.1 200 OK\r\nContent-Type: application/x-ndjson\r\nConnection: close\r\n\r\n";
+    if stream.write_all(head.as_bytes()).is_err() {
+        return;
+    }
+    for event in sync.events() {
+        if writeln!(stream, "{}", event.to_json()).and_then(|_| stream.flush()).is_err() {
+            break;
+        }
+    }
+}
+
+fn respond(request: Request, api: &Api) -> Response {
+    let Api { target, sync, hold, status, store, standby } = api;
+    let (path, query) = request.path.split_once('?').unwrap_or((&request.path, ""));
+    match (request.method.as_str(), path) {
+        ("GET", "/clipboard") => match target.get() {
+            Some(content) => Response { status: 200, body: content },
+            None => Response::status(204),
+        },
+        ("PUT", "/clipboard") => {
+            let Ok(content) = String::from_utf8(request.body) else {
+                return Response { status: 400, body: "content must be UTF-8 text\n".to_string() };
+            };
+            let mut params = query.split('&');
+            let group = params.clone().find_map(|param| param.strip_prefix("group="));
+            let name = params.clone().find_map(|param| param.strip_prefix("name="));
+            let Some(tags) = params.clone().filter_map(|param| param.strip_prefix("tag=")).map(|tag| percent_decode(tag).filter(|tag| envelope::is_tag(tag))).collect::<Option<Vec<_>>>() else {
+                return Response { status: 400, body: "invalid tag\n".to_string() };
+            };
+            let result = match (group.map(percent_decode), name.map(percent_decode)) {
+                (Some(None), _) => return Response { status: 400, body: "invalid group name\n".to_string() },
+                (_, Some(None)) => return Response { status: 400, body: "invalid file name\n".to_string() },
+                #[cfg(feature = "files")]
+                (_, Some(Some(name))) => target.file(&name, content),
+                #[cfg(not(feature = "files"))]
+                (_, Some(Some(_))) => return Response { status: 501, body: "this build cannot send files\n".to_string() },
+                (Some(Some(group)), None) => target.share(&group, content),
+                (None, None) if params.clone().any(|param| param == "template=1") => sync.publisher().template(content).map_err(|e| e.to_string()),
+                (None, None) => target.copy(content, params.any(|param| param == "force=1"), tags),
+            };
+            match result {
+                Ok(()) => Response::status(204),
+                Err(e) => {
+                    error!("Failed to set clipboard content: {}", e);
+                    Response { status: 500, body: format!("{e}\n") }
+                }
+            }
+        }
+        (_, "/clipboard") => Response::status(405),
+        ("GET", "/ping") => match query.strip_prefix("device=").map(percent_decode) {
+            Some(None) => Response::status(400),
+            Some(Some(device)) => ping(sync, Some(&device)),
+            None => ping(sync, None),
+        },
+        ("GET", "/status") => Response { status: 200, body: status.text() },
+        // Ends the standby, with the device the newest kept item came from.
+        ("POST", "/promote") if !standby.is_on() => Response { status: 409, body: "this device is not in standby\n".to_string() },
+        ("POST", "/promote") => {
+            status.set("standby", "off");
+            match standby.promote() {
+                Some(device) => Response { status: 200, body: format!("{device}\n") },
+                None => Response::status(204),
+            }
+        }
+        // Until something else pauses or resumes, like --remote-desktop
+        // pause or a rule.
+        ("POST", "/pause" | "/resume") => {
+            sync.paused().store(path == "/pause", Ordering::Relaxed);
+            Response::status(204)
+        }
+        // The sender and why on the first line, then the content.
+        ("GET", "/held") => match hold.peek() {
+            Some((envelope, reason)) => Response {
+                status: 200,
+                body: format!("{}\t{}\n{}", envelope.device.as_deref().unwrap_or("unknown"), reason, envelope.content),
+            },
+            None => Response::status(204),
+        },
+        ("POST", "/held") if hold.release() => Response::status(204),
+        ("DELETE", "/held") if hold.discard() => Response::status(204),
+        ("POST" | "DELETE", "/held") => Response { status: 404, body: "nothing is held\n".to_string() },
+        (_, "/held") => Response::status(405),
+        ("GET", "/slots") => Response { status: 200, body: slots(sync) },
+        (method, path) if path.starts_with("/slot/") => {
+            let slots = sync.slots();
+            let slot = path["/slot/".len()..].parse().ok().filter(|slot| (1..=slots.count()).contains(slot));
+            let Some(slot) = slot else {
+                return Response { status: 404, body: format!("no such slot, --slots is {}\n", slots.count()) };
+            };
+            match method {
+                "GET" => match slots.get(slot) {
+                    Some(envelope) => Response { status: 200, body: envelope.content.clone() },
+                    None => Response::status(204),
+                },
+                "PUT" => match String::from_utf8(request.body).map(|content| sync.publisher().slot(slot, content, query == "template=1")) {
+                    Ok(Ok(())) => Response::status(204),
+                    Ok(Err(_)) => Response::status(500),
+                    Err(_) => Response { status: 400, body: "content must be UTF-8 text\n".to_string() },
+                },
+                "POST" if sync.paste_slot(slot) => Response::status(204),
+                "POST" => Response { status: 404, body: format!("slot {slot} is empty\n") },
+                _ => Response::status(405),
+            }
+        }
+        (method, path) if path.starts_with("/snapshot/") => {
+            let Some(name) = percent_decode(&path["/snapshot/".len()..]) else {
+                return Response::status(400);
+            };
+            match method {
+                "PUT" => match target.formats().map(|snapshot| snapshot::save(&**store, &name, &snapshot)) {
+                    Some(Ok(())) => Response::status(204),
+                    Some(Err(e)) => Response { status: 400, body: format!("{e}\n") },
+                    None => Response { status: 404, body: "the clipboard is empty\n".to_string() },
+                },
+                "POST" => match snapshot::load(&**store, &name) {
+                    Ok(Some(snapshot)) => match target.restore(snapshot, format!("snapshot {name}"), query == "sync=1") {
+                        Ok(()) => Response::status(204),
+                        Err(e) => {
+                            error!("Failed to restore snapshot {}: {}", name, e);
+                            Response { status: 500, body: format!("{e}\n") }
+                        }
+                    },
+                    Ok(None) => Response { status: 404, body: format!("no snapshot named {name}\n") },
+                    Err(e) => Response { status: 400, body: format!("{e}\n") },
+                },
+                _ => Response::status(405),
+            }
+        }
+        _ => Response::status(404),
+    }
+}
+
+// Answers with a line of `<device> <milliseconds>` per pong, `-` standing
+// for the broker, once the device asked for has answered or PING_TIMEOUT
+// has passed.
+fn ping(sync: &ClipboardSync, device: Option<&str>) -> Response {
+    let mut events = sync.events();
+    if sync.publisher().ping(device).is_err() {
+        return Response::status(500);
+    }
+    let deadline = Instant::now() + PING_TIMEOUT;
+    let mut body = String::new();
+    while let Some(event) = events.next_timeout(deadline.saturating_duration_since(Instant::now())) {
+        if let SyncEvent::Pong { device: from, rtt } = event {
+            body += &format!("{} {}\n", from.as_deref().unwrap_or("-"), rtt.as_millis());
+            if from.is_some() && from.as_deref() == device {
+                break;
+            }
+        }
+    }
+    Response { status: 200, body }
+}
+
+// A line per slot with who put what in it, sensitive content left out.
+fn slots(sync: &ClipboardSync) -> String {
+    let slots = sync.slots();
+    (1..=slots.count())
+        .map(|slot| match slots.get(slot) {
+            Some(envelope) if envelope.sensitive => format!("{slot}\t{}\t(sensitive)\n", envelope.device.as_deref().unwrap_or("unknown")),
+            Some(envelope) => format!("{slot}\t{}\t{}\n", envelope.device.as_deref().unwrap_or("unknown"), envelope::preview(&envelope.content).escape_debug()),
+            None => format!("{slot}\t-\n"),
+        })
+        .collect()
+}
+
+pub fn percent_decode(text: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(text.len());
+    let mut rest = text.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        match byte {
+            b'%' => {
+                let hex = std::str::from_utf8(tail.get(..2)?).ok()?;
+                bytes.push(u8::from_str_radix(hex, 16).ok()?);
+                rest = &tail[2..];
+            }
+            b'+' => {
+                bytes.push(b' ');
+                rest = tail;
+            }
+            byte => {
+                bytes.push(byte);
+                rest = tail;
+            }
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+pub fn percent_encode(text: &str) -> String {
+    text.bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => (byte as char).to_string(),
+            byte => format!("%{byte:02X}"),
+        })
+        .collect()
+}
+
+// A client for the API of the daemon running for `data_dir`, which
+// publishes its address in the status file.
+pub fn request(data_dir: &Path, method: &str, path: &str, body: &str) -> io::Result<(u16, String)> {
+    let addr = status::get(data_dir, "http")
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotConnected, "cloudboard is not running with --http"))?;
+    let mut stream = TcpStream::connect(addr.as_str())?;
+    stream.set_read_timeout(Some(Duration::from_secs(10)))?;
+    exchange(&mut stream, &addr, method, path, body)
+}
+
+pub fn exchange(stream: &mut (impl Read + Write), host: &str, method: &str, path: &str, body: &str) -> io::Result<(u16, String)> {
+    write!(
+        stream,
+        "{method} {path} HTTP/1.1\r\nHost: {host}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )?;
+    stream.flush()?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response)?;
+
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "invalid response from the HTTP API");
+    let (head, body) = response.split_once("\r\n\r\n").ok_or_else(invalid)?;
+    let status: u16 = head.split(' ').nth(1).and_then(|code| code.parse().ok()).ok_or_else(invalid)?;
+    if status >= 400 {
+        return Err(io::Error::other(format!("HTTP API returned {}: {}", status, body.trim())));
+    }
+    Ok((status, body.to_string()))
+}
+
+// Like `request`, but hands over the body line by line as it arrives.
+pub fn stream(data_dir: &Path, path: &str, mut line: impl FnMut(&str)) -> io::Result<()> {
+    let addr = status::get(data_dir, "http")
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotConnected, "cloudboard is not running with --http"))?;
+    let mut stream = TcpStream::connect(addr.as_str())?;
+    write!(stream, "GET {path} HTTP/1.1\r\nHost: {addr}\r\nConnection: close\r\n\r\n")?;
+
+    let mut reader = BufReader::new(stream);
+    let mut status_line = String::new();
+    reader.read_line(&mut status_line)?;
+    if status_line.split(' ').nth(1) != Some("200") {
+        return Err(io::Error::other(format!("HTTP API returned {}", status_line.trim())));
+    }
+    let mut in_body = false;
+    for text in reader.lines() {
+        let text = text?;
+        if in_body {
+            line(&text);
+        } else if text.is_empty() {
+            in_body = true;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,41 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use crate::lock::lock;
+
+// How often the broker is pinged, and how often once nothing has been copied
+// here or received for --idle-after. Even idle, a dead connection is noticed
+// within a few minutes.
+pub const KEEP_ALIVE: Duration = Duration::from_secs(5);
+pub const IDLE_KEEP_ALIVE: Duration = Duration::from_secs(120);
+// Idle, polling backends look this many times less often, but at most once
+// per MAX_POLL, so the first copy after a break is still seen soon.
+const POLL_FACTOR: u32 = 4;
+const MAX_POLL: Duration = Duration::from_secs(2);
+
+pub struct Idle {
+    // None is never idle.
+    after: Option<Duration>,
+    last: Mutex<Instant>,
+}
+
+impl Idle {
+    pub fn new(after: Duration) -> Idle {
+        Idle { after: (!after.is_zero()).then_some(after), last: Mutex::new(Instant::now()) }
+    }
+
+    // A local copy, or an item from another device.
+    pub fn touch(&self) {
+        *lock(&self.last) = Instant::now();
+    }
+
+    pub fn is_idle(&self) -> bool {
+        self.after.is_some_and(|after| lock(&self.last).elapsed() >= after)
+    }
+
+    pub fn poll_interval(&self, interval: Duration) -> Duration {
+        match self.is_idle() {
+            true => (interval * POLL_FACTOR).min(MAX_POLL).max(interval),
+            false => interval,
+        }
+    }
+}
@@ -0,0 +1,58 @@
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use crate::envelope::Envelope;
+
+const TYPES: &[(&str, &str)] = &[
+    ("txt", "text/plain"),
+    ("md", "text/markdown"),
+    ("html", "text/html"),
+    ("csv", "text/csv"),
+    ("json", "application/json"),
+    ("xml", "application/xml"),
+    ("svg", "image/svg+xml"),
+];
+
+pub fn content_type(name: &str) -> &'static str {
+    let extension = name.rsplit_once('.').map(|(_, extension)| extension.to_ascii_lowercase());
+    TYPES.iter()
+        .find(|(known, _)| Some(*known) == extension.as_deref())
+        .map_or("text/plain", |(_, content_type)| content_type)
+}
+
+// Names come from other devices, so only the last path component is kept,
+// and nothing that could climb out of the inbox or break a header line.
+pub fn file_name(name: &str) -> Option<String> {
+    let name: String = name.rsplit(['/', '\\']).next()?.chars().filter(|c| !c.is_control()).collect();
+    let name = name.trim();
+    (!name.is_empty() && name != "." && name != "..").then(|| name.to_string())
+}
+
+// Never overwrites: a name already taken gets a number, as in `notes (2).txt`.
+pub fn save(dir: &Path, envelope: &Envelope) -> io::Result<PathBuf> {
+    fs::create_dir_all(dir)?;
+    let name = envelope.name.as_deref().and_then(file_name).unwrap_or_else(|| {
+        let extension = TYPES.iter().find(|(_, content_type)| *content_type == envelope.content_type).map_or("txt", |(extension, _)| extension);
+        format!("{}.{}", envelope.device.as_deref().unwrap_or("unknown"), extension)
+    });
+    let (stem, extension) = match name.rsplit_once('.') {
+        Some((stem, extension)) if !stem.is_empty() => (stem, format!(".{extension}")),
+        _ => (name.as_str(), String::new()),
+    };
+
+    for n in 1..1000 {
+        let path = match n {
+            1 => dir.join(&name),
+            n => dir.join(format!("{stem} ({n}){extension}")),
+        };
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(mut file) => {
+                file.write_all(envelope.content.as_bytes())?;
+                return Ok(path);
+            }
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("too many files named {name} in the inbox")))
+}
@@ -0,0 +1,202 @@
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+use zeroize::Zeroizing;
+use crate::config::{Config, Value};
+#[cfg(feature = "e2e")]
+use crate::crypto::Keyring;
+#[cfg(feature = "e2e")]
+use crate::trust::Trust;
+
+pub fn prompt(question: &str, default: Option<&str>) -> String {
+    loop {
+        match default {
+            Some(default) => print!("{question} [{default}]: "),
+            None => print!("{question}: "),
+        }
+        let _ = io::stdout().flush();
+
+        let mut line = String::new();
+        if io::stdin().lock().read_line(&mut line).unwrap_or(0) == 0 {
+            std::process::exit(1);
+        }
+        let answer = line.trim();
+        match (answer.is_empty(), default) {
+            (false, _) => return answer.to_string(),
+            (true, Some(default)) => return default.to_string(),
+            (true, None) => {}
+        }
+    }
+}
+
+// Reads a passphrase without showing it, when stdin is a terminal. Only the
+// line break is taken off, as spaces at either end may be part of it.
+pub fn prompt_secret(question: &str) -> Zeroizing<String> {
+    loop {
+        print!("{question}: ");
+        let _ = io::stdout().flush();
+
+        let mut line = Zeroizing::new(String::new());
+        let read = {
+            let _echo = Echo::off();
+            io::stdin().lock().read_line(&mut line).unwrap_or(0)
+        };
+        if read == 0 {
+            std::process::exit(1);
+        }
+        let len = line.trim_end_matches(['\r', '\n']).len();
+        line.truncate(len);
+        if !line.is_empty() {
+            return line;
+        }
+    }
+}
+
+// Turns echo back on when dropped. The line break is still echoed, so the
+// next output starts on a line of its own.
+#[cfg(unix)]
+struct Echo(Option<libc::termios>);
+
+#[cfg(unix)]
+impl Echo {
+    fn off() -> Echo {
+        unsafe {
+            let mut termios: libc::termios = std::mem::zeroed();
+            if libc::tcgetattr(libc::STDIN_FILENO, &mut termios) != 0 {
+                return Echo(None);
+            }
+            let saved = termios;
+            termios.c_lflag = (termios.c_lflag & !libc::ECHO) | libc::ECHONL;
+            if libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &termios) != 0 {
+                return Echo(None);
+            }
+            Echo(Some(saved))
+        }
+    }
+}
+
+#[cfg(unix)]
+impl Drop for Echo {
+    fn drop(&mut self) {
+        if let Some(saved) = &self.0 {
+            unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, saved) };
+        }
+    }
+}
+
+// Elsewhere the passphrase shows as it is typed.
+#[cfg(not(unix))]
+struct Echo;
+
+#[cfg(not(unix))]
+impl Echo {
+    fn off() -> Echo {
+        Echo
+    }
+}
+
+fn confirm(question: &str, default: bool) -> bool {
+    let answer = prompt(question, Some(if default { "Y/n" } else { "y/N" }));
+    match answer.to_ascii_lowercase().as_str() {
+        "y" | "yes" => true,
+        "n" | "no" => false,
+        _ => default,
+    }
+}
+
+fn current(config: &Config, key: &str) -> Option<String> {
+    match config.get(key)? {
+        Value::String(s) => Some(s.clone()),
+        Value::Integer(i) => Some(i.to_string()),
+        _ => None,
+    }
+}
+
+pub fn hostname() -> Option<String> {
+    std::env::var("COMPUTERNAME").ok()
+        .or_else(|| std::env::var("HOSTNAME").ok())
+        .or_else(|| std::fs::read_to_string("/etc/hostname").ok())
+        .or_else(|| {
+            let output = std::process::Command::new("hostname").output().ok()?;
+            Some(String::from_utf8_lossy(&output.stdout).into_owned())
+        })
+        .map(|name| name.trim().split('.').next().unwrap_or_default().to_string())
+        .filter(|name| !name.is_empty())
+}
+
+pub fn run(config_path: &Path, data_dir: &Path) {
+    let mut config = Config::load(config_path).unwrap_or_default();
+    println!("Setting up {}", config_path.display());
+
+    let server = current(&config, "server").map(|server| match current(&config, "port") {
+        Some(port) => format!("{server}:{port}"),
+        None => server,
+    });
+    let address = prompt("Broker address (host[:port])", server.as_deref());
+    let (server, port) = match address.rsplit_once(':') {
+        Some((host, port)) if port.parse::<u16>().is_ok() => (host.to_string(), port.parse().unwrap()),
+        _ => (address, 8883),
+    };
+    config.set("server", Value::String(server));
+    config.set("port", Value::Integer(port));
+
+    let user = prompt("User", current(&config, "user").as_deref());
+    let device = prompt("Device name", current(&config, "device").or_else(hostname).as_deref());
+    config.set("user", Value::String(user.clone()));
+    config.set("device", Value::String(device.clone()));
+
+    println!("Authentication uses TLS client certificates: ca.crt, {user}-{device}.crt and {user}-{device}.key");
+    let default_cert_dir = data_dir.join("certs").display().to_string();
+    let cert_dir = prompt("Certificate directory", Some(&current(&config, "cert_dir").unwrap_or(default_cert_dir)));
+    for file in ["ca.crt".to_string(), format!("{user}-{device}.crt"), format!("{user}-{device}.key")] {
+        if !Path::new(&cert_dir).join(&file).exists() {
+            println!("warning: {} is missing from {}", file, cert_dir);
+        }
+    }
+    config.set("cert_dir", Value::String(cert_dir));
+
+    #[cfg(feature = "e2e")]
+    let encryption = prompt("End-to-end encryption (none, shared, device)", Some("none"));
+    #[cfg(feature = "e2e")]
+    match encryption.as_str() {
+        "shared" => {
+            let default_keyring = data_dir.join("keyring").display().to_string();
+            let path = prompt("Keyring file", Some(&default_keyring));
+            if !Path::new(&path).exists() {
+                match Keyring::generate(Path::new(&path)) {
+                    Ok(keyring) => println!("generated a new key, import it on your other devices with: cloudboard key import <path> {}", keyring.current().code()),
+                    Err(e) => println!("warning: failed to create keyring: {}", e),
+                }
+            }
+            config.set("e2e_key", Value::String(path));
+        }
+        "device" => {
+            config.set("device_keys", Value::Bool(true));
+            match Trust::load(data_dir) {
+                Ok(trust) => println!("add this device on the others with: cloudboard trust add {} {}", device, trust.identity()),
+                Err(e) => println!("warning: failed to create device keys: {}", e),
+            }
+        }
+        _ => {}
+    }
+
+    if let Err(e) = config.save(config_path) {
+        eprintln!("Failed to write {}: {}", config_path.display(), e);
+        std::process::exit(1);
+    }
+    println!("wrote {}", config_path.display());
+
+    if confirm("Test the connection now?", true) {
+        let args = crate::cli::parse_args(&config, ["cloudboard"]);
+        match crate::test_connection(&args) {
+            Ok(()) => println!("connected to the broker"),
+            Err(e) => println!("connection failed: {}", e),
+        }
+    }
+
+    if confirm("Install cloudboard as a service for this user?", false) {
+        match crate::service::install(config_path) {
+            Ok(message) => println!("{message}"),
+            Err(e) => println!("service installation failed: {}", e),
+        }
+    }
+}
@@ -0,0 +1,43 @@
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io;
+use std::path::Path;
+use crate::status;
+
+// Locks held for as long as the daemon runs, one in the data dir and one
+// for the device name on this machine, so a second daemon for either stops
+// before it fights the first over the clipboard and the broker session. The
+// OS lets go of them when the process ends however it ends, so a crash
+// leaves nothing to clean up.
+pub struct Instance {
+    _locks: Vec<File>,
+}
+
+pub fn claim(data_dir: &Path, user: &str, device: &str) -> Result<Instance, String> {
+    let mut locks = Vec::new();
+    // Other OS users may share the temporary directory, and their lock is
+    // not ours to be kept out by.
+    let machine = std::env::temp_dir().join(format!("cloudboard-{user}-{device}.lock"));
+    for (path, what, in_data_dir) in [(data_dir.join("daemon.lock"), format!("data dir {}", data_dir.display()), true), (machine, format!("device {device}"), false)] {
+        let file = match open(&path) {
+            Ok(file) => file,
+            Err(_) if !in_data_dir => continue,
+            Err(e) => return Err(format!("cannot open {}: {}", path.display(), e)),
+        };
+        match file.try_lock() {
+            Ok(()) => locks.push(file),
+            Err(TryLockError::WouldBlock) => {
+                let pid = status::get(data_dir, "pid").filter(|_| in_data_dir).map(|pid| format!(" as process {pid}")).unwrap_or_default();
+                return Err(format!("cloudboard is already running for {what}{pid}; stop it first, or give this one a --device and --data-dir of its own"));
+            }
+            Err(TryLockError::Error(e)) => return Err(format!("cannot lock {}: {}", path.display(), e)),
+        }
+    }
+    Ok(Instance { _locks: locks })
+}
+
+fn open(path: &Path) -> io::Result<File> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    OpenOptions::new().create(true).truncate(false).write(true).open(path)
+}
@@ -0,0 +1,71 @@
+use std::sync::Arc;
+use log::{error, warn};
+use crate::logging::PUBLISH;
+use crate::store::Store;
+
+const PREFIX: &str = "journal/";
+// Kept even if some device never acks, like one that was retired without
+// being removed from the devices list.
+const MAX_ENTRIES: usize = 256;
+
+// Items published to the personal clipboard, kept as they went on the wire
+// until every device that acks has acked them. What is left at startup is
+// published again, and receivers, who go by sequence number, apply it at
+// most once.
+pub struct Journal {
+    store: Arc<dyn Store>,
+}
+
+impl Journal {
+    pub fn new(store: Arc<dyn Store>) -> Journal {
+        Journal { store }
+    }
+
+    pub fn record(&self, seq: u64, topic: &str, payload: &[u8]) {
+        let mut entry = Vec::with_capacity(topic.len() + 1 + payload.len());
+        entry.extend_from_slice(topic.as_bytes());
+        entry.push(b'\n');
+        entry.extend_from_slice(payload);
+        if let Err(e) = self.store.put(&key(seq), &entry) {
+            error!(target: PUBLISH, "Failed to journal item {}: {}", seq, e);
+            return;
+        }
+        let keys = self.store.list(PREFIX).unwrap_or_default();
+        if keys.len() > MAX_ENTRIES {
+            warn!(target: PUBLISH, "journal is full, dropping items no device has acked");
+            let _ = self.store.prune(PREFIX, &keys[keys.len() - MAX_ENTRIES]);
+        }
+    }
+
+    // Everything up to `seq` has been acked by every device that acks.
+    pub fn acked(&self, seq: u64) {
+        if let Err(e) = self.store.prune(PREFIX, &key(seq + 1)) {
+            error!(target: PUBLISH, "Failed to prune the journal: {}", e);
+        }
+    }
+
+    // The items still waiting for acks, oldest first, as sequence number,
+    // topic and payload.
+    pub fn pending(&self) -> Vec<(u64, String, Vec<u8>)> {
+        let keys = match self.store.list(PREFIX) {
+            Ok(keys) => keys,
+            Err(e) => {
+                error!(target: PUBLISH, "Failed to read the journal: {}", e);
+                return Vec::new();
+            }
+        };
+        keys.iter()
+            .filter_map(|key| {
+                let seq = key.strip_prefix(PREFIX)?.parse().ok()?;
+                let entry = self.store.get(key).ok().flatten()?;
+                let split = entry.iter().position(|b| *b == b'\n')?;
+                let topic = String::from_utf8(entry[..split].to_vec()).ok()?;
+                Some((seq, topic, entry[split + 1..].to_vec()))
+            })
+            .collect()
+    }
+}
+
+fn key(seq: u64) -> String {
+    format!("{PREFIX}{seq:020}")
+}
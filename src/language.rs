@@ -0,0 +1,109 @@
+// Rough detection of what copied text is written in, for rules with
+// `language` or `script`. Scripts go by Unicode blocks. Most of them imply
+// the language; text in Latin letters is told apart by its most common
+// words, which takes a sentence or so and leaves code and names unknown.
+
+// Looking further into a long text changes little and costs a lot.
+const SAMPLE: usize = 4096;
+// Function words that only count when several turn up.
+const MIN_WORDS: usize = 2;
+
+const SCRIPTS: &[(&str, &[(u32, u32)])] = &[
+    ("latin", &[(0x41, 0x5a), (0x61, 0x7a), (0xc0, 0xd6), (0xd8, 0xf6), (0xf8, 0x24f), (0x1e00, 0x1eff)]),
+    ("greek", &[(0x370, 0x3ff), (0x1f00, 0x1fff)]),
+    ("cyrillic", &[(0x400, 0x52f)]),
+    ("hebrew", &[(0x590, 0x5ff)]),
+    ("arabic", &[(0x600, 0x6ff), (0x750, 0x77f)]),
+    ("devanagari", &[(0x900, 0x97f)]),
+    ("thai", &[(0xe00, 0xe7f)]),
+    ("hangul", &[(0x1100, 0x11ff), (0x3130, 0x318f), (0xac00, 0xd7af)]),
+    ("kana", &[(0x3040, 0x30ff)]),
+    ("han", &[(0x3400, 0x4dbf), (0x4e00, 0x9fff), (0xf900, 0xfaff)]),
+];
+
+// Scripts `cjk` stands for in rules.
+pub const CJK: &[&str] = &["han", "kana", "hangul"];
+
+const WORDS: &[(&str, &[&str])] = &[
+    ("en", &["the", "and", "of", "to", "is", "in", "that", "it", "for", "with", "was", "on", "are", "this", "be", "you", "not", "have"]),
+    ("de", &["der", "die", "das", "und", "ist", "nicht", "ein", "eine", "zu", "den", "mit", "von", "sich", "auf", "dem", "ich"]),
+    ("fr", &["le", "la", "les", "et", "est", "un", "une", "des", "du", "que", "pas", "pour", "dans", "qui", "sur", "avec", "je"]),
+    ("es", &["el", "la", "los", "las", "y", "es", "un", "una", "que", "de", "del", "por", "para", "con", "no", "se"]),
+    ("it", &["il", "la", "le", "e", "è", "un", "una", "che", "di", "del", "per", "non", "con", "sono", "gli"]),
+    ("pt", &["o", "a", "os", "as", "e", "é", "um", "uma", "que", "de", "do", "da", "não", "para", "com", "em"]),
+    ("nl", &["de", "het", "een", "en", "is", "van", "niet", "dat", "op", "te", "zijn", "met", "voor", "ik"]),
+];
+
+// Whether `code` looks like ISO 639-1, which allows codes nothing here
+// detects, since they match nothing either way.
+pub fn is_code(code: &str) -> bool {
+    code.len() == 2 && code.bytes().all(|b| b.is_ascii_lowercase())
+}
+
+pub fn is_script(name: &str) -> bool {
+    name == "cjk" || SCRIPTS.iter().any(|(script, _)| *script == name)
+}
+
+pub fn scripts() -> Vec<&'static str> {
+    SCRIPTS.iter().map(|(script, _)| *script).collect()
+}
+
+fn script_of(c: char) -> Option<&'static str> {
+    let c = c as u32;
+    SCRIPTS.iter().find(|(_, ranges)| ranges.iter().any(|(start, end)| (*start..=*end).contains(&c))).map(|(name, _)| *name)
+}
+
+fn sample(text: &str) -> &str {
+    match text.char_indices().nth(SAMPLE) {
+        Some((end, _)) => &text[..end],
+        None => text,
+    }
+}
+
+// The script most letters are in, e.g. `latin` or `han`.
+pub fn script(text: &str) -> Option<&'static str> {
+    let mut counts = [0usize; SCRIPTS.len()];
+    for script in sample(text).chars().filter_map(script_of) {
+        if let Some(index) = SCRIPTS.iter().position(|(name, _)| *name == script) {
+            counts[index] += 1;
+        }
+    }
+    let (index, count) = counts.iter().enumerate().max_by_key(|(_, count)| **count)?;
+    (*count > 0).then_some(SCRIPTS[index].0)
+}
+
+// The ISO 639-1 code of the language, e.g. `en` or `ja`.
+pub fn language(text: &str) -> Option<&'static str> {
+    let text = sample(text);
+    // Japanese mixes kana into its Han characters; Chinese has none.
+    if text.chars().any(|c| script_of(c) == Some("kana")) {
+        return Some("ja");
+    }
+    match script(text)? {
+        "latin" => by_words(text),
+        "cyrillic" if text.chars().any(|c| "іїєґІЇЄҐ".contains(c)) => Some("uk"),
+        "cyrillic" => Some("ru"),
+        "greek" => Some("el"),
+        "hebrew" => Some("he"),
+        "arabic" => Some("ar"),
+        "devanagari" => Some("hi"),
+        "thai" => Some("th"),
+        "hangul" => Some("ko"),
+        "han" => Some("zh"),
+        _ => None,
+    }
+}
+
+// The language with the most of its common words in the text, as long as
+// no other has as many.
+fn by_words(text: &str) -> Option<&'static str> {
+    let words: Vec<String> = text.split(|c: char| !c.is_alphabetic()).filter(|word| !word.is_empty()).map(str::to_lowercase).collect();
+    let mut scores: Vec<(&str, usize)> = WORDS.iter()
+        .map(|(language, common)| (*language, words.iter().filter(|word| common.contains(&word.as_str())).count()))
+        .collect();
+    scores.sort_by_key(|(_, score)| std::cmp::Reverse(*score));
+    match scores.as_slice() {
+        [(language, best), (_, next), ..] if *best >= MIN_WORDS && best > next => Some(language),
+        _ => None,
+    }
+}
@@ -0,0 +1,716 @@
+use std::io::{self, Read};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use clipboard_rs::ClipboardContext;
+#[cfg(feature = "files")]
+use log::error;
+use log::info;
+use log::warn;
+use rumqttc::v5::{Client, Event, Incoming, MqttOptions};
+use rumqttc::{TlsConfiguration, Transport};
+use zeroize::Zeroizing;
+use crate::crypto::{E2e, Keyring};
+use crate::envelope::Envelope;
+use crate::clipboard::{Backend, Target};
+use crate::logging::{PUBLISH, RECEIVE};
+use crate::sync::{ClipboardSync, SyncEvent};
+
+pub mod acl;
+#[cfg(feature = "e2e")]
+pub mod argon2;
+mod autostart;
+mod blob;
+mod broker;
+pub mod cli;
+pub mod clipboard;
+pub mod codec;
+mod completions;
+pub mod config;
+#[cfg(feature = "http-api")]
+pub mod control;
+mod copyq;
+pub mod crl;
+pub mod crypto;
+pub mod delta;
+pub mod devices;
+mod diag;
+mod doctor;
+pub mod envelope;
+mod growth;
+pub mod hlc;
+#[cfg(feature = "http-api")]
+pub mod http;
+mod idle;
+#[cfg(feature = "files")]
+mod inbox;
+mod init;
+mod instance;
+pub mod journal;
+mod json;
+mod language;
+pub mod limit;
+mod locked;
+pub mod memory;
+mod msgpack;
+#[cfg(feature = "http-api")]
+mod native_host;
+pub mod normalize;
+pub mod lock;
+pub mod logging;
+pub mod paths;
+#[cfg(feature = "http-api")]
+mod pastejack;
+pub mod pin;
+pub mod policy;
+mod profile;
+mod secrets;
+pub mod remote_desktop;
+pub mod replay;
+#[cfg(feature = "history")]
+pub mod retention;
+pub mod revoke;
+pub mod sanitize;
+pub mod rules;
+pub mod screen_lock;
+mod service;
+mod shortcut;
+pub mod sim;
+mod slot;
+#[cfg(feature = "http-api")]
+mod snapshot;
+mod source;
+#[cfg(feature = "http-api")]
+mod standby;
+pub mod stats;
+pub mod status;
+pub mod store;
+pub mod sync;
+#[cfg(feature = "http-api")]
+mod tail;
+pub mod template;
+pub mod text;
+pub mod trigger;
+pub mod trust;
+mod tunnel;
+pub mod when;
+pub mod x25519;
+
+#[derive(clap::Args, Clone, Debug)]
+pub struct Args {
+    #[arg(short, long)]
+    pub device: String,
+
+    #[arg(short, long)]
+    pub user: String,
+
+    /// Directory with ca.crt, <user>-<device>.crt and <user>-<device>.key
+    #[arg(short, long)]
+    pub cert_dir: Option<String>,
+
+    /// CA certificate as a path or inline PEM, instead of the one in --cert-dir
+    #[arg(long)]
+    pub ca_cert: Option<String>,
+
+    /// Client certificate as a path or inline PEM
+    #[arg(long)]
+    pub client_cert: Option<String>,
+
+    /// Client private key as a path or inline PEM
+    #[arg(long)]
+    pub client_key: Option<String>,
+
+    #[arg(short, long)]
+    pub server: String,
+
+    #[arg(short, long, default_value = "8883")]
+    pub port: u16,
+
+    /// Reach the broker through this SSH host, e.g. me@bastion, with ssh and its config, including a ControlMaster
+    #[arg(long)]
+    pub via: Option<String>,
+
+    /// Reach the broker through Tor, at this SOCKS port or 127.0.0.1:9050, as for a broker that is an onion service; arti listens on 127.0.0.1:9150
+    #[arg(long, num_args = 0..=1, default_missing_value = "127.0.0.1:9050", conflicts_with = "via")]
+    pub tor: Option<SocketAddr>,
+
+    /// Also require the broker's certificate to match one of these hashes, spki:<sha256> or cert:<sha256>; see `cloudboard pin`
+    #[arg(long, value_delimiter = ',')]
+    pub pin: Vec<pin::Pin>,
+
+    /// Apply the settings this device, as the trust list names it, publishes with `cloudboard policy push`
+    #[arg(long)]
+    pub primary: Option<String>,
+
+    /// Refuse the broker's certificate, and those of clients of --control, when a CRL in these PEM files revokes it
+    #[arg(long, value_delimiter = ',')]
+    pub crl: Vec<String>,
+
+    /// Which certificates the --crl lists must cover, with any they do not refused: chain, end-entity for the peer's own only, or allow-unknown to let uncovered ones through
+    #[arg(long, value_enum, default_value = "chain")]
+    pub crl_policy: crl::Policy,
+
+    #[arg(long, value_enum, default_value = "watch")]
+    pub clipboard_backend: clipboard::Backend,
+
+    #[arg(long, default_value = "500")]
+    pub poll_interval_ms: u64,
+
+    /// Wait this long for newer content before applying what was received, so bursts set the clipboard once
+    #[arg(long, default_value = "150ms", value_parser = humantime::parse_duration)]
+    pub settle_time: Duration,
+
+    /// Hold back received content while local apps change the clipboard in quick succession, until it has been left alone this long; 0 turns this off
+    #[arg(long, default_value = "500ms", value_parser = humantime::parse_duration)]
+    pub defer_window: Duration,
+
+    /// After this long without a copy here or an item from elsewhere, ping the broker and poll the clipboard less often; 0 turns this off
+    #[arg(long, default_value = "10m", value_parser = humantime::parse_duration)]
+    pub idle_after: Duration,
+
+    /// Serve GET and PUT /clipboard on this address, e.g. 127.0.0.1:8731
+    #[arg(long)]
+    pub http: Option<SocketAddr>,
+
+    /// Also serve the HTTP API over TLS on this address, e.g. 0.0.0.0:8740, to `cloudboardctl` with a certificate from the same CA issued to a device of --user
+    #[arg(long)]
+    pub control: Option<SocketAddr>,
+
+    /// Only take --control clients with the certificates of these devices, e.g. --control-from laptop,phone
+    #[arg(long, value_delimiter = ',', requires = "control")]
+    pub control_from: Vec<String>,
+
+    /// Which format wins when received content comes in more than one
+    #[arg(long, value_enum, default_value = "html")]
+    pub prefer_format: clipboard::Prefer,
+
+    /// What to do with the local and the remote clipboard on startup
+    #[arg(long, value_enum, default_value = "idle")]
+    pub startup: clipboard::Startup,
+
+    #[arg(long, value_enum, default_value = "pause")]
+    pub remote_desktop: remote_desktop::Policy,
+
+    /// What to pause while the screen is locked
+    #[arg(long, value_enum, default_value = "pause")]
+    pub screen_lock: screen_lock::Policy,
+
+    #[arg(long)]
+    pub e2e_key: Option<PathBuf>,
+
+    /// Encrypt to the devices in the trust list instead of a shared key
+    #[arg(long, conflicts_with = "e2e_key")]
+    pub device_keys: bool,
+
+    /// Drop messages that are not signed by a device in the trust list
+    #[arg(long)]
+    pub require_signatures: bool,
+
+    /// Refuse to start without end-to-end encryption for every clipboard
+    #[arg(long)]
+    pub require_encryption: bool,
+
+    /// Do not publish content that looks like a private key, token or password
+    #[arg(long)]
+    pub filter_secrets: bool,
+
+    /// What to take out of received text before it is pasted: escapes, controls, bidi, or none
+    #[arg(long, value_enum, value_delimiter = ',', default_value = "escapes,controls,bidi")]
+    pub sanitize: Vec<sanitize::Strip>,
+
+    /// Tidy up copied text before it is published: plain, collapse, trim-trailing, trim
+    #[arg(long, value_enum, value_delimiter = ',')]
+    pub normalize: Vec<normalize::Normalize>,
+
+    /// Hold back received text that looks like a command with hidden characters or a sudo line ending in a newline, until `cloudboard held --apply`
+    #[arg(long, requires = "http")]
+    pub hold_suspicious: bool,
+
+    /// Keep what other devices send without putting it on the clipboard, until `cloudboard promote`
+    #[arg(long, requires = "http")]
+    pub standby: bool,
+
+    /// Hold back received items larger than this many bytes until `cloudboard held --apply`, rather than pasting them
+    #[arg(long, requires = "http")]
+    pub hold_larger_than: Option<usize>,
+
+    /// Warn when a received item larger than this many bytes goes on the clipboard
+    #[arg(long)]
+    pub warn_larger_than: Option<usize>,
+
+    /// Only send and apply plain text, no files or other content types
+    #[arg(long)]
+    pub text_only: bool,
+
+    /// Do not have the broker retain the latest content
+    #[arg(long)]
+    pub no_retain: bool,
+
+    /// Do not check after connecting that the broker lets this device use its topics, and stop if not
+    #[arg(long)]
+    pub no_acl_check: bool,
+
+    /// How long the broker keeps the latest content once nothing replaces it; 0 keeps it indefinitely
+    #[arg(long, default_value = "24h", value_parser = humantime::parse_duration)]
+    pub retain_expiry: Duration,
+
+    /// On Windows, keep received content out of clipboard history (Win+V) and the cloud clipboard
+    #[arg(long)]
+    pub no_windows_history: bool,
+
+    /// Do not keep the local event log behind `cloudboard stats`
+    #[arg(long)]
+    pub no_history: bool,
+
+    #[cfg(feature = "history")]
+    #[command(flatten)]
+    pub retention: retention::Retention,
+
+    /// Do not tell other devices which application content was copied in
+    #[arg(long)]
+    pub no_source: bool,
+
+    /// Only publish what is copied with Ctrl+C, Ctrl+X or Ctrl+Insert (Cmd+C or Cmd+X on macOS), not selections apps put on the clipboard themselves; X11, macOS and Windows only
+    #[arg(long)]
+    pub copy_shortcut: bool,
+
+    /// Shorthand for --text-only --max-size 10240 --filter-secrets --require-encryption --no-retain --no-history --no-source
+    #[arg(long)]
+    pub paranoid: bool,
+
+    /// Team clipboards to join, each sharing the key in <data dir>/groups/<name>.key
+    #[arg(long, value_delimiter = ',')]
+    pub group: Vec<String>,
+
+    /// Numbered clipboard slots to keep in sync besides the clipboard, for `cloudboard slot`
+    #[arg(long, default_value_t = 0)]
+    pub slots: u32,
+
+    /// Only apply content signed by these devices, as the trust list names them, e.g. --accept-from laptop,phone
+    #[arg(long, value_delimiter = ',')]
+    pub accept_from: Vec<String>,
+
+    /// Only apply content tagged with one of these at send time, e.g. --accept-tag work,url
+    #[arg(long, value_delimiter = ',')]
+    pub accept_tag: Vec<String>,
+
+    /// Largest clipboard content in bytes to send or accept
+    #[arg(long, default_value = "1048576")]
+    pub max_size: usize,
+
+    /// Send the start of content over --max-size, cut between characters, instead of dropping it
+    #[arg(long)]
+    pub truncate: bool,
+
+    /// How many recent items identical content is not published again after
+    #[arg(long, default_value = "1")]
+    pub dedup_window: usize,
+
+    /// Publish identical content again once this long has passed, e.g. 30s
+    #[arg(long, value_parser = humantime::parse_duration)]
+    pub dedup_time: Option<Duration>,
+
+    /// Content larger than this many bytes is offered and only sent to devices that fetch it
+    #[arg(long, default_value = "262144")]
+    pub lazy_threshold: usize,
+
+    /// Content larger than this many bytes is published once under its hash, and only referred to when it comes around again
+    #[arg(long)]
+    pub blob_threshold: Option<usize>,
+
+    /// Text larger than this many bytes that is an edit of the previous item is sent as the edit, to devices that all run a release that takes them
+    #[arg(long)]
+    pub delta_threshold: Option<usize>,
+
+    /// How long the broker keeps content published under its hash
+    #[arg(long, default_value = "7d", value_parser = humantime::parse_duration)]
+    pub blob_expiry: Duration,
+
+    /// Bytes of content held in memory, by queued events, offers and held items, past which the oldest is dropped
+    #[arg(long, default_value = "67108864")]
+    pub memory_limit: usize,
+
+    /// Messages in flight to the broker at once, lowered to the Receive Maximum the broker announces
+    #[arg(long, default_value_t = 32, value_parser = clap::value_parser!(u16).range(1..))]
+    pub max_inflight: u16,
+
+    /// Messages per minute accepted from each device, beyond which they are dropped
+    #[arg(long, default_value = "30")]
+    pub max_rate: u32,
+
+    /// Ignore messages the broker queued for longer than this while offline, e.g. 10m
+    #[arg(long, value_parser = humantime::parse_duration)]
+    pub max_age: Option<Duration>,
+
+    /// Save files sent with `push --file` in this directory instead of putting them on the clipboard
+    #[arg(long)]
+    pub inbox: Option<PathBuf>,
+
+    /// Envelope format version to publish, lower while some devices run an older cloudboard
+    #[arg(long, default_value_t = envelope::VERSION, value_parser = clap::value_parser!(u32).range(1..=envelope::VERSION as i64))]
+    pub envelope_version: u32,
+
+    /// How envelopes are serialized; binary is smaller but needs every device on a release that reads it
+    #[arg(long, value_enum, default_value = "text")]
+    pub envelope_encoding: envelope::Encoding,
+
+    /// How long previous keys stay valid after a rotation
+    #[arg(long, default_value = "24h", value_parser = humantime::parse_duration)]
+    pub key_grace: Duration,
+}
+
+// Room for the envelope headers, the signature and the per-device key slots
+// on top of the content. The resulting limit is announced to the broker,
+// which drops larger messages instead of sending them; the content limit
+// itself is checked on the receive path where it can be logged.
+const PACKET_OVERHEAD: usize = 64 * 1024;
+
+pub fn clipboard_topic(user: &str) -> String {
+    format!("clipboard/{user}")
+}
+
+// Holds the settings the primary device publishes, retained.
+pub fn policy_topic(user: &str) -> String {
+    format!("clipboard/{user}/policy")
+}
+
+pub fn revoke_topic(user: &str) -> String {
+    format!("clipboard/{user}/revoked")
+}
+
+pub fn control_topic(user: &str) -> String {
+    format!("clipboard/{user}/control")
+}
+
+// Holds how the shared key is derived from a passphrase, retained.
+pub fn meta_topic(user: &str) -> String {
+    format!("clipboard/{user}/meta")
+}
+
+pub fn group_topic(name: &str) -> String {
+    format!("clipboard/group/{name}")
+}
+
+pub fn ack_topic(user: &str) -> String {
+    format!("clipboard/{user}/ack")
+}
+
+// Content over --blob-threshold is retained at `<blob topic>/<sha256>`.
+pub fn blob_topic(user: &str) -> String {
+    format!("clipboard/{user}/blob")
+}
+
+// Each slot is retained at `<slot topic>/<number>`.
+pub fn slot_topic(user: &str) -> String {
+    format!("clipboard/{user}/slot")
+}
+
+// Fetch requests go to this topic and each device gets the answers on its
+// own subtopic, `<fetch topic>/<device>`.
+pub fn fetch_topic(user: &str) -> String {
+    format!("clipboard/{user}/fetch")
+}
+
+// The CA, client certificate and client key, in that order, each either a
+// path or inline PEM.
+pub fn cert_sources(args: &Args) -> io::Result<[String; 3]> {
+    find_certs(&args.user, &args.device, args.cert_dir.as_deref(), [&args.ca_cert, &args.client_cert, &args.client_key])
+}
+
+pub fn find_certs(user: &str, device: &str, cert_dir: Option<&str>, explicit: [&Option<String>; 3]) -> io::Result<[String; 3]> {
+    let cert_prefix = format!("{user}-{device}");
+    let source = |explicit: &Option<String>, name: String| match (explicit, cert_dir) {
+        (Some(explicit), _) => Ok(explicit.clone()),
+        (None, Some(dir)) => Ok(Path::new(dir).join(&name).display().to_string()),
+        (None, None) => Err(io::Error::new(io::ErrorKind::NotFound, format!("no --cert-dir to find {} in", name))),
+    };
+    let [ca, cert, key] = explicit;
+    Ok([
+        source(ca, "ca.crt".to_string())?,
+        source(cert, format!("{cert_prefix}.crt"))?,
+        source(key, format!("{cert_prefix}.key"))?,
+    ])
+}
+
+pub fn is_inline_pem(source: &str) -> bool {
+    source.trim_start().starts_with("-----BEGIN")
+}
+
+pub fn read_pem(source: &str) -> io::Result<Vec<u8>> {
+    if is_inline_pem(source) {
+        return Ok(source.as_bytes().to_vec());
+    }
+    let mut bytes = Vec::new();
+    std::fs::File::open(source)
+        .and_then(|mut file| file.read_to_end(&mut bytes))
+        .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", source, e)))?;
+    Ok(bytes)
+}
+
+pub fn mqtt_options(args: &Args, client_id: &str) -> io::Result<MqttOptions> {
+    // Syncing leans on MQTT 5 for retained items, will messages and
+    // sessions the broker keeps while a device is away, none of which NATS
+    // core or Redis pub/sub have, so those are turned down by name rather
+    // than failing to resolve.
+    match args.server.split_once("://") {
+        Some((scheme, host)) if scheme.starts_with("mqtt") => {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("--server takes a host name, like {}, and --port the port", host.split([':', '/']).next().unwrap_or(host))));
+        }
+        Some((scheme, _)) => {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("cloudboard only talks MQTT 5, so a {} server needs an MQTT broker such as mosquitto alongside it", scheme)));
+        }
+        None => {}
+    }
+    let sources = cert_sources(args)?;
+    let (transport, host, port) = match tunnel::hop(args) {
+        Some(hop) => {
+            let tunnel = tunnel::open(&hop, &args.server, args.port)?;
+            let tls = TlsConfiguration::Rustls(tunnel::tls_config(args, &sources)?);
+            (Transport::Tls(tls), tunnel.ip().to_string(), tunnel.port())
+        }
+        None if args.server.ends_with(".onion") => {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{} is an onion service, give --tor to reach it", args.server)));
+        }
+        None => (transport(args, &sources)?, args.server.clone(), args.port),
+    };
+
+    let mut mqtt_opt = MqttOptions::new(client_id, host, port);
+    mqtt_opt.set_keep_alive(idle::KEEP_ALIVE);
+    mqtt_opt.set_transport(transport);
+    mqtt_opt.set_max_packet_size(Some((args.max_size + PACKET_OVERHEAD) as u32));
+    Ok(mqtt_opt)
+}
+
+// TLS straight to the broker, read from the cert files as they are now, so
+// reconnecting can pick up renewed ones.
+pub fn transport(args: &Args, sources: &[String; 3]) -> io::Result<Transport> {
+    if !args.pin.is_empty() || !args.crl.is_empty() {
+        return Ok(Transport::Tls(TlsConfiguration::Rustls(pin::client_config(args)?)));
+    }
+    let [ca, cert, key] = sources;
+    Ok(Transport::Tls(TlsConfiguration::Simple {
+        ca: read_pem(ca)?,
+        alpn: None,
+        client_auth: Some((read_pem(cert)?, read_pem(key)?)),
+    }))
+}
+
+pub fn test_connection(args: &Args) -> Result<(), String> {
+    let options = mqtt_options(args, &format!("{}-test", args.device)).map_err(|e| e.to_string())?;
+    let (_client, mut connection) = Client::new(options, 10);
+    loop {
+        match connection.recv_timeout(Duration::from_secs(10)) {
+            Ok(Ok(Event::Incoming(Incoming::ConnAck(_)))) => return Ok(()),
+            Ok(Ok(_)) => {}
+            Ok(Err(e)) => return Err(e.to_string()),
+            Err(_) => return Err("timed out waiting for the broker".to_string()),
+        }
+    }
+}
+
+pub fn load_e2e(args: &Args, trust: &Arc<trust::Trust>) -> io::Result<Option<Arc<E2e>>> {
+    // Refused rather than ignored, which would sync in the clear.
+    if !cfg!(feature = "e2e") && (args.e2e_key.is_some() || args.device_keys) {
+        return Err(io::Error::other("--e2e-key and --device-keys need a build with the e2e feature"));
+    }
+    Ok(match &args.e2e_key {
+        Some(path) => Some(Arc::new(E2e::Shared(Mutex::new(Keyring::load(path)?)))),
+        None if args.device_keys => Some(Arc::new(E2e::Devices(trust.clone()))),
+        None => None,
+    })
+}
+
+// Signs and, with end-to-end encryption configured, seals an envelope the
+// way the engine does, for commands that publish on their own connection.
+pub fn wrap(args: &Args, data_dir: &Path, envelope: &Envelope) -> io::Result<Vec<u8>> {
+    let trust = Arc::new(trust::Trust::load(data_dir)?);
+    let mut envelope = envelope.clone();
+    envelope.version = args.envelope_version;
+    let payload = trust.sign(&Zeroizing::new(envelope.encode_as(args.envelope_encoding)));
+    Ok(match load_e2e(args, &trust)? {
+        Some(e2e) => e2e.seal(&Zeroizing::new(payload)),
+        None => payload,
+    })
+}
+
+// The daemon is the sync engine wired to the OS clipboard: local changes are
+// published and accepted messages from other devices are written back.
+pub fn run(args: Args, data_dir: &Path, triggers: &[Arc<trigger::Trigger>], rules: Vec<rules::Rule>) {
+    // The config file may be shared with builds that have more features.
+    let left_out = [
+        (args.http.is_some() && !cfg!(feature = "http-api"), "--http needs a build with the http-api feature"),
+        (args.control.is_some() && !cfg!(feature = "http-api"), "--control needs a build with the http-api feature"),
+        (args.inbox.is_some() && !cfg!(feature = "files"), "--inbox needs a build with the files feature"),
+    ];
+    if let Some((_, e)) = left_out.iter().find(|(set, _)| *set) {
+        eprintln!("Failed to start: {}", e);
+        std::process::exit(1);
+    }
+    if args.copy_shortcut && matches!(args.clipboard_backend, Backend::Virtual | Backend::Copyq) {
+        eprintln!("Failed to start: --copy-shortcut needs the watch or poll clipboard backend");
+        std::process::exit(1);
+    }
+    let _instance = instance::claim(data_dir, &args.user, &args.device).unwrap_or_else(|e| {
+        eprintln!("Failed to start: {}", e);
+        std::process::exit(1);
+    });
+    let sync = Arc::new(ClipboardSync::start(&args, data_dir).unwrap_or_else(|e| {
+        eprintln!("Failed to start: {}", e);
+        std::process::exit(1);
+    }));
+    // Subscribed before the HTTP API can hand out subscriptions of its own.
+    let events = sync.events();
+
+    if args.remote_desktop == remote_desktop::Policy::Pause {
+        remote_desktop::watch(sync.paused().clone(), Duration::from_secs(10));
+    }
+
+    #[cfg(feature = "history")]
+    if !args.no_history {
+        retention::enforce(data_dir, args.retention.clone());
+    }
+
+    let status = Arc::new(status::Status::new(data_dir));
+    memory::report(sync.memory().clone(), status.clone(), Duration::from_secs(5));
+    rules::watch(rules, &args.group, sync.clone(), status.clone());
+    let activity = Arc::new(clipboard::Activity::default());
+    let (target, shutdown_channel) = match args.clipboard_backend {
+        Backend::Virtual => (Target::Virtual(Arc::default(), sync.publisher()), clipboard::Shutdown::default()),
+        Backend::Copyq => {
+            let poll_interval = Duration::from_millis(args.poll_interval_ms);
+            (Target::Copyq(sync.publisher()), copyq::spawn(poll_interval, sync.publisher(), sync.paused().clone(), activity.clone(), sync.idle().clone(), status.clone()))
+        }
+        backend => {
+            let poll_interval = Duration::from_millis(args.poll_interval_ms);
+            // A poller only sees the change at its next poll.
+            let window = shortcut::WINDOW.max(poll_interval * 2);
+            let shortcut = args.copy_shortcut.then(|| shortcut::watch(window)).transpose().unwrap_or_else(|e| {
+                eprintln!("Failed to start: --copy-shortcut cannot see the keyboard: {}", e);
+                status.remove();
+                std::process::exit(1);
+            });
+            // Before the watcher, which would take the marker for a copy.
+            if let Err(e) = clipboard::self_test() {
+                eprintln!("Failed to start: the clipboard does not work: {}", e);
+                status.remove();
+                std::process::exit(1);
+            }
+            let ctx = ClipboardContext::new().unwrap_or_else(|e| {
+                eprintln!("Failed to start: the clipboard does not work: {}", e);
+                status.remove();
+                std::process::exit(1);
+            });
+            let ctx = Arc::new(Mutex::new(ctx));
+            let manager = clipboard::Manager::new(ctx.clone(), sync.paused().clone(), sync.publisher(), activity.clone(), !args.no_source, shortcut);
+            (Target::System(ctx, sync.publisher(), clipboard::marks(args.no_windows_history)), clipboard::spawn(backend, poll_interval, sync.idle().clone(), manager, status.clone()))
+        }
+    };
+
+    if args.startup == clipboard::Startup::Publish {
+        if let Some(content) = target.get() {
+            let _ = sync.publish(content);
+        }
+    }
+
+    let store = Arc::new(store::FileStore::new(data_dir));
+    let apply = clipboard::applier(target.clone(), args.settle_time, activity, args.defer_window, args.prefer_format);
+    let screen_lock = Arc::new(screen_lock::ScreenLock::new(args.screen_lock, apply.clone(), sync.publisher()));
+    screen_lock.clone().watch(sync.screen_locked().clone(), Duration::from_secs(2));
+    #[cfg(feature = "http-api")]
+    let standby = Arc::new(standby::Standby::new(args.standby, store.clone(), apply.clone()));
+    #[cfg(feature = "http-api")]
+    if args.standby {
+        status.set("standby", "on, run `cloudboard promote` to apply what is received");
+    }
+    #[cfg(feature = "http-api")]
+    let hold = Arc::new(pastejack::Hold::new(apply.clone(), sync.memory().clone()));
+    #[cfg(feature = "http-api")]
+    let holding = pastejack::Policy { suspicious: args.hold_suspicious, larger_than: args.hold_larger_than };
+    #[cfg(feature = "http-api")]
+    {
+        let api = http::Api { target: target.clone(), sync: sync.clone(), hold: hold.clone(), status: status.clone(), store: store.clone(), standby: standby.clone() };
+        if let Some(addr) = args.http {
+            if let Err(e) = http::serve(addr, api.clone()) {
+                eprintln!("Failed to start the HTTP API on {}: {}", addr, e);
+                status.remove();
+                std::process::exit(1);
+            }
+            status.set("http", &addr.to_string());
+        }
+        if let Some(addr) = args.control {
+            let served = control::server_config(&args).and_then(|config| http::serve_tls(addr, api, config));
+            if let Err(e) = served {
+                eprintln!("Failed to start the control API: {}", e);
+                status.remove();
+                std::process::exit(1);
+            }
+            status.set("control", &addr.to_string());
+        }
+    }
+    let link = trigger::Link::default();
+    let mut growth = growth::Growth::default();
+
+    for event in events {
+        trigger::fire(triggers, &link, &event);
+        match event {
+            #[cfg(feature = "files")]
+            SyncEvent::Received(envelope) if envelope.name.is_some() && args.inbox.is_some() => {
+                let inbox = args.inbox.as_deref().unwrap_or(Path::new("."));
+                trigger::received(triggers, &envelope);
+                match inbox::save(inbox, &envelope) {
+                    Ok(path) => {
+                        info!(target: RECEIVE, "saved a file from {} to {}", envelope.device.as_deref().unwrap_or("unknown"), path.display());
+                        status.set("inbox", &path.display().to_string());
+                        sync.publisher().applied(&envelope);
+                    }
+                    Err(e) => error!(target: RECEIVE, "Failed to save file to the inbox: {}", e),
+                }
+            }
+            SyncEvent::Received(mut envelope) => {
+                envelope.sensitive |= secrets::find(&envelope.content).is_some();
+                #[cfg(feature = "http-api")]
+                if standby.is_on() {
+                    let kept = standby.keep(&envelope);
+                    info!(target: RECEIVE, "in standby, keeping what {} sent", envelope.device.as_deref().unwrap_or("unknown"));
+                    status.set("standby", &format!("on, {} items kept", kept));
+                    sync.publisher().applied(&envelope);
+                    continue;
+                }
+                #[cfg(feature = "http-api")]
+                if let Some(reason) = holding.reason(&envelope) {
+                    warn!(target: RECEIVE, "holding what {} sent, as it {}; run `cloudboard held` to see it", envelope.device.as_deref().unwrap_or("unknown"), reason);
+                    sync.publisher().applied(&envelope);
+                    hold.hold(envelope, reason);
+                    continue;
+                }
+                trigger::received(triggers, &envelope);
+                if let Some(limit) = args.warn_larger_than.filter(|limit| envelope.content.len() > *limit) {
+                    let warning = format!("putting {} from {} on the clipboard, over the {} of --warn-larger-than", stats::format_bytes(envelope.content.len()), envelope.device.as_deref().unwrap_or("unknown"), stats::format_bytes(limit));
+                    warn!(target: RECEIVE, "{}", warning);
+                    status.set("warning", &warning);
+                }
+                if let Some(envelope) = screen_lock.received(envelope) {
+                    let _ = apply.send(envelope);
+                }
+            }
+            SyncEvent::Sent(envelope) => {
+                if let Some(unusual) = growth.copied(envelope.source.as_deref(), envelope.content.len(), stats::now()) {
+                    warn!(target: PUBLISH, "{}; if it is misbehaving, this rule in the config file keeps such copies from syncing:\n{}", unusual, unusual.rule());
+                    status.set("warning", &unusual.to_string());
+                    growth::keep(&*store, stats::now(), &unusual);
+                }
+            }
+            SyncEvent::PublishFailed(failure) => status.set("warning", &failure.to_string()),
+            SyncEvent::Offered(offer) => {
+                info!(target: RECEIVE, "{} offered {}, run `cloudboard fetch` to get it", offer.device, stats::format_bytes(offer.size));
+                status.set("offer", &offer.to_string());
+            }
+            _ => {}
+        }
+    }
+
+    shutdown_channel.stop();
+    status.remove();
+    info!("exit");
+}
@@ -0,0 +1,51 @@
+use std::collections::HashMap;
+use std::time::Instant;
+
+// Buckets start full, so a device can send a burst of `per_minute` messages
+// before it is held to the steady rate.
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+pub struct RateLimit {
+    per_minute: f64,
+    buckets: HashMap<String, Bucket>,
+}
+
+// Sender names are not authenticated until later on the receive path, so
+// this many names is the most a flood of made-up ones can make us remember.
+const MAX_BUCKETS: usize = 1024;
+
+impl RateLimit {
+    pub fn new(per_minute: u32) -> RateLimit {
+        RateLimit { per_minute: per_minute as f64, buckets: HashMap::new() }
+    }
+
+    // Returns false once `device` has used up its allowance.
+    pub fn allow(&mut self, device: &str) -> bool {
+        let now = Instant::now();
+        let per_minute = self.per_minute;
+        if self.buckets.len() >= MAX_BUCKETS && !self.buckets.contains_key(device) {
+            // Buckets that have refilled carry no state worth keeping.
+            self.buckets.retain(|_, bucket| refill(bucket, now, per_minute) < per_minute);
+            if self.buckets.len() >= MAX_BUCKETS {
+                return false;
+            }
+        }
+
+        let bucket = self.buckets.entry(device.to_string()).or_insert(Bucket { tokens: per_minute, updated: now });
+        if refill(bucket, now, per_minute) < 1.0 {
+            return false;
+        }
+        bucket.tokens -= 1.0;
+        true
+    }
+}
+
+fn refill(bucket: &mut Bucket, now: Instant, per_minute: f64) -> f64 {
+    let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+    bucket.tokens = (bucket.tokens + elapsed * per_minute / 60.0).min(per_minute);
+    bucket.updated = now;
+    bucket.tokens
+}
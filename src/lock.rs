@@ -0,0 +1,12 @@
+use std::sync::{Mutex, MutexGuard};
+use log::warn;
+
+// A thread that panicked while holding a lock must not take every later
+// clipboard read or write down with it, so recover the guard instead.
+pub fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| {
+        warn!("recovering lock poisoned by a panicked thread");
+        mutex.clear_poison();
+        e.into_inner()
+    })
+}
@@ -0,0 +1,63 @@
+use std::ops::{Deref, DerefMut};
+use log::debug;
+use zeroize::Zeroize;
+
+// A 32-byte key on the heap, where it stays put as whatever holds it moves,
+// so there is one copy to wipe when it is dropped. Its pages are locked
+// against being swapped out where mlock is available. They are left locked
+// afterwards, as other keys may share them.
+pub struct SecretKey(Box<[u8; 32]>);
+
+impl SecretKey {
+    // Filled in place, so the key is never copied through the stack.
+    pub fn with(fill: impl FnOnce(&mut [u8; 32])) -> SecretKey {
+        let mut key = SecretKey(Box::new([0; 32]));
+        lock(&key.0[..]);
+        fill(&mut key.0);
+        key
+    }
+
+    pub fn from_slice(bytes: &[u8]) -> Option<SecretKey> {
+        let bytes: &[u8; 32] = bytes.try_into().ok()?;
+        Some(SecretKey::with(|key| key.copy_from_slice(bytes)))
+    }
+}
+
+impl Clone for SecretKey {
+    fn clone(&self) -> SecretKey {
+        SecretKey::with(|key| key.copy_from_slice(&self.0[..]))
+    }
+}
+
+impl Deref for SecretKey {
+    type Target = [u8; 32];
+
+    fn deref(&self) -> &[u8; 32] {
+        &self.0
+    }
+}
+
+impl DerefMut for SecretKey {
+    fn deref_mut(&mut self) -> &mut [u8; 32] {
+        &mut self.0
+    }
+}
+
+impl Drop for SecretKey {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+#[cfg(unix)]
+fn lock(bytes: &[u8]) {
+    // SAFETY: mlock only changes how the pages behind a live allocation are
+    // paged, and is handed its exact address and length.
+    let locked = unsafe { libc::mlock(bytes.as_ptr().cast(), bytes.len()) } == 0;
+    if !locked {
+        debug!("Failed to lock key memory: {}", std::io::Error::last_os_error());
+    }
+}
+
+#[cfg(not(unix))]
+fn lock(_bytes: &[u8]) {}
@@ -0,0 +1,31 @@
+use std::fmt;
+
+pub const CONNECT: &str = "cloudboard::connect";
+pub const PUBLISH: &str = "cloudboard::publish";
+pub const RECEIVE: &str = "cloudboard::receive";
+
+// `filters` uses the RUST_LOG syntax, e.g. `info,cloudboard::receive=debug`,
+// and takes precedence over the environment.
+pub fn init(filters: Option<&str>) {
+    let mut builder = env_logger::Builder::from_default_env();
+    if let Some(filters) = filters {
+        builder.parse_filters(filters);
+    }
+    builder.init();
+}
+
+// Clipboard content must never reach the log at any level; format it
+// through this wrapper, which only ever shows the size.
+pub struct Redacted<'a>(pub &'a [u8]);
+
+impl fmt::Display for Redacted<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<{} bytes redacted>", self.0.len())
+    }
+}
+
+impl fmt::Debug for Redacted<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
@@ -0,0 +1,61 @@
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use crate::lock::lock;
+use crate::stats::format_bytes;
+use crate::status::Status;
+
+// Content held in memory, by what holds it, against one budget for the
+// whole engine; see --memory-limit. A holder that queues content drops its
+// own oldest when the budget is used up, which newer content supersedes
+// anyway, and takes the newest regardless so nothing is lost outright.
+// Everything else in memory is small or bounded in count.
+pub struct Budget {
+    limit: usize,
+    used: Mutex<BTreeMap<&'static str, usize>>,
+}
+
+impl Budget {
+    pub fn new(limit: usize) -> Budget {
+        Budget { limit, used: Mutex::new(BTreeMap::new()) }
+    }
+
+    // Whether `bytes` more would go past the limit.
+    pub fn is_over(&self, bytes: usize) -> bool {
+        self.total().saturating_add(bytes) > self.limit
+    }
+
+    pub fn add(&self, holder: &'static str, bytes: usize) {
+        *lock(&self.used).entry(holder).or_default() += bytes;
+    }
+
+    pub fn remove(&self, holder: &'static str, bytes: usize) {
+        if let Some(used) = lock(&self.used).get_mut(holder) {
+            *used = used.saturating_sub(bytes);
+        }
+    }
+
+    pub fn total(&self) -> usize {
+        lock(&self.used).values().sum()
+    }
+
+    // E.g. "1.2 MiB of 64.0 MiB (events 1.0 MiB, offers 200.0 KiB)".
+    pub fn text(&self) -> String {
+        let used = lock(&self.used);
+        let holders: Vec<String> = used.iter().filter(|(_, bytes)| **bytes > 0).map(|(holder, bytes)| format!("{} {}", holder, format_bytes(*bytes))).collect();
+        let total = format_bytes(used.values().sum());
+        if holders.is_empty() {
+            format!("{} of {}", total, format_bytes(self.limit))
+        } else {
+            format!("{} of {} ({})", total, format_bytes(self.limit), holders.join(", "))
+        }
+    }
+}
+
+// Keeps the memory line of `cloudboard status` current.
+pub fn report(budget: Arc<Budget>, status: Arc<Status>, interval: Duration) {
+    std::thread::spawn(move || loop {
+        status.set("memory", &budget.text());
+        std::thread::sleep(interval);
+    });
+}
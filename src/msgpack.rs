@@ -0,0 +1,138 @@
+// The part of MessagePack the binary envelope needs: maps with string keys,
+// and strings, unsigned integers and flat arrays of those as values.
+pub enum Value {
+    Uint(u64),
+    Str(String),
+    Array(Vec<Value>),
+}
+
+pub fn write_map_len(out: &mut Vec<u8>, len: usize) {
+    match len {
+        0..=15 => out.push(0x80 | len as u8),
+        _ => {
+            out.push(0xde);
+            out.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+    }
+}
+
+pub fn write(out: &mut Vec<u8>, value: &Value) {
+    match value {
+        Value::Uint(n) => write_uint(out, *n),
+        Value::Str(s) => write_str(out, s),
+        Value::Array(items) => {
+            match items.len() {
+                len @ 0..=15 => out.push(0x90 | len as u8),
+                len => {
+                    out.push(0xdc);
+                    out.extend_from_slice(&(len as u16).to_be_bytes());
+                }
+            }
+            for item in items {
+                write(out, item);
+            }
+        }
+    }
+}
+
+pub fn write_str(out: &mut Vec<u8>, s: &str) {
+    match s.len() {
+        len @ 0..=31 => out.push(0xa0 | len as u8),
+        len @ 32..=0xff => out.extend_from_slice(&[0xd9, len as u8]),
+        len @ 0x100..=0xffff => {
+            out.push(0xda);
+            out.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        len => {
+            out.push(0xdb);
+            out.extend_from_slice(&(len as u32).to_be_bytes());
+        }
+    }
+    out.extend_from_slice(s.as_bytes());
+}
+
+fn write_uint(out: &mut Vec<u8>, n: u64) {
+    match n {
+        0..=0x7f => out.push(n as u8),
+        0x80..=0xff => out.extend_from_slice(&[0xcc, n as u8]),
+        0x100..=0xffff => {
+            out.push(0xcd);
+            out.extend_from_slice(&(n as u16).to_be_bytes());
+        }
+        0x1_0000..=0xffff_ffff => {
+            out.push(0xce);
+            out.extend_from_slice(&(n as u32).to_be_bytes());
+        }
+        _ => {
+            out.push(0xcf);
+            out.extend_from_slice(&n.to_be_bytes());
+        }
+    }
+}
+
+// Reads a map that makes up all of `data`.
+pub fn read_map(data: &[u8]) -> Option<Vec<(String, Value)>> {
+    let mut reader = Reader { data };
+    let len = match reader.byte()? {
+        b @ 0x80..=0x8f => (b & 0x0f) as usize,
+        0xde => reader.uint(2)? as usize,
+        _ => return None,
+    };
+    let mut entries = Vec::with_capacity(len);
+    for _ in 0..len {
+        let Value::Str(key) = reader.value(false)? else {
+            return None;
+        };
+        entries.push((key, reader.value(true)?));
+    }
+    reader.data.is_empty().then_some(entries)
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        if self.data.len() < len {
+            return None;
+        }
+        let (taken, rest) = self.data.split_at(len);
+        self.data = rest;
+        Some(taken)
+    }
+
+    fn byte(&mut self) -> Option<u8> {
+        Some(self.take(1)?[0])
+    }
+
+    fn uint(&mut self, len: usize) -> Option<u64> {
+        Some(self.take(len)?.iter().fold(0, |n, b| n << 8 | *b as u64))
+    }
+
+    fn value(&mut self, array_allowed: bool) -> Option<Value> {
+        let (str_len, array_len) = match self.byte()? {
+            b @ 0x00..=0x7f => return Some(Value::Uint(b as u64)),
+            0xcc => return self.uint(1).map(Value::Uint),
+            0xcd => return self.uint(2).map(Value::Uint),
+            0xce => return self.uint(4).map(Value::Uint),
+            0xcf => return self.uint(8).map(Value::Uint),
+            b @ 0xa0..=0xbf => (Some((b & 0x1f) as usize), None),
+            0xd9 => (Some(self.uint(1)? as usize), None),
+            0xda => (Some(self.uint(2)? as usize), None),
+            0xdb => (Some(self.uint(4)? as usize), None),
+            b @ 0x90..=0x9f if array_allowed => (None, Some((b & 0x0f) as usize)),
+            0xdc if array_allowed => (None, Some(self.uint(2)? as usize)),
+            _ => return None,
+        };
+        if let Some(len) = str_len {
+            return String::from_utf8(self.take(len)?.to_vec()).ok().map(Value::Str);
+        }
+        // Arrays do not nest, so hostile input cannot recurse deeply.
+        let len = array_len?;
+        if len > self.data.len() {
+            return None;
+        }
+        (0..len).map(|_| self.value(false)).collect::<Option<Vec<_>>>().map(Value::Array)
+    }
+}
@@ -0,0 +1,145 @@
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use log::error;
+use crate::lock::lock;
+use crate::http;
+use crate::json::{parse_object, quote};
+
+// Browsers refuse messages from the host larger than this, and never send
+// more than MAX_REQUEST to it.
+const MAX_MESSAGE: usize = 1024 * 1024;
+const MAX_REQUEST: usize = 64 * 1024 * 1024;
+const HISTORY_LEN: usize = 20;
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+// Browsers start the host with their own arguments instead of a subcommand:
+// Chrome passes the extension's origin, Firefox the manifest path and the
+// extension ID.
+pub fn is_browser_launch(argv: &[String]) -> bool {
+    match argv {
+        [_, origin] => origin.starts_with("chrome-extension://"),
+        [_, manifest, _] => manifest.ends_with(".json"),
+        _ => false,
+    }
+}
+
+// The host never talks to the broker itself: it goes through the HTTP API of
+// the daemon already running for this data dir, so the browser shares that
+// daemon's device identity and connection.
+struct Host {
+    data_dir: PathBuf,
+    stdout: Mutex<io::Stdout>,
+    // Newest first, for this browser session only.
+    history: Mutex<VecDeque<String>>,
+}
+
+pub fn run(data_dir: &Path) {
+    let host = Arc::new(Host {
+        data_dir: data_dir.to_path_buf(),
+        stdout: Mutex::new(io::stdout()),
+        history: Mutex::new(VecDeque::new()),
+    });
+
+    let watcher = host.clone();
+    std::thread::spawn(move || watcher.watch());
+
+    let mut stdin = io::stdin().lock();
+    loop {
+        let mut len = [0; 4];
+        if stdin.read_exact(&mut len).is_err() {
+            // The browser closes stdin when the extension disconnects.
+            break;
+        }
+        let len = u32::from_ne_bytes(len) as usize;
+        if len > MAX_REQUEST {
+            error!("Failed to read native message: {} bytes is too long", len);
+            break;
+        }
+        let mut message = vec![0; len];
+        if let Err(e) = stdin.read_exact(&mut message) {
+            error!("Failed to read native message: {}", e);
+            break;
+        }
+        let reply = match parse_object(&String::from_utf8_lossy(&message)) {
+            Some(request) => host.handle(&request),
+            None => error_reply("the message is not a JSON object of strings"),
+        };
+        host.send(&reply);
+    }
+}
+
+impl Host {
+    fn handle(&self, request: &HashMap<String, String>) -> String {
+        match (request.get("type").map(String::as_str), request.get("content")) {
+            (Some("get"), _) => match self.get() {
+                Ok(content) => {
+                    let content = content.map_or("null".to_string(), |content| quote(&content));
+                    format!("{{\"type\":\"clipboard\",\"content\":{content}}}")
+                }
+                Err(e) => error_reply(&e.to_string()),
+            },
+            (Some("set"), Some(content)) => match self.http("PUT", content) {
+                Ok(_) => "{\"type\":\"ok\"}".to_string(),
+                Err(e) => error_reply(&e.to_string()),
+            },
+            (Some("history"), _) => {
+                let items: Vec<String> = lock(&self.history).iter().map(|item| quote(item)).collect();
+                format!("{{\"type\":\"history\",\"items\":[{}]}}", items.join(","))
+            }
+            _ => error_reply("unknown request"),
+        }
+    }
+
+    // Pushes every change of the synced clipboard to the extension, whether
+    // it came from another device, this machine or the extension itself.
+    fn watch(&self) {
+        let mut last = None;
+        loop {
+            if let Ok(Some(content)) = self.get() {
+                if last.as_ref() != Some(&content) {
+                    {
+                        let mut history = lock(&self.history);
+                        history.retain(|item| item != &content);
+                        history.push_front(content.clone());
+                        history.truncate(HISTORY_LEN);
+                    }
+                    self.send(&format!("{{\"type\":\"changed\",\"content\":{}}}", quote(&content)));
+                    last = Some(content);
+                }
+            }
+            std::thread::sleep(POLL_INTERVAL);
+        }
+    }
+
+    fn get(&self) -> io::Result<Option<String>> {
+        let (status, body) = self.http("GET", "")?;
+        Ok((status == 200).then_some(body))
+    }
+
+    fn http(&self, method: &str, body: &str) -> io::Result<(u16, String)> {
+        http::request(&self.data_dir, method, "/clipboard", body)
+    }
+
+    fn send(&self, message: &str) {
+        let message = if message.len() > MAX_MESSAGE {
+            error_reply("the clipboard content is too large for native messaging")
+        } else {
+            message.to_string()
+        };
+        let mut stdout = lock(&self.stdout);
+        let result = stdout
+            .write_all(&(message.len() as u32).to_ne_bytes())
+            .and_then(|_| stdout.write_all(message.as_bytes()))
+            .and_then(|_| stdout.flush());
+        if let Err(e) = result {
+            error!("Failed to write native message: {}", e);
+        }
+    }
+}
+
+fn error_reply(message: &str) -> String {
+    format!("{{\"type\":\"error\",\"message\":{}}}", quote(message))
+}
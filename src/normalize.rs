@@ -0,0 +1,66 @@
+use std::borrow::Cow;
+use std::sync::OnceLock;
+use clap::ValueEnum;
+use regex::Regex;
+use crate::sanitize::{sanitize, Strip};
+
+// What copied text is tidied up with before it is published, for the
+// whitespace and invisible formatting that copying from terminals, PDFs and
+// web pages drags along. The steps run in the order listed here, whatever
+// the order they are given in.
+#[derive(ValueEnum, Clone, Copy, PartialEq, Debug)]
+pub enum Normalize {
+    // ANSI escapes, zero-width characters and soft hyphens taken out, and
+    // no-break spaces made plain ones.
+    Plain,
+    // Runs of spaces and tabs inside a line made one space, and runs of
+    // blank lines one blank line. Indentation is left alone.
+    Collapse,
+    // Spaces and tabs at the end of each line, and line breaks at the end.
+    TrimTrailing,
+    // Whitespace at the start and the end.
+    Trim,
+}
+
+const INVISIBLE: &[char] = &['\u{ad}', '\u{200b}', '\u{200c}', '\u{200d}', '\u{2060}', '\u{feff}'];
+const NO_BREAK: &[char] = &['\u{a0}', '\u{202f}'];
+
+fn regex(cell: &'static OnceLock<Regex>, pattern: &str) -> &'static Regex {
+    cell.get_or_init(|| Regex::new(pattern).unwrap())
+}
+
+fn replace(text: &mut Cow<'_, str>, regex: &Regex, with: &str) {
+    if let Cow::Owned(replaced) = regex.replace_all(text, with) {
+        *text = Cow::Owned(replaced);
+    }
+}
+
+pub fn normalize<'a>(text: &'a str, steps: &[Normalize]) -> Cow<'a, str> {
+    static SPACES: OnceLock<Regex> = OnceLock::new();
+    static BLANK_LINES: OnceLock<Regex> = OnceLock::new();
+    static TRAILING: OnceLock<Regex> = OnceLock::new();
+    let mut text = Cow::Borrowed(text);
+    if steps.contains(&Normalize::Plain) {
+        if let Cow::Owned(stripped) = sanitize(&text, &[Strip::Escapes]) {
+            text = Cow::Owned(stripped);
+        }
+        if text.contains(INVISIBLE) || text.contains(NO_BREAK) {
+            text = Cow::Owned(text.chars().filter(|c| !INVISIBLE.contains(c)).map(|c| if NO_BREAK.contains(&c) { ' ' } else { c }).collect());
+        }
+    }
+    if steps.contains(&Normalize::Collapse) {
+        replace(&mut text, regex(&SPACES, r"([^ \t\r\n])[ \t]{2,}"), "$1 ");
+        replace(&mut text, regex(&BLANK_LINES, r"(\r?\n)([ \t]*\r?\n){2,}"), "$1$1");
+    }
+    if steps.contains(&Normalize::TrimTrailing) {
+        replace(&mut text, regex(&TRAILING, r"(?m)[ \t]+(\r?)$"), "$1");
+        let kept = text.trim_end_matches(['\r', '\n']).len();
+        if kept < text.len() {
+            text.to_mut().truncate(kept);
+        }
+    }
+    if steps.contains(&Normalize::Trim) && text.trim().len() < text.len() {
+        text = Cow::Owned(text.trim().to_string());
+    }
+    text
+}
@@ -0,0 +1,107 @@
+use std::sync::{mpsc, Arc, Mutex};
+use crate::envelope::Envelope;
+use crate::lock::lock;
+use crate::memory::Budget;
+use crate::stats::format_bytes;
+
+// Programs that run what follows with more privileges.
+const ELEVATE: &[&str] = &["sudo", "doas", "su", "pkexec"];
+// Commands are short; a long text with a stray zero-width space is more
+// likely prose from a web page.
+const MAX_COMMAND_LINES: usize = 20;
+
+// Why text that looks like a shell command should not go straight to the
+// clipboard, for --hold-suspicious: characters that do not show but reach
+// the shell, which make a command run something other than what it reads
+// as, or a line that elevates and ends in a newline, which the shell runs
+// as soon as it is pasted.
+pub fn suspicious(text: &str) -> Option<&'static str> {
+    let lines: Vec<&str> = text.lines().filter(|line| !line.trim().is_empty()).collect();
+    if lines.is_empty() || lines.len() > MAX_COMMAND_LINES || !lines.iter().any(|line| is_command(line)) {
+        return None;
+    }
+    if text.chars().any(is_hidden) {
+        return Some("has characters that do not show");
+    }
+    let elevates = lines.iter().any(|line| command_words(line).any(|word| ELEVATE.contains(&word)));
+    if elevates && text.ends_with('\n') {
+        return Some("runs as root as soon as it is pasted");
+    }
+    None
+}
+
+fn is_hidden(c: char) -> bool {
+    matches!(c, '\u{ad}' | '\u{200b}'..='\u{200f}' | '\u{2060}'..='\u{2064}' | '\u{feff}' | '\u{202a}'..='\u{202e}' | '\u{2066}'..='\u{2069}')
+        || (c.is_control() && !matches!(c, '\t' | '\n' | '\r'))
+}
+
+// Leaving out a copied prompt, a line starts with a word a shell would run.
+fn is_command(line: &str) -> bool {
+    let line = line.trim_start().trim_start_matches(['$', '#', '>']).trim_start();
+    let word = line.split_whitespace().next().unwrap_or_default();
+    !word.is_empty() && word.chars().all(|c| c.is_ascii_alphanumeric() || "_-./~".contains(c))
+}
+
+// The first word of each command on a line, so `cd /tmp && sudo rm` counts.
+fn command_words(line: &str) -> impl Iterator<Item = &str> {
+    line.split(['&', '|', ';'])
+        .filter_map(|command| command.trim().trim_start_matches(['$', '#']).split_whitespace().next())
+}
+
+// Every reason a received item is held back instead of pasted. Files are
+// saved rather than pasted, so none of them hold a file.
+pub struct Policy {
+    pub suspicious: bool,
+    pub larger_than: Option<usize>,
+}
+
+impl Policy {
+    pub fn reason(&self, envelope: &Envelope) -> Option<String> {
+        if envelope.name.is_some() {
+            return None;
+        }
+        if let Some(limit) = self.larger_than.filter(|limit| envelope.content.len() > *limit) {
+            return Some(format!("is {}, over the {} --hold-larger-than lets through", format_bytes(envelope.content.len()), format_bytes(limit)));
+        }
+        suspicious(&envelope.content).filter(|_| self.suspicious).map(str::to_string)
+    }
+}
+
+// The item held back last, with why, until it is applied or discarded
+// through the HTTP API. A newer item held back takes its place.
+pub struct Hold {
+    item: Mutex<Option<(Envelope, String)>>,
+    apply: mpsc::Sender<Envelope>,
+    budget: Arc<Budget>,
+}
+
+impl Hold {
+    pub fn new(apply: mpsc::Sender<Envelope>, budget: Arc<Budget>) -> Hold {
+        Hold { item: Mutex::new(None), apply, budget }
+    }
+
+    pub fn hold(&self, envelope: Envelope, reason: String) {
+        self.budget.add("held", envelope.content.len());
+        if let Some((replaced, _)) = lock(&self.item).replace((envelope, reason)) {
+            self.budget.remove("held", replaced.content.len());
+        }
+    }
+
+    pub fn peek(&self) -> Option<(Envelope, String)> {
+        lock(&self.item).clone()
+    }
+
+    pub fn release(&self) -> bool {
+        self.take().is_some_and(|envelope| self.apply.send(envelope).is_ok())
+    }
+
+    pub fn discard(&self) -> bool {
+        self.take().is_some()
+    }
+
+    fn take(&self) -> Option<Envelope> {
+        let (envelope, _) = lock(&self.item).take()?;
+        self.budget.remove("held", envelope.content.len());
+        Some(envelope)
+    }
+}
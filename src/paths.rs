@@ -0,0 +1,19 @@
+use std::path::PathBuf;
+
+pub fn data_dir() -> PathBuf {
+    if let Some(dir) = std::env::var_os("XDG_DATA_HOME") {
+        return PathBuf::from(dir).join("cloudboard");
+    }
+    if cfg!(windows) {
+        if let Some(dir) = std::env::var_os("APPDATA") {
+            return PathBuf::from(dir).join("cloudboard");
+        }
+    }
+
+    let home = std::env::var_os("HOME").map(PathBuf::from).unwrap_or_default();
+    if cfg!(target_os = "macos") {
+        home.join("Library/Application Support/cloudboard")
+    } else {
+        home.join(".local/share/cloudboard")
+    }
+}
@@ -0,0 +1,206 @@
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+use clap::Subcommand;
+use ring::digest::{digest, SHA256};
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::{ClientConfig, ClientConnection, DigitallySignedStruct, SignatureScheme};
+use crate::config::{Config, Value};
+use crate::crl::Crls;
+use crate::crypto::{from_hex, to_hex};
+use crate::doctor;
+use crate::Args;
+
+// A hash the broker's certificate has to match on top of being signed by
+// the CA, so one the CA was tricked or forced into issuing is still refused.
+// An SPKI pin outlasts renewals that keep the key, a cert pin does not.
+#[derive(Clone, PartialEq, Debug)]
+pub enum Pin {
+    Spki([u8; 32]),
+    Cert([u8; 32]),
+}
+
+impl Pin {
+    pub fn spki(cert: &CertificateDer<'_>) -> Option<Pin> {
+        let spki = doctor::subject_public_key_info(cert.as_ref())?;
+        Some(Pin::Spki(sha256(spki)))
+    }
+
+    pub fn cert(cert: &CertificateDer<'_>) -> Pin {
+        Pin::Cert(sha256(cert.as_ref()))
+    }
+
+    fn matches(&self, cert: &CertificateDer<'_>) -> bool {
+        match self {
+            Pin::Spki(_) => Pin::spki(cert).as_ref() == Some(self),
+            Pin::Cert(_) => Pin::cert(cert) == *self,
+        }
+    }
+}
+
+fn sha256(bytes: &[u8]) -> [u8; 32] {
+    digest(&SHA256, bytes).as_ref().try_into().unwrap()
+}
+
+impl FromStr for Pin {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Pin, String> {
+        let (kind, hash) = s.split_once(':').ok_or("expected spki:<sha256> or cert:<sha256>")?;
+        let hash: [u8; 32] = from_hex(hash).and_then(|hash| hash.try_into().ok()).ok_or("expected a SHA-256 hash in hex")?;
+        match kind {
+            "spki" => Ok(Pin::Spki(hash)),
+            "cert" => Ok(Pin::Cert(hash)),
+            _ => Err(format!("unknown pin type {kind}, expected spki or cert")),
+        }
+    }
+}
+
+impl fmt::Display for Pin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Pin::Spki(hash) => write!(f, "spki:{}", to_hex(hash)),
+            Pin::Cert(hash) => write!(f, "cert:{}", to_hex(hash)),
+        }
+    }
+}
+
+// Checked once the CA's verifier has passed the certificate, so a pin
+// never lets in what the CA would not.
+#[derive(Debug)]
+struct Pinned {
+    pins: Vec<Pin>,
+    verifier: Arc<dyn ServerCertVerifier>,
+}
+
+impl ServerCertVerifier for Pinned {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let verified = self.verifier.verify_server_cert(end_entity, intermediates, server_name, ocsp_response, now)?;
+        if self.pins.iter().any(|pin| pin.matches(end_entity)) {
+            return Ok(verified);
+        }
+        let presented = Pin::spki(end_entity).unwrap_or_else(|| Pin::cert(end_entity));
+        Err(rustls::Error::General(format!(
+            "the broker's certificate matches no --pin, its key is {presented}; run `cloudboard pin update` if the broker changed it"
+        )))
+    }
+
+    fn verify_tls12_signature(&self, message: &[u8], cert: &CertificateDer<'_>, dss: &DigitallySignedStruct) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.verifier.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(&self, message: &[u8], cert: &CertificateDer<'_>, dss: &DigitallySignedStruct) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.verifier.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.verifier.supported_verify_schemes()
+    }
+}
+
+// The CA's verifier, with the pins checked after it when there are any.
+pub fn verifier(verifier: Arc<dyn ServerCertVerifier>, pins: &[Pin]) -> Arc<dyn ServerCertVerifier> {
+    if pins.is_empty() {
+        verifier
+    } else {
+        Arc::new(Pinned { pins: pins.to_vec(), verifier })
+    }
+}
+
+fn tls_config(args: &Args, pins: &[Pin]) -> Result<ClientConfig, String> {
+    let files = doctor::load_files(&crate::cert_sources(args).map_err(|e| e.to_string())?)?;
+    let ca = Crls::load(args)?.server_verifier(files.roots()?)?;
+    ClientConfig::builder()
+        .dangerous()
+        .with_custom_certificate_verifier(verifier(ca, pins))
+        .with_client_auth_cert(files.chain, files.key)
+        .map_err(|e| e.to_string())
+}
+
+// What the daemon connects with when --pin or --crl is set and --via is not.
+pub fn client_config(args: &Args) -> std::io::Result<Arc<ClientConfig>> {
+    tls_config(args, &args.pin).map(Arc::new).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+}
+
+#[derive(Subcommand, Debug)]
+pub enum PinCommand {
+    /// Show the hashes of the certificate the broker presents, and whether --pin matches it
+    Show {
+        #[command(flatten)]
+        sync: Box<Args>,
+    },
+    /// Pin the key the broker presents now, once the CA vouches for it
+    Update {
+        #[command(flatten)]
+        sync: Box<Args>,
+    },
+}
+
+pub fn command(config_path: &Path, command: PinCommand) {
+    let result = match command {
+        PinCommand::Show { sync } => show(&sync),
+        PinCommand::Update { sync } => update(config_path, &sync),
+    };
+    if let Err(e) = result {
+        eprintln!("Failed to check the broker's certificate: {}", e);
+        std::process::exit(1);
+    }
+}
+
+fn show(args: &Args) -> Result<(), String> {
+    let cert = presented(args)?;
+    let spki = Pin::spki(&cert).ok_or("the certificate could not be parsed")?;
+    println!("{spki}");
+    println!("{}", Pin::cert(&cert));
+    if args.pin.is_empty() {
+        println!("nothing is pinned");
+    } else if args.pin.iter().any(|pin| pin.matches(&cert)) {
+        println!("it matches --pin");
+    } else {
+        println!("it matches no --pin, so the daemon refuses the broker");
+    }
+    Ok(())
+}
+
+// Replaces the pins in the config file. The CA is all that vouches for
+// the key here, so it is worth comparing with the broker's own.
+fn update(config_path: &Path, args: &Args) -> Result<(), String> {
+    let cert = presented(args)?;
+    let spki = Pin::spki(&cert).ok_or("the certificate could not be parsed")?;
+    if args.pin == [spki.clone()] {
+        println!("the broker's key {spki} is already pinned");
+        return Ok(());
+    }
+    let mut config = Config::load(config_path).map_err(|e| e.to_string())?;
+    config.set("pin", Value::Array(vec![spki.to_string()]));
+    config.save(config_path).map_err(|e| format!("{}: {}", config_path.display(), e))?;
+    for pin in &args.pin {
+        println!("unpinned {pin}");
+    }
+    println!("pinned {} in {}", spki, config_path.display());
+    println!("compare it with the broker's: openssl x509 -in <broker cert> -pubkey -noout | openssl pkey -pubin -outform der | openssl dgst -sha256");
+    Ok(())
+}
+
+// The broker's certificate, as long as the CA signed it, whatever it is
+// pinned to now.
+fn presented(args: &Args) -> Result<CertificateDer<'static>, String> {
+    let config = tls_config(args, &[])?;
+    let server_name = ServerName::try_from(args.server.clone()).map_err(|e| e.to_string())?;
+    let mut tls = ClientConnection::new(Arc::new(config), server_name).map_err(|e| e.to_string())?;
+    let mut socket = doctor::connect(args)?;
+    while tls.is_handshaking() {
+        tls.complete_io(&mut socket).map_err(|e| e.to_string())?;
+    }
+    let cert = tls.peer_certificates().and_then(|certs| certs.first()).ok_or("the broker presented no certificate")?;
+    Ok(cert.clone().into_owned())
+}
@@ -0,0 +1,239 @@
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use clap::Subcommand;
+use log::{error, info, warn};
+use rumqttc::v5::mqttbytes::QoS;
+use rumqttc::v5::{Client, Event, Incoming};
+use crate::cli::or_exit;
+use crate::config::{Config, Value};
+use crate::devices;
+use crate::envelope::{self, Envelope};
+use crate::hlc::{Clock, Timestamp};
+use crate::lock::lock;
+use crate::logging::{CONNECT, RECEIVE};
+use crate::store::{FileStore, Store};
+use crate::Args;
+
+const KEY: &str = "policy";
+// When the primary published the settings in force.
+const STAMP_KEY: &str = "policy.hlc";
+// What the personal clipboard is called in `paused`, next to group names.
+pub const PERSONAL: &str = "personal";
+
+// Settings the --primary device publishes for the others, in the config
+// file's format. They only ever tighten what each device has set itself:
+// the lower of the two size limits, and filters and pauses from either.
+#[derive(Default, Clone, Debug, PartialEq)]
+pub struct Settings {
+    max_size: Option<usize>,
+    text_only: bool,
+    filter_secrets: bool,
+    paused: Vec<String>,
+}
+
+impl Settings {
+    // Keys this release does not know are returned apart, so a primary on a
+    // newer one does not cut off the devices that are not yet.
+    pub fn parse(text: &str) -> Result<(Settings, Vec<String>), String> {
+        let config = Config::parse(text)?;
+        let mut settings = Settings::default();
+        let mut unknown = Vec::new();
+        for key in config.keys() {
+            match (key, config.get(key)) {
+                ("max_size", Some(Value::Integer(size))) if *size > 0 => settings.max_size = Some(*size as usize),
+                ("text_only", Some(Value::Bool(set))) => settings.text_only = *set,
+                ("filter_secrets", Some(Value::Bool(set))) => settings.filter_secrets = *set,
+                ("paused", Some(Value::Array(channels))) => settings.paused = channels.clone(),
+                ("max_size" | "text_only" | "filter_secrets" | "paused", _) => return Err(format!("invalid value for {key}")),
+                _ => unknown.push(key.to_string()),
+            }
+        }
+        Ok((settings, unknown))
+    }
+
+    fn summary(&self) -> String {
+        let mut parts = Vec::new();
+        if let Some(size) = self.max_size {
+            parts.push(format!("max_size {size}"));
+        }
+        if self.text_only {
+            parts.push("text_only".to_string());
+        }
+        if self.filter_secrets {
+            parts.push("filter_secrets".to_string());
+        }
+        if !self.paused.is_empty() {
+            parts.push(format!("paused {}", self.paused.join(", ")));
+        }
+        if parts.is_empty() { "none".to_string() } else { parts.join("; ") }
+    }
+}
+
+// The settings in force, shared by the sender and the receiver, and kept
+// in the store so they hold from startup, before the broker is reached.
+// They go with the time they were published, so older ones that were
+// signed just the same, such as an empty one from `policy clear`, cannot
+// be replayed to undo them.
+pub struct Policy {
+    primary: Option<String>,
+    store: Arc<dyn Store>,
+    settings: Mutex<(Settings, Option<Timestamp>)>,
+}
+
+impl Policy {
+    pub fn load(primary: Option<String>, store: Arc<dyn Store>) -> Policy {
+        let settings = primary.as_ref()
+            .and_then(|_| store.get(KEY).ok().flatten())
+            .and_then(|text| Settings::parse(&String::from_utf8_lossy(&text)).ok())
+            .map(|(settings, _)| settings)
+            .unwrap_or_default();
+        let stamp = primary.as_ref()
+            .and_then(|_| store.get(STAMP_KEY).ok().flatten())
+            .and_then(|text| String::from_utf8_lossy(&text).trim().parse().ok());
+        Policy { primary, store, settings: Mutex::new((settings, stamp)) }
+    }
+
+    pub fn max_size(&self, own: usize) -> usize {
+        lock(&self.settings).0.max_size.map_or(own, |size| size.min(own))
+    }
+
+    pub fn text_only(&self, own: bool) -> bool {
+        own || lock(&self.settings).0.text_only
+    }
+
+    pub fn filter_secrets(&self, own: bool) -> bool {
+        own || lock(&self.settings).0.filter_secrets
+    }
+
+    // `group` is None for the personal clipboard.
+    pub fn is_paused(&self, group: Option<&str>) -> bool {
+        let channel = group.unwrap_or(PERSONAL);
+        lock(&self.settings).0.paused.iter().any(|paused| paused == channel)
+    }
+
+    // Only settings the primary signed are applied. `signer` is the trust
+    // list entry whose key checked the signature, which is what --primary
+    // is matched against rather than the name or ID the settings claim.
+    pub fn receive(&self, published: &Envelope, signer: Option<&str>) {
+        if published.content_type != envelope::POLICY {
+            return;
+        }
+        let sender = published.device.as_deref().unwrap_or("unknown");
+        let Some(primary) = &self.primary else {
+            return;
+        };
+        let Some(signer) = signer else {
+            warn!(target: RECEIVE, "ignoring settings from {} without a valid signature", sender);
+            return;
+        };
+        if signer != primary {
+            warn!(target: RECEIVE, "ignoring settings from {}, it is not --primary", sender);
+            return;
+        }
+        let Some(stamp) = published.hlc else {
+            warn!(target: RECEIVE, "ignoring settings from {} without the time they were published", sender);
+            return;
+        };
+        let settings = match Settings::parse(&published.content) {
+            Ok((settings, unknown)) => {
+                if !unknown.is_empty() {
+                    warn!(target: RECEIVE, "leaving out settings from {} this release does not know: {}", sender, unknown.join(", "));
+                }
+                settings
+            }
+            Err(e) => {
+                warn!(target: RECEIVE, "ignoring settings from {}: {}", sender, e);
+                return;
+            }
+        };
+        let mut current = lock(&self.settings);
+        if current.1.is_some_and(|applied| stamp <= applied) {
+            if current.1 != Some(stamp) {
+                warn!(target: RECEIVE, "ignoring settings from {}, they are older than the ones applied", sender);
+            }
+            return;
+        }
+        if current.0 != settings {
+            info!(target: RECEIVE, "applying settings from {}: {}", sender, settings.summary());
+        }
+        *current = (settings, Some(stamp));
+        let kept = self.store.put(KEY, published.content.as_bytes()).and_then(|_| self.store.put(STAMP_KEY, stamp.to_string().as_bytes()));
+        if let Err(e) = kept {
+            error!(target: RECEIVE, "Failed to keep the settings: {}", e);
+        }
+    }
+}
+
+#[derive(Subcommand, Debug)]
+pub enum PolicyCommand {
+    /// Publish settings from a file for the devices with this one as --primary: max_size, text_only, filter_secrets and paused, a list of "personal" and group names
+    Push {
+        path: PathBuf,
+        #[command(flatten)]
+        sync: Box<Args>,
+    },
+    /// Take the published settings back
+    Clear {
+        #[command(flatten)]
+        sync: Box<Args>,
+    },
+    /// Show the settings this device applies from its --primary
+    Show,
+}
+
+pub fn command(data_dir: &Path, command: PolicyCommand) {
+    match command {
+        PolicyCommand::Push { path, sync } => {
+            let text = std::fs::read_to_string(&path).unwrap_or_else(|e| {
+                eprintln!("Failed to read {}: {}", path.display(), e);
+                std::process::exit(1);
+            });
+            match Settings::parse(&text) {
+                Ok((_, unknown)) if !unknown.is_empty() => {
+                    eprintln!("Failed to read {}: unknown settings {}", path.display(), unknown.join(", "));
+                    std::process::exit(1);
+                }
+                Ok(_) => publish(&sync, data_dir, text),
+                Err(e) => {
+                    eprintln!("Failed to read {}: {}", path.display(), e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        PolicyCommand::Clear { sync } => publish(&sync, data_dir, String::new()),
+        PolicyCommand::Show => {
+            let text = FileStore::new(data_dir).get(KEY).ok().flatten().map(|text| String::from_utf8_lossy(&text).into_owned());
+            match text.as_deref().map(Settings::parse) {
+                Some(Ok((settings, _))) if settings != Settings::default() => println!("{}", settings.summary()),
+                _ => println!("no settings from a primary device"),
+            }
+        }
+    }
+}
+
+// Retained and signed, so devices that start later apply it too, and can
+// tell that it came from the primary.
+fn publish(args: &Args, data_dir: &Path, text: String) {
+    let mut published = Envelope::text(&args.device, text);
+    published.content_type = envelope::POLICY.to_string();
+    published.device_id = devices::id(&FileStore::new(data_dir));
+    published.hlc = Some(Clock::new().now());
+    let payload = crate::wrap(args, data_dir, &published).unwrap_or_else(|e| {
+        eprintln!("Failed to sign the settings: {}", e);
+        std::process::exit(1);
+    });
+    let options = or_exit(crate::mqtt_options(args, &format!("{}-{}-policy", args.user, args.device)), "connect");
+    let (client, mut connection) = Client::new(options, 10);
+    or_exit(client.publish(crate::policy_topic(&args.user), QoS::AtLeastOnce, true, payload), "publish the settings");
+    for notification in connection.iter() {
+        match notification {
+            Ok(Event::Incoming(Incoming::PubAck(_))) => break,
+            Err(err) => {
+                error!(target: CONNECT, "Failed to publish the settings: {:?}", err);
+                std::process::exit(1);
+            }
+            _ => {}
+        }
+    }
+    println!("published the settings to the devices with --primary {}", args.device);
+}
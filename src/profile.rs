@@ -0,0 +1,139 @@
+use std::path::Path;
+use zeroize::Zeroizing;
+use crate::config::{Config, Value};
+use crate::crypto::{self, write_private};
+use crate::init::prompt_secret;
+use crate::trust::Trust;
+
+const MAGIC: &[u8] = b"cloudboard-profile\n";
+const CONFIG_ENTRY: &str = "config.toml";
+const KEYRING_ENTRY: &str = "keyring";
+const TRUST_ENTRY: &str = "trust.list";
+const CERTS_PREFIX: &str = "certs/";
+const CERT_SETTINGS: [&str; 3] = ["ca_cert", "client_cert", "client_key"];
+
+// A bundle is the magic line followed by entries, each a `<name> <length>`
+// line and that many bytes of file content.
+fn encode(entries: &[(String, Vec<u8>)]) -> Vec<u8> {
+    let mut out = MAGIC.to_vec();
+    for (name, content) in entries {
+        out.extend_from_slice(format!("{} {}\n", name, content.len()).as_bytes());
+        out.extend_from_slice(content);
+    }
+    out
+}
+
+fn decode(bundle: &[u8]) -> Option<Vec<(String, Vec<u8>)>> {
+    let mut rest = bundle.strip_prefix(MAGIC)?;
+    let mut entries = Vec::new();
+    while !rest.is_empty() {
+        let end = rest.iter().position(|&b| b == b'\n')?;
+        let (name, len) = std::str::from_utf8(&rest[..end]).ok()?.rsplit_once(' ')?;
+        let len: usize = len.parse().ok()?;
+        let content = rest.get(end + 1..end + 1 + len)?;
+        entries.push((name.to_string(), content.to_vec()));
+        rest = &rest[end + 1 + len..];
+    }
+    Some(entries)
+}
+
+fn fail(message: String) -> ! {
+    eprintln!("{message}");
+    std::process::exit(1);
+}
+
+fn setting(config: &Config, config_path: &Path, key: &str) -> String {
+    match config.get(key) {
+        Some(Value::String(value)) => value.clone(),
+        _ => fail(format!("{} is not set in {}, run `cloudboard init` first", key, config_path.display())),
+    }
+}
+
+fn read(path: &Path) -> Vec<u8> {
+    std::fs::read(path).unwrap_or_else(|e| fail(format!("Failed to read {}: {}", path.display(), e)))
+}
+
+// The device identity and signing keys are left out on purpose: the new
+// machine creates its own, so a copied bundle cannot impersonate this one.
+pub fn export(config_path: &Path, data_dir: &Path, path: &Path, encrypt: bool) {
+    let config = Config::load(config_path).unwrap_or_else(|e| fail(format!("Failed to load config: {}", e)));
+    let prefix = format!("{}-{}", setting(&config, config_path, "user"), setting(&config, config_path, "device"));
+
+    let mut entries = vec![(CONFIG_ENTRY.to_string(), read(config_path))];
+    for (key, name) in CERT_SETTINGS.into_iter().zip(["ca.crt".to_string(), format!("{prefix}.crt"), format!("{prefix}.key")]) {
+        let source = match config.get(key) {
+            Some(Value::String(source)) => source.clone(),
+            _ => Path::new(&setting(&config, config_path, "cert_dir")).join(&name).display().to_string(),
+        };
+        let content = crate::read_pem(&source).unwrap_or_else(|e| fail(format!("Failed to read {}", e)));
+        entries.push((format!("{CERTS_PREFIX}{name}"), content));
+    }
+    if let Some(Value::String(keyring)) = config.get("e2e_key") {
+        entries.push((KEYRING_ENTRY.to_string(), read(Path::new(keyring))));
+    }
+    if let Ok(trust_list) = std::fs::read(data_dir.join(TRUST_ENTRY)) {
+        entries.push((TRUST_ENTRY.to_string(), trust_list));
+    }
+
+    let mut bundle = encode(&entries);
+    if encrypt {
+        let passphrase = prompt_secret("Passphrase");
+        if prompt_secret("Repeat passphrase") != passphrase {
+            fail("passphrases do not match".to_string());
+        }
+        bundle = crypto::seal_with_passphrase(&passphrase, &bundle);
+    }
+    write_private(path, &bundle).unwrap_or_else(|e| fail(format!("Failed to write {}: {}", path.display(), e)));
+    println!("exported {} files to {}", entries.len(), path.display());
+    println!("set up the new machine with: cloudboard import-profile {}", path.display());
+}
+
+pub fn import(config_path: &Path, data_dir: &Path, path: &Path, force: bool) {
+    if config_path.exists() && !force {
+        fail(format!("{} already exists, use --force to replace it", config_path.display()));
+    }
+    let mut bundle = Zeroizing::new(read(path));
+    if crypto::is_passphrase_sealed(&bundle) {
+        let passphrase = prompt_secret("Passphrase");
+        bundle = crypto::open_with_passphrase(&passphrase, &bundle).unwrap_or_else(|| fail("wrong passphrase".to_string()));
+    }
+    let entries = decode(&bundle).unwrap_or_else(|| fail(format!("{} is not a cloudboard profile", path.display())));
+    let config = entries.iter().find(|(name, _)| name == CONFIG_ENTRY)
+        .and_then(|(_, content)| Config::parse(&String::from_utf8_lossy(content)).ok());
+    let Some(mut config) = config else {
+        fail(format!("{} does not contain a valid config", path.display()));
+    };
+
+    // Files go into the data dir and the config is pointed at them, since
+    // the paths from the exporting machine rarely exist here.
+    let data_dir = std::path::absolute(data_dir).unwrap_or_else(|_| data_dir.to_path_buf());
+    let cert_dir = data_dir.join("certs");
+    for (name, content) in &entries {
+        let target = match name.as_str() {
+            CONFIG_ENTRY => continue,
+            KEYRING_ENTRY => {
+                let target = data_dir.join(KEYRING_ENTRY);
+                config.set("e2e_key", Value::String(target.display().to_string()));
+                target
+            }
+            TRUST_ENTRY => data_dir.join(TRUST_ENTRY),
+            name => match name.strip_prefix(CERTS_PREFIX).filter(|file| !file.contains(['/', '\\']) && !file.starts_with('.')) {
+                Some(file) => cert_dir.join(file),
+                None => fail(format!("unexpected file {} in profile", name)),
+            },
+        };
+        write_private(&target, content).unwrap_or_else(|e| fail(format!("Failed to write {}: {}", target.display(), e)));
+    }
+    config.set("cert_dir", Value::String(cert_dir.display().to_string()));
+    for key in CERT_SETTINGS {
+        config.remove(key);
+    }
+    config.save(config_path).unwrap_or_else(|e| fail(format!("Failed to write {}: {}", config_path.display(), e)));
+    println!("imported {} into {}", path.display(), config_path.display());
+
+    if config.get("device_keys") == Some(&Value::Bool(true)) {
+        let trust = Trust::load(&data_dir).unwrap_or_else(|e| fail(format!("Failed to read the trust list: {e}")));
+        println!("this machine has a new identity, trust it on your other devices with:");
+        println!("  cloudboard trust add <device> {}", trust.identity());
+    }
+}
@@ -0,0 +1,86 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use clap::ValueEnum;
+use log::{info, warn};
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum Policy {
+    Pause,
+    Ignore,
+}
+
+const PROCESSES: &[(&str, &str)] = &[
+    ("parsecd", "Parsec"),
+    ("vncserver", "VNC"),
+    ("x11vnc", "VNC"),
+    ("Xvnc", "VNC"),
+    ("winvnc", "VNC"),
+    ("tvnserver", "VNC"),
+    ("xrdp", "RDP"),
+];
+
+pub fn detect() -> Option<&'static str> {
+    if std::env::var("SESSIONNAME").is_ok_and(|name| name.starts_with("RDP-")) {
+        return Some("RDP");
+    }
+    if std::env::var_os("XRDP_SESSION").is_some() {
+        return Some("RDP");
+    }
+    if std::env::var_os("VNCDESKTOP").is_some() {
+        return Some("VNC");
+    }
+
+    let running = running_processes();
+    PROCESSES.iter()
+        .find(|(process, _)| running.iter().any(|name| name.trim_end_matches(".exe").eq_ignore_ascii_case(process)))
+        .map(|(_, product)| *product)
+}
+
+#[cfg(target_os = "linux")]
+fn running_processes() -> Vec<String> {
+    let Ok(entries) = std::fs::read_dir("/proc") else {
+        return Vec::new();
+    };
+    entries.flatten()
+        .filter_map(|entry| std::fs::read_to_string(entry.path().join("comm")).ok())
+        .map(|comm| comm.trim().to_string())
+        .collect()
+}
+
+#[cfg(not(target_os = "linux"))]
+fn running_processes() -> Vec<String> {
+    let output = if cfg!(windows) {
+        std::process::Command::new("tasklist").args(["/fo", "csv", "/nh"]).output()
+    } else {
+        std::process::Command::new("ps").args(["-A", "-o", "comm="]).output()
+    };
+    let Ok(output) = output else {
+        return Vec::new();
+    };
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| line.split(',').next())
+        .map(|name| name.trim_matches('"').rsplit('/').next().unwrap_or_default().to_string())
+        .collect()
+}
+
+// Remote desktop software usually syncs the clipboard itself, so keep
+// cloudboard out of the way for as long as such a session is active.
+pub fn watch(paused: Arc<AtomicBool>, interval: Duration) {
+    std::thread::spawn(move || {
+        let mut current = None;
+        loop {
+            let detected = detect();
+            if detected != current {
+                match detected {
+                    Some(product) => warn!("{} session detected, pausing sync", product),
+                    None => info!("remote desktop session ended, resuming sync"),
+                }
+                paused.store(detected.is_some(), Ordering::Relaxed);
+                current = detected;
+            }
+            std::thread::sleep(interval);
+        }
+    });
+}
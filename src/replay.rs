@@ -0,0 +1,106 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use log::{error, warn};
+use crate::logging::RECEIVE;
+use crate::store::Store;
+
+const SEQUENCE_FILE: &str = "sequence";
+const PEERS_FILE: &str = "peers.seq";
+
+// Both sides persist their counters so a restart neither reuses sequence
+// numbers nor forgets what has already been applied.
+pub struct Sequence {
+    store: Arc<dyn Store>,
+    next: u64,
+}
+
+impl Sequence {
+    pub fn load(store: Arc<dyn Store>) -> Sequence {
+        let next = load(&*store, SEQUENCE_FILE)
+            .and_then(|content| content.trim().parse().ok())
+            .unwrap_or(1);
+        Sequence { store, next }
+    }
+
+    pub fn advance(&mut self) -> u64 {
+        let seq = self.next;
+        self.next += 1;
+        if let Err(e) = self.store.put(SEQUENCE_FILE, self.next.to_string().as_bytes()) {
+            error!("Failed to save sequence number: {}", e);
+        }
+        seq
+    }
+}
+
+// What has been accepted is only kept in memory until it is committed,
+// once applied or decided against, so an item lost to a crash between the
+// two is accepted again when it is redelivered.
+pub struct ReplayGuard {
+    store: Arc<dyn Store>,
+    last: HashMap<String, u64>,
+    committed: HashMap<String, u64>,
+}
+
+impl ReplayGuard {
+    pub fn load(store: Arc<dyn Store>) -> ReplayGuard {
+        let committed: HashMap<String, u64> = load(&*store, PEERS_FILE)
+            .unwrap_or_default()
+            .lines()
+            .filter_map(|line| line.rsplit_once(' '))
+            .filter_map(|(device, seq)| Some((device.to_string(), seq.parse().ok()?)))
+            .collect();
+        ReplayGuard { store, last: committed.clone(), committed }
+    }
+
+    // Once a device has sent a sequence number, messages from it without one
+    // are treated as forged rather than as coming from an old client.
+    pub fn accept(&mut self, device: &str, seq: Option<u64>) -> bool {
+        let last = self.last.get(device).copied();
+        match (seq, last) {
+            (None, None) => true,
+            (None, Some(_)) => {
+                warn!(target: RECEIVE, "rejecting message from {} without sequence number", device);
+                false
+            }
+            (Some(seq), Some(last)) if seq <= last => {
+                warn!(target: RECEIVE, "rejecting replayed message from {} (seq {} <= {})", device, seq, last);
+                false
+            }
+            (Some(seq), _) => {
+                self.last.insert(device.to_string(), seq);
+                true
+            }
+        }
+    }
+
+    pub fn is_committed(&self, device: &str, seq: u64) -> bool {
+        self.committed.get(device).is_some_and(|committed| seq <= *committed)
+    }
+
+    pub fn commit(&mut self, device: &str, seq: u64) {
+        if self.is_committed(device, seq) {
+            return;
+        }
+        self.committed.insert(device.to_string(), seq);
+        let last = self.last.entry(device.to_string()).or_insert(seq);
+        *last = (*last).max(seq);
+        self.save();
+    }
+
+    fn save(&self) {
+        let content: String = self.committed.iter().map(|(device, seq)| format!("{device} {seq}\n")).collect();
+        if let Err(e) = self.store.put(PEERS_FILE, content.as_bytes()) {
+            error!("Failed to save peer sequence numbers: {}", e);
+        }
+    }
+}
+
+fn load(store: &dyn Store, key: &str) -> Option<String> {
+    match store.get(key) {
+        Ok(value) => value.map(|value| String::from_utf8_lossy(&value).into_owned()),
+        Err(e) => {
+            error!("Failed to load {}: {}", key, e);
+            None
+        }
+    }
+}
@@ -0,0 +1,108 @@
+use std::fs::OpenOptions;
+use std::io::{self, Read, Seek, Write};
+use std::path::Path;
+use std::time::Duration;
+use log::{error, info};
+use crate::stats::{self, format_bytes};
+
+// Checked this often by the daemon, and at startup.
+const INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+// How much of the event log behind `cloudboard stats` is kept. The oldest
+// events go first, whichever limit they are over.
+#[derive(clap::Args, Clone, Debug)]
+pub struct Retention {
+    /// Keep at most this many events in the log behind `cloudboard stats`
+    #[arg(long)]
+    pub history_max_items: Option<usize>,
+    /// Drop events older than this from the log, e.g. 90d
+    #[arg(long, value_parser = humantime::parse_duration)]
+    pub history_max_age: Option<Duration>,
+    /// Keep the log under this many bytes
+    #[arg(long, default_value = "16777216")]
+    pub history_max_bytes: u64,
+}
+
+pub struct Pruned {
+    pub removed: usize,
+    pub kept: usize,
+    pub bytes: u64,
+}
+
+// Rewrites the log in place rather than replacing it, as the daemon keeps
+// it open for appending, and under the lock it appends with.
+pub fn prune(data_dir: &Path, retention: &Retention, dry_run: bool) -> io::Result<Pruned> {
+    let path = data_dir.join(stats::FILE_NAME);
+    let mut file = match OpenOptions::new().read(true).write(true).open(&path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Pruned { removed: 0, kept: 0, bytes: 0 }),
+        Err(e) => return Err(e),
+    };
+    file.lock()?;
+    let mut content = Vec::new();
+    file.read_to_end(&mut content)?;
+    let lines: Vec<&[u8]> = content.split_inclusive(|b| *b == b'\n').collect();
+    let start = first_kept(&lines, retention);
+    let bytes = lines[..start].iter().map(|line| line.len() as u64).sum();
+    if start > 0 && !dry_run {
+        let kept = &content[bytes as usize..];
+        file.rewind()?;
+        file.write_all(kept)?;
+        file.set_len(kept.len() as u64)?;
+    }
+    Ok(Pruned { removed: start, kept: lines.len() - start, bytes })
+}
+
+// Lines are appended in order, so everything before the first one kept
+// goes. Lines that do not start with a time are judged by their place.
+fn first_kept(lines: &[&[u8]], retention: &Retention) -> usize {
+    let mut start = 0;
+    if let Some(max_age) = retention.history_max_age {
+        let cutoff = stats::now().saturating_sub(max_age.as_secs());
+        let time = |line: &[u8]| std::str::from_utf8(line).ok()?.split('\t').next()?.parse::<u64>().ok();
+        start = lines.iter().position(|line| time(line).is_some_and(|time| time >= cutoff)).unwrap_or(lines.len());
+    }
+    if let Some(max_items) = retention.history_max_items {
+        start = start.max(lines.len().saturating_sub(max_items));
+    }
+    let mut size = 0;
+    let within = lines.iter().rev().take_while(|line| {
+        size += line.len() as u64;
+        size <= retention.history_max_bytes
+    });
+    start.max(lines.len() - within.count())
+}
+
+pub fn enforce(data_dir: &Path, retention: Retention) {
+    let data_dir = data_dir.to_path_buf();
+    std::thread::spawn(move || loop {
+        run_once(&data_dir, &retention);
+        std::thread::sleep(INTERVAL);
+    });
+}
+
+fn run_once(data_dir: &Path, retention: &Retention) {
+    match prune(data_dir, retention, false) {
+        Ok(pruned) if pruned.removed > 0 => {
+            info!("pruned {} events ({}) from the stats log, {} left", pruned.removed, format_bytes(pruned.bytes as usize), pruned.kept);
+        }
+        Ok(_) => {}
+        Err(e) => error!("Failed to prune the stats log: {}", e),
+    }
+}
+
+pub fn command(data_dir: &Path, retention: &Retention, now: bool) {
+    let pruned = match prune(data_dir, retention, !now) {
+        Ok(pruned) => pruned,
+        Err(e) => {
+            eprintln!("Failed to prune the stats log: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let size = format_bytes(pruned.bytes as usize);
+    match (pruned.removed, now) {
+        (0, _) => println!("nothing to prune, {} events are within the limits", pruned.kept),
+        (removed, true) => println!("removed {} events ({}), {} left", removed, size, pruned.kept),
+        (removed, false) => println!("would remove {} events ({}) of {}, run with --now to prune", removed, size, removed + pruned.kept),
+    }
+}
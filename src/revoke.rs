@@ -0,0 +1,181 @@
+use std::collections::BTreeSet;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use log::{error, info, warn};
+use rumqttc::v5::mqttbytes::QoS;
+use rumqttc::v5::{Client, Event, Incoming};
+use crate::cli::or_exit;
+use crate::crypto::to_hex;
+use crate::devices;
+use crate::envelope::{self, Envelope};
+use crate::lock::lock;
+use crate::logging::{CONNECT, RECEIVE};
+use crate::store::{FileStore, Store};
+use crate::trust::Trust;
+use crate::Args;
+
+const KEY: &str = "revoked";
+// How a signing key is written next to names and IDs.
+const KEY_PREFIX: &str = "ed25519:";
+
+// Devices no longer let in, by name and ID, one per line, and by the key
+// they signed with, which a device cannot shed by taking another name. A
+// revocation is retained on its own topic under the revoked device, so
+// devices that were offline at the time still hear of it when they connect,
+// and it holds whether or not the broker still takes the device's
+// certificate.
+pub struct Revoked {
+    store: Arc<dyn Store>,
+    devices: Mutex<BTreeSet<String>>,
+}
+
+impl Revoked {
+    pub fn load(store: Arc<dyn Store>) -> Revoked {
+        let devices = load(&*store);
+        Revoked { store, devices: Mutex::new(devices) }
+    }
+
+    pub fn is_revoked(&self, envelope: &Envelope) -> bool {
+        let devices = lock(&self.devices);
+        envelope.device_id.as_ref().is_some_and(|id| devices.contains(id)) || envelope.device.as_ref().is_some_and(|name| devices.contains(name))
+    }
+
+    pub fn is_revoked_key(&self, key: &[u8; 32]) -> bool {
+        lock(&self.devices).contains(&key_name(key))
+    }
+
+    // Once a device is revoked, only signed messages are let in, as an
+    // unsigned one could be from it under any name.
+    pub fn any(&self) -> bool {
+        !lock(&self.devices).is_empty()
+    }
+
+    // A revocation counts only if a trusted device signed it, and one from
+    // a device that is itself revoked does not. `signer` is the trust list
+    // entry that checked the signature. `me` is this device's name, ID and
+    // key, which it does not refuse itself by.
+    pub fn receive(&self, revocation: &Envelope, signer: Option<&str>, trust: &Trust, me: [&str; 3]) {
+        if revocation.content_type != envelope::REVOKE {
+            return;
+        }
+        let revoker = revocation.device.as_deref().unwrap_or("unknown");
+        let Some(signer) = signer else {
+            warn!(target: RECEIVE, "ignoring a revocation from {} without a valid signature of a trusted device", revoker);
+            return;
+        };
+        if lock(&self.devices).contains(signer) {
+            return;
+        }
+        let mut names: Vec<String> = revocation.content.split_whitespace().map(str::to_string).collect();
+        if names.iter().any(|name| me.contains(&name.as_str())) {
+            warn!(target: RECEIVE, "{} revoked this device, the other devices refuse its messages from now on", revoker);
+            return;
+        }
+        // Revocations from before keys were in them go by the keys the trust
+        // list has.
+        names.extend(keys(trust, &names));
+        let added = {
+            let mut devices = lock(&self.devices);
+            let before = devices.len();
+            devices.extend(names.iter().cloned());
+            let added = devices.len() > before;
+            if added {
+                save(&*self.store, &devices);
+            }
+            added
+        };
+        let names: Vec<&str> = names.iter().map(String::as_str).filter(|name| !name.starts_with(KEY_PREFIX)).collect();
+        if let Err(e) = trust.remove(&names) {
+            error!(target: RECEIVE, "Failed to remove a revoked device from the trust list: {}", e);
+        }
+        if added {
+            info!(target: RECEIVE, "{} revoked device {}, refusing its messages", revoker, names.join(" "));
+        }
+    }
+}
+
+pub fn key_name(key: &[u8; 32]) -> String {
+    format!("{KEY_PREFIX}{}", to_hex(key))
+}
+
+// The signing keys the trust list has for `names`, not already among them.
+fn keys(trust: &Trust, names: &[String]) -> Vec<String> {
+    let mut keys: Vec<String> = names.iter().filter_map(|name| trust.signer(name)).map(|(_, key)| key_name(&key)).collect();
+    keys.retain(|key| !names.contains(key));
+    keys.dedup();
+    keys
+}
+
+fn load(store: &dyn Store) -> BTreeSet<String> {
+    let text = store.get(KEY).ok().flatten().map(|bytes| String::from_utf8_lossy(&bytes).into_owned()).unwrap_or_default();
+    text.lines().map(str::trim).filter(|line| !line.is_empty()).map(str::to_string).collect()
+}
+
+fn save(store: &dyn Store, devices: &BTreeSet<String>) {
+    let text: String = devices.iter().map(|device| format!("{device}\n")).collect();
+    if let Err(e) = store.put(KEY, text.as_bytes()) {
+        error!(target: RECEIVE, "Failed to keep the revoked devices: {}", e);
+    }
+}
+
+// The names and IDs, leaving out keys.
+pub fn list(data_dir: &Path) -> BTreeSet<String> {
+    load(&FileStore::new(data_dir)).into_iter().filter(|device| !device.starts_with(KEY_PREFIX)).collect()
+}
+
+// The device is named the way `devices` shows it, or by its ID; both go
+// into the revocation when this device knows them, and so does its signing
+// key if the trust list has it.
+pub fn run(args: &Args, data_dir: &Path, device: &str) {
+    let store = FileStore::new(data_dir);
+    if devices::id(&store).as_deref() == Some(device) || device == args.device {
+        eprintln!("Failed to revoke {}: it is this device", device);
+        std::process::exit(1);
+    }
+    let mut names = vec![device.to_string()];
+    for (id, name) in devices::names(&store) {
+        if id == device {
+            names.push(name);
+        } else if name == device {
+            names.push(id);
+        }
+    }
+    let trust = Trust::load(data_dir).unwrap_or_else(|e| {
+        eprintln!("Failed to read the trust list: {}", e);
+        std::process::exit(1);
+    });
+    let keys = keys(&trust, &names);
+    let mut revocation = Envelope::text(&args.device, [names.as_slice(), &keys].concat().join(" "));
+    revocation.content_type = envelope::REVOKE.to_string();
+    revocation.device_id = devices::id(&store);
+    let payload = crate::wrap(args, data_dir, &revocation).unwrap_or_else(|e| {
+        eprintln!("Failed to sign the revocation: {}", e);
+        std::process::exit(1);
+    });
+
+    let topic = format!("{}/{}", crate::revoke_topic(&args.user), names[0]);
+    let options = or_exit(crate::mqtt_options(args, &format!("{}-{}-revoke", args.user, args.device)), "connect");
+    let (client, mut connection) = Client::new(options, 10);
+    or_exit(client.publish(topic, QoS::AtLeastOnce, true, payload), "publish the revocation");
+    for notification in connection.iter() {
+        match notification {
+            Ok(Event::Incoming(Incoming::PubAck(_))) => break,
+            Err(err) => {
+                error!(target: CONNECT, "Failed to publish the revocation: {:?}", err);
+                std::process::exit(1);
+            }
+            _ => {}
+        }
+    }
+    let names: Vec<&str> = names.iter().map(String::as_str).collect();
+    if let Err(e) = trust.remove(&names) {
+        eprintln!("Failed to remove {} from the trust list: {}", device, e);
+    }
+    let mut revoked = load(&store);
+    revoked.extend(names.iter().map(|name| name.to_string()).chain(keys));
+    save(&store, &revoked);
+    println!("revoked {}", names.join(" "));
+    if args.e2e_key.is_some() {
+        println!("it still has the shared key, which `cloudboard key rotate` cannot take from it: import a fresh key on the other devices with `cloudboard key import`, or move them to --device-keys");
+    }
+}
@@ -0,0 +1,281 @@
+use std::io::{BufRead, BufReader};
+use std::process::{Command, Stdio};
+use std::sync::atomic::Ordering;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::Arc;
+use std::time::Duration;
+use log::{debug, info, warn};
+use crate::config::{Config, Value};
+use crate::language;
+use crate::lock::lock;
+use crate::status::Status;
+use crate::sync::ClipboardSync;
+
+// Checked this often even without change events, for desktops and
+// networks no monitor reports on.
+const POLL_INTERVAL: Duration = Duration::from_secs(30);
+// Switching workspaces or bringing a VPN up comes as a handful of events.
+const SETTLE: Duration = Duration::from_millis(250);
+
+// A `[rule.<name>]` table switches how this device syncs while it is on a
+// VPN or a workspace: `vpn` names a network interface, with a trailing `*`
+// for any with that prefix, and `workspace` a desktop by its name or its
+// number counting from 1. A rule with both needs both. The first rule in
+// the file that matches applies, `pause = true` stopping sync and
+// `group = "<name>"` sending local changes to that group instead of the
+// personal clipboard, which is not applied meanwhile. Rules are read on
+// Linux, workspaces from EWMH window managers on X11.
+//
+// A rule with `language` or `script` instead looks at each copied or
+// received text: an ISO 639-1 code like `en`, or a script like `latin`,
+// `cyrillic` or `cjk`, or a list of them, with `!` in front for any but
+// that one. Text in a language that cannot be told has none, which `!en`
+// matches. `source` looks at the application it was copied in, in the same
+// way, and `larger_than` at its size in bytes. The first such rule that
+// matches decides, `group` sending a local copy to that group instead and
+// `drop = true` neither publishing a copy nor applying a received one.
+pub struct Rule {
+    name: String,
+    vpn: Option<String>,
+    workspace: Option<String>,
+    languages: Vec<String>,
+    scripts: Vec<String>,
+    sources: Vec<String>,
+    larger_than: Option<usize>,
+    pause: bool,
+    drop: bool,
+    group: Option<String>,
+}
+
+impl Rule {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn drops(&self) -> bool {
+        self.drop
+    }
+
+    pub fn group(&self) -> Option<&str> {
+        self.group.as_deref()
+    }
+
+    fn is_content(&self) -> bool {
+        !self.languages.is_empty() || !self.scripts.is_empty() || !self.sources.is_empty() || self.larger_than.is_some()
+    }
+}
+
+// The first content rule that matches `text`, copied in `source`.
+pub fn matching<'a>(rules: &'a [Rule], text: &str, source: Option<&str>) -> Option<&'a Rule> {
+    if rules.is_empty() {
+        return None;
+    }
+    let (detected_language, detected_script) = (language::language(text), language::script(text));
+    let cjk = detected_script.is_some_and(|script| language::CJK.contains(&script));
+    rules.iter().find(|rule| {
+        let language = matches(&rule.languages, |wanted| Some(wanted) == detected_language);
+        let script = matches(&rule.scripts, |wanted| Some(wanted) == detected_script || (wanted == "cjk" && cjk));
+        let app = matches(&rule.sources, |wanted| Some(wanted) == source);
+        language && script && app && rule.larger_than.is_none_or(|size| text.len() > size)
+    })
+}
+
+// None of the `!` values, and one of the others if there are any.
+fn matches(values: &[String], is: impl Fn(&str) -> bool) -> bool {
+    let (negated, wanted): (Vec<&str>, Vec<&str>) = values.iter().map(String::as_str).partition(|value| value.starts_with('!'));
+    !negated.iter().any(|value| is(&value[1..])) && (wanted.is_empty() || wanted.iter().any(|value| is(value)))
+}
+
+// Rules whose `devices` list leaves out `device` are skipped, like triggers.
+pub fn load(config: &Config, device: &str) -> Result<Vec<Rule>, String> {
+    let mut rules = Vec::new();
+    for name in config.tables("rule") {
+        let get = |key: &str| config.get(&format!("rule.{name}.{key}"));
+        let string = |key: &str| match get(key) {
+            None => Ok(None),
+            Some(Value::String(s)) => Ok(Some(s.clone())),
+            Some(Value::Integer(i)) if key == "workspace" => Ok(Some(i.to_string())),
+            Some(_) => Err(format!("rule {}: {} must be a string", name, key)),
+        };
+        match get("devices") {
+            None => {}
+            Some(Value::Array(devices)) if devices.iter().any(|d| d == device) => {}
+            Some(Value::Array(_)) => continue,
+            Some(_) => return Err(format!("rule {}: devices must be a list of strings", name)),
+        }
+        let bool = |key: &str| match get(key) {
+            None => Ok(false),
+            Some(Value::Bool(value)) => Ok(*value),
+            Some(_) => Err(format!("rule {}: {} must be true or false", name, key)),
+        };
+        let strings = |key: &str| match get(key) {
+            None => Ok(Vec::new()),
+            Some(Value::String(s)) => Ok(vec![s.clone()]),
+            Some(Value::Array(values)) => Ok(values.clone()),
+            Some(_) => Err(format!("rule {}: {} must be a string or a list of strings", name, key)),
+        };
+        let rule = Rule {
+            name: name.clone(),
+            vpn: string("vpn")?,
+            workspace: string("workspace")?,
+            languages: strings("language")?,
+            scripts: strings("script")?,
+            sources: strings("source")?,
+            larger_than: match get("larger_than") {
+                None => None,
+                Some(Value::Integer(size)) if *size >= 0 => Some(*size as usize),
+                Some(_) => return Err(format!("rule {}: larger_than must be a number of bytes", name)),
+            },
+            pause: bool("pause")?,
+            drop: bool("drop")?,
+            group: string("group")?,
+        };
+        if let Some(language) = rule.languages.iter().find(|l| !language::is_code(l.trim_start_matches('!'))) {
+            return Err(format!("rule {}: unknown language {}, expected a code like en", name, language));
+        }
+        if let Some(script) = rule.scripts.iter().find(|s| !language::is_script(s.trim_start_matches('!'))) {
+            return Err(format!("rule {}: unknown script {}, expected one of {}, cjk", name, script, language::scripts().join(", ")));
+        }
+        if rule.is_content() {
+            if rule.vpn.is_some() || rule.workspace.is_some() {
+                return Err(format!("rule {}: language, script, source and larger_than cannot be combined with vpn or workspace", name));
+            }
+            if rule.pause {
+                return Err(format!("rule {}: pause needs vpn or workspace, use drop for content", name));
+            }
+            if !rule.drop && rule.group.is_none() {
+                return Err(format!("rule {}: drop or group is required", name));
+            }
+        } else {
+            if rule.vpn.is_none() && rule.workspace.is_none() {
+                return Err(format!("rule {}: vpn, workspace, language, script, source or larger_than is required", name));
+            }
+            if rule.drop {
+                return Err(format!("rule {}: drop needs language, script, source or larger_than, use pause for vpn and workspace", name));
+            }
+            if !rule.pause && rule.group.is_none() {
+                return Err(format!("rule {}: pause or group is required", name));
+            }
+        }
+        rules.push(rule);
+    }
+    Ok(rules)
+}
+
+struct Situation {
+    // Network interfaces that are not down.
+    interfaces: Vec<String>,
+    // The current workspace's number, from 1, and name.
+    workspace: Option<(u32, Option<String>)>,
+}
+
+impl Situation {
+    fn current() -> Situation {
+        Situation { interfaces: interfaces(), workspace: workspace() }
+    }
+
+    fn matches(&self, rule: &Rule) -> bool {
+        let vpn = rule.vpn.as_deref().is_none_or(|vpn| match vpn.strip_suffix('*') {
+            Some(prefix) => self.interfaces.iter().any(|name| name.starts_with(prefix)),
+            None => self.interfaces.iter().any(|name| name == vpn),
+        });
+        let workspace = rule.workspace.as_deref().is_none_or(|wanted| match &self.workspace {
+            Some((number, name)) => number.to_string() == wanted || name.as_deref() == Some(wanted),
+            None => false,
+        });
+        vpn && workspace
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn interfaces() -> Vec<String> {
+    let Ok(entries) = std::fs::read_dir("/sys/class/net") else {
+        return Vec::new();
+    };
+    // Tunnels report their state as unknown rather than up.
+    entries.flatten()
+        .filter(|entry| std::fs::read_to_string(entry.path().join("operstate")).is_ok_and(|state| state.trim() != "down"))
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+        .collect()
+}
+
+#[cfg(not(target_os = "linux"))]
+fn interfaces() -> Vec<String> {
+    Vec::new()
+}
+
+fn workspace() -> Option<(u32, Option<String>)> {
+    let output = Command::new("xprop").args(["-root", "_NET_CURRENT_DESKTOP", "_NET_DESKTOP_NAMES"]).stderr(Stdio::null()).output().ok()?;
+    let output = String::from_utf8_lossy(&output.stdout);
+    let value = |property: &str| output.lines().find(|line| line.starts_with(property))?.split_once(" = ").map(|(_, value)| value.to_string());
+    let index: u32 = value("_NET_CURRENT_DESKTOP(")?.trim().parse().ok()?;
+    let name = value("_NET_DESKTOP_NAMES(").and_then(|names| {
+        names.split(", ").nth(index as usize).map(|name| name.trim_matches('"').to_string())
+    });
+    Some((index + 1, name))
+}
+
+// Each line from a monitor is a change worth a look. Monitors that are not
+// installed, or exit, leave it to polling.
+fn monitor(program: &'static str, args: &'static [&'static str], changed: mpsc::Sender<()>) {
+    std::thread::spawn(move || {
+        let child = Command::new(program).args(args).stdout(Stdio::piped()).stderr(Stdio::null()).spawn();
+        let Ok(mut child) = child else {
+            debug!("{} is not available, checking rules every {}s", program, POLL_INTERVAL.as_secs());
+            return;
+        };
+        let Some(stdout) = child.stdout.take() else {
+            return;
+        };
+        for _ in BufReader::new(stdout).lines().map_while(Result::ok) {
+            if changed.send(()).is_err() {
+                break;
+            }
+        }
+        let _ = child.kill();
+        let _ = child.wait();
+    });
+}
+
+pub fn watch(rules: Vec<Rule>, groups: &[String], sync: Arc<ClipboardSync>, status: Arc<Status>) {
+    for rule in &rules {
+        if let Some(group) = rule.group.as_ref().filter(|group| !groups.contains(group)) {
+            warn!("rule {} sends to group {}, which is not in --group", rule.name, group);
+        }
+    }
+    // Content rules are checked by the engine on each item.
+    let (content, rules): (Vec<Rule>, Vec<Rule>) = rules.into_iter().partition(Rule::is_content);
+    *lock(sync.content_rules()) = content;
+    if rules.is_empty() {
+        return;
+    }
+    let (changed, changes) = mpsc::channel();
+    monitor("ip", &["-o", "monitor", "link", "address"], changed.clone());
+    monitor("xprop", &["-root", "-spy", "_NET_CURRENT_DESKTOP"], changed);
+
+    std::thread::spawn(move || {
+        let mut current: Option<usize> = None;
+        loop {
+            let situation = Situation::current();
+            let matched = rules.iter().position(|rule| situation.matches(rule));
+            if matched != current {
+                let (old, new) = (current.map(|i| &rules[i]), matched.map(|i| &rules[i]));
+                match new {
+                    Some(rule) => info!("rule {} applies", rule.name),
+                    None => info!("no rule applies, syncing as usual"),
+                }
+                if old.is_some_and(|rule| rule.pause) || new.is_some_and(|rule| rule.pause) {
+                    sync.paused().store(new.is_some_and(|rule| rule.pause), Ordering::Relaxed);
+                }
+                *lock(sync.route()) = new.and_then(|rule| rule.group.clone());
+                status.set("rule", new.map_or("-", |rule| rule.name.as_str()));
+                current = matched;
+            }
+            match changes.recv_timeout(POLL_INTERVAL) {
+                Ok(()) => while changes.recv_timeout(SETTLE).is_ok() {},
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => std::thread::sleep(POLL_INTERVAL),
+            }
+        }
+    });
+}
@@ -0,0 +1,61 @@
+use std::borrow::Cow;
+use std::sync::OnceLock;
+use clap::ValueEnum;
+use regex::Regex;
+
+// What is taken out of received text before it reaches the clipboard, as
+// text pasted into a terminal can run commands through escape sequences or
+// control characters, and bidi overrides make code read differently from
+// how it runs.
+#[derive(ValueEnum, Clone, Copy, PartialEq, Debug)]
+pub enum Strip {
+    // Whole ANSI escape sequences: CSI, like colors and cursor movement, and
+    // OSC, DCS and the other strings, like window titles and hyperlinks.
+    Escapes,
+    // C0 and C1 controls and DEL, except tab and line breaks.
+    Controls,
+    // Bidi embeddings, overrides and isolates.
+    Bidi,
+    // Nothing, leaving received text as it was sent.
+    None,
+}
+
+// A string ends at BEL or ST; one that never does runs to the end, as it
+// would in a terminal.
+const ESCAPE: &str = r"(?x)
+    (\x1b\[|\x{9b}) [0-?]* [\x20-/]* [@-~]
+  | (\x1b[P\]X^_]|[\x{90}\x{9d}\x{98}\x{9e}\x{9f}]) [^\x07\x1b\x{9c}]* (\x07|\x1b\\|\x{9c})?
+  | \x1b [\x20-/]* [0-~]
+";
+
+fn escape() -> &'static Regex {
+    static COMPILED: OnceLock<Regex> = OnceLock::new();
+    COMPILED.get_or_init(|| Regex::new(ESCAPE).unwrap())
+}
+
+fn is_control(c: char) -> bool {
+    c.is_control() && !matches!(c, '\t' | '\n' | '\r')
+}
+
+fn is_bidi(c: char) -> bool {
+    matches!(c, '\u{202a}'..='\u{202e}' | '\u{2066}'..='\u{2069}')
+}
+
+pub fn sanitize<'a>(text: &'a str, strip: &[Strip]) -> Cow<'a, str> {
+    let mut text = Cow::Borrowed(text);
+    if strip.contains(&Strip::None) {
+        return text;
+    }
+    if strip.contains(&Strip::Escapes) {
+        if let Cow::Owned(stripped) = escape().replace_all(&text, "") {
+            text = Cow::Owned(stripped);
+        }
+    }
+    let controls = strip.contains(&Strip::Controls);
+    let bidi = strip.contains(&Strip::Bidi);
+    let unwanted = |c: char| (controls && is_control(c)) || (bidi && is_bidi(c));
+    if text.chars().any(unwanted) {
+        text = Cow::Owned(text.chars().filter(|c| !unwanted(*c)).collect());
+    }
+    text
+}
@@ -0,0 +1,119 @@
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::Duration;
+use clap::ValueEnum;
+use log::info;
+use crate::envelope::Envelope;
+use crate::lock::lock;
+use crate::logging::RECEIVE;
+use crate::sync::Publisher;
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum Policy {
+    /// Publish nothing while the screen is locked
+    Pause,
+    /// Also leave the clipboard alone until unlocked, then put the newest received item on it
+    PauseAll,
+    /// Keep syncing
+    Ignore,
+}
+
+// Whether the screen is locked, as far as the platform tells: the session's
+// LockedHint in logind, or the freedesktop screensaver for desktops that do
+// not set it; the session's CGSSessionScreenIsLocked on macOS; and the lock
+// screen's LogonUI on Windows. Unknown is unlocked.
+#[cfg(target_os = "linux")]
+pub fn is_locked() -> bool {
+    let session = std::env::var("XDG_SESSION_ID").unwrap_or_else(|_| "auto".to_string());
+    output(Command::new("loginctl").args(["show-session", &session, "-p", "LockedHint", "--value"])).is_some_and(|hint| hint.trim() == "yes")
+        || output(Command::new("gdbus").args([
+            "call", "--session", "--timeout", "1",
+            "--dest", "org.freedesktop.ScreenSaver",
+            "--object-path", "/org/freedesktop/ScreenSaver",
+            "--method", "org.freedesktop.ScreenSaver.GetActive",
+        ]))
+        .is_some_and(|active| active.contains("true"))
+}
+
+#[cfg(target_os = "macos")]
+pub fn is_locked() -> bool {
+    output(Command::new("ioreg").args(["-n", "Root", "-d1"])).is_some_and(|root| root.contains("\"CGSSessionScreenIsLocked\"=Yes"))
+}
+
+#[cfg(windows)]
+pub fn is_locked() -> bool {
+    output(Command::new("tasklist").args(["/fi", "imagename eq LogonUI.exe", "/fo", "csv", "/nh"])).is_some_and(|tasks| tasks.contains("LogonUI.exe"))
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+pub fn is_locked() -> bool {
+    false
+}
+
+#[cfg_attr(not(any(target_os = "linux", target_os = "macos", windows)), allow(dead_code))]
+fn output(command: &mut Command) -> Option<String> {
+    let output = command.stderr(std::process::Stdio::null()).output().ok()?;
+    output.status.success().then(|| String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+// The screen as last seen, and with pause-all the newest item received
+// while it was locked. One lock for both, so an item is never kept just as
+// the screen is unlocked and then left behind.
+pub struct ScreenLock {
+    policy: Policy,
+    state: Mutex<(bool, Option<Envelope>)>,
+    apply: mpsc::Sender<Envelope>,
+    publisher: Publisher,
+}
+
+impl ScreenLock {
+    pub fn new(policy: Policy, apply: mpsc::Sender<Envelope>, publisher: Publisher) -> ScreenLock {
+        ScreenLock { policy, state: Mutex::new((false, None)), apply, publisher }
+    }
+
+    // Hands back what may go on the clipboard now, and keeps the rest.
+    pub fn received(&self, envelope: Envelope) -> Option<Envelope> {
+        let mut state = lock(&self.state);
+        if !state.0 || self.policy != Policy::PauseAll {
+            return Some(envelope);
+        }
+        info!(target: RECEIVE, "screen is locked, keeping what {} sent until it is unlocked", envelope.device.as_deref().unwrap_or("unknown"));
+        if let Some(superseded) = state.1.replace(envelope) {
+            self.publisher.applied(&superseded);
+        }
+        None
+    }
+
+    fn changed(&self, locked: bool) {
+        let mut state = lock(&self.state);
+        state.0 = locked;
+        if let Some(kept) = state.1.take() {
+            let _ = self.apply.send(kept);
+        }
+    }
+
+    // `locked` is the sync engine's, which publishes nothing while it is set.
+    // It is apart from pausing, so a pause from elsewhere lasts past unlocking.
+    pub fn watch(self: Arc<Self>, locked_flag: Arc<AtomicBool>, interval: Duration) {
+        if self.policy == Policy::Ignore {
+            return;
+        }
+        std::thread::spawn(move || {
+            let mut current = false;
+            loop {
+                let locked = is_locked();
+                if locked != current {
+                    match locked {
+                        true => info!("screen locked, pausing publishing{}", if self.policy == Policy::PauseAll { " and receiving" } else { "" }),
+                        false => info!("screen unlocked, resuming sync"),
+                    }
+                    locked_flag.store(locked, Ordering::Relaxed);
+                    self.changed(locked);
+                    current = locked;
+                }
+                std::thread::sleep(interval);
+            }
+        });
+    }
+}
@@ -0,0 +1,23 @@
+use std::sync::OnceLock;
+use regex::Regex;
+
+// Credentials with a recognisable shape. This catches the common accidents,
+// like copying an API key or a private key file, not every password.
+const PATTERNS: &[(&str, &str)] = &[
+    ("private key", r"-----BEGIN [A-Z ]*PRIVATE KEY( BLOCK)?-----"),
+    ("AWS access key", r"\b(AKIA|ASIA)[0-9A-Z]{16}\b"),
+    ("GitHub token", r"\b(gh[pousr]_[A-Za-z0-9]{36,}|github_pat_[A-Za-z0-9_]{22,})\b"),
+    ("Slack token", r"\bxox[abposr]-[A-Za-z0-9-]{10,}"),
+    ("API key", r"\b(sk|rk)_(live|test)_[A-Za-z0-9]{24,}\b|\bsk-[A-Za-z0-9_-]{32,}"),
+    ("JSON web token", r"\beyJ[A-Za-z0-9_-]{10,}\.eyJ[A-Za-z0-9_-]{10,}\.[A-Za-z0-9_-]{10,}"),
+    ("password", r"(?i)\b(password|passwd|secret|api[_-]?key)\s*[:=]\s*\S{6,}"),
+];
+
+// Returns what kind of secret the content looks like it contains.
+pub fn find(content: &str) -> Option<&'static str> {
+    static COMPILED: OnceLock<Vec<(&str, Regex)>> = OnceLock::new();
+    let compiled = COMPILED.get_or_init(|| {
+        PATTERNS.iter().map(|(kind, pattern)| (*kind, Regex::new(pattern).unwrap())).collect()
+    });
+    compiled.iter().find(|(_, pattern)| pattern.is_match(content)).map(|(kind, _)| *kind)
+}
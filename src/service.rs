@@ -0,0 +1,106 @@
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+pub fn home() -> PathBuf {
+    std::env::var_os("HOME").map(PathBuf::from).unwrap_or_default()
+}
+
+pub fn config_home() -> PathBuf {
+    std::env::var_os("XDG_CONFIG_HOME").map(PathBuf::from).unwrap_or_else(|| home().join(".config"))
+}
+
+fn unit_path() -> PathBuf {
+    config_home().join("systemd/user/cloudboard.service")
+}
+
+fn plist_path() -> PathBuf {
+    home().join("Library/LaunchAgents/io.github.featherl.cloudboard.plist")
+}
+
+// Whether `install` has registered the service on this platform.
+pub fn is_installed() -> bool {
+    (cfg!(target_os = "linux") && unit_path().exists()) || (cfg!(target_os = "macos") && plist_path().exists())
+}
+
+// Registers cloudboard as a per-user service running with the given config
+// file, and returns a note on how to control it.
+pub fn install(config_path: &Path) -> io::Result<String> {
+    let exe = std::env::current_exe()?;
+    if cfg!(target_os = "linux") {
+        install_systemd(&exe, config_path)
+    } else if cfg!(target_os = "macos") {
+        install_launchd(&exe, config_path)
+    } else {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "service installation is not supported on this platform"))
+    }
+}
+
+fn install_systemd(exe: &Path, config_path: &Path) -> io::Result<String> {
+    let unit_path = unit_path();
+    let unit = format!(
+        "[Unit]\n\
+         Description=cloudboard clipboard sync\n\
+         After=graphical-session.target network-online.target\n\
+         \n\
+         [Service]\n\
+         ExecStart=\"{}\" --config \"{}\"\n\
+         Restart=on-failure\n\
+         Environment=RUST_LOG=info\n\
+         \n\
+         [Install]\n\
+         WantedBy=default.target\n",
+        exe.display(),
+        config_path.display(),
+    );
+    std::fs::create_dir_all(unit_path.parent().unwrap())?;
+    std::fs::write(&unit_path, unit)?;
+
+    let status = Command::new("systemctl").args(["--user", "daemon-reload"]).status()?;
+    if !status.success() {
+        return Err(io::Error::other("systemctl --user daemon-reload failed"));
+    }
+    let status = Command::new("systemctl").args(["--user", "enable", "--now", "cloudboard.service"]).status()?;
+    if !status.success() {
+        return Err(io::Error::other("systemctl --user enable failed"));
+    }
+    Ok(format!("installed {}, manage it with systemctl --user status cloudboard", unit_path.display()))
+}
+
+fn install_launchd(exe: &Path, config_path: &Path) -> io::Result<String> {
+    let plist_path = plist_path();
+    let plist = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <!DOCTYPE plist PUBLIC \"-//Apple//DTD PLIST 1.0//EN\" \"http://www.apple.com/DTDs/PropertyList-1.0.dtd\">\n\
+         <plist version=\"1.0\">\n\
+         <dict>\n\
+         \t<key>Label</key>\n\
+         \t<string>io.github.featherl.cloudboard</string>\n\
+         \t<key>ProgramArguments</key>\n\
+         \t<array>\n\
+         \t\t<string>{}</string>\n\
+         \t\t<string>--config</string>\n\
+         \t\t<string>{}</string>\n\
+         \t</array>\n\
+         \t<key>RunAtLoad</key>\n\
+         \t<true/>\n\
+         \t<key>KeepAlive</key>\n\
+         \t<true/>\n\
+         </dict>\n\
+         </plist>\n",
+        xml_escape(&exe.display().to_string()),
+        xml_escape(&config_path.display().to_string()),
+    );
+    std::fs::create_dir_all(plist_path.parent().unwrap())?;
+    std::fs::write(&plist_path, plist)?;
+
+    let status = Command::new("launchctl").arg("load").arg(&plist_path).status()?;
+    if !status.success() {
+        return Err(io::Error::other("launchctl load failed"));
+    }
+    Ok(format!("installed {}", plist_path.display()))
+}
+
+pub fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
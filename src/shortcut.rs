@@ -0,0 +1,138 @@
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use log::error;
+use crate::lock::lock;
+
+// Fast enough to catch a key tapped as quickly as people do.
+const POLL: Duration = Duration::from_millis(30);
+// How long after the shortcut the clipboard may change and still count.
+pub const WINDOW: Duration = Duration::from_secs(1);
+
+// For --copy-shortcut: when Ctrl+C, Ctrl+X or Ctrl+Insert, or Cmd+C or
+// Cmd+X on macOS, was last held down. Only those keys are ever looked at,
+// by polling whether they are down, so nothing else that is typed is seen.
+pub struct Shortcut {
+    last: Mutex<Option<Instant>>,
+    window: Duration,
+}
+
+impl Shortcut {
+    // Whether the clipboard changing now follows a copy shortcut closely
+    // enough to be its doing.
+    pub fn is_recent(&self) -> bool {
+        lock(&self.last).is_some_and(|last| last.elapsed() <= self.window)
+    }
+}
+
+pub fn watch(window: Duration) -> io::Result<Arc<Shortcut>> {
+    let mut keyboard = Keyboard::open()?;
+    let shortcut = Arc::new(Shortcut { last: Mutex::new(None), window });
+    let watched = shortcut.clone();
+    std::thread::spawn(move || loop {
+        match keyboard.is_copying() {
+            Ok(true) => *lock(&watched.last) = Some(Instant::now()),
+            Ok(false) => {}
+            Err(e) => {
+                // Nothing is published until the keyboard can be seen again.
+                error!("Failed to read the keyboard for --copy-shortcut: {}", e);
+                std::thread::sleep(Duration::from_secs(5));
+                match Keyboard::open() {
+                    Ok(reopened) => keyboard = reopened,
+                    Err(e) => error!("Failed to read the keyboard for --copy-shortcut: {}", e),
+                }
+            }
+        }
+        std::thread::sleep(POLL);
+    });
+    Ok(shortcut)
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+struct Keyboard {
+    connection: x11rb::rust_connection::RustConnection,
+    control: Vec<u8>,
+    copy: Vec<u8>,
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+impl Keyboard {
+    fn open() -> io::Result<Keyboard> {
+        use x11rb::connection::Connection;
+        use x11rb::protocol::xproto::ConnectionExt;
+        const CONTROL: &[u32] = &[0xffe3, 0xffe4];
+        // c, C, x, X and Insert.
+        const COPY: &[u32] = &[0x63, 0x43, 0x78, 0x58, 0xff63];
+
+        // X11 only shows the keyboard to XWayland windows there.
+        if std::env::var_os("WAYLAND_DISPLAY").is_some() {
+            return Err(io::Error::other("Wayland does not let applications see the keyboard"));
+        }
+        let (connection, _) = x11rb::connect(None).map_err(io::Error::other)?;
+        let (min, max) = (connection.setup().min_keycode, connection.setup().max_keycode);
+        let mapping = connection.get_keyboard_mapping(min, max - min + 1).map_err(io::Error::other)?.reply().map_err(io::Error::other)?;
+        let per_keycode = usize::from(mapping.keysyms_per_keycode.max(1));
+        let keycodes = |wanted: &[u32]| -> Vec<u8> {
+            let keycodes = mapping.keysyms.chunks(per_keycode).zip(min..=max);
+            keycodes.filter(|(keysyms, _)| keysyms.iter().any(|keysym| wanted.contains(keysym))).map(|(_, keycode)| keycode).collect()
+        };
+        let (control, copy) = (keycodes(CONTROL), keycodes(COPY));
+        Ok(Keyboard { connection, control, copy })
+    }
+
+    fn is_copying(&mut self) -> io::Result<bool> {
+        use x11rb::protocol::xproto::ConnectionExt;
+        let keys = self.connection.query_keymap().map_err(io::Error::other)?.reply().map_err(io::Error::other)?.keys;
+        let down = |keycode: &u8| keys[usize::from(*keycode / 8)] & (1 << (keycode % 8)) != 0;
+        Ok(self.control.iter().any(down) && self.copy.iter().any(down))
+    }
+}
+
+#[cfg(target_os = "macos")]
+#[link(name = "ApplicationServices", kind = "framework")]
+extern "C" {
+    fn CGEventSourceKeyState(state: i32, key: u16) -> bool;
+}
+
+#[cfg(target_os = "macos")]
+struct Keyboard;
+
+#[cfg(target_os = "macos")]
+impl Keyboard {
+    fn open() -> io::Result<Keyboard> {
+        Ok(Keyboard)
+    }
+
+    fn is_copying(&mut self) -> io::Result<bool> {
+        // The combined state of every keyboard, and virtual key codes for
+        // both Command keys, then C and X.
+        const HID_SYSTEM: i32 = 1;
+        // SAFETY: CGEventSourceKeyState only reads the state it is asked for.
+        let down = |key: u16| unsafe { CGEventSourceKeyState(HID_SYSTEM, key) };
+        Ok((down(0x37) || down(0x36)) && (down(0x08) || down(0x07)))
+    }
+}
+
+#[cfg(windows)]
+#[link(name = "user32")]
+extern "system" {
+    fn GetAsyncKeyState(key: i32) -> i16;
+}
+
+#[cfg(windows)]
+struct Keyboard;
+
+#[cfg(windows)]
+impl Keyboard {
+    fn open() -> io::Result<Keyboard> {
+        Ok(Keyboard)
+    }
+
+    fn is_copying(&mut self) -> io::Result<bool> {
+        // VK_CONTROL, then C, X and VK_INSERT. The high bit is set while
+        // the key is down.
+        // SAFETY: GetAsyncKeyState only reads the state of the key.
+        let down = |key: i32| unsafe { GetAsyncKeyState(key) } < 0;
+        Ok(down(0x11) && (down(0x43) || down(0x58) || down(0x2d)))
+    }
+}
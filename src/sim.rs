@@ -0,0 +1,279 @@
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashSet};
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use crate::hlc::{Clock, Timestamp};
+use crate::replay::{ReplayGuard, Sequence};
+use crate::store::MemoryStore;
+
+// Devices copying against each other over a simulated broker, each with the
+// sequence numbers, replay guard and clock the daemon has, to see that
+// every item is applied at most once and that they all end on the same
+// clipboard. The broker delivers what one device sends in the order it was
+// sent, as MQTT does, apart from what `reorder` lets overtake. Time is
+// simulated, so a run takes as long as the work and not the latency.
+#[derive(clap::Args, Clone, Debug)]
+pub struct Settings {
+    /// How many devices copy against each other
+    #[arg(long, default_value = "3")]
+    pub devices: usize,
+
+    /// How many times each device copies
+    #[arg(long, default_value = "20")]
+    pub copies: usize,
+
+    /// The average time between two copies on one device
+    #[arg(long, default_value = "500ms", value_parser = humantime::parse_duration)]
+    pub interval: Duration,
+
+    /// How long a message takes from one device to another
+    #[arg(long, default_value = "50ms", value_parser = humantime::parse_duration)]
+    pub latency: Duration,
+
+    /// Up to this much longer, at random, which mixes up messages from different devices
+    #[arg(long, default_value = "200ms", value_parser = humantime::parse_duration)]
+    pub jitter: Duration,
+
+    /// The share of deliveries lost, from 0 to 1; each is sent again after --retry, and a lost ack delivers it twice
+    #[arg(long, default_value = "0.1")]
+    pub loss: f64,
+
+    /// The share of messages that overtake those sent before them by the same device, from 0 to 1
+    #[arg(long, default_value = "0")]
+    pub reorder: f64,
+
+    /// How long QoS 1 takes to send a lost message again
+    #[arg(long, default_value = "1s", value_parser = humantime::parse_duration)]
+    pub retry: Duration,
+
+    /// Start the random choices from this, to run the same simulation again
+    #[arg(long)]
+    pub seed: Option<u64>,
+}
+
+impl Default for Settings {
+    fn default() -> Settings {
+        Settings {
+            devices: 3,
+            copies: 20,
+            interval: Duration::from_millis(500),
+            latency: Duration::from_millis(50),
+            jitter: Duration::from_millis(200),
+            loss: 0.1,
+            reorder: 0.0,
+            retry: Duration::from_secs(1),
+            seed: None,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Report {
+    pub seed: u64,
+    // Every delivery, those of the same item again included.
+    pub delivered: usize,
+    pub applied: usize,
+    // Taken and committed, but older than the clipboard it arrived at.
+    pub older: usize,
+    // Deliveries of an item already delivered, as after a lost ack.
+    pub duplicates: usize,
+    // Items that came after a later one from the same device, which the
+    // replay guard counts as committed with it.
+    pub overtaken: usize,
+    // Items the replay guard turned down otherwise, which in a simulation
+    // without crashes must not happen either.
+    pub refused: usize,
+    // Items one device took more than once, which must never happen.
+    pub applied_twice: usize,
+    // What each device ended on, and what had the newest timestamp.
+    pub clipboards: Vec<Option<String>>,
+    pub newest: Option<String>,
+}
+
+impl Report {
+    pub fn converged(&self) -> bool {
+        self.clipboards.iter().all(|clipboard| *clipboard == self.newest)
+    }
+
+    pub fn is_ok(&self) -> bool {
+        self.converged() && self.applied_twice == 0 && self.refused == 0 && self.applied + self.older + self.duplicates + self.overtaken == self.delivered
+    }
+}
+
+struct Message {
+    from: usize,
+    seq: u64,
+    hlc: Timestamp,
+    content: String,
+}
+
+enum Happening {
+    Copy(usize, usize),
+    Deliver(usize, Arc<Message>),
+}
+
+struct Device {
+    sequence: Sequence,
+    guard: ReplayGuard,
+    clock: Clock,
+    clipboard: Option<String>,
+    // Items delivered and items taken, as sender and sequence number.
+    delivered: HashSet<(usize, u64)>,
+    taken: HashSet<(usize, u64)>,
+    // When the last message from each device gets here.
+    arrives: Vec<u64>,
+}
+
+impl Device {
+    fn new(devices: usize) -> Device {
+        let store = Arc::new(MemoryStore::new());
+        Device {
+            sequence: Sequence::load(store.clone()),
+            guard: ReplayGuard::load(store),
+            clock: Clock::new(),
+            clipboard: None,
+            delivered: HashSet::new(),
+            taken: HashSet::new(),
+            arrives: vec![0; devices],
+        }
+    }
+
+    // What the receive path does with an item, in the same order, with
+    // applying and committing it as one step.
+    fn receive(&mut self, message: &Message, millis: u64, report: &mut Report) {
+        let id = name(message.from);
+        report.delivered += 1;
+        let again = !self.delivered.insert((message.from, message.seq));
+        if self.guard.is_committed(&id, message.seq) {
+            if again {
+                report.duplicates += 1;
+            } else {
+                report.overtaken += 1;
+            }
+            return;
+        }
+        if !self.guard.accept(&id, Some(message.seq)) {
+            report.refused += 1;
+            return;
+        }
+        if !self.taken.insert((message.from, message.seq)) {
+            report.applied_twice += 1;
+        }
+        self.guard.commit(&id, message.seq);
+        if self.clock.observe_at(message.hlc, millis) {
+            self.clipboard = Some(message.content.clone());
+            report.applied += 1;
+        } else {
+            report.older += 1;
+        }
+    }
+}
+
+// Happenings are taken in time order, and those at the same time in the
+// order they were scheduled.
+fn schedule(queue: &mut BinaryHeap<Reverse<(u64, usize)>>, happenings: &mut Vec<Option<Happening>>, at: u64, happening: Happening) {
+    queue.push(Reverse((at, happenings.len())));
+    happenings.push(Some(happening));
+}
+
+pub fn run(settings: &Settings) -> Report {
+    let seed = settings.seed.unwrap_or_else(|| SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos() as u64);
+    let mut random = Random(seed | 1);
+    let micros = |duration: Duration| duration.as_micros() as u64;
+    let mut devices: Vec<Device> = (0..settings.devices).map(|_| Device::new(settings.devices)).collect();
+    let mut happenings = Vec::new();
+    let mut queue = BinaryHeap::new();
+    for device in 0..settings.devices {
+        let mut at = 0;
+        for copy in 0..settings.copies {
+            at += (micros(settings.interval) as f64 * 2.0 * random.next()) as u64;
+            schedule(&mut queue, &mut happenings, at, Happening::Copy(device, copy));
+        }
+    }
+
+    // The clocks run on simulated time from the real time now, and agree.
+    let start = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64;
+    let mut report = Report { seed, ..Report::default() };
+    let mut newest: Option<(Timestamp, String)> = None;
+    while let Some(Reverse((now, index))) = queue.pop() {
+        let Some(happening) = happenings[index].take() else {
+            continue;
+        };
+        match happening {
+            Happening::Copy(from, copy) => {
+                let device = &mut devices[from];
+                let message = Arc::new(Message { from, seq: device.sequence.advance(), hlc: device.clock.now_at(start + now / 1000), content: format!("{} copy {}", name(from), copy + 1) });
+                device.clipboard = Some(message.content.clone());
+                if newest.as_ref().is_none_or(|(hlc, _)| message.hlc > *hlc) {
+                    newest = Some((message.hlc, message.content.clone()));
+                }
+                for to in (0..settings.devices).filter(|to| *to != from) {
+                    let mut at = now + micros(settings.latency) + (micros(settings.jitter) as f64 * random.next()) as u64;
+                    while random.next() < settings.loss {
+                        at += micros(settings.retry);
+                    }
+                    if random.next() >= settings.reorder {
+                        at = at.max(devices[to].arrives[from]);
+                        devices[to].arrives[from] = at;
+                    }
+                    schedule(&mut queue, &mut happenings, at, Happening::Deliver(to, message.clone()));
+                    if random.next() < settings.loss {
+                        schedule(&mut queue, &mut happenings, at + micros(settings.retry), Happening::Deliver(to, message.clone()));
+                    }
+                }
+            }
+            Happening::Deliver(to, message) => devices[to].receive(&message, start + now / 1000, &mut report),
+        }
+    }
+    report.clipboards = devices.into_iter().map(|device| device.clipboard).collect();
+    report.newest = newest.map(|(_, content)| content);
+    report
+}
+
+fn name(device: usize) -> String {
+    // a, b, ... z, then d26 and on.
+    match u8::try_from(device).ok().filter(|device| *device < 26) {
+        Some(device) => ((b'a' + device) as char).to_string(),
+        None => format!("d{device}"),
+    }
+}
+
+// xorshift64*, as the runs only need to be repeatable.
+struct Random(u64);
+
+impl Random {
+    // In [0, 1).
+    fn next(&mut self) -> f64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        (self.0.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let yes = |ok: bool| if ok { "yes" } else { "NO" };
+        writeln!(f, "seed {}", self.seed)?;
+        writeln!(f, "{} deliveries: {} applied, {} older than the clipboard, {} duplicates, {} overtaken", self.delivered, self.applied, self.older, self.duplicates, self.overtaken)?;
+        writeln!(f, "every item taken at most once: {}", yes(self.applied_twice == 0 && self.refused == 0))?;
+        write!(f, "every device ended on {}: {}", self.newest.as_deref().unwrap_or("nothing"), yes(self.converged()))?;
+        for (device, clipboard) in self.clipboards.iter().enumerate().filter(|(_, clipboard)| **clipboard != self.newest) {
+            write!(f, "\n  {} is on {}", name(device), clipboard.as_deref().unwrap_or("nothing"))?;
+        }
+        Ok(())
+    }
+}
+
+pub fn command(settings: Settings) {
+    if settings.devices < 2 || !(0.0..1.0).contains(&settings.loss) || !(0.0..=1.0).contains(&settings.reorder) {
+        eprintln!("Failed to simulate: it takes at least 2 --devices, a --loss under 1 and a --reorder from 0 to 1");
+        std::process::exit(1);
+    }
+    let report = run(&settings);
+    println!("{report}");
+    if !report.is_ok() {
+        std::process::exit(1);
+    }
+}
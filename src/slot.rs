@@ -0,0 +1,100 @@
+use std::collections::BTreeMap;
+#[cfg(feature = "http-api")]
+use std::io;
+#[cfg(feature = "http-api")]
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+#[cfg(feature = "http-api")]
+use clap::Subcommand;
+use crate::envelope::Envelope;
+#[cfg(feature = "http-api")]
+use crate::http;
+use crate::lock::lock;
+use crate::memory::Budget;
+
+// Numbered clipboards next to the clipboard itself, see --slots, for
+// carrying several items between machines at once. Each holds whatever was
+// put in it last, on any device; the broker retains it, which is how a
+// device finds the slots again when it starts.
+pub struct Slots {
+    count: u32,
+    held: Mutex<BTreeMap<u32, Envelope>>,
+    budget: Arc<Budget>,
+}
+
+impl Slots {
+    pub fn new(count: u32, budget: Arc<Budget>) -> Slots {
+        Slots { count, held: Mutex::new(BTreeMap::new()), budget }
+    }
+
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+
+    // Keeps the item unless the slot holds one put there later, by the
+    // clocks of the devices, so the order they arrive in does not matter.
+    pub fn put(&self, envelope: Envelope) -> bool {
+        let Some(slot) = envelope.slot.filter(|slot| (1..=self.count).contains(slot)) else {
+            return false;
+        };
+        let mut held = lock(&self.held);
+        if held.get(&slot).is_some_and(|current| current.hlc >= envelope.hlc) {
+            return false;
+        }
+        self.budget.add("slots", envelope.content.len());
+        if let Some(replaced) = held.insert(slot, envelope) {
+            self.budget.remove("slots", replaced.content.len());
+        }
+        true
+    }
+
+    pub fn get(&self, slot: u32) -> Option<Envelope> {
+        lock(&self.held).get(&slot).cloned()
+    }
+}
+
+#[cfg(feature = "http-api")]
+#[derive(Subcommand, Debug)]
+pub enum SlotCommand {
+    /// Put the current clipboard, or this content, in a slot on every device
+    Copy {
+        slot: u32,
+        content: Option<String>,
+        /// Fill in placeholders like {date} when it is pasted
+        #[arg(long)]
+        template: bool,
+    },
+    /// Put what a slot holds on the clipboard
+    Paste {
+        slot: u32,
+    },
+    /// Show what each slot holds
+    List,
+}
+
+// Each is a single command so a desktop shortcut can run it.
+#[cfg(feature = "http-api")]
+pub fn command(data_dir: &Path, command: SlotCommand) {
+    let result = match command {
+        SlotCommand::Copy { slot, content, template } => copy(data_dir, slot, content, template),
+        SlotCommand::Paste { slot } => http::request(data_dir, "POST", &format!("/slot/{slot}"), "").map(drop),
+        SlotCommand::List => http::request(data_dir, "GET", "/slots", "").map(|(_, body)| print!("{body}")),
+    };
+    if let Err(e) = result {
+        eprintln!("Failed to reach the slots: {}", e);
+        std::process::exit(1);
+    }
+}
+
+#[cfg(feature = "http-api")]
+fn copy(data_dir: &Path, slot: u32, content: Option<String>, template: bool) -> io::Result<()> {
+    let content = match content {
+        Some(content) => content,
+        None => match http::request(data_dir, "GET", "/clipboard", "")? {
+            (200, body) => body,
+            _ => return Err(io::Error::other("the clipboard is empty")),
+        },
+    };
+    let query = if template { "?template=1" } else { "" };
+    http::request(data_dir, "PUT", &format!("/slot/{slot}{query}"), &content).map(drop)
+}
@@ -0,0 +1,73 @@
+use std::io;
+use std::path::Path;
+use clap::Subcommand;
+use crate::codec::Decoded;
+use crate::http;
+use crate::store::{FileStore, Store};
+
+const PREFIX: &str = "snapshots/";
+
+// Clipboards saved on this device to put back later, like before something
+// that overwrites the clipboard. Each keeps the formats the clipboard is
+// synced in, as files under `snapshots/<name>/` in the data dir.
+pub fn save(store: &dyn Store, name: &str, snapshot: &Decoded) -> io::Result<()> {
+    let name = checked(name)?;
+    store.put(&format!("{PREFIX}{name}/text"), snapshot.text.as_bytes())?;
+    // Written empty too, so a snapshot saved over keeps no HTML of the last.
+    store.put(&format!("{PREFIX}{name}/html"), snapshot.html.as_deref().unwrap_or_default().as_bytes())
+}
+
+pub fn load(store: &dyn Store, name: &str) -> io::Result<Option<Decoded>> {
+    let name = checked(name)?;
+    let read = |format: &str| store.get(&format!("{PREFIX}{name}/{format}")).map(|bytes| bytes.map(|bytes| String::from_utf8_lossy(&bytes).into_owned()));
+    let Some(text) = read("text")? else {
+        return Ok(None);
+    };
+    Ok(Some(Decoded { text, html: read("html")?.filter(|html| !html.is_empty()) }))
+}
+
+pub fn names(store: &dyn Store) -> io::Result<Vec<String>> {
+    let mut names: Vec<String> = store.list(PREFIX)?.iter().filter_map(|key| key[PREFIX.len()..].strip_suffix("/text").map(str::to_string)).collect();
+    names.dedup();
+    Ok(names)
+}
+
+// A name is a single file name, so one cannot reach outside `snapshots/`.
+fn checked(name: &str) -> io::Result<&str> {
+    if name.is_empty() || name.contains(['/', '\\']) || name.starts_with('.') {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("invalid snapshot name {:?}", name)));
+    }
+    Ok(name)
+}
+
+#[derive(Subcommand, Debug)]
+pub enum SnapshotCommand {
+    /// Save the running daemon's clipboard, with its HTML if it has any
+    Save {
+        name: String,
+    },
+    /// Put a saved clipboard back
+    Restore {
+        name: String,
+        /// Also publish it to the other devices, as copied in the snapshot
+        #[arg(long)]
+        sync: bool,
+    },
+    /// Show the saved clipboards
+    List,
+}
+
+pub fn command(data_dir: &Path, command: SnapshotCommand) {
+    let result = match command {
+        SnapshotCommand::Save { name } => http::request(data_dir, "PUT", &format!("/snapshot/{}", http::percent_encode(&name)), "").map(drop),
+        SnapshotCommand::Restore { name, sync } => {
+            let query = if sync { "?sync=1" } else { "" };
+            http::request(data_dir, "POST", &format!("/snapshot/{}{query}", http::percent_encode(&name)), "").map(drop)
+        }
+        SnapshotCommand::List => names(&FileStore::new(data_dir)).map(|names| names.iter().for_each(|name| println!("{name}"))),
+    };
+    if let Err(e) = result {
+        eprintln!("Failed to reach the snapshots: {}", e);
+        std::process::exit(1);
+    }
+}
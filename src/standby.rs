@@ -0,0 +1,71 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use log::{error, info};
+use crate::envelope::Envelope;
+use crate::hlc::Timestamp;
+use crate::logging::RECEIVE;
+use crate::stats;
+use crate::store::Store;
+
+const PREFIX: &str = "standby/";
+// The newest this many are kept; the oldest goes first.
+const KEPT: usize = 100;
+
+// A device in standby, with --standby, takes everything the others send and
+// keeps it in the data dir instead of putting it on the clipboard, until
+// `cloudboard promote` puts the newest there and ends the standby. What is
+// sensitive is not written down, and is let go of like a paused item.
+pub struct Standby {
+    on: AtomicBool,
+    store: Arc<dyn Store>,
+    apply: mpsc::Sender<Envelope>,
+}
+
+impl Standby {
+    pub fn new(on: bool, store: Arc<dyn Store>, apply: mpsc::Sender<Envelope>) -> Standby {
+        Standby { on: AtomicBool::new(on), store, apply }
+    }
+
+    pub fn is_on(&self) -> bool {
+        self.on.load(Ordering::Relaxed)
+    }
+
+    // Returns how many items are kept now. They go by the sender's clock,
+    // which is what the newest is decided by on the clipboard too.
+    pub fn keep(&self, envelope: &Envelope) -> usize {
+        let hlc = envelope.hlc.unwrap_or(Timestamp { millis: stats::now() * 1000, counter: 0 });
+        if !envelope.sensitive {
+            let mut entry = format!("{}\t{}\n", envelope.device.as_deref().unwrap_or("unknown"), envelope.content_type).into_bytes();
+            entry.extend_from_slice(envelope.content.as_bytes());
+            if let Err(e) = self.store.put(&format!("{PREFIX}{:020}-{:010}", hlc.millis, hlc.counter), &entry) {
+                error!(target: RECEIVE, "Failed to keep an item for standby: {}", e);
+            }
+        }
+        let keys = self.store.list(PREFIX).unwrap_or_default();
+        if keys.len() > KEPT {
+            let _ = self.store.prune(PREFIX, &keys[keys.len() - KEPT]);
+        }
+        keys.len().min(KEPT)
+    }
+
+    // Ends the standby, with the device it came from if there was an item
+    // to put on the clipboard. What was kept goes, so a later standby does
+    // not start on it.
+    pub fn promote(&self) -> Option<String> {
+        self.on.store(false, Ordering::Relaxed);
+        let key = self.store.list(PREFIX).ok()?.pop()?;
+        let entry = self.store.get(&key).ok().flatten();
+        if let Err(e) = self.store.prune(PREFIX, &format!("{key}~")) {
+            error!(target: RECEIVE, "Failed to remove the items kept for standby: {}", e);
+        }
+        let entry = entry?;
+        let entry = String::from_utf8_lossy(&entry);
+        let (head, content) = entry.split_once('\n')?;
+        let (device, content_type) = head.split_once('\t')?;
+        let mut envelope = Envelope::text(device, content.to_string());
+        envelope.content_type = content_type.to_string();
+        info!(target: RECEIVE, "promoted, putting what {} sent last on the clipboard", device);
+        self.apply.send(envelope).ok()?;
+        Some(device.to_string())
+    }
+}
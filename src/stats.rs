@@ -0,0 +1,434 @@
+#[cfg(feature = "history")]
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+#[cfg(feature = "history")]
+use std::io;
+use std::io::Write;
+use std::path::Path;
+use std::sync::Mutex;
+#[cfg(feature = "history")]
+use std::time::Duration;
+use std::time::{SystemTime, UNIX_EPOCH};
+use log::error;
+#[cfg(feature = "history")]
+use crate::devices;
+#[cfg(feature = "history")]
+use crate::growth;
+#[cfg(feature = "history")]
+use crate::json::quote;
+use crate::lock::lock;
+#[cfg(feature = "history")]
+use crate::store::FileStore;
+#[cfg(feature = "history")]
+use crate::when::Render;
+
+pub const FILE_NAME: &str = "stats.log";
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Kind {
+    Sent,
+    Received,
+    Dropped,
+    Filtered,
+    // Over --max-size or --max-rate.
+    Limited,
+    // A round trip of `cloudboard ping`, recorded with the milliseconds in
+    // place of the size and `-` as the device for the broker.
+    Ping,
+}
+
+impl Kind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Kind::Sent => "sent",
+            Kind::Received => "received",
+            Kind::Dropped => "dropped",
+            Kind::Filtered => "filtered",
+            Kind::Limited => "limited",
+            Kind::Ping => "ping",
+        }
+    }
+
+    #[cfg(feature = "history")]
+    fn parse(s: &str) -> Option<Kind> {
+        match s {
+            "sent" => Some(Kind::Sent),
+            "received" => Some(Kind::Received),
+            "dropped" => Some(Kind::Dropped),
+            "filtered" => Some(Kind::Filtered),
+            "limited" => Some(Kind::Limited),
+            "ping" => Some(Kind::Ping),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(feature = "history")]
+pub struct Record {
+    pub time: u64,
+    pub kind: Kind,
+    pub device: String,
+    pub content_type: String,
+    pub bytes: usize,
+    // The application the item was copied in, if its sender told.
+    pub source: Option<String>,
+    pub tags: Vec<String>,
+}
+
+#[cfg(feature = "history")]
+impl Record {
+    fn parse(line: &str) -> Option<Record> {
+        let mut fields = line.split('\t');
+        Some(Record {
+            time: fields.next()?.parse().ok()?,
+            kind: Kind::parse(fields.next()?)?,
+            device: fields.next()?.to_string(),
+            content_type: fields.next()?.to_string(),
+            bytes: fields.next()?.parse().ok()?,
+            // Lines from before sources, or tags, were recorded end here.
+            source: fields.next().filter(|source| *source != "-").map(str::to_string),
+            tags: fields.next().filter(|tags| *tags != "-").map(|tags| tags.split(',').map(str::to_string).collect()).unwrap_or_default(),
+        })
+    }
+}
+
+pub struct Recorder {
+    file: Mutex<Option<File>>,
+}
+
+impl Recorder {
+    pub fn open(data_dir: &Path) -> Recorder {
+        let file = std::fs::create_dir_all(data_dir)
+            .and_then(|_| OpenOptions::new().create(true).append(true).open(data_dir.join(FILE_NAME)));
+        let file = match file {
+            Ok(file) => Some(file),
+            Err(e) => {
+                error!("Failed to open stats file in {}: {}", data_dir.display(), e);
+                None
+            }
+        };
+        Recorder { file: Mutex::new(file) }
+    }
+
+    pub fn disabled() -> Recorder {
+        Recorder { file: Mutex::new(None) }
+    }
+
+    pub fn record(&self, kind: Kind, device: &str, content_type: &str, bytes: usize, source: Option<&str>, tags: &[String]) {
+        let mut file = lock(&self.file);
+        if let Some(file) = file.as_mut() {
+            // Names with tabs would shift the columns. Tags have none, see
+            // envelope::is_tag.
+            let source = source.filter(|source| !source.contains('\t')).unwrap_or("-");
+            let tags = if tags.is_empty() { "-".to_string() } else { tags.join(",") };
+            let line = format!("{}\t{}\t{}\t{}\t{}\t{}\t{}\n", now(), kind.as_str(), device, content_type, bytes, source, tags);
+            // Pruning rewrites the file under the same lock, possibly from
+            // `cloudboard prune` in another process.
+            let result = file.lock().and_then(|_| file.write_all(line.as_bytes()));
+            let _ = file.unlock();
+            if let Err(e) = result {
+                error!("Failed to write stats: {}", e);
+            }
+        }
+    }
+}
+
+pub fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()
+}
+
+#[cfg(feature = "history")]
+pub fn load(data_dir: &Path, since: Option<Duration>, tag: Option<&str>) -> Vec<Record> {
+    let path = data_dir.join(FILE_NAME);
+    let Ok(content) = std::fs::read_to_string(&path) else {
+        return Vec::new();
+    };
+    let cutoff = since.map_or(0, |since| now().saturating_sub(since.as_secs()));
+    content.lines()
+        .filter_map(Record::parse)
+        .filter(|record| record.time >= cutoff)
+        .filter(|record| tag.is_none_or(|tag| record.tags.iter().any(|tagged| tagged == tag)))
+        .collect()
+}
+
+#[cfg(feature = "history")]
+#[derive(clap::Args, Debug)]
+pub struct StatsArgs {
+    #[command(subcommand)]
+    command: Option<StatsCommand>,
+    /// Only include events newer than this, e.g. 1h or 7d
+    #[arg(long, global = true, value_parser = humantime::parse_duration)]
+    since: Option<Duration>,
+    /// Only include events for items with this tag
+    #[arg(long, global = true)]
+    tag: Option<String>,
+}
+
+#[cfg(feature = "history")]
+#[derive(clap::Subcommand, Debug)]
+enum StatsCommand {
+    /// List the recorded events with when they happened, newest last
+    List,
+    /// Print every recorded event, with sizes, types and devices but never contents
+    Export {
+        /// csv with a header row, or json with one object per line
+        #[arg(long, value_enum, default_value = "csv")]
+        format: Format,
+    },
+}
+
+#[cfg(feature = "history")]
+#[derive(clap::ValueEnum, Clone, Copy, PartialEq, Debug)]
+enum Format {
+    Csv,
+    Json,
+}
+
+#[cfg(feature = "history")]
+#[derive(Default)]
+struct DeviceStats {
+    sent: usize,
+    received: usize,
+    bytes: usize,
+    dropped: usize,
+    filtered: usize,
+    limited: usize,
+    pings: Vec<usize>,
+}
+
+#[cfg(feature = "history")]
+pub fn print(data_dir: &Path, args: StatsArgs, render: Render) {
+    match args.command {
+        Some(StatsCommand::List) => return list(data_dir, args.since, args.tag.as_deref(), render),
+        Some(StatsCommand::Export { format }) => return export(data_dir, args.since, args.tag.as_deref(), format),
+        None => {}
+    }
+    let records = load(data_dir, args.since, args.tag.as_deref());
+    match args.since {
+        Some(since) => println!("last {}", humantime::format_duration(since)),
+        None => println!("all time"),
+    }
+    if let Some(tag) = &args.tag {
+        println!("tagged {tag}");
+    }
+    if records.is_empty() {
+        println!("no sync events recorded");
+        return;
+    }
+
+    // Devices are recorded by ID, so a renamed one keeps its history and is
+    // shown under its latest name.
+    let names = devices::names(&FileStore::new(data_dir));
+    let mut broker_pings = Vec::new();
+    let mut devices: HashMap<&str, DeviceStats> = HashMap::new();
+    let mut content_types: HashMap<&str, usize> = HashMap::new();
+    let mut sources: HashMap<&str, usize> = HashMap::new();
+    let mut tags: HashMap<&str, usize> = HashMap::new();
+    let (mut transferred, mut transferred_bytes) = (0usize, 0usize);
+    for record in &records {
+        if record.kind == Kind::Ping && record.device == "-" {
+            broker_pings.push(record.bytes);
+            continue;
+        }
+        let device = devices.entry(names.get(&record.device).unwrap_or(&record.device)).or_default();
+        match record.kind {
+            Kind::Sent | Kind::Received => {
+                if record.kind == Kind::Sent {
+                    device.sent += 1;
+                } else {
+                    device.received += 1;
+                }
+                device.bytes += record.bytes;
+                transferred += 1;
+                transferred_bytes += record.bytes;
+                *content_types.entry(&record.content_type).or_default() += 1;
+                if let Some(source) = &record.source {
+                    *sources.entry(source).or_default() += 1;
+                }
+                for tag in &record.tags {
+                    *tags.entry(tag).or_default() += 1;
+                }
+            }
+            Kind::Dropped => device.dropped += 1,
+            Kind::Filtered => device.filtered += 1,
+            Kind::Limited => device.limited += 1,
+            Kind::Ping => device.pings.push(record.bytes),
+        }
+    }
+
+    let mut devices: Vec<_> = devices.into_iter().collect();
+    devices.sort_by(|a, b| a.0.cmp(b.0));
+    println!();
+    println!("{:<20} {:>8} {:>8} {:>10} {:>8} {:>8} {:>8} {:>8}", "DEVICE", "SENT", "RECEIVED", "BYTES", "DROPPED", "FILTERED", "LIMITED", "LATENCY");
+    for (name, device) in &devices {
+        println!("{:<20} {:>8} {:>8} {:>10} {:>8} {:>8} {:>8} {:>8}",
+                 name, device.sent, device.received, format_bytes(device.bytes), device.dropped, device.filtered, device.limited, format_latency(&device.pings));
+    }
+
+    println!();
+    if !broker_pings.is_empty() {
+        println!("broker latency: {}", format_latency(&broker_pings));
+    }
+    if let Some(average) = transferred_bytes.checked_div(transferred) {
+        println!("average payload: {}", format_bytes(average));
+    }
+    let mut content_types: Vec<_> = content_types.into_iter().collect();
+    content_types.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+    println!("top content types:");
+    for (content_type, count) in content_types.iter().take(5) {
+        println!("  {:<24} {}", content_type, count);
+    }
+    if !sources.is_empty() {
+        let mut sources: Vec<_> = sources.into_iter().collect();
+        sources.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        println!("top sources:");
+        for (source, count) in sources.iter().take(5) {
+            println!("  {:<24} {}", source, count);
+        }
+    }
+    if !tags.is_empty() {
+        let mut tags: Vec<_> = tags.into_iter().collect();
+        tags.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        println!("top tags:");
+        for (tag, count) in tags.iter().take(5) {
+            println!("  {:<24} {}", tag, count);
+        }
+    }
+
+    // What the daemon warned about as it happened, see growth.
+    let cutoff = args.since.map_or(0, |since| now().saturating_sub(since.as_secs()));
+    let warnings: Vec<_> = growth::load(&FileStore::new(data_dir)).into_iter().filter(|(time, _)| *time >= cutoff).collect();
+    if !warnings.is_empty() {
+        println!("unusually large copies:");
+        for (time, unusual) in &warnings {
+            println!("  {}  {}", render.absolute(*time), unusual);
+        }
+        println!("rules in the config file that keep them from syncing:");
+        let mut suggested = Vec::new();
+        for (_, unusual) in &warnings {
+            if !suggested.contains(&&unusual.source) {
+                suggested.push(&unusual.source);
+                println!("{}", unusual.rule());
+            }
+        }
+    }
+}
+
+// For people, where `export` is for programs, so times are local and
+// relative, and devices go by their current names.
+#[cfg(feature = "history")]
+fn list(data_dir: &Path, since: Option<Duration>, tag: Option<&str>, render: Render) {
+    let records = load(data_dir, since, tag);
+    if records.is_empty() {
+        println!("no sync events recorded");
+        return;
+    }
+    let names = devices::names(&FileStore::new(data_dir));
+    let mut stdout = io::stdout().lock();
+    let _ = writeln!(stdout, "{:<25} {:>10}  {:<9} {:<20} {:<24} {:>10}  {:<24} TAGS", "TIME", "", "EVENT", "DEVICE", "TYPE", "SIZE", "SOURCE");
+    for record in records {
+        let device = match record.device.as_str() {
+            "-" => "broker",
+            id => names.get(id).map_or(id, String::as_str),
+        };
+        let (content_type, size) = match record.kind {
+            Kind::Ping => ("-", format!("{} ms", record.bytes)),
+            _ => (record.content_type.as_str(), format_bytes(record.bytes)),
+        };
+        let line = format!(
+            "{:<25} {:>10}  {:<9} {:<20} {:<24} {:>10}  {:<24} {}",
+            render.absolute(record.time), render.relative(record.time), record.kind.as_str(), device, content_type, size,
+            record.source.as_deref().unwrap_or("-"), if record.tags.is_empty() { "-".to_string() } else { record.tags.join(",") },
+        );
+        if writeln!(stdout, "{line}").is_err() {
+            return;
+        }
+    }
+}
+
+// One row per event, with the device's current name next to its ID. Pings
+// carry their round trip in place of a size.
+#[cfg(feature = "history")]
+fn export(data_dir: &Path, since: Option<Duration>, tag: Option<&str>, format: Format) {
+    let names = devices::names(&FileStore::new(data_dir));
+    let mut stdout = io::stdout().lock();
+    if format == Format::Csv {
+        let _ = writeln!(stdout, "time,unix_time,event,device_id,device,type,bytes,rtt_ms,source,tags");
+    }
+    for record in load(data_dir, since, tag) {
+        let time = humantime::format_rfc3339_seconds(UNIX_EPOCH + Duration::from_secs(record.time)).to_string();
+        // Broker pings are recorded under `-`.
+        let (device_id, device) = match record.device.as_str() {
+            "-" => ("", "broker"),
+            id => (id, names.get(id).map_or(id, String::as_str)),
+        };
+        let (content_type, bytes, rtt) = match record.kind {
+            Kind::Ping => (None, None, Some(record.bytes)),
+            _ => (Some(record.content_type.as_str()), Some(record.bytes), None),
+        };
+        let line = match format {
+            Format::Csv => {
+                let number = |n: Option<usize>| n.map(|n| n.to_string()).unwrap_or_default();
+                let fields = [
+                    time, record.time.to_string(), record.kind.as_str().to_string(), device_id.to_string(), device.to_string(),
+                    content_type.unwrap_or_default().to_string(), number(bytes), number(rtt), record.source.clone().unwrap_or_default(),
+                    record.tags.join(" "),
+                ];
+                fields.iter().map(|field| csv_field(field)).collect::<Vec<_>>().join(",")
+            }
+            Format::Json => {
+                let number = |n: Option<usize>| n.map_or("null".to_string(), |n| n.to_string());
+                format!(
+                    "{{\"time\":{},\"unix_time\":{},\"event\":\"{}\",\"device_id\":{},\"device\":{},\"type\":{},\"bytes\":{},\"rtt_ms\":{},\"source\":{},\"tags\":[{}]}}",
+                    quote(&time), record.time, record.kind.as_str(),
+                    if device_id.is_empty() { "null".to_string() } else { quote(device_id) },
+                    quote(device), content_type.map_or("null".to_string(), quote), number(bytes), number(rtt),
+                    record.source.as_deref().map_or("null".to_string(), quote),
+                    record.tags.iter().map(|tag| quote(tag)).collect::<Vec<_>>().join(","),
+                )
+            }
+        };
+        // Stops quietly when the reader goes away, as `head` does.
+        if writeln!(stdout, "{line}").is_err() {
+            return;
+        }
+    }
+}
+
+// Device and application names come from other devices, so ones that a
+// spreadsheet would run as a formula are kept as text with a leading `'`.
+#[cfg(feature = "history")]
+fn csv_field(field: &str) -> String {
+    let field = match field.starts_with(['=', '+', '-', '@']) {
+        true => format!("'{field}"),
+        false => field.to_string(),
+    };
+    match field.contains([',', '"', '\n', '\r']) {
+        true => format!("\"{}\"", field.replace('"', "\"\"")),
+        false => field,
+    }
+}
+
+// The average of the measured round trips, in milliseconds.
+#[cfg(feature = "history")]
+fn format_latency(pings: &[usize]) -> String {
+    match pings.iter().sum::<usize>().checked_div(pings.len()) {
+        Some(average) => format!("{average} ms"),
+        None => "-".to_string(),
+    }
+}
+
+pub fn format_bytes(bytes: usize) -> String {
+    const UNITS: &[&str] = &["B", "KiB", "MiB", "GiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
@@ -0,0 +1,65 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use log::error;
+use crate::lock::lock;
+
+const FILE_NAME: &str = "status";
+
+// The running daemon mirrors its health into a small file in the data dir,
+// which `cloudboard status` prints.
+pub struct Status {
+    path: PathBuf,
+    fields: Mutex<BTreeMap<String, String>>,
+}
+
+impl Status {
+    pub fn new(data_dir: &Path) -> Status {
+        let status = Status {
+            path: data_dir.join(FILE_NAME),
+            fields: Mutex::new(BTreeMap::new()),
+        };
+        status.set("pid", &std::process::id().to_string());
+        status
+    }
+
+    pub fn set(&self, key: &str, value: &str) {
+        let mut fields = lock(&self.fields);
+        if fields.get(key).is_some_and(|current| current == value) {
+            return;
+        }
+        fields.insert(key.to_string(), value.to_string());
+
+        let content = text(&fields);
+        let tmp = self.path.with_extension("tmp");
+        if let Err(e) = std::fs::write(&tmp, content).and_then(|_| std::fs::rename(&tmp, &self.path)) {
+            error!("Failed to write status file: {}", e);
+        }
+    }
+
+    // What the status file holds, for the control API.
+    pub fn text(&self) -> String {
+        text(&lock(&self.fields))
+    }
+
+    pub fn remove(&self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+fn text(fields: &BTreeMap<String, String>) -> String {
+    fields.iter().map(|(key, value)| format!("{key}: {value}\n")).collect()
+}
+
+// A field the running daemon has published, e.g. the address of its HTTP API.
+pub fn get(data_dir: &Path, key: &str) -> Option<String> {
+    let content = std::fs::read_to_string(data_dir.join(FILE_NAME)).ok()?;
+    content.lines().find_map(|line| Some(line.strip_prefix(key)?.strip_prefix(": ")?.to_string()))
+}
+
+pub fn print(data_dir: &Path) {
+    match std::fs::read_to_string(data_dir.join(FILE_NAME)) {
+        Ok(content) => print!("{content}"),
+        Err(_) => println!("cloudboard is not running"),
+    }
+}
@@ -0,0 +1,131 @@
+use std::collections::BTreeMap;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Mutex;
+use crate::lock::lock;
+
+// Keys are `/`-separated and sorted byte-wise, so time-ordered data such as
+// history keeps zero-padded timestamps in its keys and prunes by cutoff.
+pub trait Store: Send + Sync {
+    fn put(&self, key: &str, value: &[u8]) -> io::Result<()>;
+
+    fn get(&self, key: &str) -> io::Result<Option<Vec<u8>>>;
+
+    // Keys starting with `prefix`, in sorted order.
+    fn list(&self, prefix: &str) -> io::Result<Vec<String>>;
+
+    // Removes the keys starting with `prefix` that sort before `before` and
+    // returns how many were removed.
+    fn prune(&self, prefix: &str, before: &str) -> io::Result<usize>;
+}
+
+#[derive(Default)]
+pub struct MemoryStore {
+    entries: Mutex<BTreeMap<String, Vec<u8>>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> MemoryStore {
+        MemoryStore::default()
+    }
+}
+
+impl Store for MemoryStore {
+    fn put(&self, key: &str, value: &[u8]) -> io::Result<()> {
+        lock(&self.entries).insert(key.to_string(), value.to_vec());
+        Ok(())
+    }
+
+    fn get(&self, key: &str) -> io::Result<Option<Vec<u8>>> {
+        Ok(lock(&self.entries).get(key).cloned())
+    }
+
+    fn list(&self, prefix: &str) -> io::Result<Vec<String>> {
+        Ok(lock(&self.entries).keys().filter(|key| key.starts_with(prefix)).cloned().collect())
+    }
+
+    fn prune(&self, prefix: &str, before: &str) -> io::Result<usize> {
+        let mut entries = lock(&self.entries);
+        let count = entries.len();
+        entries.retain(|key, _| !(key.starts_with(prefix) && key.as_str() < before));
+        Ok(count - entries.len())
+    }
+}
+
+// Each key is a file under the directory, with `/` in keys becoming
+// subdirectories, so the data dir itself is a store of its own files.
+pub struct FileStore {
+    dir: PathBuf,
+}
+
+impl FileStore {
+    pub fn new(dir: &Path) -> FileStore {
+        FileStore { dir: dir.to_path_buf() }
+    }
+
+    fn path(&self, key: &str) -> io::Result<PathBuf> {
+        let relative = Path::new(key);
+        if key.is_empty() || !relative.components().all(|component| matches!(component, Component::Normal(_))) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("invalid store key {:?}", key)));
+        }
+        Ok(self.dir.join(relative))
+    }
+
+    fn walk(&self, dir: &Path, prefix: &str, keys: &mut Vec<String>) -> io::Result<()> {
+        let entries = match std::fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        for entry in entries {
+            let entry = entry?;
+            let Some(name) = entry.file_name().to_str().map(str::to_string) else {
+                continue;
+            };
+            let key = if prefix.is_empty() { name } else { format!("{prefix}/{name}") };
+            if entry.file_type()?.is_dir() {
+                self.walk(&entry.path(), &key, keys)?;
+            } else if !key.ends_with(".tmp") {
+                keys.push(key);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Store for FileStore {
+    fn put(&self, key: &str, value: &[u8]) -> io::Result<()> {
+        let path = self.path(key)?;
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let mut tmp = path.clone().into_os_string();
+        tmp.push(".tmp");
+        std::fs::write(&tmp, value).and_then(|_| std::fs::rename(&tmp, &path))
+    }
+
+    fn get(&self, key: &str) -> io::Result<Option<Vec<u8>>> {
+        match std::fs::read(self.path(key)?) {
+            Ok(value) => Ok(Some(value)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn list(&self, prefix: &str) -> io::Result<Vec<String>> {
+        let mut keys = Vec::new();
+        self.walk(&self.dir, "", &mut keys)?;
+        keys.retain(|key| key.starts_with(prefix));
+        keys.sort();
+        Ok(keys)
+    }
+
+    fn prune(&self, prefix: &str, before: &str) -> io::Result<usize> {
+        let mut count = 0;
+        for key in self.list(prefix)?.into_iter().filter(|key| key.as_str() < before) {
+            std::fs::remove_file(self.path(&key)?)?;
+            count += 1;
+        }
+        Ok(count)
+    }
+}
@@ -0,0 +1,2164 @@
+use std::borrow::Cow;
+use std::ops::Deref;
+use std::collections::VecDeque;
+use std::fmt;
+use std::io;
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Condvar, Mutex, PoisonError, Weak};
+use std::task::{Context, Poll, Waker};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use futures_core::Stream;
+use futures_sink::Sink;
+use log::{debug, error, info, warn};
+use rumqttc::v5::mqttbytes::v5::{ConnectProperties, DisconnectReasonCode, LastWill, PubAckReason, Publish, PublishProperties};
+use rumqttc::v5::mqttbytes::QoS;
+use rumqttc::v5::mqttbytes::valid_topic;
+use rumqttc::v5::{Client, ClientError, Connection, ConnectionError, Event, Incoming, Request, StateError};
+use zeroize::Zeroizing;
+use crate::acl;
+use crate::blob::{self, Blobs};
+use crate::clipboard;
+use crate::codec;
+use crate::crypto::{self, E2e, Key, Keyring, Plaintext};
+use crate::delta::Delta;
+use crate::devices::{self, Devices};
+use crate::diag::Connections;
+use crate::envelope::{self, Encoding, Envelope, Offer};
+use crate::hlc::Clock;
+use crate::idle::{self, Idle};
+#[cfg(feature = "files")]
+use crate::inbox;
+use crate::journal::Journal;
+use crate::json::quote;
+use crate::limit::RateLimit;
+use crate::lock::lock;
+use crate::logging::{CONNECT, PUBLISH, RECEIVE};
+use crate::memory::Budget;
+use crate::policy::{self, Policy};
+use crate::replay::{ReplayGuard, Sequence};
+use crate::revoke::{self, Revoked};
+use crate::rules::{self, Rule};
+use crate::normalize::{normalize, Normalize};
+use crate::sanitize::{sanitize, Strip};
+use crate::secrets;
+use crate::slot::Slots;
+use crate::stats::{self, Kind, Recorder};
+use crate::store::{FileStore, Store};
+use crate::text;
+use crate::trust::{self, Trust};
+use crate::Args;
+
+#[derive(Clone, Debug)]
+pub enum SyncEvent {
+    Connected,
+    Disconnected(String),
+    // Content from another device that passed every check on the receive
+    // path; applying it is up to the caller.
+    Received(Envelope),
+    // Content this device published to the personal clipboard.
+    Sent(Envelope),
+    // Content too large to be sent right away, which `fetch` asks for.
+    Offered(Offer),
+    // The answer to a ping from this device, or without a device, the ping
+    // itself coming back from the broker.
+    Pong { device: Option<String>, rtt: Duration },
+    // Another device connected, or dropped off without disconnecting.
+    Presence { device: String, online: bool },
+    // Content from another device that a check on the receive path turned
+    // down, as dropped, filtered or limited; see `stats`.
+    Rejected { device: String, decision: Kind, content_type: String, size: usize },
+    // Something this device published that will not reach the broker.
+    PublishFailed(PublishFailure),
+}
+
+impl SyncEvent {
+    // The content it carries, which is what can make an event large.
+    fn size(&self) -> usize {
+        match self {
+            SyncEvent::Received(envelope) | SyncEvent::Sent(envelope) => envelope.content.len(),
+            _ => 0,
+        }
+    }
+
+    // One line of JSON, as `cloudboard watch` prints it.
+    pub fn to_json(&self) -> String {
+        let optional = |value: Option<String>| value.unwrap_or_else(|| "null".to_string());
+        match self {
+            SyncEvent::Connected => "{\"event\":\"connected\"}".to_string(),
+            SyncEvent::Disconnected(error) => format!("{{\"event\":\"disconnected\",\"error\":{}}}", quote(error)),
+            SyncEvent::Received(envelope) | SyncEvent::Sent(envelope) => format!(
+                "{{\"event\":\"{}\",\"device\":{},\"seq\":{},\"group\":{},\"source\":{},\"tags\":[{}],\"type\":{},\"content\":{}}}",
+                if matches!(self, SyncEvent::Sent(_)) { "sent" } else { "received" },
+                optional(envelope.device.as_deref().map(quote)),
+                optional(envelope.seq.map(|seq| seq.to_string())),
+                optional(envelope.group.as_deref().map(quote)),
+                optional(envelope.source.as_deref().map(quote)),
+                envelope.tags.iter().map(|tag| quote(tag)).collect::<Vec<_>>().join(","),
+                quote(&envelope.content_type),
+                quote(&envelope.content),
+            ),
+            SyncEvent::Offered(offer) => format!(
+                "{{\"event\":\"offered\",\"device\":{},\"size\":{},\"type\":{},\"sha256\":{},\"preview\":{}}}",
+                quote(&offer.device),
+                offer.size,
+                quote(&offer.content_type),
+                quote(&offer.sha256),
+                quote(&offer.preview),
+            ),
+            SyncEvent::Pong { device, rtt } => format!(
+                "{{\"event\":\"pong\",\"device\":{},\"rtt_ms\":{}}}",
+                optional(device.as_deref().map(quote)),
+                rtt.as_millis(),
+            ),
+            SyncEvent::Presence { device, online } => format!("{{\"event\":\"presence\",\"device\":{},\"online\":{}}}", quote(device), online),
+            SyncEvent::Rejected { device, decision, content_type, size } => format!(
+                "{{\"event\":\"rejected\",\"device\":{},\"decision\":{},\"type\":{},\"size\":{}}}",
+                quote(device),
+                quote(decision.as_str()),
+                quote(content_type),
+                size,
+            ),
+            SyncEvent::PublishFailed(failure) => format!("{{\"event\":\"publish_failed\",\"reason\":{},\"error\":{}}}", quote(failure.as_str()), quote(&failure.to_string())),
+        }
+    }
+}
+
+// Why a message did not make it to the broker, each handled its own way.
+// Losing the connection is not one of them: what is published meanwhile
+// waits in the client and goes out on reconnect, as `Disconnected` tells.
+#[derive(Clone, Debug)]
+pub enum PublishFailure {
+    // Over the largest packet the broker takes, so it is dropped rather
+    // than refused again on every reconnect. A broker that only says so by
+    // disconnecting does not give its maximum.
+    TooLarge { size: usize, max: Option<usize> },
+    // Refused by the broker's ACL, which reconnecting with the credentials
+    // read again can fix if they were renewed.
+    NotAuthorized,
+    // Refused for another reason, as the broker gave it.
+    Refused(String),
+    // A topic no message can go to, like one made from a name with a
+    // wildcard in it.
+    InvalidTopic(String),
+}
+
+impl PublishFailure {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PublishFailure::TooLarge { .. } => "too_large",
+            PublishFailure::NotAuthorized => "not_authorized",
+            PublishFailure::Refused(_) => "refused",
+            PublishFailure::InvalidTopic(_) => "invalid_topic",
+        }
+    }
+}
+
+impl fmt::Display for PublishFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PublishFailure::TooLarge { size, max: Some(max) } => write!(f, "dropped a {} message, the broker takes at most {}", stats::format_bytes(*size), stats::format_bytes(*max)),
+            PublishFailure::TooLarge { size, max: None } => write!(f, "dropped a {} message the broker disconnected over as too large", stats::format_bytes(*size)),
+            PublishFailure::NotAuthorized => f.write_str("the broker is not letting this device publish; reconnecting with its certificate read again"),
+            PublishFailure::Refused(reason) => write!(f, "the broker refused a message: {}", reason),
+            PublishFailure::InvalidTopic(topic) => write!(f, "dropped a message to {}, which is not a valid topic", topic),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Closed;
+
+impl fmt::Display for Closed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("the sync engine has stopped")
+    }
+}
+
+impl std::error::Error for Closed {}
+
+enum Outgoing {
+    Copy { content: String, force: bool, source: Option<String>, tags: Vec<String> },
+    Template(String),
+    Share { group: String, content: String },
+    Slot { slot: u32, content: String, template: bool },
+    // Content this device is about to put on its clipboard, which is not
+    // published back when the watcher sees it.
+    Remember(String),
+    #[cfg(feature = "files")]
+    File { name: String, content: String },
+    // An item from another device went as far as it goes on this one.
+    Applied { device: String, seq: u64, personal: bool },
+    // The content of an offer, for the device that asked for it.
+    Fetched { device: String, content: String },
+    // Asks for the whole of an edit whose base this device does not have.
+    Fetch(String),
+    // Publishes a probe to each topic, see acl::Check.
+    Probe(Vec<String>),
+    Ping { device: Option<String> },
+    // While the receiver waits for a blob.
+    Subscribe(String),
+    Unsubscribe(String),
+    Pong { device: String, sent: u64 },
+    Online,
+}
+
+// Content over this many bytes goes in the bulk lane, behind everything
+// else waiting to be published.
+const BULK: usize = 64 * 1024;
+
+impl Outgoing {
+    // Files and large items, which would otherwise hold up a short copy
+    // made while they wait for the broker.
+    fn is_bulk(&self) -> bool {
+        match self {
+            Outgoing::Copy { content, .. } | Outgoing::Template(content) | Outgoing::Share { content, .. } | Outgoing::Slot { content, .. } | Outgoing::Fetched { content, .. } => content.len() > BULK,
+            #[cfg(feature = "files")]
+            Outgoing::File { .. } => true,
+            _ => false,
+        }
+    }
+
+    // What was copied or sent on this device, as opposed to what the engine
+    // sends on its own.
+    fn is_local(&self) -> bool {
+        match self {
+            Outgoing::Copy { .. } | Outgoing::Template(_) | Outgoing::Share { .. } | Outgoing::Slot { .. } => true,
+            #[cfg(feature = "files")]
+            Outgoing::File { .. } => true,
+            _ => false,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Publisher(mpsc::Sender<Outgoing>);
+
+impl Publisher {
+    pub fn publish(&self, content: String) -> Result<(), Closed> {
+        self.0.send(Outgoing::Copy { content, force: false, source: None, tags: Vec::new() }).map_err(|_| Closed)
+    }
+
+    // Publishes a local copy, made in the `source` application.
+    pub fn copied(&self, content: String, source: Option<String>) -> Result<(), Closed> {
+        self.0.send(Outgoing::Copy { content, force: false, source, tags: Vec::new() }).map_err(|_| Closed)
+    }
+
+    // Publishes content even if it repeats something recent.
+    pub fn force(&self, content: String) -> Result<(), Closed> {
+        self.0.send(Outgoing::Copy { content, force: true, source: None, tags: Vec::new() }).map_err(|_| Closed)
+    }
+
+    // Publishes a snapshot put back on the clipboard, even if it repeats
+    // something recent.
+    pub fn restored(&self, content: String, source: String) -> Result<(), Closed> {
+        self.0.send(Outgoing::Copy { content, force: true, source: Some(source), tags: Vec::new() }).map_err(|_| Closed)
+    }
+
+    // Publishes a copy with `tags`, as `cloudboard push --tag` does.
+    pub fn tagged(&self, content: String, force: bool, tags: Vec<String>) -> Result<(), Closed> {
+        self.0.send(Outgoing::Copy { content, force, source: None, tags }).map_err(|_| Closed)
+    }
+
+    // Publishes content to a team clipboard joined with --group, leaving the
+    // personal clipboard alone.
+    pub fn share(&self, group: &str, content: String) -> Result<(), Closed> {
+        self.0.send(Outgoing::Share { group: group.to_string(), content }).map_err(|_| Closed)
+    }
+
+    // Publishes a template, which each device expands as it applies it,
+    // without touching this clipboard.
+    pub fn template(&self, content: String) -> Result<(), Closed> {
+        self.0.send(Outgoing::Template(content)).map_err(|_| Closed)
+    }
+
+    // Puts content, or a template with `template`, in a numbered slot on
+    // every device, leaving the clipboard alone.
+    pub fn slot(&self, slot: u32, content: String, template: bool) -> Result<(), Closed> {
+        self.0.send(Outgoing::Slot { slot, content, template }).map_err(|_| Closed)
+    }
+
+    pub fn remember(&self, content: &str) {
+        let _ = self.0.send(Outgoing::Remember(content.to_string()));
+    }
+
+    // Publishes content as a file, which receivers with --inbox save instead
+    // of putting on the clipboard.
+    #[cfg(feature = "files")]
+    pub fn file(&self, name: &str, content: String) -> Result<(), Closed> {
+        self.0.send(Outgoing::File { name: name.to_string(), content }).map_err(|_| Closed)
+    }
+
+    // Tells the engine a received item has been applied, or saved to the
+    // inbox, so it is not applied again if it is delivered again.
+    pub fn applied(&self, envelope: &Envelope) {
+        if let (Some(_), Some(seq)) = (&envelope.device, envelope.seq) {
+            let personal = envelope.group.is_none();
+            let _ = self.0.send(Outgoing::Applied { device: envelope.sender().to_string(), seq, personal });
+        }
+    }
+
+    // Asks one device, or every device, to answer with a pong event; the
+    // broker's answer comes first.
+    pub fn ping(&self, device: Option<&str>) -> Result<(), Closed> {
+        self.0.send(Outgoing::Ping { device: device.map(str::to_string) }).map_err(|_| Closed)
+    }
+}
+
+// A team clipboard shared by several users. Its members are told apart by
+// their certificate names, `<user>-<device>`, and share a key of their own
+// instead of the personal one.
+struct Group {
+    name: String,
+    topic: String,
+    e2e: Option<Arc<E2e>>,
+}
+
+impl Group {
+    fn load(data_dir: &Path, name: &str) -> io::Result<Group> {
+        let path = data_dir.join("groups").join(format!("{name}.key"));
+        let e2e = match Keyring::load(&path) {
+            Ok(_) if !cfg!(feature = "e2e") => {
+                return Err(io::Error::other(format!("group {name} has a key, which needs a build with the e2e feature")));
+            }
+            Ok(keyring) => Some(Arc::new(E2e::Shared(Mutex::new(keyring)))),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                warn!("group {} has no key at {}, its messages are not end-to-end encrypted", name, path.display());
+                None
+            }
+            Err(e) => return Err(e),
+        };
+        Ok(Group { name: name.to_string(), topic: crate::group_topic(name), e2e })
+    }
+}
+
+// Apps re-set the same content at their own cadence and applying received
+// content makes the clipboard watcher see it again, so content matching one
+// of the last `window` items, published or received, is not published. With
+// `time` set, a match older than that no longer counts.
+struct Dedup {
+    window: usize,
+    time: Option<Duration>,
+    recent: VecDeque<(u64, Instant)>,
+}
+
+impl Dedup {
+    fn new(window: usize, time: Option<Duration>) -> Dedup {
+        Dedup { window, time, recent: VecDeque::new() }
+    }
+
+    fn is_recent(&self, content: &str) -> bool {
+        let hash = clipboard::hash_text(content);
+        self.recent.iter().any(|(recent, seen)| *recent == hash && self.time.is_none_or(|time| seen.elapsed() < time))
+    }
+
+    fn remember(&mut self, content: &str) {
+        let hash = clipboard::hash_text(content);
+        self.recent.retain(|(recent, _)| *recent != hash);
+        self.recent.push_front((hash, Instant::now()));
+        self.recent.truncate(self.window);
+    }
+}
+
+// Publishing only queues the content for the publish thread, so the sink is
+// always ready and has nothing to flush.
+impl Sink<String> for Publisher {
+    type Error = Closed;
+
+    fn poll_ready(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<(), Closed>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn start_send(self: Pin<&mut Self>, content: String) -> Result<(), Closed> {
+        self.publish(content)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<(), Closed>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<(), Closed>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+// Events are counted against the memory budget while they wait, and the
+// oldest with content gives way once it is used up, as applying only ever
+// takes the newest anyway.
+struct Queue {
+    events: VecDeque<SyncEvent>,
+    waker: Option<Waker>,
+    closed: bool,
+    budget: Arc<Budget>,
+}
+
+impl Queue {
+    fn push(&mut self, event: SyncEvent) {
+        let size = event.size();
+        let mut dropped = 0;
+        while self.budget.is_over(size) {
+            let Some(oldest) = self.events.iter().position(|queued| queued.size() > 0) else {
+                break;
+            };
+            if let Some(oldest) = self.events.remove(oldest) {
+                self.budget.remove("events", oldest.size());
+                dropped += 1;
+            }
+        }
+        if dropped > 0 {
+            warn!("dropped the {} oldest events waiting to be handled, to stay within --memory-limit", dropped);
+        }
+        self.budget.add("events", size);
+        self.events.push_back(event);
+    }
+
+    fn pop(&mut self) -> Option<SyncEvent> {
+        let event = self.events.pop_front()?;
+        self.budget.remove("events", event.size());
+        Some(event)
+    }
+}
+
+impl Drop for Queue {
+    fn drop(&mut self) {
+        while self.pop().is_some() {}
+    }
+}
+
+struct Subscriber {
+    queue: Mutex<Queue>,
+    ready: Condvar,
+}
+
+impl Subscriber {
+    fn update(&self, f: impl FnOnce(&mut Queue)) {
+        let mut queue = lock(&self.queue);
+        f(&mut queue);
+        if let Some(waker) = queue.waker.take() {
+            waker.wake();
+        }
+        self.ready.notify_all();
+    }
+}
+
+// Every subscriber gets its own copy of each event, so the daemon and an
+// embedder can observe the same engine side by side.
+#[derive(Clone)]
+struct Broadcast {
+    subscribers: Arc<Mutex<Vec<Weak<Subscriber>>>>,
+    budget: Arc<Budget>,
+}
+
+impl Broadcast {
+    fn new(budget: Arc<Budget>) -> Broadcast {
+        Broadcast { subscribers: Arc::default(), budget }
+    }
+
+    fn subscribe(&self) -> Events {
+        let queue = Queue { events: VecDeque::new(), waker: None, closed: false, budget: self.budget.clone() };
+        let subscriber = Arc::new(Subscriber { queue: Mutex::new(queue), ready: Condvar::new() });
+        lock(&self.subscribers).push(Arc::downgrade(&subscriber));
+        Events(subscriber)
+    }
+
+    // Events can carry content of several megabytes, so the last subscriber,
+    // usually the only one, gets the event itself instead of a copy.
+    fn send(&self, event: SyncEvent) {
+        let mut subscribers = lock(&self.subscribers);
+        subscribers.retain(|subscriber| subscriber.strong_count() > 0);
+        let live: Vec<_> = subscribers.iter().filter_map(Weak::upgrade).collect();
+        let Some((last, others)) = live.split_last() else {
+            return;
+        };
+        for subscriber in others {
+            subscriber.update(|queue| queue.push(event.clone()));
+        }
+        last.update(|queue| queue.push(event));
+    }
+
+    fn close(&self) {
+        for subscriber in lock(&self.subscribers).iter().filter_map(Weak::upgrade) {
+            subscriber.update(|queue| queue.closed = true);
+        }
+    }
+}
+
+// Usable both as a `Stream` and, for callers without an async runtime, as a
+// blocking iterator. Both end when the engine stops.
+pub struct Events(Arc<Subscriber>);
+
+impl Iterator for Events {
+    type Item = SyncEvent;
+
+    fn next(&mut self) -> Option<SyncEvent> {
+        let mut queue = lock(&self.0.queue);
+        loop {
+            if let Some(event) = queue.pop() {
+                return Some(event);
+            }
+            if queue.closed {
+                return None;
+            }
+            queue = self.0.ready.wait(queue).unwrap_or_else(PoisonError::into_inner);
+        }
+    }
+}
+
+impl Events {
+    // Like `next`, but gives up after `timeout`.
+    pub fn next_timeout(&mut self, timeout: Duration) -> Option<SyncEvent> {
+        let deadline = Instant::now() + timeout;
+        let mut queue = lock(&self.0.queue);
+        loop {
+            if let Some(event) = queue.pop() {
+                return Some(event);
+            }
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if queue.closed || remaining.is_zero() {
+                return None;
+            }
+            queue = self.0.ready.wait_timeout(queue, remaining).unwrap_or_else(PoisonError::into_inner).0;
+        }
+    }
+}
+
+impl Stream for Events {
+    type Item = SyncEvent;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<SyncEvent>> {
+        let mut queue = lock(&self.0.queue);
+        match queue.pop() {
+            Some(event) => Poll::Ready(Some(event)),
+            None if queue.closed => Poll::Ready(None),
+            None => {
+                queue.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+// The engine speaks to the broker only: nothing here reads or writes an OS
+// clipboard, which is left to whoever feeds `publish` and consumes `events`.
+pub struct ClipboardSync {
+    publisher: Publisher,
+    broadcast: Broadcast,
+    // Subscribed before the threads start so the first caller of `events`
+    // does not miss anything that arrives while it is being set up.
+    first: Mutex<Option<Events>>,
+    paused: Arc<AtomicBool>,
+    screen_locked: Arc<AtomicBool>,
+    idle: Arc<Idle>,
+    route: Arc<Mutex<Option<String>>>,
+    content_rules: Arc<Mutex<Vec<Rule>>>,
+    slots: Arc<Slots>,
+    dedup: Arc<Mutex<Dedup>>,
+    budget: Arc<Budget>,
+}
+
+impl ClipboardSync {
+    pub fn start(args: &Args, data_dir: &Path) -> io::Result<ClipboardSync> {
+        ClipboardSync::start_with_store(args, data_dir, Arc::new(FileStore::new(data_dir)))
+    }
+
+    // Sequence numbers and what each peer has sent are kept in `store`; the
+    // device keys are still read from the data dir.
+    pub fn start_with_store(args: &Args, data_dir: &Path, store: Arc<dyn Store>) -> io::Result<ClipboardSync> {
+        let trust = Arc::new(Trust::load(data_dir)?);
+        let e2e = crate::load_e2e(args, &trust)?;
+        if args.require_encryption && e2e.is_none() {
+            return Err(io::Error::other("--require-encryption needs --e2e-key or --device-keys"));
+        }
+        let stats = Arc::new(if args.no_history || !cfg!(feature = "history") { Recorder::disabled() } else { Recorder::open(data_dir) });
+        let paused = Arc::new(AtomicBool::new(false));
+        let screen_locked = Arc::new(AtomicBool::new(false));
+        let idle = Arc::new(Idle::new(args.idle_after));
+        let route = Arc::new(Mutex::new(None));
+        let content_rules = Arc::new(Mutex::new(Vec::new()));
+        let capabilities = Arc::new(Mutex::new(codec::Peers::default()));
+        let synced = Arc::new(Mutex::new(None));
+        let policy = Arc::new(Policy::load(args.primary.clone(), store.clone()));
+        let clock = Arc::new(Clock::new());
+        // The sender commits what has been applied, the receiver checks it.
+        let replay_guard = Arc::new(Mutex::new(ReplayGuard::load(store.clone())));
+        let journal = Arc::new(Journal::new(store.clone()));
+        let blobs = Arc::new(Blobs::new(store.clone(), args.blob_expiry));
+        let budget = Arc::new(Budget::new(args.memory_limit));
+        let connections = Arc::new(Connections::load(store.clone()));
+        let broadcast = Broadcast::new(budget.clone());
+        let slots = Arc::new(Slots::new(args.slots, budget.clone()));
+        let first = broadcast.subscribe();
+
+        // A persistent session under a stable client ID has the broker queue
+        // what is published while this device is briefly offline, which only
+        // works for QoS 1 subscriptions.
+        let device_id = devices::identify(&*store, &args.device)?;
+        let mut options = crate::mqtt_options(args, &format!("{}-{}", args.user, device_id))?;
+        options.set_clean_start(false);
+        // Past the window publishes wait in the client, where local copies
+        // coalesce, rather than the broker refusing them.
+        options.set_outgoing_inflight_upper_limit(args.max_inflight);
+        let mut properties = options.connect_properties().unwrap_or_else(ConnectProperties::new);
+        properties.session_expiry_interval = Some(u32::MAX);
+        options.set_connect_properties(properties);
+        // Encrypted with the key current at startup, so after a rotation
+        // devices only read it for as long as --key-grace.
+        let mut will = Envelope::text(&args.device, "offline".to_string());
+        will.content_type = envelope::PRESENCE.to_string();
+        will.version = args.envelope_version;
+        will.device_id = Some(device_id.clone());
+        let will = encode_signal(&will, args.envelope_encoding, &trust, e2e.as_deref());
+        options.set_last_will(LastWill::new(crate::ack_topic(&args.user), will, QoS::AtLeastOnce, false, None));
+        let (client, connection) = Client::new(options, 10);
+        let groups: Arc<Vec<Group>> = Arc::new(args.group.iter().map(|name| Group::load(data_dir, name)).collect::<io::Result<_>>()?);
+        if let Some(group) = groups.iter().find(|group| args.require_encryption && group.e2e.is_none()) {
+            return Err(io::Error::other(format!("--require-encryption needs a key for group {}", group.name)));
+        }
+        // Team clipboards share this connection; the receiver tells them
+        // apart by topic. A connection per user is the least there can be,
+        // since the broker grants topics by the client certificate.
+        let mut subscribed: Vec<String> = groups.iter().map(|group| group.topic.clone()).collect();
+        let topic = crate::clipboard_topic(&args.user);
+        subscribed.extend([topic.clone(), crate::control_topic(&args.user), crate::revoke_topic(&args.user) + "/+"]);
+        if args.primary.is_some() {
+            subscribed.push(crate::policy_topic(&args.user));
+        }
+        subscribed.extend([crate::ack_topic(&args.user), crate::fetch_topic(&args.user), crate::fetch_topic(&args.user) + "/" + &args.device]);
+        if args.slots > 0 {
+            subscribed.push(crate::slot_topic(&args.user) + "/+");
+        }
+        for filter in &subscribed {
+            client.subscribe(filter.clone(), QoS::AtLeastOnce).map_err(io::Error::other)?;
+        }
+        info!(target: CONNECT, "subscribed {}", topic);
+
+        let offered = Arc::new(Mutex::new(VecDeque::new()));
+        let dedup = Arc::new(Mutex::new(Dedup::new(args.dedup_window, args.dedup_time)));
+        let (publish_sender, publish_receiver) = mpsc::channel();
+        let sender = Sender {
+            client,
+            topic,
+            fetch_topic: crate::fetch_topic(&args.user),
+            ack_topic: crate::ack_topic(&args.user),
+            store: store.clone(),
+            device: args.device.clone(),
+            device_id: device_id.clone(),
+            member: format!("{}-{}", args.user, args.device),
+            groups: groups.clone(),
+            max_size: args.max_size,
+            truncate: args.truncate,
+            lazy_threshold: args.lazy_threshold,
+            normalize: args.normalize.clone(),
+            blob_topic: crate::blob_topic(&args.user),
+            slot_topic: crate::slot_topic(&args.user),
+            slots: slots.clone(),
+            connections: connections.clone(),
+            blob_threshold: args.blob_threshold,
+            blob_expiry: args.blob_expiry.as_secs().try_into().unwrap_or(u32::MAX),
+            blobs: blobs.clone(),
+            envelope_version: args.envelope_version,
+            envelope_encoding: args.envelope_encoding,
+            filter_secrets: args.filter_secrets,
+            #[cfg(feature = "files")]
+            text_only: args.text_only,
+            retain: !args.no_retain,
+            retain_expiry: args.retain_expiry.as_secs().try_into().unwrap_or(u32::MAX),
+            offered: offered.clone(),
+            budget: budget.clone(),
+            dedup: dedup.clone(),
+            events: broadcast.clone(),
+            sequence: Sequence::load(store.clone()),
+            journal: journal.clone(),
+            replay_guard: replay_guard.clone(),
+            clock: clock.clone(),
+            trust: trust.clone(),
+            e2e: e2e.clone(),
+            stats: stats.clone(),
+            paused: paused.clone(),
+            screen_locked: screen_locked.clone(),
+            idle: idle.clone(),
+            route: route.clone(),
+            content_rules: content_rules.clone(),
+            capabilities: capabilities.clone(),
+            policy: policy.clone(),
+            delta_threshold: args.delta_threshold,
+            synced: synced.clone(),
+        };
+        std::thread::spawn(move || sender.run(publish_receiver));
+
+        let mut receiver = Receiver {
+            control_topic: crate::control_topic(&args.user),
+            fetch_topic: crate::fetch_topic(&args.user),
+            ack_topic: crate::ack_topic(&args.user),
+            devices: Devices::load(store.clone()),
+            response_topic: crate::fetch_topic(&args.user) + "/" + &args.device,
+            publisher: Publisher(publish_sender.clone()),
+            member: format!("{}-{}", args.user, args.device),
+            groups,
+            offered,
+            dedup: dedup.clone(),
+            pending: None,
+            blob_topic: crate::blob_topic(&args.user),
+            blobs,
+            slot_topic: crate::slot_topic(&args.user),
+            slots: slots.clone(),
+            revoke_topic: crate::revoke_topic(&args.user),
+            revoked: Revoked::load(store.clone()),
+            connections,
+            waiting: None,
+            device: args.device.clone(),
+            device_id,
+            require_signatures: args.require_signatures,
+            text_only: args.text_only,
+            sanitize: args.sanitize.clone(),
+            accept_from: args.accept_from.clone(),
+            accept_tag: args.accept_tag.clone(),
+            adopt_retained: args.startup == clipboard::Startup::Adopt,
+            max_size: args.max_size,
+            max_age: args.max_age,
+            rate_limit: RateLimit::new(args.max_rate),
+            clock,
+            key_grace: args.key_grace,
+            replay_guard,
+            journal,
+            trust,
+            e2e,
+            stats,
+            paused: paused.clone(),
+            idle: idle.clone(),
+            keep_alive: idle::KEEP_ALIVE,
+            route: route.clone(),
+            content_rules: content_rules.clone(),
+            capabilities,
+            policy,
+            policy_topic: crate::policy_topic(&args.user),
+            synced,
+            acl: acl::Check::new(subscribed, !args.no_acl_check),
+            probed: vec![crate::clipboard_topic(&args.user), crate::ack_topic(&args.user), crate::fetch_topic(&args.user)],
+            args: args.clone(),
+            events: broadcast.clone(),
+        };
+        std::thread::spawn(move || receiver.run(connection));
+
+        Ok(ClipboardSync {
+            publisher: Publisher(publish_sender),
+            broadcast,
+            first: Mutex::new(Some(first)),
+            paused,
+            screen_locked,
+            idle,
+            route,
+            content_rules,
+            slots,
+            dedup,
+            budget,
+        })
+    }
+
+    pub fn publish(&self, content: String) -> Result<(), Closed> {
+        self.publisher.publish(content)
+    }
+
+    pub fn publisher(&self) -> Publisher {
+        self.publisher.clone()
+    }
+
+    pub fn events(&self) -> Events {
+        lock(&self.first).take().unwrap_or_else(|| self.broadcast.subscribe())
+    }
+
+    // While set, nothing is published and received content is dropped.
+    pub fn paused(&self) -> &Arc<AtomicBool> {
+        &self.paused
+    }
+
+    // While set, what is copied here is not published; received content
+    // still arrives.
+    pub fn screen_locked(&self) -> &Arc<AtomicBool> {
+        &self.screen_locked
+    }
+
+    pub fn idle(&self) -> &Arc<Idle> {
+        &self.idle
+    }
+
+    // While set to a group, local changes go to that group instead of the
+    // personal clipboard, which is not applied meanwhile.
+    pub fn route(&self) -> &Arc<Mutex<Option<String>>> {
+        &self.route
+    }
+
+    // Rules on the language or script of text, checked on each copy and
+    // on each text received for the personal clipboard.
+    pub fn content_rules(&self) -> &Arc<Mutex<Vec<Rule>>> {
+        &self.content_rules
+    }
+
+    pub fn slots(&self) -> &Arc<Slots> {
+        &self.slots
+    }
+
+    // Hands what a slot holds to whoever applies received content, as
+    // though it had just arrived, and keeps it from being published back.
+    pub fn paste_slot(&self, slot: u32) -> bool {
+        let Some(mut envelope) = self.slots.get(slot) else {
+            return false;
+        };
+        // It was applied as an item when it arrived, if at all.
+        envelope.seq = None;
+        lock(&self.dedup).remember(&envelope.content);
+        self.broadcast.send(SyncEvent::Received(envelope));
+        true
+    }
+
+    // What holds content in memory, against --memory-limit.
+    pub fn memory(&self) -> &Arc<Budget> {
+        &self.budget
+    }
+}
+
+// How many of this device's latest offers are kept to be fetched.
+const OFFERS_KEPT: usize = 4;
+
+struct Sender {
+    client: Client,
+    topic: String,
+    fetch_topic: String,
+    ack_topic: String,
+    store: Arc<dyn Store>,
+    device: String,
+    device_id: String,
+    // This device's name in groups.
+    member: String,
+    groups: Arc<Vec<Group>>,
+    max_size: usize,
+    truncate: bool,
+    lazy_threshold: usize,
+    blob_topic: String,
+    slot_topic: String,
+    slots: Arc<Slots>,
+    connections: Arc<Connections>,
+    blob_threshold: Option<usize>,
+    blob_expiry: u32,
+    blobs: Arc<Blobs>,
+    envelope_version: u32,
+    envelope_encoding: envelope::Encoding,
+    filter_secrets: bool,
+    #[cfg(feature = "files")]
+    text_only: bool,
+    retain: bool,
+    retain_expiry: u32,
+    // Hashes and contents of recent offers, newest first.
+    offered: Arc<Mutex<VecDeque<(String, String)>>>,
+    budget: Arc<Budget>,
+    dedup: Arc<Mutex<Dedup>>,
+    events: Broadcast,
+    sequence: Sequence,
+    journal: Arc<Journal>,
+    replay_guard: Arc<Mutex<ReplayGuard>>,
+    clock: Arc<Clock>,
+    trust: Arc<Trust>,
+    e2e: Option<Arc<E2e>>,
+    stats: Arc<Recorder>,
+    paused: Arc<AtomicBool>,
+    screen_locked: Arc<AtomicBool>,
+    idle: Arc<Idle>,
+    route: Arc<Mutex<Option<String>>>,
+    content_rules: Arc<Mutex<Vec<Rule>>>,
+    capabilities: Arc<Mutex<codec::Peers>>,
+    policy: Arc<Policy>,
+    delta_threshold: Option<usize>,
+    normalize: Vec<Normalize>,
+    // The last personal item sent or received, which edits are made against.
+    synced: Arc<Mutex<Option<String>>>,
+}
+
+impl Sender {
+    fn run(mut self, outgoing: mpsc::Receiver<Outgoing>) {
+        // What the devices had not acked before a crash or restart. Only the
+        // live path sets the retained copy, so this never replaces a newer
+        // one, and receivers drop what they have already applied.
+        for (seq, topic, payload) in self.journal.pending() {
+            debug!(target: PUBLISH, "publishing item {} again, it was not acked", seq);
+            if let Err(e) = self.client.publish(topic, QoS::AtLeastOnce, false, payload) {
+                if self.stopped(&e) {
+                    return;
+                }
+            }
+        }
+        let mut queued = VecDeque::new();
+        while let Some(first) = queued.pop_front().or_else(|| outgoing.recv().ok()) {
+            // Publishing blocks while the broker's window is full or the
+            // connection is down, so a burst piles up here, and a local copy
+            // with a newer one behind it would only be replaced on arrival.
+            queued.push_front(first);
+            queued.extend(outgoing.try_iter());
+            // The bulk lane waits while anything else does, each lane in
+            // the order it was queued.
+            let next = queued.iter().position(|waiting| !waiting.is_bulk()).unwrap_or(0);
+            let Some(message) = queued.remove(next) else {
+                break;
+            };
+            if next > 0 {
+                debug!(target: PUBLISH, "publishing ahead of {} bulk items", next);
+                // The copies it overtakes are coalesced into it, the newest:
+                // sent after it, one of them would be stamped newer and
+                // replace it on the other devices. The rest of the bulk
+                // lane waits its turn.
+                if matches!(message, Outgoing::Copy { .. }) {
+                    let mut index = 0;
+                    let before = queued.len();
+                    queued.retain(|waiting| {
+                        index += 1;
+                        index > next || !matches!(waiting, Outgoing::Copy { .. })
+                    });
+                    if queued.len() < before {
+                        info!(target: PUBLISH, "not publishing {} large copies that were waiting, a newer copy replaces them", before - queued.len());
+                    }
+                }
+            }
+            // The same content again, as the watcher sees a tagged push,
+            // replaces nothing and is dropped as a repeat instead.
+            let replaced = match &message {
+                Outgoing::Copy { content, .. } => queued.iter().any(|newer| matches!(newer, Outgoing::Copy { content: newer, .. } if newer != content)),
+                _ => false,
+            };
+            if replaced {
+                debug!(target: PUBLISH, "not publishing a copy that a newer one replaces");
+                continue;
+            }
+            if message.is_local() {
+                self.idle.touch();
+            }
+            let result = match message {
+                Outgoing::Copy { content, force, source, tags } => self.copy(content, force, source, tags, "text/plain"),
+                Outgoing::Template(content) => self.copy(content, true, None, Vec::new(), envelope::TEMPLATE),
+                Outgoing::Share { group, content } => self.share(&group, content),
+                Outgoing::Slot { slot, content, template } => self.slot(slot, content, template),
+                Outgoing::Remember(content) => {
+                    lock(&self.dedup).remember(&content);
+                    Ok(())
+                }
+                #[cfg(feature = "files")]
+                Outgoing::File { name, content } => self.file(&name, content),
+                Outgoing::Applied { device, seq, personal } => {
+                    lock(&self.replay_guard).commit(&device, seq);
+                    if personal { self.ack(device, seq) } else { Ok(()) }
+                }
+                Outgoing::Fetched { device, content } => {
+                    let topic = format!("{}/{}", self.fetch_topic, device);
+                    let mut envelope = Envelope::text(&self.device, content);
+                    envelope.sensitive = secrets::find(&envelope.content).is_some();
+                    let e2e = self.e2e.clone();
+                    self.publish(topic, &mut envelope, e2e.as_deref()).map(|_| ())
+                }
+                Outgoing::Fetch(sha256) => {
+                    let mut request = Envelope::text(&self.device, sha256);
+                    request.content_type = envelope::FETCH.to_string();
+                    request.version = self.envelope_version;
+                    request.device_id = Some(self.device_id.clone());
+                    let payload = encode_signal(&request, self.envelope_encoding, &self.trust, self.e2e.as_deref());
+                    self.client.publish(self.fetch_topic.clone(), QoS::AtLeastOnce, false, payload).map_err(Box::new)
+                }
+                Outgoing::Ping { device } => {
+                    let content = format!("{} {}", now_millis(), device.as_deref().unwrap_or("*"));
+                    self.signal(envelope::PING, content)
+                }
+                Outgoing::Pong { device, sent } => self.signal(envelope::PONG, format!("{device} {sent}")),
+                Outgoing::Probe(topics) => self.probe(topics),
+                Outgoing::Online => self.signal(envelope::PRESENCE, "online".to_string()).and_then(|_| self.signal(envelope::CAPABILITIES, codec::capabilities())),
+                Outgoing::Subscribe(topic) => self.client.subscribe(topic, QoS::AtLeastOnce).map_err(Box::new),
+                Outgoing::Unsubscribe(topic) => self.client.unsubscribe(topic).map_err(Box::new),
+            };
+            if let Err(e) = result {
+                if self.stopped(&e) {
+                    break;
+                }
+            }
+        }
+    }
+
+    // The client only fails a request it cannot take, which is one for a
+    // topic it turns down before sending, or any at all once the connection
+    // has stopped for good.
+    fn stopped(&self, e: &ClientError) -> bool {
+        match e {
+            ClientError::Request(Request::Publish(publish)) if !valid_topic(&String::from_utf8_lossy(&publish.topic)) => {
+                let failure = PublishFailure::InvalidTopic(String::from_utf8_lossy(&publish.topic).into_owned());
+                warn!(target: PUBLISH, "{}", failure);
+                self.connections.record("publish dropped", &failure);
+                self.events.send(SyncEvent::PublishFailed(failure));
+                false
+            }
+            _ => {
+                error!(target: PUBLISH, "Failed to publish message: {}", e);
+                self.connections.record("publish failed", e);
+                true
+            }
+        }
+    }
+
+    fn copy(&mut self, content: String, force: bool, source: Option<String>, tags: Vec<String>, content_type: &str) -> Result<(), Box<ClientError>> {
+        if self.paused.load(Ordering::Relaxed) || self.is_screen_locked(content.len()) {
+            return Ok(());
+        }
+        // Templates are published as written.
+        let normalized = match normalize(&content, &self.normalize) {
+            Cow::Owned(normalized) if content_type == "text/plain" => Some(normalized),
+            _ => None,
+        };
+        let content = match normalized {
+            Some(normalized) => {
+                debug!(target: PUBLISH, "normalized {} bytes to {}", content.len(), normalized.len());
+                normalized
+            }
+            None => content,
+        };
+        {
+            let mut dedup = lock(&self.dedup);
+            if !force && dedup.is_recent(&content) {
+                debug!(target: PUBLISH, "not publishing {} bytes, published or received recently", content.len());
+                return Ok(());
+            }
+            dedup.remember(&content);
+        }
+        let decided = rules::matching(&lock(&self.content_rules), &content, source.as_deref()).map(|rule| (rule.name().to_string(), rule.group().map(str::to_string)));
+        match decided {
+            Some((rule, Some(group))) => {
+                debug!(target: PUBLISH, "rule {} sends {} bytes to group {}", rule, content.len(), group);
+                return self.share(&group, content);
+            }
+            Some((rule, None)) => {
+                info!(target: PUBLISH, "not publishing {} bytes, rule {} drops them", content.len(), rule);
+                return Ok(());
+            }
+            None => {}
+        }
+        let route = lock(&self.route).clone();
+        if let Some(group) = route {
+            return self.share(&group, content);
+        }
+        if self.is_paused(None, content.len()) {
+            return Ok(());
+        }
+        let Some(content) = self.fit(content) else {
+            return Ok(());
+        };
+        if self.is_secret(&content) {
+            return Ok(());
+        }
+
+        let (content, content_type) = self.negotiate(content, content_type);
+        let mut envelope = Envelope::text(&self.device, content);
+        envelope.sensitive = secrets::find(&envelope.content).is_some();
+        envelope.hlc = Some(self.clock.now());
+        envelope.source = source;
+        envelope.tags = tags;
+        envelope.content_type = content_type.to_string();
+        let e2e = self.e2e.clone();
+        let base = lock(&self.synced).replace(envelope.content.clone());
+        let delta = base
+            .filter(|_| self.delta_threshold.is_some_and(|threshold| envelope.content.len() > threshold))
+            .filter(|_| lock(&self.capabilities).all_take(envelope::DELTA))
+            .and_then(|base| Delta::between(&base, &envelope.content, &envelope.content_type));
+        let seq = if let Some(delta) = delta {
+            // Kept like an offer, for devices that have to fetch it whole.
+            self.keep_offered(delta.sha256.clone(), &envelope.content);
+            let mut editing = Envelope::text(&self.device, delta.encode());
+            editing.content_type = envelope::DELTA.to_string();
+            editing.sensitive = envelope.sensitive;
+            editing.hlc = envelope.hlc;
+            editing.source = envelope.source.clone();
+            editing.tags = envelope.tags.clone();
+            debug!(target: PUBLISH, "sending {} bytes as an edit of {} bytes", envelope.content.len(), editing.content.len());
+            self.publish(self.topic.clone(), &mut editing, e2e.as_deref())?
+        } else if envelope.content.len() > self.lazy_threshold {
+            let offer = Offer {
+                device: self.device.clone(),
+                sha256: envelope::sha256(&envelope.content),
+                size: envelope.content.len(),
+                content_type: envelope.content_type.clone(),
+                preview: envelope::preview(&envelope.content),
+            };
+            let mut offering = Envelope::text(&self.device, offer.encode());
+            offering.content_type = envelope::OFFER.to_string();
+            offering.sensitive = envelope.sensitive;
+            offering.hlc = envelope.hlc;
+            offering.source = envelope.source.clone();
+            offering.tags = envelope.tags.clone();
+            self.keep_offered(offer.sha256, &envelope.content);
+            self.publish(self.topic.clone(), &mut offering, e2e.as_deref())?
+        } else if self.blob_threshold.is_some_and(|threshold| envelope.content.len() > threshold) {
+            let reference = Offer {
+                device: self.device.clone(),
+                sha256: envelope::sha256(&envelope.content),
+                size: envelope.content.len(),
+                content_type: envelope.content_type.clone(),
+                preview: String::new(),
+            };
+            if !self.blobs.is_fresh(&reference.sha256) {
+                self.publish_blob(&reference.sha256, &envelope.content, e2e.as_deref())?;
+            }
+            let mut referring = Envelope::text(&self.device, reference.encode());
+            referring.content_type = envelope::BLOB.to_string();
+            referring.sensitive = envelope.sensitive;
+            referring.hlc = envelope.hlc;
+            referring.source = envelope.source.clone();
+            referring.tags = envelope.tags.clone();
+            self.publish(self.topic.clone(), &mut referring, e2e.as_deref())?
+        } else {
+            self.publish(self.topic.clone(), &mut envelope, e2e.as_deref())?
+        };
+        devices::sent(&*self.store, seq);
+        envelope.seq = Some(seq);
+        self.events.send(SyncEvent::Sent(envelope));
+        Ok(())
+    }
+
+    // Older offers give way to stay within --memory-limit too.
+    fn keep_offered(&self, sha256: String, content: &str) {
+        let mut offered = lock(&self.offered);
+        while offered.len() >= OFFERS_KEPT || (!offered.is_empty() && self.budget.is_over(content.len())) {
+            if let Some((_, oldest)) = offered.pop_back() {
+                self.budget.remove("offers", oldest.len());
+            }
+        }
+        offered.push_front((sha256, content.to_string()));
+        self.budget.add("offers", content.len());
+    }
+
+    // The content itself goes out only sealed, as the hash in the signed
+    // reference is what vouches for it.
+    fn publish_blob(&mut self, sha256: &str, content: &str, e2e: Option<&E2e>) -> Result<(), Box<ClientError>> {
+        let payload = match e2e {
+            Some(e2e) => e2e.seal(content.as_bytes()),
+            None => content.as_bytes().to_vec(),
+        };
+        let properties = PublishProperties { message_expiry_interval: Some(self.blob_expiry), ..Default::default() };
+        self.client.publish_with_properties(format!("{}/{}", self.blob_topic, sha256), QoS::AtLeastOnce, true, payload, properties)?;
+        info!(target: PUBLISH, "publish {} bytes to cloud as a blob", content.len());
+        self.blobs.put(sha256, content);
+        Ok(())
+    }
+
+    // Files are neither deduplicated nor offered, so they always arrive whole
+    // and keep their name.
+    #[cfg(feature = "files")]
+    fn file(&mut self, name: &str, content: String) -> Result<(), Box<ClientError>> {
+        if self.paused.load(Ordering::Relaxed) || self.is_screen_locked(content.len()) {
+            return Ok(());
+        }
+        if self.policy.text_only(self.text_only) {
+            warn!(target: PUBLISH, "not publishing a file, --text-only is set");
+            return Ok(());
+        }
+        let Some(name) = inbox::file_name(name) else {
+            warn!(target: PUBLISH, "not publishing a file without a usable name");
+            return Ok(());
+        };
+        if self.is_paused(None, content.len()) {
+            return Ok(());
+        }
+        if content.len() > self.policy.max_size(self.max_size) {
+            warn!(target: PUBLISH, "not publishing {} bytes, over --max-size", content.len());
+            return Ok(());
+        }
+        if self.is_secret(&content) {
+            return Ok(());
+        }
+        let mut envelope = Envelope::text(&self.device, content);
+        envelope.content_type = inbox::content_type(&name).to_string();
+        envelope.name = Some(name);
+        envelope.hlc = Some(self.clock.now());
+        let e2e = self.e2e.clone();
+        let seq = self.publish(self.topic.clone(), &mut envelope, e2e.as_deref())?;
+        devices::sent(&*self.store, seq);
+        self.events.send(SyncEvent::Sent(envelope));
+        Ok(())
+    }
+
+    fn ack(&mut self, device: String, seq: u64) -> Result<(), Box<ClientError>> {
+        self.signal(envelope::ACK, format!("{device} {seq}"))
+    }
+
+    // Acks, pings and pongs go to the ack topic and are not numbered:
+    // replaying one only repeats what it says, and leaving them out keeps
+    // the sequence to the items themselves.
+    fn signal(&mut self, content_type: &str, content: String) -> Result<(), Box<ClientError>> {
+        let mut signal = Envelope::text(&self.device, content);
+        signal.content_type = content_type.to_string();
+        signal.version = self.envelope_version;
+        signal.device_id = Some(self.device_id.clone());
+        let payload = encode_signal(&signal, self.envelope_encoding, &self.trust, self.e2e.as_deref());
+        self.client.publish(self.ack_topic.clone(), QoS::AtLeastOnce, false, payload).map_err(Box::new)
+    }
+
+    fn probe(&mut self, topics: Vec<String>) -> Result<(), Box<ClientError>> {
+        let mut probe = Envelope::text(&self.device, format!("acl check {}", now_millis()));
+        probe.content_type = envelope::PROBE.to_string();
+        probe.version = self.envelope_version;
+        probe.device_id = Some(self.device_id.clone());
+        let payload = encode_signal(&probe, self.envelope_encoding, &self.trust, self.e2e.as_deref());
+        for topic in topics {
+            self.client.publish(topic, QoS::AtLeastOnce, false, payload.clone())?;
+        }
+        Ok(())
+    }
+
+    // Group content is always sent in full, as offers are only fetched from
+    // devices of the same user.
+    fn share(&mut self, name: &str, content: String) -> Result<(), Box<ClientError>> {
+        let Some(group) = self.groups.iter().find(|group| group.name == name) else {
+            warn!(target: PUBLISH, "not publishing to group {}, it is not in --group", name);
+            return Ok(());
+        };
+        if self.is_paused(Some(name), content.len()) {
+            return Ok(());
+        }
+        let Some(content) = self.fit(content) else {
+            return Ok(());
+        };
+        if self.is_secret(&content) {
+            return Ok(());
+        }
+        let (topic, e2e) = (group.topic.clone(), group.e2e.clone());
+        let mut envelope = Envelope::text(&self.member, content);
+        envelope.sensitive = secrets::find(&envelope.content).is_some();
+        envelope.group = Some(name.to_string());
+        envelope.hlc = Some(self.clock.now());
+        self.publish(topic, &mut envelope, e2e.as_deref()).map(|_| ())
+    }
+
+    // Slots are always retained, and sent in full for devices that look in
+    // them much later.
+    fn slot(&mut self, slot: u32, content: String, template: bool) -> Result<(), Box<ClientError>> {
+        if self.is_paused(None, content.len()) {
+            return Ok(());
+        }
+        let Some(content) = self.fit(content) else {
+            return Ok(());
+        };
+        if self.is_secret(&content) {
+            return Ok(());
+        }
+        let (content, content_type) = self.negotiate(content, if template { envelope::TEMPLATE } else { "text/plain" });
+        let mut envelope = Envelope::text(&self.device, content);
+        envelope.sensitive = secrets::find(&envelope.content).is_some();
+        envelope.slot = Some(slot);
+        envelope.hlc = Some(self.clock.now());
+        envelope.content_type = content_type.to_string();
+        let e2e = self.e2e.clone();
+        self.publish(format!("{}/{}", self.slot_topic, slot), &mut envelope, e2e.as_deref())?;
+        self.slots.put(envelope);
+        Ok(())
+    }
+
+    // Content of a type some device does not take goes out as the plain
+    // text its codec falls back to, for every device alike.
+    fn negotiate(&self, content: String, content_type: &str) -> (String, &'static str) {
+        let codec = codec::find(content_type).unwrap_or(codec::PLAIN);
+        if lock(&self.capabilities).all_take(codec.content_type()) {
+            return (content, codec.content_type());
+        }
+        debug!(target: PUBLISH, "publishing {} as text/plain, a device does not take it", codec.content_type());
+        (codec.fallback(content), codec::PLAIN.content_type())
+    }
+
+    // Content over --max-size is dropped, or with --truncate cut down to
+    // whole characters that fit. Files are never cut.
+    fn fit(&self, content: String) -> Option<String> {
+        let max_size = self.policy.max_size(self.max_size);
+        if content.len() <= max_size {
+            return Some(content);
+        }
+        if !self.truncate {
+            warn!(target: PUBLISH, "not publishing {} bytes, over --max-size", content.len());
+            return None;
+        }
+        let cut = text::truncate(&content, max_size);
+        warn!(target: PUBLISH, "publishing the first {} of {} bytes, over --max-size", cut.len(), content.len());
+        Some(cut.to_string())
+    }
+
+    fn is_screen_locked(&self, size: usize) -> bool {
+        if !self.screen_locked.load(Ordering::Relaxed) {
+            return false;
+        }
+        info!(target: PUBLISH, "not publishing {} bytes copied while the screen is locked", size);
+        true
+    }
+
+    // `group` is None for the personal clipboard.
+    fn is_paused(&self, group: Option<&str>, size: usize) -> bool {
+        if !self.policy.is_paused(group) {
+            return false;
+        }
+        info!(target: PUBLISH, "not publishing {} bytes, the primary device paused {}", size, group.unwrap_or(policy::PERSONAL));
+        true
+    }
+
+    fn is_secret(&self, content: &str) -> bool {
+        let Some(kind) = self.policy.filter_secrets(self.filter_secrets).then(|| secrets::find(content)).flatten() else {
+            return false;
+        };
+        warn!(target: PUBLISH, "not publishing {} bytes that look like they contain a secret ({})", content.len(), kind);
+        true
+    }
+
+    // Returns the sequence number the envelope was published with, which is
+    // also set on it. It is borrowed so the caller can pass it on without
+    // a copy of the content.
+    fn publish(&mut self, topic: String, envelope: &mut Envelope, e2e: Option<&E2e>) -> Result<u64, Box<ClientError>> {
+        let seq = self.sequence.advance();
+        envelope.version = self.envelope_version;
+        envelope.device_id = Some(self.device_id.clone());
+        envelope.seq = Some(seq);
+        let content_len = envelope.content.len();
+        let payload = self.trust.sign(&Zeroizing::new(envelope.encode_as(self.envelope_encoding)));
+        let payload = match e2e {
+            Some(e2e) => e2e.seal(&Zeroizing::new(payload)),
+            None => payload,
+        };
+        // The broker keeps the latest content for devices that start with
+        // --startup adopt.
+        if topic == self.topic {
+            self.journal.record(seq, &topic, &payload);
+        }
+        let retain = topic.starts_with(&self.slot_topic) || self.retain && (topic == self.topic || self.groups.iter().any(|group| group.topic == topic));
+        // Expiry applies to the retained copy, so a clipboard that has not
+        // changed in a while is not handed to every device that starts.
+        if retain && self.retain_expiry > 0 {
+            let properties = PublishProperties { message_expiry_interval: Some(self.retain_expiry), ..Default::default() };
+            self.client.publish_with_properties(topic, QoS::AtLeastOnce, true, payload, properties)?;
+        } else {
+            self.client.publish(topic, QoS::AtLeastOnce, retain, payload)?;
+        }
+        info!(target: PUBLISH, "publish {} bytes to cloud", content_len);
+        self.stats.record(Kind::Sent, &self.device_id, &envelope.content_type, content_len, envelope.source.as_deref(), &envelope.tags);
+        Ok(seq)
+    }
+}
+
+// The rate limit bucket of unsigned messages. Trust list names cannot
+// have spaces, so no device's is this.
+const UNSIGNED: &str = "unsigned messages";
+
+struct Receiver {
+    control_topic: String,
+    fetch_topic: String,
+    ack_topic: String,
+    devices: Devices,
+    response_topic: String,
+    publisher: Publisher,
+    member: String,
+    groups: Arc<Vec<Group>>,
+    offered: Arc<Mutex<VecDeque<(String, String)>>>,
+    dedup: Arc<Mutex<Dedup>>,
+    // The latest offer from another device, which only a fetch response
+    // with matching content may replace.
+    pending: Option<Offer>,
+    blob_topic: String,
+    blobs: Arc<Blobs>,
+    // The latest item that refers to a blob not here yet, by its hash.
+    waiting: Option<(String, Envelope)>,
+    device: String,
+    device_id: String,
+    require_signatures: bool,
+    text_only: bool,
+    sanitize: Vec<Strip>,
+    accept_from: Vec<String>,
+    accept_tag: Vec<String>,
+    adopt_retained: bool,
+    max_size: usize,
+    max_age: Option<Duration>,
+    rate_limit: RateLimit,
+    slot_topic: String,
+    slots: Arc<Slots>,
+    revoke_topic: String,
+    revoked: Revoked,
+    connections: Arc<Connections>,
+    clock: Arc<Clock>,
+    key_grace: Duration,
+    replay_guard: Arc<Mutex<ReplayGuard>>,
+    journal: Arc<Journal>,
+    trust: Arc<Trust>,
+    e2e: Option<Arc<E2e>>,
+    stats: Arc<Recorder>,
+    paused: Arc<AtomicBool>,
+    idle: Arc<Idle>,
+    // What pings go by now, see `keep_alive`.
+    keep_alive: Duration,
+    route: Arc<Mutex<Option<String>>>,
+    content_rules: Arc<Mutex<Vec<Rule>>>,
+    capabilities: Arc<Mutex<codec::Peers>>,
+    policy: Arc<Policy>,
+    policy_topic: String,
+    synced: Arc<Mutex<Option<String>>>,
+    acl: acl::Check,
+    // The topics this device publishes on and hears from itself.
+    probed: Vec<String>,
+    // For reading the certificate again when the broker stops taking it.
+    args: Args,
+    events: Broadcast,
+}
+
+impl Receiver {
+    // Idle, the broker is pinged less often, which takes connecting again
+    // for the broker to expect it. Pinging more often than agreed is fine,
+    // so coming back needs no new connection.
+    fn keep_alive(&mut self, connection: &mut Connection) {
+        let wanted = if self.idle.is_idle() { idle::IDLE_KEEP_ALIVE } else { idle::KEEP_ALIVE };
+        if wanted == self.keep_alive {
+            return;
+        }
+        self.keep_alive = wanted;
+        connection.eventloop.options.set_keep_alive(wanted);
+        if wanted == idle::IDLE_KEEP_ALIVE {
+            info!(target: CONNECT, "idle, reconnecting to ping the broker every {}", humantime::format_duration(wanted));
+            connection.eventloop.clean();
+        } else {
+            info!(target: CONNECT, "active again, pinging the broker every {}", humantime::format_duration(wanted));
+        }
+    }
+
+    fn run(&mut self, mut connection: Connection) {
+        let mut failures = 0;
+        loop {
+            // Waiting is bounded while probes are out.
+            let notification = match self.acl.deadline() {
+                Some(deadline) => match connection.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                    Ok(notification) => notification,
+                    Err(_) => {
+                        if let Some(missing) = self.acl.missing() {
+                            self.refuse(format!("the broker did not forward what this device published to {missing}"));
+                        }
+                        break;
+                    }
+                },
+                None => match connection.recv() {
+                    Ok(notification) => notification,
+                    Err(_) => break,
+                },
+            };
+            self.keep_alive(&mut connection);
+            match notification {
+                Ok(Event::Incoming(Incoming::ConnAck(connack))) => {
+                    let session = if connack.session_present { "resumed the session" } else { "started a new session" };
+                    match connack.properties.and_then(|properties| properties.receive_max) {
+                        Some(receive_max) => self.connections.record("connected", format!("{session}, the broker takes {receive_max} messages in flight")),
+                        None => self.connections.record("connected", session),
+                    }
+                    failures = 0;
+                    let _ = self.publisher.0.send(Outgoing::Online);
+                    if self.acl.start(&self.probed) {
+                        let _ = self.publisher.0.send(Outgoing::Probe(self.probed.clone()));
+                    }
+                    self.events.send(SyncEvent::Connected);
+                }
+                Ok(Event::Incoming(Incoming::SubAck(suback))) => {
+                    let codes: Vec<String> = suback.return_codes.iter().map(|code| format!("{code:?}")).collect();
+                    let reason = suback.properties.and_then(|properties| properties.reason_string).map(|reason| format!(": {reason}"));
+                    self.connections.record("suback", format!("{}{}", codes.join(", "), reason.unwrap_or_default()));
+                    if let Err(problem) = self.acl.suback(&suback.return_codes) {
+                        self.refuse(problem);
+                        break;
+                    }
+                }
+                Ok(Event::Incoming(Incoming::Disconnect(disconnect))) => {
+                    let reason = disconnect.properties.and_then(|properties| properties.reason_string).map(|reason| format!(": {reason}"));
+                    self.connections.record("broker disconnect", format!("{:?}{}", disconnect.reason_code, reason.unwrap_or_default()));
+                }
+                Ok(Event::Incoming(Incoming::Publish(publish))) if self.acl.is_waiting(&String::from_utf8_lossy(&publish.topic)) && self.is_own_probe(&publish) => {
+                    self.acl.returned(&String::from_utf8_lossy(&publish.topic));
+                }
+                Ok(Event::Incoming(Incoming::Publish(publish))) if publish.topic == self.control_topic => {
+                    self.receive_control(&publish.payload);
+                }
+                Ok(Event::Incoming(Incoming::Publish(publish))) if publish.topic == self.fetch_topic => self.serve(&publish),
+                Ok(Event::Incoming(Incoming::Publish(publish))) if publish.topic == self.ack_topic => self.receive_ack(&publish),
+                // Retained, and only subscribed to while wanted.
+                Ok(Event::Incoming(Incoming::Publish(publish))) if publish.topic.starts_with(self.blob_topic.as_bytes()) => self.receive_blob(&publish),
+                Ok(Event::Incoming(Incoming::Publish(publish))) if publish.topic.starts_with(self.slot_topic.as_bytes()) => self.receive_slot(&publish),
+                Ok(Event::Incoming(Incoming::Publish(publish))) if publish.topic.starts_with(self.revoke_topic.as_bytes()) => self.receive_revocation(&publish),
+                Ok(Event::Incoming(Incoming::Publish(publish))) if publish.topic == self.policy_topic => self.receive_policy(&publish),
+                // What `cloudboard clear --remote` sends to remove the
+                // retained content.
+                Ok(Event::Incoming(Incoming::Publish(publish))) if publish.payload.is_empty() => {}
+                Ok(Event::Incoming(Incoming::Publish(publish))) if publish.retain && !self.adopt_retained => {
+                    info!(target: RECEIVE, "ignoring content retained from before startup");
+                }
+                Ok(Event::Incoming(Incoming::Publish(publish))) => {
+                    let Some(envelope) = self.accept(&publish) else {
+                        continue;
+                    };
+                    if publish.topic == self.response_topic {
+                        let expected = self.pending.as_ref().map(|offer| offer.sha256.as_str());
+                        if expected != Some(envelope::sha256(&envelope.content).as_str()) {
+                            warn!(target: RECEIVE, "dropping fetched content that matches no offer");
+                            self.reject(Kind::Dropped, &envelope);
+                            continue;
+                        }
+                        self.pending = None;
+                    }
+                    match (&envelope.source, &envelope.device) {
+                        (Some(source), Some(device)) => info!(target: RECEIVE, "get {} bytes from cloud, copied in {} on {}", envelope.content.len(), source, device),
+                        _ => info!(target: RECEIVE, "get {} bytes from cloud", envelope.content.len()),
+                    }
+                    self.devices.seen(&envelope);
+                    self.stats.record(Kind::Received, envelope.sender(), &envelope.content_type, envelope.content.len(), envelope.source.as_deref(), &envelope.tags);
+                    if envelope.content_type == envelope::OFFER {
+                        // Nothing is applied until the content is fetched,
+                        // and that answer is applied by itself.
+                        self.publisher.applied(&envelope);
+                        if let Some(mut offer) = Offer::decode(envelope.device.as_deref().unwrap_or("unknown"), &envelope.content) {
+                            offer.preview = sanitize(&offer.preview, &self.sanitize).into_owned();
+                            self.pending = Some(offer.clone());
+                            self.events.send(SyncEvent::Offered(offer));
+                        }
+                    } else if envelope.content_type == envelope::BLOB {
+                        self.resolve(envelope);
+                    } else if envelope.content_type == envelope::DELTA {
+                        self.patch(envelope);
+                    } else {
+                        self.idle.touch();
+                        self.deliver(envelope);
+                    }
+                }
+                // Taking over the session is how the broker handles a second
+                // client with this ID, and left alone the two would take it
+                // back from each other on every reconnect.
+                Err(ConnectionError::MqttState(StateError::ServerDisconnect { reason_code: DisconnectReasonCode::SessionTakenOver, .. })) => {
+                    error!(target: CONNECT, "Another client connected with device ID {}, stopping; is a copy of this device's data dir running elsewhere? Remove device.id from the copy to give it an ID of its own", self.device_id);
+                    self.connections.record("taken over", format!("another client connected with device ID {}", self.device_id));
+                    self.events.send(SyncEvent::Disconnected(format!("device ID {} is in use by another client", self.device_id)));
+                    break;
+                }
+                // Each of these drops the connection, and the next notification
+                // reconnects, but they are about one message and not the
+                // link, so nothing backs off.
+                Err(ConnectionError::MqttState(StateError::OutgoingPacketTooLarge { pkt_size, max })) => {
+                    self.publish_failed(PublishFailure::TooLarge { size: pkt_size as usize, max: Some(max as usize) });
+                }
+                // What the broker had not acked is sent again on reconnect,
+                // and the largest of it is what it disconnected over.
+                Err(ConnectionError::MqttState(StateError::ServerDisconnect { reason_code: DisconnectReasonCode::PacketTooLarge, .. })) => {
+                    let largest = connection.eventloop.pending.iter().enumerate().filter_map(|(index, request)| match request {
+                        Request::Publish(publish) => Some((index, publish.payload.len())),
+                        _ => None,
+                    }).max_by_key(|(_, size)| *size);
+                    if let Some((index, size)) = largest {
+                        connection.eventloop.pending.remove(index);
+                        self.publish_failed(PublishFailure::TooLarge { size, max: None });
+                    }
+                }
+                // Refused while probing is the ACL leaving out a topic of
+                // this device's, which no reconnect gets past.
+                Err(ConnectionError::MqttState(StateError::PubAckFail { reason: PubAckReason::NotAuthorized })) if self.acl.deadline().is_some() => {
+                    self.refuse("the broker refused what this device published to its topics".to_string());
+                    break;
+                }
+                Err(ConnectionError::MqttState(StateError::PubAckFail { reason: PubAckReason::NotAuthorized })) => {
+                    self.publish_failed(PublishFailure::NotAuthorized);
+                    if crate::tunnel::hop(&self.args).is_none() {
+                        match crate::cert_sources(&self.args).and_then(|sources| crate::transport(&self.args, &sources)) {
+                            Ok(transport) => {
+                                connection.eventloop.options.set_transport(transport);
+                            }
+                            Err(e) => error!(target: CONNECT, "Failed to read the certificate again: {}", e),
+                        }
+                    }
+                }
+                Err(ConnectionError::MqttState(StateError::PubAckFail { reason })) => self.publish_failed(PublishFailure::Refused(format!("{reason:?}"))),
+                // The next notification is a reconnect attempt, which would
+                // fail in a tight loop while the broker is unreachable, so
+                // attempts back off up to half a minute apart.
+                Err(err) => {
+                    error!(target: CONNECT, "Failed to receive notification: {:?}", err);
+                    self.acl.interrupted();
+                    self.connections.record(if failures == 0 { "disconnected" } else { "connect failed" }, &err);
+                    self.events.send(SyncEvent::Disconnected(err.to_string()));
+                    if failures > 0 {
+                        std::thread::sleep(Duration::from_secs((1 << (failures - 1).min(5)).min(30)));
+                    }
+                    failures += 1;
+                }
+                _ => {}
+            }
+        }
+        self.events.close();
+    }
+
+    // Anyone who ever held the shared key can seal a control message, so a
+    // rotation is only taken when a device in the trust list, or this one,
+    // signed it.
+    // An empty payload is `key rotate` clearing what was retained.
+    fn receive_control(&mut self, payload: &[u8]) {
+        if payload.is_empty() {
+            return;
+        }
+        let Some(keyring) = self.e2e.as_deref().and_then(E2e::keyring) else {
+            return;
+        };
+        let Some(message) = lock(keyring).open(payload) else {
+            warn!(target: RECEIVE, "ignoring control message that could not be decrypted");
+            return;
+        };
+        let (signature, message) = trust::split_signed(&message);
+        let signer = signature
+            .and_then(|signature| self.trust.signed_by(&signature, message, &self.device))
+            .filter(|(_, key)| !self.revoked.is_revoked_key(key));
+        let Some((signer, _)) = signer else {
+            warn!(target: RECEIVE, "ignoring control message that no device in the trust list signed");
+            return;
+        };
+        let message = String::from_utf8_lossy(message);
+
+        if let Some(key) = message.strip_prefix("rotate ").and_then(Key::from_code) {
+            let mut keyring = lock(keyring);
+            if !keyring.contains(&key.id) {
+                info!("rotating to key {} from {}", key.id, signer);
+                keyring.rotate(key, self.key_grace);
+                if let Err(e) = keyring.save() {
+                    error!("Failed to save keyring: {}", e);
+                }
+            }
+        }
+    }
+
+    fn is_own_probe(&self, publish: &Publish) -> bool {
+        let Some(payload) = unseal(self.e2e.as_deref(), &publish.payload) else {
+            return false;
+        };
+        let (_, payload) = trust::split_signed(&payload);
+        Envelope::decode(payload).is_some_and(|probe| probe.content_type == envelope::PROBE && probe.device_id.as_deref() == Some(self.device_id.as_str()))
+    }
+
+    // The message is gone either way, and probes out on the dropped
+    // connection are sent again on the next.
+    fn publish_failed(&mut self, failure: PublishFailure) {
+        warn!(target: PUBLISH, "{}", failure);
+        self.acl.interrupted();
+        self.connections.record("publish failed", &failure);
+        self.events.send(SyncEvent::PublishFailed(failure));
+    }
+
+    // Stops for good, as the broker would keep this device from syncing.
+    fn refuse(&mut self, problem: String) {
+        error!(target: CONNECT, "Stopping, {}; check the broker's ACL for this device, `cloudboard gen-broker-config` prints one that works", problem);
+        self.connections.record("acl check failed", &problem);
+        self.events.send(SyncEvent::Disconnected(problem));
+    }
+
+    // Counted against the trust list entry that checked the signature, so
+    // forged messages cannot use up a real device's allowance. Everything
+    // unsigned shares one.
+    fn allow(&mut self, signer: Option<&str>) -> bool {
+        self.rate_limit.allow(signer.unwrap_or(UNSIGNED))
+    }
+
+    // Runs a message through every check on the receive path, recording it
+    // as dropped or filtered if any of them rejects it.
+    fn accept(&mut self, publish: &Publish) -> Option<Envelope> {
+        let group = self.groups.iter().find(|group| group.topic == publish.topic);
+        let e2e = match group {
+            Some(group) => group.e2e.clone(),
+            None => self.e2e.clone(),
+        };
+        let Some(payload) = unseal(e2e.as_deref(), &publish.payload) else {
+            self.stats.record(Kind::Dropped, "unknown", "unknown", publish.payload.len(), None, &[]);
+            return None;
+        };
+        let (signature, payload) = trust::split_signed(&payload);
+        let Some(mut envelope) = Envelope::decode(payload) else {
+            self.stats.record(Kind::Dropped, "unknown", "unknown", publish.payload.len(), None, &[]);
+            return None;
+        };
+        if envelope.content_type == envelope::PROBE {
+            return None;
+        }
+        let sender = envelope.device.as_deref().unwrap_or("unknown");
+        let id = envelope.sender();
+        if !envelope.is_supported() {
+            warn!(target: RECEIVE, "dropping version {} envelope from {}, upgrade this device to read it", envelope.version, sender);
+            return self.reject(Kind::Dropped, &envelope);
+        }
+        let group = group.map(|group| group.name.clone());
+        if self.is_own(&envelope, if group.is_some() { &self.member } else { &self.device }) {
+            return None;
+        }
+        if self.revoked.is_revoked(&envelope) {
+            warn!(target: RECEIVE, "dropping message from {}, it was revoked", sender);
+            return self.reject(Kind::Dropped, &envelope);
+        }
+        if envelope.group != group {
+            warn!(target: RECEIVE, "dropping message from {} meant for another clipboard", sender);
+            return self.reject(Kind::Dropped, &envelope);
+        }
+        // The size limit comes before the signature check so a flood costs
+        // as little as possible.
+        if envelope.content.len() > self.policy.max_size(self.max_size) {
+            warn!(target: RECEIVE, "dropping {} bytes from {}, over --max-size", envelope.content.len(), sender);
+            return self.reject(Kind::Limited, &envelope);
+        }
+        if self.policy.is_paused(envelope.group.as_deref()) {
+            info!(target: RECEIVE, "ignoring message from {}, the primary device paused {}", sender, envelope.group.as_deref().unwrap_or(policy::PERSONAL));
+            return self.reject(Kind::Filtered, &envelope);
+        }
+        if self.policy.text_only(self.text_only) && (!(codec::find(&envelope.content_type).is_some_and(|codec| codec.is_text()) || envelope.content_type == envelope::DELTA) || envelope.name.is_some()) {
+            warn!(target: RECEIVE, "dropping {} from {}, --text-only is set", envelope.content_type, sender);
+            return self.reject(Kind::Filtered, &envelope);
+        }
+
+        let signer = match self.verify(&envelope, signature.as_deref(), payload) {
+            Ok(signer) => signer,
+            Err(why) => {
+                warn!(target: RECEIVE, "dropping message from {}, {}", sender, why);
+                return self.reject(Kind::Dropped, &envelope);
+            }
+        };
+        if !self.allow(signer.as_deref()) {
+            warn!(target: RECEIVE, "dropping message from {}, over --max-rate", sender);
+            return self.reject(Kind::Limited, &envelope);
+        }
+        // The name or ID a message claims is not to be gone by, only the
+        // trust list entry that checked its signature.
+        if !self.accept_from.is_empty() && !signer.as_ref().is_some_and(|signer| self.accept_from.contains(signer)) {
+            info!(target: RECEIVE, "ignoring message from {}, it is not in accept_from", sender);
+            return self.reject(Kind::Filtered, &envelope);
+        }
+        if !self.accept_tag.is_empty() && !envelope.tags.iter().any(|tag| self.accept_tag.contains(tag)) {
+            info!(target: RECEIVE, "ignoring message from {}, it has none of the tags in accept_tag", sender);
+            return self.reject(Kind::Filtered, &envelope);
+        }
+        // The age is measured against the sender's clock, so skew between
+        // devices shifts the cutoff by the same amount.
+        if let (Some(max_age), Some(hlc)) = (self.max_age, envelope.hlc) {
+            let age = stats::now().saturating_sub(hlc.millis / 1000);
+            if age > max_age.as_secs() {
+                info!(target: RECEIVE, "ignoring message from {}, it was queued for {}s", sender, age);
+                return self.reject(Kind::Filtered, &envelope);
+            }
+        }
+        if let Some(seq) = envelope.seq.filter(|seq| envelope.device.is_some() && lock(&self.replay_guard).is_committed(id, *seq)) {
+            // The sender is publishing from its journal again, having
+            // missed the ack; another one lets it move on.
+            info!(target: RECEIVE, "ignoring item {} from {}, it was already applied", seq, sender);
+            self.publisher.applied(&envelope);
+            return None;
+        }
+        if envelope.device.is_some() && !lock(&self.replay_guard).accept(id, envelope.seq) {
+            return self.reject(Kind::Dropped, &envelope);
+        }
+        // From here on the item is decided against for good, which counts
+        // as applying it.
+        if self.paused.load(Ordering::Relaxed) {
+            info!(target: RECEIVE, "sync paused, ignoring message from cloud");
+            self.publisher.applied(&envelope);
+            return self.reject(Kind::Dropped, &envelope);
+        }
+        if let Some(route) = lock(&self.route).as_deref().filter(|_| envelope.group.is_none()) {
+            info!(target: RECEIVE, "ignoring message from {}, a rule sends this device's changes to group {}", sender, route);
+            self.publisher.applied(&envelope);
+            return self.reject(Kind::Filtered, &envelope);
+        }
+        // Queued or delayed messages can arrive after newer content, which
+        // must not be overwritten with them.
+        if let Some(hlc) = envelope.hlc {
+            if !self.clock.observe(hlc) {
+                info!(target: RECEIVE, "ignoring message from {}, it is older than the current clipboard", sender);
+                self.publisher.applied(&envelope);
+                return self.reject(Kind::Filtered, &envelope);
+            }
+        }
+        envelope.signer = signer;
+        Some(envelope)
+    }
+
+    // Acked and committed once whoever consumes the event calls
+    // `Publisher::applied`. Anything still waiting for its blob is older
+    // and given up on.
+    fn deliver(&mut self, mut envelope: Envelope) {
+        if let Some((sha256, waiting)) = self.waiting.take() {
+            self.publisher.applied(&waiting);
+            let _ = self.publisher.0.send(Outgoing::Unsubscribe(format!("{}/{}", self.blob_topic, sha256)));
+        }
+        if envelope.name.is_none() && envelope.group.is_none() {
+            *lock(&self.synced) = Some(envelope.content.clone());
+        }
+        // Files are saved rather than pasted, and keep what they were sent
+        // with.
+        if envelope.name.is_none() {
+            if let Cow::Owned(sanitized) = sanitize(&envelope.content, &self.sanitize) {
+                info!(target: RECEIVE, "removed {} bytes of escapes or control characters from what {} sent", envelope.content.len() - sanitized.len(), envelope.device.as_deref().unwrap_or("unknown"));
+                envelope.content = sanitized;
+            }
+            if envelope.group.is_none() {
+                let dropped_by = rules::matching(&lock(&self.content_rules), &envelope.content, envelope.source.as_deref()).filter(|rule| rule.drops()).map(|rule| rule.name().to_string());
+                if let Some(rule) = dropped_by {
+                    info!(target: RECEIVE, "ignoring message from {}, rule {} drops it", envelope.device.as_deref().unwrap_or("unknown"), rule);
+                    self.publisher.applied(&envelope);
+                    self.reject(Kind::Filtered, &envelope);
+                    return;
+                }
+            }
+        }
+        lock(&self.dedup).remember(&envelope.content);
+        self.events.send(SyncEvent::Received(envelope));
+    }
+
+    // Applies an edit to the last item synced. Without that item, or if the
+    // result is not what was sent, the whole item is fetched instead.
+    fn patch(&mut self, mut envelope: Envelope) {
+        let sender = envelope.device.clone().unwrap_or_else(|| "unknown".to_string());
+        let Some(delta) = Delta::decode(&envelope.content) else {
+            warn!(target: RECEIVE, "dropping an invalid edit from {}", sender);
+            self.publisher.applied(&envelope);
+            return;
+        };
+        let patched = lock(&self.synced).as_deref().and_then(|base| delta.apply(base));
+        match patched {
+            Some(content) if content.len() > self.policy.max_size(self.max_size) => {
+                warn!(target: RECEIVE, "dropping {} bytes from {}, over --max-size", content.len(), sender);
+                self.publisher.applied(&envelope);
+                self.reject(Kind::Limited, &envelope);
+            }
+            Some(content) => {
+                envelope.content = content;
+                envelope.content_type = delta.content_type;
+                self.deliver(envelope);
+            }
+            None => {
+                info!(target: RECEIVE, "fetching the whole of an edit from {}, it does not apply here", sender);
+                self.publisher.applied(&envelope);
+                self.pending = Some(Offer { device: sender, sha256: delta.sha256.clone(), size: 0, content_type: delta.content_type, preview: String::new() });
+                let _ = self.publisher.0.send(Outgoing::Fetch(delta.sha256));
+            }
+        }
+    }
+
+    // Puts the content in place of a reference, from the blobs here or once
+    // it arrives on the blob topic.
+    fn resolve(&mut self, mut envelope: Envelope) {
+        let reference = Offer::decode(envelope.sender(), &envelope.content).filter(|reference| blob::is_hash(&reference.sha256));
+        let Some(reference) = reference else {
+            warn!(target: RECEIVE, "dropping an invalid blob reference from {}", envelope.device.as_deref().unwrap_or("unknown"));
+            self.publisher.applied(&envelope);
+            return;
+        };
+        envelope.content_type = reference.content_type;
+        match self.blobs.get(&reference.sha256) {
+            Some(content) => {
+                envelope.content = content;
+                self.deliver(envelope);
+            }
+            None => {
+                debug!(target: RECEIVE, "fetching a blob of {} bytes", reference.size);
+                if let Some((_, waiting)) = self.waiting.take() {
+                    self.publisher.applied(&waiting);
+                }
+                let topic = format!("{}/{}", self.blob_topic, reference.sha256);
+                let _ = self.publisher.0.send(Outgoing::Subscribe(topic));
+                self.waiting = Some((reference.sha256, envelope));
+            }
+        }
+    }
+
+    fn receive_blob(&mut self, publish: &Publish) {
+        let topic = std::str::from_utf8(&publish.topic).unwrap_or_default();
+        let sha256 = topic.strip_prefix(&self.blob_topic).and_then(|rest| rest.strip_prefix('/'));
+        if sha256.is_none() || self.waiting.as_ref().map(|(wanted, _)| wanted.as_str()) != sha256 {
+            return;
+        }
+        let Some((sha256, mut envelope)) = self.waiting.take() else {
+            return;
+        };
+        let _ = self.publisher.0.send(Outgoing::Unsubscribe(topic.to_string()));
+        let content = unseal(self.e2e.as_deref(), &publish.payload)
+            .and_then(|content| String::from_utf8(content.to_vec()).ok())
+            .filter(|content| envelope::sha256(content) == sha256);
+        let Some(content) = content else {
+            warn!(target: RECEIVE, "dropping a blob that does not match its hash");
+            self.publisher.applied(&envelope);
+            return;
+        };
+        info!(target: RECEIVE, "get {} bytes from cloud as a blob", content.len());
+        self.blobs.put(&sha256, &content);
+        envelope.content = content;
+        self.deliver(envelope);
+    }
+
+    // Slots are kept rather than applied, including what this device put
+    // in them, which it reads back from the retained copy after a restart.
+    // Only the checks that protect the content apply.
+    // This device's own revocations, published by `cloudboard revoke`, are
+    // taken as they are, which brings the daemon up to date with it.
+    fn receive_revocation(&mut self, publish: &Publish) {
+        let Some(payload) = unseal(self.e2e.as_deref(), &publish.payload) else {
+            return;
+        };
+        let (signature, payload) = trust::split_signed(&payload);
+        let Some(revocation) = Envelope::decode(payload).filter(Envelope::is_supported) else {
+            return;
+        };
+        let signer = self.verify(&revocation, signature.as_deref(), payload).ok().flatten();
+        let key = revoke::key_name(&self.trust.verifying_key());
+        self.revoked.receive(&revocation, signer.as_deref(), &self.trust, [&self.device, &self.device_id, &key]);
+    }
+
+    fn receive_policy(&mut self, publish: &Publish) {
+        let Some(payload) = unseal(self.e2e.as_deref(), &publish.payload) else {
+            return;
+        };
+        let (signature, payload) = trust::split_signed(&payload);
+        let Some(published) = Envelope::decode(payload).filter(Envelope::is_supported) else {
+            return;
+        };
+        let signer = self.verify(&published, signature.as_deref(), payload).ok().flatten();
+        self.policy.receive(&published, signer.as_deref());
+    }
+
+    fn receive_slot(&mut self, publish: &Publish) {
+        let topic = std::str::from_utf8(&publish.topic).unwrap_or_default();
+        let slot = topic.strip_prefix(&self.slot_topic).and_then(|rest| rest.strip_prefix('/')).and_then(|slot| slot.parse().ok());
+        let Some(payload) = unseal(self.e2e.as_deref(), &publish.payload) else {
+            return;
+        };
+        let (signature, payload) = trust::split_signed(&payload);
+        let Some(mut envelope) = Envelope::decode(payload).filter(|envelope| envelope.slot.is_some() && envelope.slot == slot) else {
+            warn!(target: RECEIVE, "dropping a message on {} meant for another clipboard", topic);
+            return;
+        };
+        let sender = envelope.device.as_deref().unwrap_or("unknown");
+        if self.revoked.is_revoked(&envelope) {
+            warn!(target: RECEIVE, "dropping message from {}, it was revoked", sender);
+            return;
+        }
+        let signer = match self.verify(&envelope, signature.as_deref(), payload) {
+            Ok(signer) => signer,
+            Err(why) => {
+                warn!(target: RECEIVE, "dropping message from {}, {}", sender, why);
+                return;
+            }
+        };
+        if envelope.content.len() > self.policy.max_size(self.max_size) {
+            warn!(target: RECEIVE, "dropping {} bytes from {}, over --max-size", envelope.content.len(), sender);
+            return;
+        }
+        envelope.signer = signer;
+        let size = envelope.content.len();
+        if let Some(slot) = slot.filter(|_| self.slots.put(envelope)) {
+            info!(target: RECEIVE, "get {} bytes from cloud into slot {}", size, slot);
+        }
+    }
+
+    // Answers requests for content this device offered. Only holders of the
+    // end-to-end keys can read the answer, so the request itself needs no
+    // more than the signature policy.
+    fn serve(&mut self, publish: &Publish) {
+        let Some(payload) = unseal(self.e2e.as_deref(), &publish.payload) else {
+            return;
+        };
+        let (signature, payload) = trust::split_signed(&payload);
+        let Some(request) = Envelope::decode(payload).filter(|request| request.is_supported() && request.content_type == envelope::FETCH) else {
+            return;
+        };
+        if self.is_own(&request, &self.device) || self.revoked.is_revoked(&request) {
+            return;
+        }
+        let Some(requester) = request.device.clone() else {
+            return;
+        };
+        let signer = match self.verify(&request, signature.as_deref(), payload) {
+            Ok(signer) => signer,
+            Err(why) => {
+                warn!(target: RECEIVE, "ignoring fetch request from {}, {}", requester, why);
+                return;
+            }
+        };
+        if !self.allow(signer.as_deref()) {
+            warn!(target: RECEIVE, "ignoring fetch request from {}, over --max-rate", requester);
+            return;
+        }
+
+        let content = lock(&self.offered).iter().find(|(sha256, _)| *sha256 == request.content).map(|(_, content)| content.clone());
+        if let Some(content) = content {
+            info!(target: RECEIVE, "sending {} offered bytes to {}", content.len(), requester);
+            let _ = self.publisher.0.send(Outgoing::Fetched { device: requester, content });
+        }
+    }
+
+    fn receive_ack(&mut self, publish: &Publish) {
+        let Some(payload) = unseal(self.e2e.as_deref(), &publish.payload) else {
+            return;
+        };
+        let (signature, payload) = trust::split_signed(&payload);
+        let Some(signal) = Envelope::decode(payload).filter(Envelope::is_supported) else {
+            return;
+        };
+        if self.is_own(&signal, &self.device) {
+            if signal.content_type == envelope::PING {
+                self.pong(None, signal.content.split(' ').next());
+            }
+            return;
+        }
+        if self.revoked.is_revoked(&signal) {
+            return;
+        }
+        let Ok(signer) = self.verify(&signal, signature.as_deref(), payload) else {
+            return;
+        };
+        // Acks and pongs name the device they answer by ID, or by name
+        // when they come from a release from before device IDs.
+        let addressed = signal.content.split_once(' ');
+        let is_me = |device: &str| device == self.device_id || device == self.device;
+        match signal.content_type.as_str() {
+            envelope::ACK => match addressed {
+                Some((device, seq)) if is_me(device) => match seq.parse() {
+                    Ok(seq) => {
+                        self.devices.acked(&signal, seq);
+                        if let Some(acked) = self.devices.min_acked() {
+                            self.journal.acked(acked);
+                        }
+                    }
+                    Err(_) => self.devices.seen(&signal),
+                },
+                _ => self.devices.seen(&signal),
+            },
+            envelope::PING => {
+                self.devices.seen(&signal);
+                let sent = addressed.filter(|(_, device)| *device == "*" || is_me(device)).and_then(|(sent, _)| sent.parse().ok());
+                if let Some(sent) = sent.filter(|_| self.allow(signer.as_deref())) {
+                    let _ = self.publisher.0.send(Outgoing::Pong { device: signal.sender().to_string(), sent });
+                }
+            }
+            envelope::PONG => {
+                self.devices.seen(&signal);
+                if let Some((_, sent)) = addressed.filter(|(device, _)| is_me(device)) {
+                    self.pong(Some(&signal), Some(sent));
+                }
+            }
+            envelope::PRESENCE => {
+                let online = signal.content == "online";
+                self.devices.presence(&signal, online);
+                let device = signal.device.clone().unwrap_or_else(|| signal.sender().to_string());
+                if !online {
+                    info!(target: RECEIVE, "{} went offline", device);
+                }
+                self.events.send(SyncEvent::Presence { device, online });
+            }
+            envelope::CAPABILITIES => {
+                self.devices.seen(&signal);
+                lock(&self.capabilities).announced(signal.sender(), &signal.content);
+            }
+            _ => {}
+        }
+    }
+
+    // `sent` is when the ping left, by this device's clock. Without a pong
+    // it is this device's own ping, come back from the broker.
+    fn pong(&self, pong: Option<&Envelope>, sent: Option<&str>) {
+        let Some(sent) = sent.and_then(|sent| sent.parse::<u64>().ok()) else {
+            return;
+        };
+        let rtt = Duration::from_millis(now_millis().saturating_sub(sent));
+        self.stats.record(Kind::Ping, pong.map_or("-", Envelope::sender), "-", rtt.as_millis() as usize, None, &[]);
+        let device = pong.map(|pong| pong.device.clone().unwrap_or_else(|| pong.sender().to_string()));
+        self.events.send(SyncEvent::Pong { device, rtt });
+    }
+
+    // A message is this device's own if it carries its ID, or for one
+    // without an ID, its name, which in a group is `member`.
+    fn is_own(&self, envelope: &Envelope, name: &str) -> bool {
+        match &envelope.device_id {
+            Some(id) => *id == self.device_id,
+            None => envelope.device.as_deref() == Some(name),
+        }
+    }
+
+    // The name of the trust list entry whose key checked the signature, or
+    // None with no entry to check it against. Entries can be made under a
+    // device's ID, which survives renaming it, or under its name, and the ID
+    // goes first. A device the trust list has a key for is only taken at its
+    // word if it signed, or anyone who can publish could pass for it; this
+    // device's own messages, which it takes back from the broker, are
+    // checked against its own key. Err says why the message is dropped.
+    fn verify(&self, envelope: &Envelope, signature: Option<&[u8]>, payload: &[u8]) -> Result<Option<String>, &'static str> {
+        let signer = match envelope.device_id.as_deref() {
+            Some(id) if id == self.device_id => Some((self.device.clone(), self.trust.verifying_key())),
+            id => id.and_then(|id| self.trust.signer(id)).or_else(|| self.trust.signer(envelope.device.as_deref()?)),
+        };
+        let Some((name, key)) = signer else {
+            return match (self.require_signatures, self.revoked.any()) {
+                (true, _) => Err("it is not signed by a device in the trust list"),
+                (false, true) => Err("it is not signed by a device in the trust list, which it must be once a device is revoked"),
+                (false, false) => Ok(None),
+            };
+        };
+        match signature {
+            Some(_) if self.revoked.is_revoked_key(&key) => Err("it is signed with a revoked key"),
+            Some(signature) if trust::verify(&key, signature, payload) => Ok(Some(name)),
+            Some(_) => Err("its signature is invalid"),
+            None => Err("it is unsigned, and the trust list has a key for the device it claims to be"),
+        }
+    }
+
+    fn reject(&self, kind: Kind, envelope: &Envelope) -> Option<Envelope> {
+        self.stats.record(kind, envelope.sender(), &envelope.content_type, envelope.content.len(), envelope.source.as_deref(), &envelope.tags);
+        self.events.send(SyncEvent::Rejected {
+            device: envelope.device.clone().unwrap_or_else(|| "unknown".to_string()),
+            decision: kind,
+            content_type: envelope.content_type.clone(),
+            size: envelope.content.len(),
+        });
+        None
+    }
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64
+}
+
+// Signs a signal and seals it for whoever reads the ack topic.
+fn encode_signal(signal: &Envelope, encoding: Encoding, trust: &Trust, e2e: Option<&E2e>) -> Vec<u8> {
+    let payload = trust.sign(&Zeroizing::new(signal.encode_as(encoding)));
+    match e2e {
+        Some(e2e) => e2e.seal(&Zeroizing::new(payload)),
+        None => payload,
+    }
+}
+
+// Unencrypted payloads are borrowed as they came from the broker, so the
+// only copy of their content is the one the envelope is decoded into.
+// Decrypted ones are wiped once that is done.
+enum Unsealed<'a> {
+    Plain(&'a [u8]),
+    Opened(Plaintext),
+}
+
+impl Deref for Unsealed<'_> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            Unsealed::Plain(payload) => payload,
+            Unsealed::Opened(payload) => payload,
+        }
+    }
+}
+
+fn unseal<'a>(e2e: Option<&E2e>, payload: &'a [u8]) -> Option<Unsealed<'a>> {
+    match e2e {
+        Some(e2e) if crypto::is_sealed(payload) => e2e.open(payload).map(Unsealed::Opened),
+        Some(_) => {
+            warn!(target: RECEIVE, "ignoring unencrypted message");
+            None
+        }
+        None if crypto::is_sealed(payload) => {
+            warn!(target: RECEIVE, "ignoring encrypted message, end-to-end encryption is not configured");
+            None
+        }
+        None => Some(Unsealed::Plain(payload)),
+    }
+}
+
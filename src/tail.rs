@@ -0,0 +1,95 @@
+use std::collections::HashMap;
+use std::io::IsTerminal;
+use std::path::Path;
+use crate::http;
+use crate::json::parse_object;
+use crate::stats::{self, format_bytes};
+use crate::when::Render;
+
+const RED: &str = "31";
+const GREEN: &str = "32";
+const YELLOW: &str = "33";
+const BLUE: &str = "34";
+const MAGENTA: &str = "35";
+const CYAN: &str = "36";
+const DIM: &str = "2";
+
+#[derive(clap::Args, Debug)]
+pub struct TailArgs {
+    /// Only show events from or about these devices
+    #[arg(long, value_delimiter = ',')]
+    device: Vec<String>,
+    /// Only show these events, e.g. received,rejected
+    #[arg(long, value_delimiter = ',')]
+    event: Vec<String>,
+    /// Never color the output, which is otherwise colored on a terminal unless NO_COLOR is set
+    #[arg(long)]
+    no_color: bool,
+}
+
+// The daemon's events as they happen, one line each with the time of day.
+// Contents are never shown, only their size and type.
+pub fn run(data_dir: &Path, args: &TailArgs, render: Render) {
+    let color = !args.no_color && std::env::var_os("NO_COLOR").is_none() && std::io::stdout().is_terminal();
+    let result = http::stream(data_dir, "/events", |line| {
+        let Some(event) = parse_object(line) else {
+            return;
+        };
+        let get = |key: &str| event.get(key).map(String::as_str).filter(|value| *value != "null");
+        let name = get("event").unwrap_or("unknown");
+        if !args.event.is_empty() && !args.event.iter().any(|wanted| wanted == name || Some(wanted.as_str()) == get("decision")) {
+            return;
+        }
+        if !args.device.is_empty() && !get("device").is_some_and(|device| args.device.iter().any(|wanted| wanted == device)) {
+            return;
+        }
+        if let Some((label, code, detail)) = describe(&event) {
+            let time = render.clock(stats::now());
+            let device = get("device").unwrap_or("-");
+            match color {
+                true => println!("\x1b[{DIM}m{time}\x1b[0m \x1b[{code}m{label:<12}\x1b[0m {device:<16} {detail}"),
+                false => println!("{time} {label:<12} {device:<16} {detail}"),
+            }
+        }
+    });
+    if let Err(e) = result {
+        eprintln!("Failed to attach to the daemon: {}", e);
+        std::process::exit(1);
+    }
+}
+
+// The label, its color and what follows the device name.
+fn describe(event: &HashMap<String, String>) -> Option<(String, &'static str, String)> {
+    let get = |key: &str| event.get(key).map(String::as_str).filter(|value| *value != "null");
+    let size = |key: &str| get(key).and_then(|size| size.parse().ok()).map(format_bytes).unwrap_or_default();
+    let item = |size: String| {
+        let mut detail = format!("{size:>10}  {}", get("type").unwrap_or("-"));
+        if let Some(seq) = get("seq") {
+            detail += &format!("  seq {seq}");
+        }
+        if let Some(group) = get("group") {
+            detail += &format!("  group {group}");
+        }
+        if let Some(source) = get("source") {
+            detail += &format!("  from {source}");
+        }
+        detail
+    };
+    let content_size = || format_bytes(get("content").map_or(0, str::len));
+    Some(match get("event")? {
+        "received" => ("received".to_string(), GREEN, item(content_size())),
+        "sent" => ("sent".to_string(), BLUE, item(content_size())),
+        "offered" => ("offered".to_string(), CYAN, item(size("size"))),
+        "rejected" => {
+            let decision = get("decision").unwrap_or("rejected");
+            let code = if decision == "filtered" { YELLOW } else { RED };
+            (decision.to_string(), code, item(size("size")))
+        }
+        "connected" => ("connected".to_string(), GREEN, String::new()),
+        "disconnected" => ("disconnected".to_string(), RED, get("error").unwrap_or_default().to_string()),
+        "presence" if get("online") == Some("true") => ("online".to_string(), MAGENTA, String::new()),
+        "presence" => ("offline".to_string(), MAGENTA, String::new()),
+        "pong" => ("pong".to_string(), DIM, format!("{} ms", get("rtt_ms").unwrap_or("?"))),
+        other => (other.to_string(), DIM, String::new()),
+    })
+}
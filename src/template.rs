@@ -0,0 +1,51 @@
+use crate::{init, stats, when};
+
+// Templates are published with `cloudboard push --template` or `slot copy
+// --template`, and filled in by each device as it applies them, so a
+// snippet reads right wherever it is pasted: `{date}` and `{time}` in the
+// local timezone, `{hostname}`, and `{clipboard}` for what was on the
+// clipboard just before. `{{` and `}}` stand for braces, and anything else
+// in braces is left as it is.
+pub fn expand(template: &str, clipboard: impl FnOnce() -> Option<String>) -> String {
+    let render = when::Render::new(false);
+    let now = stats::now();
+    let mut clipboard = Some(clipboard);
+    let mut current = None;
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find(['{', '}']) {
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+        if let Some(tail) = rest.strip_prefix("{{").or_else(|| rest.strip_prefix("}}")) {
+            out.push_str(&rest[..1]);
+            rest = tail;
+            continue;
+        }
+        let placeholder = rest.strip_prefix('{').and_then(|tail| tail.split_once('}')).map(|(name, _)| name);
+        let value = match placeholder {
+            Some("date") => Some(render.date(now)),
+            Some("time") => Some(render.clock(now)),
+            Some("hostname") => Some(init::hostname().unwrap_or_default()),
+            Some("clipboard") => {
+                // Read once however often it comes up.
+                if let Some(read) = clipboard.take() {
+                    current = Some(read().unwrap_or_default());
+                }
+                current.clone()
+            }
+            _ => None,
+        };
+        match (placeholder, value) {
+            (Some(name), Some(value)) => {
+                out.push_str(&value);
+                rest = &rest[name.len() + 2..];
+            }
+            _ => {
+                out.push_str(&rest[..1]);
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
@@ -0,0 +1,45 @@
+use std::sync::OnceLock;
+use regex::Regex;
+
+// Extended grapheme clusters as UAX #29 spells them out as a regular
+// expression, so a cut never separates an emoji from its modifier, a flag's
+// two halves, a letter from its accents or a Hangul syllable's jamo. The
+// conjunct rule for Indic scripts is not covered.
+const GRAPHEME: &str = r"(?x)
+    \r\n
+  | \p{gcb=Control} | \p{gcb=CR} | \p{gcb=LF}
+  | \p{gcb=Prepend}*
+    (
+        \p{gcb=L}* (\p{gcb=V}+ | \p{gcb=LV} \p{gcb=V}* | \p{gcb=LVT}) \p{gcb=T}*
+      | \p{gcb=L}+
+      | \p{gcb=T}+
+      | \p{gcb=RI} \p{gcb=RI}
+      | \p{Extended_Pictographic} (\p{gcb=Extend}* \p{gcb=ZWJ} \p{Extended_Pictographic})*
+      | [^\p{gcb=Control}\p{gcb=CR}\p{gcb=LF}]
+    )
+    [\p{gcb=Extend}\p{gcb=ZWJ}\p{gcb=SpacingMark}]*
+  | \p{any}
+";
+
+fn grapheme() -> &'static Regex {
+    static COMPILED: OnceLock<Regex> = OnceLock::new();
+    COMPILED.get_or_init(|| Regex::new(GRAPHEME).unwrap())
+}
+
+pub fn graphemes(text: &str) -> impl Iterator<Item = &str> {
+    grapheme().find_iter(text).map(|cluster| cluster.as_str())
+}
+
+// The longest start of `text` that fits in `max_bytes` and ends between
+// two grapheme clusters.
+pub fn truncate(text: &str, max_bytes: usize) -> &str {
+    if text.len() <= max_bytes {
+        return text;
+    }
+    let end = grapheme().find_iter(text)
+        .map(|cluster| cluster.end())
+        .take_while(|end| *end <= max_bytes)
+        .last()
+        .unwrap_or(0);
+    &text[..end]
+}
@@ -0,0 +1,331 @@
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use log::{error, info, warn};
+use regex::Regex;
+use crate::config::{Config, Value};
+use crate::envelope::Envelope;
+use crate::lock::lock;
+use crate::sync::SyncEvent;
+
+// Variables kept for triggers that do not set `inherit_env`, enough for
+// commands to be found and behave.
+const KEPT_ENV: &[&str] = &["PATH", "HOME", "LANG", "TMPDIR"];
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum On {
+    Received,
+    Sent,
+    Both,
+    Connected,
+    Disconnected,
+    // The connection has been down for `after`.
+    Outage,
+    // Another device connected, or dropped off without disconnecting.
+    DeviceOnline,
+    DeviceOffline,
+}
+
+// A `[trigger.<name>]` table in the config file, e.g.
+//
+//   [trigger.magnet]
+//   match = "^magnet:\\?"
+//   command = ["transmission-remote", "--add", "-"]
+//   devices = ["nas"]
+//
+// The command is run directly, never through a shell, with the content on
+// stdin, the sender in CLOUDBOARD_DEVICE and the item's tags, comma
+// separated, in CLOUDBOARD_TAGS. With `tags = ["work", "url"]` it runs only
+// for items with one of them.
+//
+// Received content only runs triggers once it is signed by a device in the
+// trust list, and with `from = ["laptop"]` only by these, as the trust list
+// names them. `unsigned = true` lets in content without a signature too,
+// for devices that sync without signing. Content held as suspicious, or
+// kept while in standby, runs none.
+//
+// A trigger is not sandboxed: its command runs as this user, with only
+// PATH, HOME, LANG and TMPDIR of the environment unless `inherit_env =
+// true`, in `cwd` if set, and is killed after `timeout` seconds, 30 unless
+// set. Anything stricter is up to the command, like a wrapper that drops
+// privileges.
+//
+// Triggers on the broker connection run with nothing on stdin, the event in
+// CLOUDBOARD_EVENT and, once it is down, the last error in CLOUDBOARD_ERROR.
+// `device-online` and `device-offline` run when another device connects or
+// drops off, with its name in CLOUDBOARD_DEVICE:
+//
+//   [trigger.offline]
+//   on = "outage"
+//   after = 300
+//   command = ["curl", "-d", "cloudboard lost the broker", "https://ntfy.sh/my-alerts"]
+pub struct Trigger {
+    name: String,
+    pattern: Option<Regex>,
+    content_type: Option<String>,
+    tags: Option<Vec<String>>,
+    from: Option<Vec<String>>,
+    unsigned: bool,
+    on: On,
+    after: Duration,
+    command: Vec<String>,
+    cwd: Option<PathBuf>,
+    inherit_env: bool,
+    timeout: Duration,
+}
+
+// Triggers whose `devices` list leaves out `device` are skipped, so one
+// config file can be shared by every device.
+pub fn load(config: &Config, device: &str) -> Result<Vec<Arc<Trigger>>, String> {
+    let mut triggers = Vec::new();
+    for name in config.tables("trigger") {
+        let get = |key: &str| config.get(&format!("trigger.{name}.{key}"));
+        let string = |key: &str| match get(key) {
+            None => Ok(None),
+            Some(Value::String(s)) => Ok(Some(s.clone())),
+            Some(_) => Err(format!("trigger {}: {} must be a string", name, key)),
+        };
+        let list = |key: &str| match get(key) {
+            None => Ok(None),
+            Some(Value::Array(items)) => Ok(Some(items.clone())),
+            Some(_) => Err(format!("trigger {}: {} must be a list of strings", name, key)),
+        };
+
+        if let Some(devices) = list("devices")? {
+            if !devices.iter().any(|d| d == device) {
+                continue;
+            }
+        }
+        let command = list("command")?.filter(|command| !command.is_empty())
+            .ok_or_else(|| format!("trigger {}: command is required", name))?;
+        let pattern = string("match")?
+            .map(|pattern| Regex::new(&pattern).map_err(|e| format!("trigger {}: {}", name, e)))
+            .transpose()?;
+        let on = match string("on")?.as_deref() {
+            None | Some("received") => On::Received,
+            Some("sent") => On::Sent,
+            Some("both") => On::Both,
+            Some("connected") => On::Connected,
+            Some("disconnected") => On::Disconnected,
+            Some("outage") => On::Outage,
+            Some("device-online") => On::DeviceOnline,
+            Some("device-offline") => On::DeviceOffline,
+            Some(other) => return Err(format!("trigger {}: on must be received, sent, both, connected, disconnected, outage, device-online or device-offline, not {}", name, other)),
+        };
+        let after = match get("after") {
+            None => Duration::from_secs(300),
+            Some(Value::Integer(secs)) if *secs > 0 => Duration::from_secs(*secs as u64),
+            Some(_) => return Err(format!("trigger {}: after must be a number of seconds", name)),
+        };
+        let timeout = match get("timeout") {
+            None => Duration::from_secs(30),
+            Some(Value::Integer(secs)) if *secs > 0 => Duration::from_secs(*secs as u64),
+            Some(_) => return Err(format!("trigger {}: timeout must be a number of seconds", name)),
+        };
+        let inherit_env = match get("inherit_env") {
+            None => false,
+            Some(Value::Bool(inherit)) => *inherit,
+            Some(_) => return Err(format!("trigger {}: inherit_env must be true or false", name)),
+        };
+        let unsigned = match get("unsigned") {
+            None => false,
+            Some(Value::Bool(unsigned)) => *unsigned,
+            Some(_) => return Err(format!("trigger {}: unsigned must be true or false", name)),
+        };
+
+        triggers.push(Arc::new(Trigger {
+            name: name.clone(),
+            pattern,
+            content_type: string("type")?,
+            tags: list("tags")?,
+            from: list("from")?,
+            unsigned,
+            on,
+            after,
+            command,
+            cwd: string("cwd")?.map(PathBuf::from),
+            inherit_env,
+            timeout,
+        }));
+    }
+    Ok(triggers)
+}
+
+// Each matching trigger runs on a thread of its own, so a slow command
+// never holds up syncing. Received content is left to `received`, once the
+// caller knows it is not held.
+pub fn fire(triggers: &[Arc<Trigger>], link: &Link, event: &SyncEvent) {
+    let (envelope, on) = match event {
+        SyncEvent::Sent(envelope) => (envelope, On::Sent),
+        SyncEvent::Connected => return link.connected(triggers),
+        SyncEvent::Disconnected(error) => return link.disconnected(triggers, error),
+        SyncEvent::Presence { device, online } => {
+            let (on, event) = if *online { (On::DeviceOnline, "device-online") } else { (On::DeviceOffline, "device-offline") };
+            for trigger in triggers.iter().filter(|trigger| trigger.on == on) {
+                let trigger = trigger.clone();
+                let env = vec![("CLOUDBOARD_EVENT", event.to_string()), ("CLOUDBOARD_DEVICE", device.clone())];
+                std::thread::spawn(move || trigger.run(String::new(), env));
+            }
+            return;
+        }
+        _ => return,
+    };
+    run_matching(triggers, envelope, on);
+}
+
+pub fn received(triggers: &[Arc<Trigger>], envelope: &Envelope) {
+    run_matching(triggers, envelope, On::Received);
+}
+
+fn run_matching(triggers: &[Arc<Trigger>], envelope: &Envelope, on: On) {
+    for trigger in triggers.iter().filter(|trigger| trigger.on == on || trigger.on == On::Both) {
+        if on == On::Received && !trigger.trusts(envelope.signer.as_deref()) {
+            continue;
+        }
+        if trigger.content_type.as_ref().is_some_and(|content_type| *content_type != envelope.content_type) {
+            continue;
+        }
+        if trigger.pattern.as_ref().is_some_and(|pattern| !pattern.is_match(&envelope.content)) {
+            continue;
+        }
+        if trigger.tags.as_ref().is_some_and(|tags| !tags.iter().any(|tag| envelope.tags.contains(tag))) {
+            continue;
+        }
+        let trigger = trigger.clone();
+        let content = envelope.content.clone();
+        let env = vec![("CLOUDBOARD_DEVICE", envelope.device.clone().unwrap_or_default()), ("CLOUDBOARD_TAGS", envelope.tags.join(","))];
+        std::thread::spawn(move || trigger.run(content, env));
+    }
+}
+
+// The state of the broker connection, so connection triggers run when it
+// changes and not on every failed attempt to reconnect.
+#[derive(Clone, Default)]
+pub struct Link(Arc<Mutex<LinkState>>);
+
+#[derive(Default)]
+struct LinkState {
+    up: bool,
+    // Counts the times the connection went down, so an outage trigger
+    // waiting on one that has since ended knows not to run.
+    downs: u64,
+    error: String,
+}
+
+impl Link {
+    fn connected(&self, triggers: &[Arc<Trigger>]) {
+        let mut state = lock(&self.0);
+        if state.up {
+            return;
+        }
+        state.up = true;
+        run_all(triggers, On::Connected, "connected", "");
+    }
+
+    // Also counts a first connection that fails, for outage triggers.
+    fn disconnected(&self, triggers: &[Arc<Trigger>], error: &str) {
+        let mut state = lock(&self.0);
+        state.error = error.to_string();
+        if !state.up && state.downs > 0 {
+            return;
+        }
+        if state.up {
+            run_all(triggers, On::Disconnected, "disconnected", error);
+        }
+        state.up = false;
+        state.downs += 1;
+        let downs = state.downs;
+        for trigger in triggers.iter().filter(|trigger| trigger.on == On::Outage) {
+            let (trigger, link) = (trigger.clone(), self.clone());
+            std::thread::spawn(move || {
+                std::thread::sleep(trigger.after);
+                let state = lock(&link.0);
+                if state.up || state.downs != downs {
+                    return;
+                }
+                let env = vec![("CLOUDBOARD_EVENT", "outage".to_string()), ("CLOUDBOARD_ERROR", state.error.clone())];
+                drop(state);
+                trigger.run(String::new(), env);
+            });
+        }
+    }
+}
+
+fn run_all(triggers: &[Arc<Trigger>], on: On, event: &str, error: &str) {
+    for trigger in triggers.iter().filter(|trigger| trigger.on == on) {
+        let trigger = trigger.clone();
+        let mut env = vec![("CLOUDBOARD_EVENT", event.to_string())];
+        if !error.is_empty() {
+            env.push(("CLOUDBOARD_ERROR", error.to_string()));
+        }
+        std::thread::spawn(move || trigger.run(String::new(), env));
+    }
+}
+
+impl Trigger {
+    fn trusts(&self, signer: Option<&str>) -> bool {
+        match (signer, &self.from) {
+            (None, _) => self.unsigned && self.from.is_none(),
+            (Some(signer), Some(from)) => from.iter().any(|device| device == signer),
+            (Some(_), None) => true,
+        }
+    }
+
+    fn run(&self, content: String, env: Vec<(&str, String)>) {
+        let mut command = Command::new(&self.command[0]);
+        command.args(&self.command[1..])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::inherit());
+        if !self.inherit_env {
+            command.env_clear();
+            for name in KEPT_ENV {
+                if let Some(value) = std::env::var_os(name) {
+                    command.env(name, value);
+                }
+            }
+        }
+        command.envs(env);
+        if let Some(cwd) = &self.cwd {
+            command.current_dir(cwd);
+        }
+
+        let mut child = match command.spawn() {
+            Ok(child) => child,
+            Err(e) => {
+                error!("Failed to run trigger {}: {}", self.name, e);
+                return;
+            }
+        };
+        info!("running trigger {}", self.name);
+        if let Some(mut stdin) = child.stdin.take() {
+            std::thread::spawn(move || {
+                let _ = stdin.write_all(content.as_bytes());
+            });
+        }
+
+        let deadline = Instant::now() + self.timeout;
+        loop {
+            match child.try_wait() {
+                Ok(Some(status)) if status.success() => return,
+                Ok(Some(status)) => {
+                    warn!("trigger {} failed: {}", self.name, status);
+                    return;
+                }
+                Ok(None) if Instant::now() >= deadline => {
+                    warn!("trigger {} timed out after {:?}, killing it", self.name, self.timeout);
+                    let _ = child.kill();
+                    let _ = child.wait();
+                    return;
+                }
+                Ok(None) => std::thread::sleep(Duration::from_millis(100)),
+                Err(e) => {
+                    error!("Failed to wait for trigger {}: {}", self.name, e);
+                    return;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,334 @@
+use std::fmt::Write as _;
+use std::io;
+use std::path::{Path, PathBuf};
+use clap::Subcommand;
+use log::warn;
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, CHACHA20_POLY1305, NONCE_LEN};
+use ring::digest::{digest, SHA256};
+use ring::rand::{SecureRandom, SystemRandom};
+use ring::signature::{Ed25519KeyPair, KeyPair, UnparsedPublicKey, ED25519};
+use zeroize::Zeroizing;
+use crate::cli::or_exit;
+use crate::crypto::{from_hex, to_hex, write_private, Plaintext};
+use crate::devices;
+use crate::revoke;
+use crate::locked::SecretKey;
+use crate::store::FileStore;
+use crate::x25519;
+
+const MAGIC: &[u8] = b"cloudboard-sealed-to\n";
+const SIGNED_MAGIC: &[u8] = b"cloudboard-signed\n";
+const IDENTITY_FILE: &str = "identity.key";
+const SIGNING_FILE: &str = "signing.key";
+const TRUST_FILE: &str = "trust.list";
+
+#[derive(Subcommand, Debug)]
+pub enum TrustCommand {
+    /// Trust a device's identity, as printed by `trust list` on that device
+    Add {
+        /// The device's name, or its ID from `trust list` to keep trusting it when it is renamed
+        device: String,
+        identity: String,
+    },
+    /// Stop encrypting to a device
+    Remove {
+        device: String,
+    },
+    /// Show this device's public key and the trusted devices
+    List,
+}
+
+// A device identity is its X25519 key for encryption and, optionally, its
+// Ed25519 key for signatures, written as `<x25519>:<ed25519>` in hex.
+pub struct Device {
+    pub name: String,
+    pub public: [u8; 32],
+    pub verifying: Option<[u8; 32]>,
+}
+
+impl Device {
+    fn parse(name: &str, identity: &str) -> Option<Device> {
+        let (public, verifying) = match identity.trim().split_once(':') {
+            Some((public, verifying)) => (public, Some(parse_public_key(verifying)?)),
+            None => (identity, None),
+        };
+        Some(Device { name: name.to_string(), public: parse_public_key(public)?, verifying })
+    }
+
+    fn identity(&self) -> String {
+        match &self.verifying {
+            Some(verifying) => format!("{}:{}", to_hex(&self.public), to_hex(verifying)),
+            None => to_hex(&self.public),
+        }
+    }
+}
+
+pub struct Trust {
+    dir: PathBuf,
+    secret: SecretKey,
+    pub public: [u8; 32],
+    // ring keeps its own copy of the seed, which it does not wipe.
+    signing: Ed25519KeyPair,
+}
+
+impl Trust {
+    pub fn load(data_dir: &Path) -> io::Result<Trust> {
+        let secret = load_or_create_key(&data_dir.join(IDENTITY_FILE))?;
+        let seed = load_or_create_key(&data_dir.join(SIGNING_FILE))?;
+        let signing = Ed25519KeyPair::from_seed_unchecked(&*seed)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "invalid signing key"))?;
+
+        Ok(Trust {
+            dir: data_dir.to_path_buf(),
+            public: x25519::public_key(&secret),
+            secret,
+            signing,
+        })
+    }
+
+    pub fn identity(&self) -> String {
+        format!("{}:{}", to_hex(&self.public), to_hex(self.signing.public_key().as_ref()))
+    }
+
+    // Read on every use so `cloudboard trust` changes apply to a running
+    // daemon without a restart.
+    pub fn devices(&self) -> io::Result<Vec<Device>> {
+        let content = match std::fs::read_to_string(self.dir.join(TRUST_FILE)) {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        Ok(content.lines()
+            .filter_map(|line| line.split_once(' '))
+            .filter_map(|(name, identity)| Device::parse(name, identity))
+            .collect())
+    }
+
+    fn save_devices(&self, devices: &[Device]) -> io::Result<()> {
+        let content = devices.iter().fold(String::new(), |mut out, device| {
+            let _ = writeln!(out, "{} {}", device.name, device.identity());
+            out
+        });
+        write_private(&self.dir.join(TRUST_FILE), content.as_bytes())
+    }
+
+    // Whether any of `names` was there to remove.
+    pub fn remove(&self, names: &[&str]) -> io::Result<bool> {
+        let mut devices = self.devices()?;
+        let before = devices.len();
+        devices.retain(|device| !names.contains(&device.name.as_str()));
+        if devices.len() == before {
+            return Ok(false);
+        }
+        self.save_devices(&devices)?;
+        Ok(true)
+    }
+
+    pub fn sign(&self, envelope: &[u8]) -> Vec<u8> {
+        let signature = self.signing.sign(envelope);
+        let header = format!("sig: {}\n\n", to_hex(signature.as_ref()));
+        let mut out = Vec::with_capacity(SIGNED_MAGIC.len() + header.len() + envelope.len());
+        out.extend_from_slice(SIGNED_MAGIC);
+        out.extend_from_slice(header.as_bytes());
+        out.extend_from_slice(envelope);
+        out
+    }
+
+    // The name and key of the entry a device is trusted under, if it has
+    // a key to check its signatures with.
+    pub fn signer(&self, device: &str) -> Option<(String, [u8; 32])> {
+        let device = self.devices().ok()?.into_iter().find(|d| d.name == device)?;
+        Some((device.name, device.verifying?))
+    }
+
+    // The name and key of the trusted device, or of this one as `me`, whose
+    // key checks a signature made over something other than an envelope.
+    pub fn signed_by(&self, signature: &[u8], message: &[u8], me: &str) -> Option<(String, [u8; 32])> {
+        let own = (me.to_string(), self.verifying_key());
+        std::iter::once(own)
+            .chain(self.devices().ok()?.into_iter().filter_map(|device| Some((device.name, device.verifying?))))
+            .find(|(_, key)| verify(key, signature, message))
+    }
+
+    // What this device's own signatures are checked with.
+    pub fn verifying_key(&self) -> [u8; 32] {
+        self.signing.public_key().as_ref().try_into().unwrap()
+    }
+
+    // The payload is sealed with a random content key, which is then wrapped
+    // for every trusted device with a key derived from an ephemeral X25519
+    // exchange against that device's public key.
+    pub fn seal(&self, plaintext: &[u8]) -> Vec<u8> {
+        let rng = SystemRandom::new();
+        let devices = self.devices().unwrap_or_else(|e| {
+            warn!("Failed to read trust list: {}", e);
+            Vec::new()
+        });
+        if devices.is_empty() {
+            warn!("no trusted devices, nobody will be able to decrypt this message");
+        }
+
+        let content_key = SecretKey::with(|key| rng.fill(key).unwrap());
+        let ephemeral = SecretKey::with(|key| rng.fill(key).unwrap());
+        let mut nonce = [0u8; NONCE_LEN];
+        rng.fill(&mut nonce).unwrap();
+        let ephemeral_public = x25519::public_key(&ephemeral);
+
+        let mut header = String::new();
+        let _ = writeln!(header, "ephemeral: {}", to_hex(&ephemeral_public));
+        let _ = writeln!(header, "nonce: {}", to_hex(&nonce));
+        for recipient in devices.iter().map(|device| &device.public) {
+            let kek = wrap_key(&Zeroizing::new(x25519::scalarmult(&ephemeral, recipient)), &ephemeral_public, recipient);
+            let mut wrapped = content_key.to_vec();
+            aead(&*kek).seal_in_place_append_tag(Nonce::assume_unique_for_key([0; NONCE_LEN]), Aad::empty(), &mut wrapped).unwrap();
+            let _ = writeln!(header, "to: {} {}", to_hex(recipient), to_hex(&wrapped));
+        }
+        header.push('\n');
+
+        let mut out = MAGIC.to_vec();
+        out.extend_from_slice(header.as_bytes());
+        seal_onto(&aead(&*content_key), nonce, &mut out, plaintext);
+        out
+    }
+
+    pub fn open(&self, payload: &[u8]) -> Option<Plaintext> {
+        let split = payload.windows(2).position(|w| w == b"\n\n")? + 2;
+        let (header, body) = payload.split_at(split);
+        let fields = std::str::from_utf8(header.strip_prefix(MAGIC)?).ok()?;
+
+        let me = to_hex(&self.public);
+        let (mut ephemeral, mut nonce, mut wrapped) = (None, None, None);
+        for line in fields.lines() {
+            match line.split_once(": ") {
+                Some(("ephemeral", value)) => ephemeral = parse_public_key(value),
+                Some(("nonce", value)) => nonce = from_hex(value),
+                Some(("to", value)) => {
+                    if let Some(key) = value.strip_prefix(&me).and_then(|rest| rest.strip_prefix(' ')) {
+                        wrapped = from_hex(key);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let ephemeral = ephemeral?;
+        let shared = Zeroizing::new(x25519::scalarmult(&self.secret, &ephemeral));
+        if *shared == [0; 32] {
+            return None;
+        }
+        let kek = wrap_key(&shared, &ephemeral, &self.public);
+        let mut wrapped = Zeroizing::new(wrapped?);
+        let content_key = aead(&*kek).open_in_place(Nonce::assume_unique_for_key([0; NONCE_LEN]), Aad::empty(), &mut wrapped).ok()?;
+
+        let nonce = Nonce::try_assume_unique_for_key(&nonce?).ok()?;
+        open_body(&aead(content_key), nonce, header, body)
+    }
+}
+
+pub fn is_sealed(payload: &[u8]) -> bool {
+    payload.starts_with(MAGIC)
+}
+
+// Splits a signed payload into its signature and the signed envelope bytes;
+// unsigned payloads are returned as they are.
+pub fn split_signed(payload: &[u8]) -> (Option<Vec<u8>>, &[u8]) {
+    let Some(rest) = payload.strip_prefix(SIGNED_MAGIC) else {
+        return (None, payload);
+    };
+    let Some(split) = rest.windows(2).position(|w| w == b"\n\n") else {
+        return (None, payload);
+    };
+    let signature = std::str::from_utf8(&rest[..split]).ok()
+        .and_then(|line| line.strip_prefix("sig: "))
+        .and_then(from_hex);
+    (signature, &rest[split + 2..])
+}
+
+pub fn verify(key: &[u8; 32], signature: &[u8], envelope: &[u8]) -> bool {
+    UnparsedPublicKey::new(&ED25519, key).verify(envelope, signature).is_ok()
+}
+
+fn load_or_create_key(path: &Path) -> io::Result<SecretKey> {
+    match std::fs::read_to_string(path).map(Zeroizing::new) {
+        Ok(content) => from_hex(content.trim()).map(Zeroizing::new).and_then(|key| SecretKey::from_slice(&key)).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidData, format!("invalid key in {}", path.display()))
+        }),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            let key = SecretKey::with(|key| SystemRandom::new().fill(key).unwrap());
+            write_private(path, Zeroizing::new(to_hex(&*key)).as_bytes())?;
+            Ok(key)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+fn parse_public_key(hex: &str) -> Option<[u8; 32]> {
+    from_hex(hex.trim())?.try_into().ok()
+}
+
+fn wrap_key(shared: &[u8; 32], ephemeral: &[u8; 32], recipient: &[u8; 32]) -> SecretKey {
+    let input = Zeroizing::new([b"cloudboard-wrap".as_slice(), shared, ephemeral, recipient].concat());
+    SecretKey::with(|key| key.copy_from_slice(digest(&SHA256, &input).as_ref()))
+}
+
+pub fn aead(key: &[u8]) -> LessSafeKey {
+    LessSafeKey::new(UnboundKey::new(&CHACHA20_POLY1305, key).unwrap())
+}
+
+// Appends `plaintext` to `out` sealed, with what `out` already holds as the
+// associated data. The plaintext is copied once, straight into its place.
+pub fn seal_onto(key: &LessSafeKey, nonce: [u8; NONCE_LEN], out: &mut Vec<u8>, plaintext: &[u8]) {
+    let header_len = out.len();
+    out.reserve(plaintext.len() + key.algorithm().tag_len());
+    out.extend_from_slice(plaintext);
+    let (header, body) = out.split_at_mut(header_len);
+    let tag = key.seal_in_place_separate_tag(Nonce::assume_unique_for_key(nonce), Aad::from(&*header), body).unwrap();
+    out.extend_from_slice(tag.as_ref());
+}
+
+// Opens `body` in a buffer of its own, which is then cut down to the
+// plaintext in place rather than copied again.
+pub fn open_body(key: &LessSafeKey, nonce: Nonce, header: &[u8], body: &[u8]) -> Option<Plaintext> {
+    let mut body = Zeroizing::new(body.to_vec());
+    let len = key.open_in_place(nonce, Aad::from(header), &mut body).ok()?.len();
+    body.truncate(len);
+    Some(body)
+}
+
+pub fn command(data_dir: &Path, command: TrustCommand) {
+    let trust = or_exit(Trust::load(data_dir), "load this device's keys");
+    let mut devices = or_exit(trust.devices(), "read the trust list");
+    match command {
+        TrustCommand::Add { device, identity } => {
+            // The list has one device a line, its name and identity split by a space.
+            if device.is_empty() || device.contains(char::is_whitespace) {
+                eprintln!("Failed to trust {:?}: the name cannot be empty or have spaces", device);
+                std::process::exit(1);
+            }
+            let entry = or_exit(Device::parse(&device, &identity).ok_or("the identity must be <x25519>[:<ed25519>] in hex"), &format!("trust {device}"));
+            devices.retain(|d| d.name != device);
+            devices.push(entry);
+            or_exit(trust.save_devices(&devices), "save the trust list");
+            println!("trusted {}", device);
+        }
+        TrustCommand::Remove { device } => {
+            if !or_exit(trust.remove(&[&device]), "save the trust list") {
+                println!("{} is not trusted", device);
+                return;
+            }
+            println!("removed {}", device);
+        }
+        TrustCommand::List => {
+            println!("this device: {}", trust.identity());
+            if let Some(id) = devices::id(&FileStore::new(data_dir)) {
+                println!("device id: {id}");
+            }
+            for device in &devices {
+                println!("{:<20} {}", device.name, device.identity());
+            }
+            for device in revoke::list(data_dir) {
+                println!("{:<20} revoked", device);
+            }
+        }
+    }
+}
@@ -0,0 +1,210 @@
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::process::{Command, Stdio};
+use std::sync::Arc;
+use log::{debug, error, info};
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::client::WebPkiServerVerifier;
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::{ClientConfig, DigitallySignedStruct, SignatureScheme};
+use crate::crl::Crls;
+use crate::doctor;
+use crate::pin;
+use crate::logging::CONNECT;
+use crate::Args;
+
+// How the broker is reached when not directly, underneath TLS, which is
+// still between this device and the broker either way.
+#[derive(Clone, Debug)]
+pub enum Hop {
+    // An SSH host, for --via.
+    Ssh(String),
+    // A SOCKS5 proxy that resolves the broker's name itself, for --tor,
+    // which is how an onion service is reached.
+    Socks(SocketAddr),
+}
+
+impl fmt::Display for Hop {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Hop::Ssh(via) => f.write_str(via),
+            Hop::Socks(proxy) => write!(f, "the SOCKS proxy at {}", proxy),
+        }
+    }
+}
+
+pub fn hop(args: &Args) -> Option<Hop> {
+    match (&args.via, args.tor) {
+        (Some(via), _) => Some(Hop::Ssh(via.clone())),
+        (None, Some(proxy)) => Some(Hop::Socks(proxy)),
+        (None, None) => None,
+    }
+}
+
+// A local port that dials the broker through `hop` for every connection
+// made to it, the way ProxyJump does. Each tunnel lives exactly as long as
+// its connection, so the reconnects the receiver already backs off between
+// are what reopen it. Over SSH, ssh sees stdin close and exits with the
+// daemon however that stops, and a ControlMaster set up in the ssh config
+// carries the tunnels over one login.
+pub fn open(hop: &Hop, server: &str, port: u16) -> io::Result<SocketAddr> {
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let addr = listener.local_addr()?;
+    let target = if server.contains(':') { format!("[{server}]:{port}") } else { format!("{server}:{port}") };
+    let (hop, server) = (hop.clone(), server.to_string());
+    info!(target: CONNECT, "reaching {} through {}", target, hop);
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let (hop, server, target) = (hop.clone(), server.clone(), target.clone());
+                    std::thread::spawn(move || {
+                        let bridged = match &hop {
+                            Hop::Ssh(via) => bridge(stream, via, &target),
+                            Hop::Socks(proxy) => socks(stream, *proxy, &server, port),
+                        };
+                        if let Err(e) = bridged {
+                            error!(target: CONNECT, "Failed to tunnel through {}: {}", hop, e);
+                        }
+                    });
+                }
+                Err(e) => error!(target: CONNECT, "Failed to accept tunnel connection: {}", e),
+            }
+        }
+    });
+    Ok(addr)
+}
+
+fn bridge(stream: TcpStream, via: &str, target: &str) -> io::Result<()> {
+    // A daemon has no one to answer a password or host key prompt.
+    let mut ssh = Command::new("ssh")
+        .args(["-W", target, "-o", "BatchMode=yes", "--", via])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| io::Error::new(e.kind(), format!("ssh: {}", e)))?;
+    let (mut stdin, mut stdout) = (ssh.stdin.take().unwrap(), ssh.stdout.take().unwrap());
+    let mut upstream = stream.try_clone()?;
+    let writer = std::thread::spawn(move || pump(&mut upstream, &mut stdin));
+    let _ = pump(&mut stdout, &mut &stream);
+    let _ = stream.shutdown(std::net::Shutdown::Both);
+    let _ = writer.join();
+
+    let mut stderr = String::new();
+    let _ = ssh.stderr.take().unwrap().read_to_string(&mut stderr);
+    let status = ssh.wait()?;
+    debug!(target: CONNECT, "tunnel through {} closed with {}", via, status);
+    // 255 is ssh failing, rather than the tunnel simply ending.
+    match status.code() {
+        Some(255) => Err(io::Error::other(stderr.trim().to_string())),
+        _ => Ok(()),
+    }
+}
+
+// A SOCKS5 CONNECT by name and without authentication, as Tor and arti
+// take it. The name is never resolved here, which for an onion service
+// could not work and for any other broker would leak it to the DNS.
+fn socks(stream: TcpStream, proxy: SocketAddr, server: &str, port: u16) -> io::Result<()> {
+    let refused = |what: String| io::Error::new(io::ErrorKind::ConnectionRefused, what);
+    let mut upstream = TcpStream::connect(proxy)?;
+    upstream.write_all(&[5, 1, 0])?;
+    let mut reply = [0; 2];
+    upstream.read_exact(&mut reply)?;
+    if reply != [5, 0] {
+        return Err(refused(format!("{} is not a SOCKS5 proxy that takes connections without a password", proxy)));
+    }
+    let name = u8::try_from(server.len()).map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "the server name is too long for SOCKS"))?;
+    let mut request = vec![5, 1, 0, 3, name];
+    request.extend_from_slice(server.as_bytes());
+    request.extend_from_slice(&port.to_be_bytes());
+    upstream.write_all(&request)?;
+    let mut reply = [0; 4];
+    upstream.read_exact(&mut reply)?;
+    if reply[1] != 0 {
+        // Tor's extended codes, such as 0xf0 for an onion service it
+        // cannot find, come through here as they are.
+        return Err(refused(format!("the proxy could not reach {}:{} (SOCKS reply {:#04x})", server, port, reply[1])));
+    }
+    // The address it connected from, which is of no use here.
+    let bound = match reply[3] {
+        1 => 4,
+        4 => 16,
+        3 => {
+            let mut len = [0];
+            upstream.read_exact(&mut len)?;
+            len[0] as usize
+        }
+        _ => return Err(refused("the proxy sent a malformed reply".to_string())),
+    };
+    io::copy(&mut (&upstream).take(bound as u64 + 2), &mut io::sink())?;
+    debug!(target: CONNECT, "connected to {}:{} through {}", server, port, proxy);
+    let mut writer_from = stream.try_clone()?;
+    let mut writer_to = upstream.try_clone()?;
+    let writer = std::thread::spawn(move || pump(&mut writer_from, &mut writer_to));
+    let _ = pump(&mut &upstream, &mut &stream);
+    let _ = stream.shutdown(std::net::Shutdown::Both);
+    let _ = upstream.shutdown(std::net::Shutdown::Both);
+    let _ = writer.join();
+    Ok(())
+}
+
+// Rather than io::copy, which on Linux splices between the socket and the
+// pipe and was seen to stall partway through the TLS handshake.
+fn pump(from: &mut impl Read, to: &mut impl Write) -> io::Result<()> {
+    let mut buf = [0; 16 * 1024];
+    loop {
+        let n = from.read(&mut buf)?;
+        if n == 0 {
+            return Ok(());
+        }
+        to.write_all(&buf[..n])?;
+    }
+}
+
+// The broker is reached at 127.0.0.1, so its certificate is checked for
+// the name it has beyond the tunnel.
+#[derive(Debug)]
+struct Beyond {
+    name: ServerName<'static>,
+    verifier: Arc<WebPkiServerVerifier>,
+}
+
+impl ServerCertVerifier for Beyond {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        self.verifier.verify_server_cert(end_entity, intermediates, &self.name, ocsp_response, now)
+    }
+
+    fn verify_tls12_signature(&self, message: &[u8], cert: &CertificateDer<'_>, dss: &DigitallySignedStruct) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.verifier.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(&self, message: &[u8], cert: &CertificateDer<'_>, dss: &DigitallySignedStruct) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.verifier.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.verifier.supported_verify_schemes()
+    }
+}
+
+pub fn tls_config(args: &Args, sources: &[String; 3]) -> io::Result<Arc<ClientConfig>> {
+    let invalid = |e: String| io::Error::new(io::ErrorKind::InvalidData, e);
+    let files = doctor::load_files(sources).map_err(invalid)?;
+    let name = ServerName::try_from(args.server.clone()).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let verifier = Crls::load(args).and_then(|crls| crls.server_verifier(files.roots()?)).map_err(invalid)?;
+    let config = ClientConfig::builder()
+        .dangerous()
+        .with_custom_certificate_verifier(pin::verifier(Arc::new(Beyond { name, verifier }), &args.pin))
+        .with_client_auth_cert(files.chain, files.key)
+        .map_err(io::Error::other)?;
+    Ok(Arc::new(config))
+}
@@ -0,0 +1,99 @@
+use std::time::Duration;
+use crate::stats;
+
+// How times from the stats log and the daemon are shown to people: in the
+// local timezone, or in UTC with --utc, and relative to now where that
+// reads better. They are kept as UTC seconds everywhere else.
+#[derive(Clone, Copy, Debug)]
+pub struct Render {
+    utc: bool,
+    now: u64,
+}
+
+impl Render {
+    pub fn new(utc: bool) -> Render {
+        Render { utc, now: stats::now() }
+    }
+
+    // E.g. "2026-10-14 15:03:07 +0200", or "2026-10-14 13:03:07 UTC".
+    pub fn absolute(&self, time: u64) -> String {
+        let (offset, zone) = match self.offset(time) {
+            None => (0, "UTC".to_string()),
+            Some(offset) => {
+                let sign = if offset < 0 { '-' } else { '+' };
+                (offset, format!("{sign}{:02}{:02}", offset.unsigned_abs() / 3600, offset.unsigned_abs() / 60 % 60))
+            }
+        };
+        let (date, clock) = civil(time.saturating_add_signed(offset));
+        format!("{date} {clock} {zone}")
+    }
+
+    // Just the date, e.g. "2026-10-14".
+    pub fn date(&self, time: u64) -> String {
+        civil(time.saturating_add_signed(self.offset(time).unwrap_or(0))).0
+    }
+
+    // Just the time of day, e.g. "15:03:07".
+    pub fn clock(&self, time: u64) -> String {
+        civil(time.saturating_add_signed(self.offset(time).unwrap_or(0))).1
+    }
+
+    // E.g. "3m ago", in the largest unit that fits.
+    pub fn relative(&self, time: u64) -> String {
+        let (seconds, suffix) = match self.now.checked_sub(time) {
+            Some(seconds) => (seconds, " ago"),
+            // Another device's clock can be ahead of this one.
+            None => (time - self.now, " from now"),
+        };
+        let rounded = humantime::format_duration(Duration::from_secs(seconds)).to_string();
+        format!("{}{}", rounded.split(' ').next().unwrap_or_default(), suffix)
+    }
+
+    // Seconds east of UTC at `time`, or none for UTC itself.
+    fn offset(&self, time: u64) -> Option<i64> {
+        if self.utc {
+            return None;
+        }
+        local_offset(time)
+    }
+}
+
+#[cfg(unix)]
+fn local_offset(time: u64) -> Option<i64> {
+    let time = libc::time_t::try_from(time).ok()?;
+    // SAFETY: an all-zero tm is a valid value, and localtime_r only writes
+    // to the one it is given.
+    let tm = unsafe {
+        let mut tm: libc::tm = std::mem::zeroed();
+        if libc::localtime_r(&time, &mut tm).is_null() {
+            return None;
+        }
+        tm
+    };
+    Some(tm.tm_gmtoff as i64)
+}
+
+// Without a timezone database to ask, times are shown in UTC and say so.
+#[cfg(not(unix))]
+fn local_offset(_time: u64) -> Option<i64> {
+    None
+}
+
+// The date and time of day of `seconds` since the epoch, using the days
+// to civil date conversion from Howard Hinnant's date algorithms.
+fn civil(seconds: u64) -> (String, String) {
+    let (days, second) = (seconds / 86400, seconds % 86400);
+    let days = days as i64 + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month + 2) / 5 + 1;
+    let month = if month < 10 { month + 3 } else { month - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (
+        format!("{year:04}-{month:02}-{day:02}"),
+        format!("{:02}:{:02}:{:02}", second / 3600, second / 60 % 60, second % 60),
+    )
+}
@@ -0,0 +1,149 @@
+// X25519 (RFC 7748) over 16 limbs of 16 bits, following TweetNaCl. ring can
+// only do agreement with ephemeral keys, but device identities are static.
+
+type Fe = [i64; 16];
+
+const A24: Fe = [0xdb41, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+const BASE: [u8; 32] = {
+    let mut base = [0u8; 32];
+    base[0] = 9;
+    base
+};
+
+pub fn public_key(secret: &[u8; 32]) -> [u8; 32] {
+    scalarmult(secret, &BASE)
+}
+
+pub fn scalarmult(scalar: &[u8; 32], point: &[u8; 32]) -> [u8; 32] {
+    let mut z = *scalar;
+    z[31] = (z[31] & 127) | 64;
+    z[0] &= 248;
+
+    let x = unpack(point);
+    let mut a = [0; 16];
+    let mut b = x;
+    let mut c = [0; 16];
+    let mut d = [0; 16];
+    a[0] = 1;
+    d[0] = 1;
+
+    for i in (0..=254).rev() {
+        let bit = ((z[i >> 3] >> (i & 7)) & 1) as i64;
+        swap(&mut a, &mut b, bit);
+        swap(&mut c, &mut d, bit);
+        let mut e = add(&a, &c);
+        a = sub(&a, &c);
+        c = add(&b, &d);
+        b = sub(&b, &d);
+        d = mul(&e, &e);
+        let f = mul(&a, &a);
+        a = mul(&c, &a);
+        c = mul(&b, &e);
+        e = add(&a, &c);
+        a = sub(&a, &c);
+        b = mul(&a, &a);
+        c = sub(&d, &f);
+        a = mul(&c, &A24);
+        a = add(&a, &d);
+        c = mul(&c, &a);
+        a = mul(&d, &f);
+        d = mul(&b, &x);
+        b = mul(&e, &e);
+        swap(&mut a, &mut b, bit);
+        swap(&mut c, &mut d, bit);
+    }
+
+    pack(&mul(&a, &invert(&c)))
+}
+
+fn carry(o: &mut Fe) {
+    for i in 0..16 {
+        o[i] += 1 << 16;
+        let c = o[i] >> 16;
+        if i < 15 {
+            o[i + 1] += c - 1;
+        } else {
+            o[0] += 38 * (c - 1);
+        }
+        o[i] -= c << 16;
+    }
+}
+
+fn swap(p: &mut Fe, q: &mut Fe, bit: i64) {
+    let mask = !(bit - 1);
+    for i in 0..16 {
+        let t = mask & (p[i] ^ q[i]);
+        p[i] ^= t;
+        q[i] ^= t;
+    }
+}
+
+fn pack(n: &Fe) -> [u8; 32] {
+    let mut t = *n;
+    carry(&mut t);
+    carry(&mut t);
+    carry(&mut t);
+    for _ in 0..2 {
+        let mut m = [0; 16];
+        m[0] = t[0] - 0xffed;
+        for i in 1..15 {
+            m[i] = t[i] - 0xffff - ((m[i - 1] >> 16) & 1);
+            m[i - 1] &= 0xffff;
+        }
+        m[15] = t[15] - 0x7fff - ((m[14] >> 16) & 1);
+        let borrow = (m[15] >> 16) & 1;
+        m[14] &= 0xffff;
+        swap(&mut t, &mut m, 1 - borrow);
+    }
+
+    let mut out = [0u8; 32];
+    for i in 0..16 {
+        out[2 * i] = t[i] as u8;
+        out[2 * i + 1] = (t[i] >> 8) as u8;
+    }
+    out
+}
+
+fn unpack(n: &[u8; 32]) -> Fe {
+    let mut o = [0; 16];
+    for i in 0..16 {
+        o[i] = n[2 * i] as i64 + ((n[2 * i + 1] as i64) << 8);
+    }
+    o[15] &= 0x7fff;
+    o
+}
+
+fn add(a: &Fe, b: &Fe) -> Fe {
+    std::array::from_fn(|i| a[i] + b[i])
+}
+
+fn sub(a: &Fe, b: &Fe) -> Fe {
+    std::array::from_fn(|i| a[i] - b[i])
+}
+
+fn mul(a: &Fe, b: &Fe) -> Fe {
+    let mut t = [0i64; 31];
+    for i in 0..16 {
+        for j in 0..16 {
+            t[i + j] += a[i] * b[j];
+        }
+    }
+    for i in 0..15 {
+        t[i] += 38 * t[i + 16];
+    }
+    let mut o: Fe = std::array::from_fn(|i| t[i]);
+    carry(&mut o);
+    carry(&mut o);
+    o
+}
+
+fn invert(i: &Fe) -> Fe {
+    let mut c = *i;
+    for a in (0..=253).rev() {
+        c = mul(&c, &c);
+        if a != 2 && a != 4 {
+            c = mul(&c, i);
+        }
+    }
+    c
+}
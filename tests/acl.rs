@@ -0,0 +1,59 @@
+use std::time::{Duration, Instant};
+use rumqttc::v5::mqttbytes::v5::SubscribeReasonCode;
+use rumqttc::v5::mqttbytes::QoS;
+use cloudboard::acl::Check;
+
+fn topics(names: &[&str]) -> Vec<String> {
+    names.iter().map(|name| name.to_string()).collect()
+}
+
+fn later() -> Instant {
+    Instant::now() + Duration::from_secs(60)
+}
+
+#[test]
+fn names_a_refused_subscription() {
+    let mut check = Check::new(topics(&["u/clipboard", "u/control"]), true);
+    assert!(check.suback(&[SubscribeReasonCode::Success(QoS::AtLeastOnce)]).is_ok());
+    let refused = check.suback(&[SubscribeReasonCode::NotAuthorized]).unwrap_err();
+    assert!(refused.contains("u/control"), "{refused}");
+    assert!(check.suback(&[SubscribeReasonCode::NotAuthorized]).is_ok());
+}
+
+#[test]
+fn passes_once_every_probe_returns() {
+    let mut check = Check::new(topics(&["u/clipboard"]), true);
+    assert!(check.start(&topics(&["u/clipboard", "u/control"])));
+    assert!(check.is_waiting("u/control"));
+    check.returned("u/clipboard");
+    check.returned("u/control");
+    assert!(!check.is_waiting("u/control"));
+    assert_eq!(check.deadline(), None);
+    assert_eq!(check.missing_at(later()), None);
+    assert!(!check.start(&topics(&["u/clipboard"])));
+}
+
+#[test]
+fn names_probes_that_never_return() {
+    let mut check = Check::new(topics(&["u/clipboard"]), true);
+    check.start(&topics(&["u/clipboard", "u/control", "u/slot"]));
+    check.returned("u/clipboard");
+    assert_eq!(check.missing(), None);
+    assert_eq!(check.missing_at(later()).as_deref(), Some("u/control, u/slot"));
+}
+
+#[test]
+fn probes_again_after_a_lost_connection() {
+    let mut check = Check::new(topics(&["u/clipboard"]), true);
+    check.start(&topics(&["u/clipboard"]));
+    check.interrupted();
+    assert_eq!(check.missing_at(later()), None);
+    assert!(check.start(&topics(&["u/clipboard"])));
+}
+
+#[test]
+fn does_nothing_when_disabled() {
+    let mut check = Check::new(topics(&["u/clipboard"]), false);
+    assert!(check.suback(&[SubscribeReasonCode::NotAuthorized]).is_ok());
+    assert!(!check.start(&topics(&["u/clipboard"])));
+}
@@ -0,0 +1,18 @@
+// The known answers from RFC 9106 and RFC 7693, which every device has to
+// derive the same key from a passphrase by.
+#![cfg(feature = "e2e")]
+use cloudboard::argon2::{self, Params};
+use cloudboard::crypto::from_hex;
+
+#[test]
+fn argon2id_matches_rfc_9106() {
+    let mut tag = [0u8; 32];
+    argon2::argon2id_keyed(&[1; 32], &[2; 16], &[3; 8], &[4; 12], &Params { memory_kib: 32, passes: 3, lanes: 4 }, &mut tag);
+    assert_eq!(tag.to_vec(), from_hex("0d640df58d78766c08c037a34a8b53c9d01ef0452d75b65eb52520e96b01e659").unwrap());
+}
+
+#[test]
+fn blake2b_matches_rfc_7693() {
+    let expected = from_hex("ba80a53f981c4d0d6a2797b69f12f6e94c212f14685ac4b74b12bb6fdbffa2d17d87c5392aab792dc252d5de4533cc9518d38aa8dbf1925ab92386edd4009923").unwrap();
+    assert_eq!(argon2::blake2b_512(b"abc").to_vec(), expected);
+}
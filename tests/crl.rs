@@ -0,0 +1,55 @@
+// Fixtures are from a test CA, with revoked.crt on its list and good.crt
+// not, both valid for localhost until 2126.
+use clap::Parser;
+use rustls::client::danger::ServerCertVerifier;
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::RootCertStore;
+use cloudboard::crl::Crls;
+use cloudboard::Args;
+
+const CA: &str = include_str!("fixtures/crl/ca.crt");
+const CRL: &str = include_str!("fixtures/crl/ca.crl");
+
+#[derive(Parser)]
+struct Cli {
+    #[command(flatten)]
+    args: Args,
+}
+
+// Inline PEM starts with dashes, so it is given as --crl=<pem>.
+fn crls(crl: Option<&str>) -> Result<Crls, String> {
+    let crl = crl.map(|pem| format!("--crl={pem}"));
+    let cli = Cli::parse_from(["cloudboard", "-d", "laptop", "-u", "u", "-s", "localhost"].into_iter().map(str::to_string).chain(crl));
+    Crls::load(&cli.args)
+}
+
+fn cert(pem: &str) -> CertificateDer<'static> {
+    rustls_pemfile::certs(&mut pem.as_bytes()).next().unwrap().unwrap()
+}
+
+fn verify(crls: &Crls, pem: &str) -> Result<(), rustls::Error> {
+    let mut roots = RootCertStore::empty();
+    roots.add(cert(CA)).unwrap();
+    let verifier = crls.server_verifier(roots).unwrap();
+    let name = ServerName::try_from("localhost").unwrap();
+    verifier.verify_server_cert(&cert(pem), &[], &name, &[], UnixTime::now()).map(|_| ())
+}
+
+#[test]
+fn refuses_a_revoked_certificate() {
+    let crls = crls(Some(CRL)).unwrap();
+    assert!(verify(&crls, include_str!("fixtures/crl/revoked.crt")).is_err());
+    assert!(verify(&crls, include_str!("fixtures/crl/good.crt")).is_ok());
+}
+
+#[test]
+fn takes_any_certificate_without_a_list() {
+    let crls = crls(None).unwrap();
+    assert!(crls.is_empty());
+    assert!(verify(&crls, include_str!("fixtures/crl/revoked.crt")).is_ok());
+}
+
+#[test]
+fn refuses_a_file_without_a_list() {
+    assert!(crls(Some(CA)).is_err());
+}
@@ -0,0 +1,39 @@
+use cloudboard::delta::Delta;
+
+fn paragraph() -> String {
+    "The quick brown fox jumps over the lazy dog. ".repeat(20)
+}
+
+#[test]
+fn applies_to_its_base() {
+    let base = paragraph();
+    let edited = base.replacen("lazy", "sleepy", 1);
+    let delta = Delta::between(&base, &edited, "text/plain").expect("a small edit");
+    let decoded = Delta::decode(&delta.encode()).unwrap();
+    assert_eq!(decoded, delta);
+    assert_eq!(decoded.apply(&base).as_deref(), Some(edited.as_str()));
+    assert_eq!(decoded.content_type, "text/plain");
+}
+
+#[test]
+fn refuses_another_base() {
+    let base = paragraph();
+    let delta = Delta::between(&base, &format!("{base}and more"), "text/plain").unwrap();
+    assert_eq!(delta.apply(&base.replacen("fox", "cat", 1)), None);
+    assert_eq!(delta.apply(""), None);
+}
+
+#[test]
+fn keeps_to_character_boundaries() {
+    let base = "é".repeat(200);
+    let edited = format!("{}è{}", "é".repeat(100), "é".repeat(99));
+    let delta = Delta::between(&base, &edited, "text/plain").unwrap();
+    assert_eq!(delta.apply(&base).as_deref(), Some(edited.as_str()));
+}
+
+#[test]
+fn is_not_sent_when_it_saves_little() {
+    let base = paragraph();
+    assert_eq!(Delta::between(&base, "something else entirely", "text/plain"), None);
+    assert_eq!(Delta::between("short", "shorter", "text/plain"), None);
+}
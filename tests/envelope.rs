@@ -0,0 +1,169 @@
+// Payloads as written by every released envelope format version, which
+// current clients must keep reading.
+use cloudboard::envelope::{self, Encoding, Envelope};
+use cloudboard::hlc::Timestamp;
+
+fn decode(fixture: &[u8]) -> Envelope {
+    Envelope::decode(fixture).expect("fixture should decode")
+}
+
+#[test]
+fn v0_bare_text() {
+    let envelope = decode(include_bytes!("fixtures/envelope/v0.txt"));
+    assert_eq!(envelope.version, 0);
+    assert!(envelope.is_supported());
+    assert_eq!(envelope.device, None);
+    assert_eq!(envelope.content_type, "text/plain");
+    assert_eq!(envelope.content, "just some text\n");
+}
+
+#[test]
+fn v1_without_version_header() {
+    let envelope = decode(include_bytes!("fixtures/envelope/v1.txt"));
+    assert_eq!(envelope.version, 1);
+    assert!(envelope.is_supported());
+    assert_eq!(envelope.device.as_deref(), Some("laptop"));
+    assert_eq!(envelope.seq, None);
+    assert_eq!(envelope.content, "hello from v1");
+}
+
+#[test]
+fn v1_with_optional_headers() {
+    let envelope = decode(include_bytes!("fixtures/envelope/v1-headers.txt"));
+    assert_eq!(envelope.version, 1);
+    assert_eq!(envelope.seq, Some(42));
+    assert_eq!(envelope.hlc, Some(Timestamp { millis: 1760000000000, counter: 3 }));
+    assert_eq!(envelope.group.as_deref(), Some("team"));
+    assert_eq!(envelope.content, "hello\n\nwith a blank line");
+}
+
+#[test]
+fn v2() {
+    let envelope = decode(include_bytes!("fixtures/envelope/v2.txt"));
+    assert_eq!(envelope.version, 2);
+    assert!(envelope.is_supported());
+    assert_eq!(envelope.device.as_deref(), Some("phone"));
+    assert_eq!(envelope.seq, Some(7));
+    assert_eq!(envelope.content, "hello from v2");
+}
+
+// A version from after this build still decodes, unknown headers and all,
+// so the receiver can say where it came from before dropping it.
+#[test]
+fn newer_version_is_unsupported() {
+    let envelope = decode(include_bytes!("fixtures/envelope/v3.txt"));
+    assert_eq!(envelope.version, 3);
+    assert!(!envelope.is_supported());
+    assert_eq!(envelope.device.as_deref(), Some("tablet"));
+}
+
+#[test]
+fn current_version_round_trips() {
+    let mut envelope = Envelope::text("laptop", "hello".to_string());
+    envelope.seq = Some(1);
+    let decoded = decode(&envelope.encode());
+    assert_eq!(decoded.version, envelope::VERSION);
+    assert_eq!(decoded.seq, Some(1));
+    assert_eq!(decoded.content, "hello");
+}
+
+// What --envelope-version 1 publishes has to match the v1 fixture byte for
+// byte, or devices still on v1 would not read it.
+#[test]
+fn writes_v1_for_older_devices() {
+    let mut envelope = Envelope::text("laptop", "hello from v1".to_string());
+    envelope.version = 1;
+    assert_eq!(envelope.encode(), include_bytes!("fixtures/envelope/v1.txt"));
+}
+
+#[test]
+fn v2_binary() {
+    let envelope = decode(include_bytes!("fixtures/envelope/v2-binary.bin"));
+    assert_eq!(envelope.version, 2);
+    assert_eq!(envelope.device.as_deref(), Some("phone"));
+    assert_eq!(envelope.seq, Some(7));
+    assert_eq!(envelope.hlc, Some(Timestamp { millis: 1760000000000, counter: 0 }));
+    assert_eq!(envelope.content_type, "text/plain");
+    assert_eq!(envelope.content, "hello in binary");
+}
+
+#[test]
+fn binary_round_trips_and_is_smaller() {
+    let mut envelope = Envelope::text("laptop", "x".repeat(300));
+    envelope.seq = Some(123456);
+    envelope.hlc = Some(Timestamp { millis: 1760000000000, counter: 2 });
+    envelope.group = Some("team".to_string());
+    envelope.slot = Some(3);
+    envelope.content_type = envelope::OFFER.to_string();
+    let binary = envelope.encode_as(Encoding::Binary);
+    assert!(binary.len() < envelope.encode().len());
+
+    let decoded = decode(&binary);
+    assert_eq!(decoded.version, envelope::VERSION);
+    assert_eq!(decoded.device.as_deref(), Some("laptop"));
+    assert_eq!(decoded.seq, Some(123456));
+    assert_eq!(decoded.hlc, envelope.hlc);
+    assert_eq!(decoded.group.as_deref(), Some("team"));
+    assert_eq!(decoded.slot, Some(3));
+    assert_eq!(decoded.content_type, envelope::OFFER);
+    assert_eq!(decoded.content, envelope.content);
+}
+
+#[test]
+fn truncated_binary_is_rejected() {
+    let binary = Envelope::text("laptop", "hello".to_string()).encode_as(Encoding::Binary);
+    assert!(Envelope::decode(&binary[..binary.len() - 1]).is_none());
+}
+
+// Peers track devices by ID, and by name only for releases from before IDs.
+#[test]
+fn sender_is_the_device_id_when_there_is_one() {
+    let mut envelope = Envelope::text("laptop", "hello".to_string());
+    assert_eq!(envelope.sender(), "laptop");
+
+    envelope.device_id = Some("5f0c2a8e-3d41-4b7a-9e26-0d8f6c1b2a93".to_string());
+    for encoding in [Encoding::Text, Encoding::Binary] {
+        let decoded = decode(&envelope.encode_as(encoding));
+        assert_eq!(decoded.device.as_deref(), Some("laptop"));
+        assert_eq!(decoded.sender(), "5f0c2a8e-3d41-4b7a-9e26-0d8f6c1b2a93");
+    }
+}
+
+#[test]
+fn sensitive_flag_round_trips() {
+    let mut envelope = Envelope::text("laptop", "hunter2".to_string());
+    assert!(!decode(&envelope.encode()).sensitive);
+
+    envelope.sensitive = true;
+    for encoding in [Encoding::Text, Encoding::Binary] {
+        assert!(decode(&envelope.encode_as(encoding)).sensitive);
+    }
+}
+
+#[test]
+fn source_round_trips_without_control_characters() {
+    let mut envelope = Envelope::text("laptop", "hello".to_string());
+    envelope.source = Some("Firefox".to_string());
+    for encoding in [Encoding::Text, Encoding::Binary] {
+        assert_eq!(decode(&envelope.encode_as(encoding)).source.as_deref(), Some("Firefox"));
+    }
+
+    envelope.source = Some("\x1b]0;evil\x07".to_string());
+    for encoding in [Encoding::Text, Encoding::Binary] {
+        assert_eq!(decode(&envelope.encode_as(encoding)).source, None);
+    }
+}
+
+#[test]
+fn tags_round_trip_and_invalid_ones_are_dropped() {
+    let mut envelope = Envelope::text("laptop", "hello".to_string());
+    envelope.tags = vec!["work".to_string(), "url".to_string()];
+    for encoding in [Encoding::Text, Encoding::Binary] {
+        assert_eq!(decode(&envelope.encode_as(encoding)).tags, ["work", "url"]);
+    }
+
+    envelope.tags = vec!["work".to_string(), "two words".to_string(), String::new()];
+    for encoding in [Encoding::Text, Encoding::Binary] {
+        assert_eq!(decode(&envelope.encode_as(encoding)).tags, ["work"]);
+    }
+}
@@ -0,0 +1,48 @@
+use cloudboard::hlc::{Clock, Timestamp};
+
+fn at(millis: u64, counter: u32) -> Timestamp {
+    Timestamp { millis, counter }
+}
+
+#[test]
+fn keeps_order_when_the_clock_stalls_or_steps_back() {
+    let clock = Clock::new();
+    assert_eq!(clock.now_at(1000), at(1000, 0));
+    assert_eq!(clock.now_at(1000), at(1000, 1));
+    assert_eq!(clock.now_at(900), at(1000, 2));
+    assert_eq!(clock.now_at(1001), at(1001, 0));
+}
+
+#[test]
+fn drops_stale_timestamps() {
+    let clock = Clock::new();
+    let local = clock.now_at(1000);
+    assert!(!clock.observe_at(local, 1000));
+    assert!(!clock.observe_at(at(999, 5), 1000));
+    assert!(clock.observe_at(at(1000, 1), 1000));
+    assert!(!clock.observe_at(at(1000, 1), 1000));
+}
+
+#[test]
+fn follows_a_newer_peer() {
+    let clock = Clock::new();
+    clock.now_at(1000);
+    assert!(clock.observe_at(at(5000, 3), 1000));
+    assert!(!clock.observe_at(at(4000, 0), 1000));
+    assert_eq!(clock.now_at(1000), at(5000, 4));
+}
+
+#[test]
+fn does_not_follow_a_peer_far_ahead() {
+    let clock = Clock::new();
+    clock.now_at(1000);
+    assert!(clock.observe_at(at(1000 + 3_600_000, 0), 1000));
+    assert_eq!(clock.now_at(1000), at(1000, 2));
+}
+
+#[test]
+fn round_trips_through_text() {
+    assert_eq!(at(1700000000000, 7).to_string(), "1700000000000-7");
+    assert_eq!("1700000000000-7".parse(), Ok(at(1700000000000, 7)));
+    assert!("1700000000000".parse::<Timestamp>().is_err());
+}
@@ -0,0 +1,23 @@
+// Names as `cloudboardctl` puts them in a query, which the daemon has to
+// read back the same.
+#![cfg(feature = "http-api")]
+use cloudboard::http::{percent_decode, percent_encode};
+
+#[test]
+fn reads_back_encoded_names() {
+    for name in ["work", "design team", "r&d", "a=b+c", "50%", "équipe"] {
+        assert_eq!(percent_decode(&percent_encode(name)).as_deref(), Some(name));
+    }
+}
+
+#[test]
+fn reads_plus_as_space() {
+    assert_eq!(percent_decode("design+team").as_deref(), Some("design team"));
+}
+
+#[test]
+fn refuses_broken_escapes() {
+    assert_eq!(percent_decode("50%"), None);
+    assert_eq!(percent_decode("%zz"), None);
+    assert_eq!(percent_decode("%ff"), None);
+}
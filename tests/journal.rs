@@ -0,0 +1,41 @@
+use std::sync::Arc;
+use cloudboard::journal::Journal;
+use cloudboard::store::MemoryStore;
+
+#[test]
+fn replays_what_is_not_acked_oldest_first() {
+    let journal = Journal::new(Arc::new(MemoryStore::new()));
+    journal.record(10, "u/clipboard", b"ten");
+    journal.record(9, "u/clipboard", b"nine");
+    journal.record(11, "u/group/work", b"eleven\nsecond line");
+    assert_eq!(journal.pending(), vec![
+        (9, "u/clipboard".to_string(), b"nine".to_vec()),
+        (10, "u/clipboard".to_string(), b"ten".to_vec()),
+        (11, "u/group/work".to_string(), b"eleven\nsecond line".to_vec()),
+    ]);
+}
+
+#[test]
+fn prunes_what_is_acked() {
+    let journal = Journal::new(Arc::new(MemoryStore::new()));
+    for seq in 1..=5 {
+        journal.record(seq, "u/clipboard", seq.to_string().as_bytes());
+    }
+    journal.acked(3);
+    let pending: Vec<u64> = journal.pending().into_iter().map(|(seq, _, _)| seq).collect();
+    assert_eq!(pending, vec![4, 5]);
+    journal.acked(5);
+    assert!(journal.pending().is_empty());
+}
+
+#[test]
+fn keeps_the_newest_when_never_acked() {
+    let journal = Journal::new(Arc::new(MemoryStore::new()));
+    for seq in 1..=300 {
+        journal.record(seq, "u/clipboard", b"item");
+    }
+    let pending = journal.pending();
+    assert_eq!(pending.len(), 256);
+    assert_eq!(pending.first().map(|(seq, _, _)| *seq), Some(45));
+    assert_eq!(pending.last().map(|(seq, _, _)| *seq), Some(300));
+}
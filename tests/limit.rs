@@ -0,0 +1,30 @@
+use cloudboard::limit::RateLimit;
+
+#[test]
+fn allows_a_burst_then_refuses() {
+    let mut limit = RateLimit::new(3);
+    assert!(limit.allow("laptop"));
+    assert!(limit.allow("laptop"));
+    assert!(limit.allow("laptop"));
+    assert!(!limit.allow("laptop"));
+}
+
+#[test]
+fn limits_each_device_on_its_own() {
+    let mut limit = RateLimit::new(1);
+    assert!(limit.allow("laptop"));
+    assert!(!limit.allow("laptop"));
+    assert!(limit.allow("phone"));
+    assert!(!limit.allow("phone"));
+}
+
+#[test]
+fn flood_of_names_does_not_take_known_devices_allowance() {
+    let mut limit = RateLimit::new(2);
+    assert!(limit.allow("laptop"));
+    for i in 0..1023 {
+        assert!(limit.allow(&format!("made-up-{i}")));
+    }
+    assert!(!limit.allow("another-made-up"));
+    assert!(limit.allow("laptop"));
+}
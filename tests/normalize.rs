@@ -0,0 +1,31 @@
+use cloudboard::normalize::{normalize, Normalize};
+
+#[test]
+fn trims_trailing_whitespace_per_line() {
+    let steps = [Normalize::TrimTrailing];
+    assert_eq!(normalize("ls -la  \n  cd src\t\n\n", &steps), "ls -la\n  cd src");
+    assert_eq!(normalize("a \r\nb\r\n", &steps), "a\r\nb");
+    assert_eq!(normalize("  kept", &steps), "  kept");
+}
+
+#[test]
+fn collapses_runs_but_keeps_indentation() {
+    let steps = [Normalize::Collapse];
+    assert_eq!(normalize("fn  main()\t\t{\n    body\n}", &steps), "fn main() {\n    body\n}");
+    assert_eq!(normalize("one\n\n\n\ntwo\n \n\nthree", &steps), "one\n\ntwo\n\nthree");
+}
+
+#[test]
+fn strips_formatting_for_plain_text() {
+    let steps = [Normalize::Plain];
+    assert_eq!(normalize("\x1b[1mbold\x1b[0m", &steps), "bold");
+    assert_eq!(normalize("zero\u{200b}width soft\u{ad}hyphen", &steps), "zerowidth softhyphen");
+    assert_eq!(normalize("no\u{a0}break", &steps), "no break");
+}
+
+#[test]
+fn runs_every_step_in_order() {
+    let steps = [Normalize::Trim, Normalize::TrimTrailing, Normalize::Collapse, Normalize::Plain];
+    assert_eq!(normalize("\n  copied\u{a0}\u{a0}from   a PDF  \n\n", &steps), "copied from a PDF");
+    assert!(matches!(normalize("untouched", &steps), std::borrow::Cow::Borrowed(_)));
+}
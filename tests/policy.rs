@@ -0,0 +1,100 @@
+use std::sync::Arc;
+use cloudboard::envelope::{self, Envelope};
+use cloudboard::hlc::Timestamp;
+use cloudboard::policy::{Policy, Settings};
+use cloudboard::store::{MemoryStore, Store};
+
+fn settings(from: &str, text: &str, millis: u64) -> Envelope {
+    let mut published = Envelope::text(from, text.to_string());
+    published.content_type = envelope::POLICY.to_string();
+    published.hlc = Some(Timestamp { millis, counter: 0 });
+    published
+}
+
+#[test]
+fn parses_known_settings_and_returns_the_rest() {
+    let (settings, unknown) = Settings::parse("max_size = 1000\ntext_only = true\npaused = [\"personal\"]\nfuture = 1\n").unwrap();
+    assert_ne!(settings, Settings::default());
+    assert_eq!(unknown, vec!["future"]);
+    assert!(Settings::parse("max_size = \"big\"\n").is_err());
+    assert!(Settings::parse("max_size = 0\n").is_err());
+    assert!(Settings::parse("text_only = 1\n").is_err());
+}
+
+#[test]
+fn size_limit_is_the_own_one_without_a_primary() {
+    let policy = Policy::load(None, Arc::new(MemoryStore::new()));
+    assert_eq!(policy.max_size(1000), 1000);
+}
+
+#[test]
+fn applies_settings_signed_by_the_primary() {
+    let policy = Policy::load(Some("laptop".to_string()), Arc::new(MemoryStore::new()));
+    policy.receive(&settings("laptop", "max_size = 1000\ntext_only = true\npaused = [\"personal\"]\n", 1000), Some("laptop"));
+    assert_eq!(policy.max_size(5000), 1000);
+    assert_eq!(policy.max_size(500), 500);
+    assert!(policy.text_only(false));
+    assert!(!policy.filter_secrets(false));
+    assert!(policy.is_paused(None));
+    assert!(!policy.is_paused(Some("work")));
+}
+
+#[test]
+fn ignores_settings_not_signed_by_the_primary() {
+    let policy = Policy::load(Some("laptop".to_string()), Arc::new(MemoryStore::new()));
+    policy.receive(&settings("laptop", "max_size = 1000\n", 1000), None);
+    policy.receive(&settings("laptop", "max_size = 1000\n", 1000), Some("phone"));
+    assert_eq!(policy.max_size(5000), 5000);
+}
+
+#[test]
+fn ignores_settings_without_a_primary() {
+    let policy = Policy::load(None, Arc::new(MemoryStore::new()));
+    policy.receive(&settings("laptop", "text_only = true\n", 1000), Some("laptop"));
+    assert!(!policy.text_only(false));
+}
+
+#[test]
+fn keeps_the_settings_across_restarts() {
+    let store: Arc<dyn Store> = Arc::new(MemoryStore::new());
+    Policy::load(Some("laptop".to_string()), store.clone()).receive(&settings("laptop", "filter_secrets = true\n", 1000), Some("laptop"));
+    assert!(Policy::load(Some("laptop".to_string()), store.clone()).filter_secrets(false));
+    assert!(!Policy::load(None, store).filter_secrets(false));
+}
+
+#[test]
+fn ignores_older_settings_replayed() {
+    let policy = Policy::load(Some("laptop".to_string()), Arc::new(MemoryStore::new()));
+    let paused = settings("laptop", "paused = [\"personal\"]\n", 2000);
+    policy.receive(&paused, Some("laptop"));
+    policy.receive(&settings("laptop", "", 1000), Some("laptop"));
+    assert!(policy.is_paused(None));
+    policy.receive(&paused, Some("laptop"));
+    assert!(policy.is_paused(None));
+}
+
+#[test]
+fn ignores_settings_without_a_timestamp() {
+    let policy = Policy::load(Some("laptop".to_string()), Arc::new(MemoryStore::new()));
+    let mut published = settings("laptop", "text_only = true\n", 1000);
+    published.hlc = None;
+    policy.receive(&published, Some("laptop"));
+    assert!(!policy.text_only(false));
+}
+
+#[test]
+fn keeps_refusing_older_settings_after_restart() {
+    let store: Arc<dyn Store> = Arc::new(MemoryStore::new());
+    Policy::load(Some("laptop".to_string()), store.clone()).receive(&settings("laptop", "text_only = true\n", 2000), Some("laptop"));
+    let policy = Policy::load(Some("laptop".to_string()), store);
+    policy.receive(&settings("laptop", "", 1000), Some("laptop"));
+    assert!(policy.text_only(false));
+}
+
+#[test]
+fn cleared_settings_lift_the_limits() {
+    let policy = Policy::load(Some("laptop".to_string()), Arc::new(MemoryStore::new()));
+    policy.receive(&settings("laptop", "max_size = 1000\n", 1000), Some("laptop"));
+    policy.receive(&settings("laptop", "", 2000), Some("laptop"));
+    assert_eq!(policy.max_size(5000), 5000);
+}
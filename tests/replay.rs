@@ -0,0 +1,54 @@
+use std::sync::Arc;
+use cloudboard::replay::{ReplayGuard, Sequence};
+use cloudboard::store::{MemoryStore, Store};
+
+#[test]
+fn rejects_replayed_sequence_numbers() {
+    let mut guard = ReplayGuard::load(Arc::new(MemoryStore::new()));
+    assert!(guard.accept("laptop", Some(5)));
+    assert!(!guard.accept("laptop", Some(5)));
+    assert!(!guard.accept("laptop", Some(3)));
+    assert!(guard.accept("laptop", Some(6)));
+}
+
+#[test]
+fn keeps_devices_apart() {
+    let mut guard = ReplayGuard::load(Arc::new(MemoryStore::new()));
+    assert!(guard.accept("laptop", Some(5)));
+    assert!(guard.accept("phone", Some(1)));
+}
+
+#[test]
+fn rejects_missing_sequence_number_once_one_was_sent() {
+    let mut guard = ReplayGuard::load(Arc::new(MemoryStore::new()));
+    assert!(guard.accept("old-client", None));
+    assert!(guard.accept("old-client", None));
+    assert!(guard.accept("laptop", Some(1)));
+    assert!(!guard.accept("laptop", None));
+}
+
+#[test]
+fn accepts_uncommitted_items_again_after_restart() {
+    let store: Arc<dyn Store> = Arc::new(MemoryStore::new());
+    let mut guard = ReplayGuard::load(store.clone());
+    assert!(guard.accept("laptop", Some(1)));
+    assert!(guard.accept("laptop", Some(2)));
+    guard.commit("laptop", 1);
+    assert!(guard.is_committed("laptop", 1));
+    assert!(!guard.is_committed("laptop", 2));
+
+    let mut guard = ReplayGuard::load(store);
+    assert!(guard.is_committed("laptop", 1));
+    assert!(!guard.accept("laptop", Some(1)));
+    assert!(!guard.accept("laptop", None));
+    assert!(guard.accept("laptop", Some(2)));
+}
+
+#[test]
+fn sequence_numbers_survive_restart() {
+    let store: Arc<dyn Store> = Arc::new(MemoryStore::new());
+    let mut sequence = Sequence::load(store.clone());
+    assert_eq!(sequence.advance(), 1);
+    assert_eq!(sequence.advance(), 2);
+    assert_eq!(Sequence::load(store).advance(), 3);
+}
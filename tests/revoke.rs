@@ -0,0 +1,102 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+use cloudboard::envelope::{self, Envelope};
+use cloudboard::revoke::{key_name, Revoked};
+use cloudboard::store::MemoryStore;
+use cloudboard::trust::Trust;
+
+// A data dir with this device's keys and a trust list of laptop and phone,
+// along with phone's signing key.
+fn setup(test: &str) -> (PathBuf, Trust, [u8; 32]) {
+    let dir = std::env::temp_dir().join(format!("cloudboard-revoke-{}-{}", test, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let mut identities = String::new();
+    for device in ["laptop", "phone"] {
+        std::fs::create_dir_all(dir.join(device)).unwrap();
+        let other = Trust::load(&dir.join(device)).unwrap();
+        identities.push_str(&format!("{} {}\n", device, other.identity()));
+    }
+    let phone = Trust::load(&dir.join("phone")).unwrap().verifying_key();
+    std::fs::write(dir.join("trust.list"), identities).unwrap();
+    let trust = Trust::load(&dir).unwrap();
+    (dir, trust, phone)
+}
+
+fn revocation(from: &str, devices: &str) -> Envelope {
+    let mut revocation = Envelope::text(from, devices.to_string());
+    revocation.content_type = envelope::REVOKE.to_string();
+    revocation
+}
+
+fn from(device: &str, id: &str) -> Envelope {
+    let mut envelope = Envelope::text(device, String::new());
+    envelope.device_id = Some(id.to_string());
+    envelope
+}
+
+fn trusted(trust: &Trust) -> Vec<String> {
+    trust.devices().unwrap().into_iter().map(|device| device.name).collect()
+}
+
+#[test]
+fn revokes_by_name_id_and_key() {
+    let (dir, trust, phone) = setup("signed");
+    let own = key_name(&trust.verifying_key());
+    let revoked = Revoked::load(Arc::new(MemoryStore::new()));
+    assert!(!revoked.any());
+    revoked.receive(&revocation("laptop", "phone 1234"), Some("laptop"), &trust, ["desktop", "5678", &own]);
+    assert!(revoked.any());
+    assert!(revoked.is_revoked(&from("phone", "0000")));
+    assert!(revoked.is_revoked(&from("renamed", "1234")));
+    assert!(!revoked.is_revoked(&from("laptop", "9999")));
+    assert!(revoked.is_revoked_key(&phone));
+    assert!(!revoked.is_revoked_key(&trust.verifying_key()));
+    assert_eq!(trusted(&trust), vec!["laptop"]);
+    let _ = std::fs::remove_dir_all(dir);
+}
+
+#[test]
+fn ignores_unsigned_revocations() {
+    let (dir, trust, phone) = setup("unsigned");
+    let own = key_name(&trust.verifying_key());
+    let revoked = Revoked::load(Arc::new(MemoryStore::new()));
+    revoked.receive(&revocation("laptop", "phone"), None, &trust, ["desktop", "5678", &own]);
+    assert!(!revoked.any());
+    assert!(!revoked.is_revoked_key(&phone));
+    assert_eq!(trusted(&trust), vec!["laptop", "phone"]);
+    let _ = std::fs::remove_dir_all(dir);
+}
+
+#[test]
+fn ignores_revocations_of_this_device() {
+    let (dir, trust, _) = setup("me");
+    let own = key_name(&trust.verifying_key());
+    let revoked = Revoked::load(Arc::new(MemoryStore::new()));
+    revoked.receive(&revocation("laptop", "phone desktop"), Some("laptop"), &trust, ["desktop", "5678", &own]);
+    revoked.receive(&revocation("laptop", &own), Some("laptop"), &trust, ["desktop", "5678", &own]);
+    assert!(!revoked.any());
+    let _ = std::fs::remove_dir_all(dir);
+}
+
+#[test]
+fn ignores_revocations_signed_by_a_revoked_device() {
+    let (dir, trust, _) = setup("revoker");
+    let own = key_name(&trust.verifying_key());
+    let revoked = Revoked::load(Arc::new(MemoryStore::new()));
+    revoked.receive(&revocation("laptop", "phone"), Some("laptop"), &trust, ["desktop", "5678", &own]);
+    revoked.receive(&revocation("phone", "laptop"), Some("phone"), &trust, ["desktop", "5678", &own]);
+    assert!(!revoked.is_revoked(&from("laptop", "9999")));
+    let _ = std::fs::remove_dir_all(dir);
+}
+
+#[test]
+fn keeps_revocations_across_restarts() {
+    let (dir, trust, phone) = setup("restart");
+    let own = key_name(&trust.verifying_key());
+    let store = Arc::new(MemoryStore::new());
+    Revoked::load(store.clone()).receive(&revocation("laptop", "phone"), Some("laptop"), &trust, ["desktop", "5678", &own]);
+    let revoked = Revoked::load(store);
+    assert!(revoked.is_revoked(&from("phone", "0000")));
+    assert!(revoked.is_revoked_key(&phone));
+    let _ = std::fs::remove_dir_all(dir);
+}
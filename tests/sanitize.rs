@@ -0,0 +1,41 @@
+use cloudboard::sanitize::{sanitize, Strip};
+
+const ALL: &[Strip] = &[Strip::Escapes, Strip::Controls, Strip::Bidi];
+
+#[test]
+fn removes_escape_sequences_whole() {
+    let cases = [
+        ("\x1b[31mred\x1b[0m", "red"),
+        // a window title, ended by BEL and by ST
+        ("\x1b]0;title\x07ls", "ls"),
+        ("\x1b]8;;http://example.com\x1b\\link\x1b]8;;\x1b\\", "link"),
+        // an 8-bit CSI, and a bracketed paste end that would run what follows
+        ("\u{9b}2Jclear", "clear"),
+        ("safe\x1b[201~rm -rf ~\n", "saferm -rf ~\n"),
+        ("\x1b(Bascii", "ascii"),
+    ];
+    for (text, expected) in cases {
+        assert_eq!(sanitize(text, ALL), expected, "{text:?}");
+    }
+}
+
+#[test]
+fn keeps_tabs_and_line_breaks() {
+    let text = "a\tb\r\nc\n";
+    assert_eq!(sanitize(text, ALL), text);
+    assert_eq!(sanitize("a\x00b\x08c\x7fd", ALL), "abcd");
+}
+
+#[test]
+fn removes_bidi_overrides() {
+    assert_eq!(sanitize("access\u{202e}\u{2066}level\u{2069}", ALL), "accesslevel");
+    assert_eq!(sanitize("access\u{202e}", &[Strip::Controls]), "access\u{202e}");
+}
+
+#[test]
+fn none_leaves_text_alone() {
+    let text = "\x1b[31m\u{202e}\x00";
+    assert_eq!(sanitize(text, &[Strip::None]), text);
+    // Without escapes, only the escape character itself is a control.
+    assert_eq!(sanitize("\x1b[31mred", &[Strip::Controls]), "[31mred");
+}
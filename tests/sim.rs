@@ -0,0 +1,19 @@
+use std::time::Duration;
+use cloudboard::sim::{self, Settings};
+
+#[test]
+fn converges_despite_loss_and_reordering() {
+    for seed in 1..=20 {
+        let report = sim::run(&Settings { devices: 4, copies: 30, loss: 0.3, reorder: 0.2, seed: Some(seed), ..Settings::default() });
+        assert!(report.is_ok(), "seed {seed}:\n{report}");
+        assert_eq!(report.applied_twice, 0);
+    }
+}
+
+#[test]
+fn delivers_everything_once_without_loss() {
+    let report = sim::run(&Settings { devices: 3, copies: 10, loss: 0.0, jitter: Duration::ZERO, seed: Some(1), ..Settings::default() });
+    assert!(report.is_ok(), "{report}");
+    assert_eq!(report.delivered, 3 * 2 * 10);
+    assert_eq!(report.duplicates + report.overtaken, 0);
+}
@@ -0,0 +1,41 @@
+use std::cell::Cell;
+use cloudboard::template::expand;
+
+#[test]
+fn fills_in_the_clipboard_once() {
+    let reads = Cell::new(0);
+    let clipboard = || {
+        reads.set(reads.get() + 1);
+        Some("old".to_string())
+    };
+    assert_eq!(expand("was {clipboard}, still {clipboard}", clipboard), "was old, still old");
+    assert_eq!(reads.get(), 1);
+}
+
+#[test]
+fn leaves_the_clipboard_alone_unless_asked() {
+    assert_eq!(expand("no placeholders", || panic!("read the clipboard")), "no placeholders");
+}
+
+#[test]
+fn keeps_what_is_not_a_placeholder() {
+    let cases = [
+        ("{{date}}", "{date}"),
+        ("a }} b {{", "a } b {"),
+        ("{unknown} {", "{unknown} {"),
+        ("fn main() { }", "fn main() { }"),
+        ("{clipboard", "{clipboard"),
+    ];
+    for (template, expected) in cases {
+        assert_eq!(expand(template, || None), expected, "{template:?}");
+    }
+}
+
+#[test]
+fn fills_in_the_date() {
+    let expanded = expand("on {date} at {time}", || None);
+    let (date, time) = expanded.strip_prefix("on ").and_then(|rest| rest.split_once(" at ")).unwrap();
+    assert_eq!(date.len(), 10);
+    assert_eq!(date.matches('-').count(), 2);
+    assert_eq!(time.len(), 8);
+}
@@ -0,0 +1,51 @@
+use cloudboard::envelope;
+use cloudboard::text::{graphemes, truncate};
+
+#[test]
+fn splits_clusters_across_scripts() {
+    let cases: &[(&str, &[&str])] = &[
+        ("abc", &["a", "b", "c"]),
+        ("日本語", &["日", "本", "語"]),
+        // e and a combining acute accent
+        ("e\u{301}x", &["e\u{301}", "x"]),
+        // a family joined with ZWJs, and a thumbs up with a skin tone
+        ("👨‍👩‍👧👍🏽", &["👨‍👩‍👧", "👍🏽"]),
+        // two flags, each a pair of regional indicators
+        ("🇩🇪🇯🇵", &["🇩🇪", "🇯🇵"]),
+        // Hangul written as conjoining jamo
+        ("\u{1100}\u{1161}\u{11A8}가", &["\u{1100}\u{1161}\u{11A8}", "가"]),
+        // Devanagari with a vowel sign
+        ("नमस्ते", &["न", "म", "स्", "ते"]),
+        ("สวัสดี", &["ส", "วั", "ส", "ดี"]),
+        ("a\r\nb", &["a", "\r\n", "b"]),
+    ];
+    for (text, expected) in cases {
+        assert_eq!(graphemes(text).collect::<Vec<_>>(), *expected, "{text:?}");
+    }
+}
+
+#[test]
+fn truncate_keeps_whole_clusters() {
+    assert_eq!(truncate("hello", 10), "hello");
+    assert_eq!(truncate("hello", 3), "hel");
+    // 3 bytes each, so 4 bytes only fit one
+    assert_eq!(truncate("日本語", 4), "日");
+    // the family is 18 bytes, so a limit inside it keeps nothing of it
+    assert_eq!(truncate("a👨‍👩‍👧", 10), "a");
+    assert_eq!(truncate("🇩🇪🇯🇵", 12), "🇩🇪");
+    assert_eq!(truncate("e\u{301}e\u{301}", 4), "e\u{301}");
+    assert_eq!(truncate("日本語", 2), "");
+}
+
+#[test]
+fn preview_does_not_split_clusters() {
+    let flags = "🇩🇪".repeat(300);
+    let preview = envelope::preview(&flags);
+    assert_eq!(preview, "🇩🇪".repeat(200));
+
+    let accented = "e\u{301}".repeat(5000);
+    let preview = envelope::preview(&accented);
+    assert_eq!(preview, "e\u{301}".repeat(200));
+
+    assert_eq!(envelope::preview("  one\n\ttwo  "), "one two");
+}
@@ -0,0 +1,58 @@
+// Triggers on received content, which run a command whose output shows in
+// a directory of the test's own.
+#![cfg(unix)]
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use cloudboard::config::Config;
+use cloudboard::envelope::Envelope;
+use cloudboard::trigger;
+
+fn dir(test: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("cloudboard-trigger-{}-{}", test, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+// Fires a trigger with `extra` settings on content signed by `signer` and
+// returns whether its command ran.
+fn runs(test: &str, extra: &str, signer: Option<&str>) -> bool {
+    let dir = dir(test);
+    let config = Config::parse(&format!("[trigger.t]\ncommand = [\"sh\", \"-c\", \"cat > ran\"]\ncwd = {:?}\n{extra}", dir.display().to_string())).unwrap();
+    let triggers = trigger::load(&config, "desktop").unwrap();
+    let mut envelope = Envelope::text("laptop", "hello".to_string());
+    envelope.signer = signer.map(str::to_string);
+    trigger::received(&triggers, &envelope);
+    let ran = wait_for(&dir.join("ran"));
+    let _ = std::fs::remove_dir_all(dir);
+    ran
+}
+
+fn wait_for(path: &Path) -> bool {
+    let deadline = Instant::now() + Duration::from_secs(1);
+    while Instant::now() < deadline {
+        if std::fs::read_to_string(path).is_ok_and(|content| content == "hello") {
+            return true;
+        }
+        std::thread::sleep(Duration::from_millis(20));
+    }
+    false
+}
+
+#[test]
+fn runs_on_signed_content() {
+    assert!(runs("signed", "", Some("laptop")));
+}
+
+#[test]
+fn leaves_out_unsigned_content() {
+    assert!(!runs("unsigned", "", None));
+    assert!(runs("unsigned-allowed", "unsigned = true\n", None));
+}
+
+#[test]
+fn runs_only_for_devices_in_from() {
+    assert!(runs("from", "from = [\"laptop\"]\n", Some("laptop")));
+    assert!(!runs("from-other", "from = [\"phone\"]\n", Some("laptop")));
+    assert!(!runs("from-unsigned", "from = [\"laptop\"]\nunsigned = true\n", None));
+}
@@ -0,0 +1,47 @@
+// The known answers from RFC 7748, which key agreement with other devices
+// depends on matching.
+use cloudboard::crypto::from_hex;
+use cloudboard::x25519;
+
+fn key(hex: &str) -> [u8; 32] {
+    from_hex(hex).unwrap().try_into().unwrap()
+}
+
+#[test]
+fn scalar_multiplication_matches_rfc_7748() {
+    let vectors = [
+        (
+            "a546e36bf0527c9d3b16154b82465edd62144c0ac1fc5a18506a2244ba449ac4",
+            "e6db6867583030db3594c1a424b15f7c726624ec26b3353b10a903a6d0ab1c4c",
+            "c3da55379de9c6908e94ea4df28d084f32eccf03491c71f754b4075577a28552",
+        ),
+        (
+            "4b66e9d4d1b4673c5ad22691957d6af5c11b6421e0ea01d42ca4169e7918ba0d",
+            "e5210f12786811d3f4b7959d0538ae2c31dbe7106fc03c3efc4cd549c715a493",
+            "95cbde9476e8907d7aade45cb4b873f88b595a68799fa152e6f8f7647aac7957",
+        ),
+    ];
+    for (scalar, point, expected) in vectors {
+        assert_eq!(x25519::scalarmult(&key(scalar), &key(point)), key(expected));
+    }
+}
+
+// The 1000-iteration answer takes too long unoptimized.
+#[test]
+fn one_iteration_matches_rfc_7748() {
+    let nine = key("0900000000000000000000000000000000000000000000000000000000000000");
+    assert_eq!(x25519::scalarmult(&nine, &nine), key("422c8e7a6227d7bca1350b3e2bb7279f7897b87bb6854b783c60e80311ae3079"));
+}
+
+#[test]
+fn key_agreement_matches_rfc_7748() {
+    let alice = key("77076d0a7318a57d3c16c17251b26645df4c2f87ebc0992ab177fba51db92c2a");
+    let bob = key("5dab087e624a8a4b79e17f8b83800ee66f3bb1292618b6fd1c2f8b27ff88e0eb");
+    let alice_public = x25519::public_key(&alice);
+    let bob_public = x25519::public_key(&bob);
+    assert_eq!(alice_public, key("8520f0098930a754748b7ddcb43ef75a0dbf3a0d26381af4eba4a98eaa9b4e6a"));
+    assert_eq!(bob_public, key("de9edb7d7b7dc1b4d35b61c2ece435373f8343c85b78674dadfc7e146f882b4f"));
+    let shared = key("4a5d9d5ba4ce2de1728e3bf480350f25e07e21c947d19e3376f09b3c1e161742");
+    assert_eq!(x25519::scalarmult(&alice, &bob_public), shared);
+    assert_eq!(x25519::scalarmult(&bob, &alice_public), shared);
+}